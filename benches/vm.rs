@@ -0,0 +1,65 @@
+//! Compares the bytecode VM with the AST interpreter, run with `cargo bench`
+
+use std::time::{Duration, Instant};
+
+use fan_rs::bytecode::Program;
+use fan_rs::interpreter::{Interpreter, Value};
+use fan_rs::parser::Parser;
+use fan_rs::vm::Vm;
+
+const SOURCE: &str = "
+    automata Counter {
+        state Base<context: (int64, int64)> {
+            if self.previous.is_me() {
+                context[0] += 1;
+            }
+            if context[0] == context[1] {
+                link self -> Done<context[0], self.steps>;
+            } else {
+                link self -> Base<context>;
+            }
+        }
+        state Done<total: int64, steps: int64> { }
+    }
+    automata Digits: Mealy<signal, out> {
+        state Number<signal: char, acc: int64> {
+            out = (acc * 10 + (signal - '0')) % 1000000007;
+            link self -> Number<out>;
+        }
+    }
+    fn fib(n: int64) -> int64 {
+        if n < 2 { return n; }
+        fib(n - 1) + fib(n - 2)
+    }
+";
+
+fn measure<T>(name: &str, work: usize, unit: &str, mut f: impl FnMut() -> T) -> Duration {
+    let start = Instant::now();
+    std::hint::black_box(f());
+    let elapsed = start.elapsed();
+    println!("{:<28} {:>10.2?} {:>14.0} {}/s", name, elapsed, work as f64 / elapsed.as_secs_f64(), unit);
+    elapsed
+}
+
+fn main() {
+    let module = Parser::parse_str(SOURCE).expect("benchmark source parses");
+    let program = Program::compile(&module);
+    let mut interpreter = Interpreter::new(&module);
+    let mut vm = Vm::new(&program);
+
+    const TRANSITIONS: i64 = 1_000_000;
+    let context = || vec![Value::Tuple(vec![Value::Int(0), Value::Int(TRANSITIONS)])];
+    let ast = measure("moore / interpreter", TRANSITIONS as usize, "transitions", || interpreter.run_automata("Counter", context()).unwrap());
+    let bytecode = measure("moore / vm", TRANSITIONS as usize, "transitions", || vm.run_automata("Counter", context()).unwrap());
+    println!("{:<28} {:>10.1}x", "moore speedup", ast.as_secs_f64() / bytecode.as_secs_f64());
+
+    let input: Vec<Value> = (0..TRANSITIONS).map(|i| Value::Char(char::from(b'0' + (i % 10) as u8))).collect();
+    let ast = measure("mealy / interpreter", input.len(), "signals", || interpreter.run_mealy("Digits", vec![Value::Int(0)], input.iter().cloned()).unwrap());
+    let bytecode = measure("mealy / vm", input.len(), "signals", || vm.run_mealy("Digits", vec![Value::Int(0)], input.iter().cloned()).unwrap());
+    println!("{:<28} {:>10.1}x", "mealy speedup", ast.as_secs_f64() / bytecode.as_secs_f64());
+
+    const CALLS: usize = 2_692_537; // calls made by fib(30)
+    let ast = measure("fib(30) / interpreter", CALLS, "calls", || interpreter.call_function("fib", vec![Value::Int(30)]).unwrap());
+    let bytecode = measure("fib(30) / vm", CALLS, "calls", || vm.call_function("fib", vec![Value::Int(30)]).unwrap());
+    println!("{:<28} {:>10.1}x", "fib speedup", ast.as_secs_f64() / bytecode.as_secs_f64());
+}
@@ -0,0 +1,127 @@
+//! `fan!` macro expanding inline FAN into the generated Rust types and functions,
+//! see `fan_rs::codegen`. Comments inside the macro are Rust `//` comments, as the input is tokenized by rustc.
+//!
+//! ```
+//! use fan_macros::fan;
+//!
+//! fan! {
+//!     automata Counter {
+//!         state Count<n: int64> {
+//!             if n < 3 {
+//!                 link self -> Count<n + 1>;
+//!             }
+//!         }
+//!     }
+//! }
+//!
+//! assert_eq!(Counter::start(0).run(), Counter::Count { n: 3 });
+//! ```
+//!
+//! FAN errors become `compile_error!` at the offending token:
+//!
+//! ```compile_fail
+//! fan_macros::fan! {
+//!     fn f() { g(); }
+//! }
+//! ```
+
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+
+use fan_rs::codegen::to_rust;
+use fan_rs::diagnostic::Diagnostic;
+use fan_rs::lexer::Position;
+use fan_rs::parser::Parser;
+
+#[proc_macro]
+pub fn fan(input: TokenStream) -> TokenStream {
+    let source = Source::layout(input);
+    let generated = Parser::parse_str(&source.text)
+        .map_err(|e| vec![Diagnostic::from(&e)])
+        .and_then(|module| to_rust(&module).map_err(|e| Vec::from(&e)));
+    match generated {
+        Ok(code) => code.parse().expect("generated code is valid Rust"),
+        Err(diagnostics) => diagnostics.iter().map(|d| source.compile_error(d)).collect(),
+    }
+}
+
+/// Macro input laid out as FAN source text, remembering the span of every token
+struct Source {
+    text: String,
+    spans: Vec<(Position, Span)>,
+}
+
+impl Source {
+    fn layout(input: TokenStream) -> Self {
+        let mut source = Source { text: String::new(), spans: vec![] };
+        let mut lines: Vec<String> = vec![];
+        let first = input.clone().into_iter().next().map(|t| t.span().line()).unwrap_or(1);
+        source.push_stream(input, first, &mut lines);
+        source.text = lines.join("\n");
+        source
+    }
+
+    fn push_stream(&mut self, input: TokenStream, first: usize, lines: &mut Vec<String>) {
+        for tree in input {
+            match tree {
+                TokenTree::Group(g) => {
+                    let (open, close) = match g.delimiter() {
+                        Delimiter::Parenthesis => ("(", ")"),
+                        Delimiter::Brace => ("{", "}"),
+                        Delimiter::Bracket => ("[", "]"),
+                        Delimiter::None => ("", ""),
+                    };
+                    self.push(open, g.span_open(), first, lines);
+                    self.push_stream(g.stream(), first, lines);
+                    self.push(close, g.span_close(), first, lines);
+                },
+                t => self.push(&t.to_string(), t.span(), first, lines),
+            }
+        }
+    }
+
+    /// Places the token text where it was written, rustc columns are one based
+    fn push(&mut self, text: &str, span: Span, first: usize, lines: &mut Vec<String>) {
+        if text.is_empty() {
+            return;
+        }
+        let line = span.line().saturating_sub(first);
+        if lines.len() <= line {
+            lines.resize(line + 1, String::new());
+        }
+        let l = &mut lines[line];
+        let len = l.chars().count();
+        // tokens of the same span, e.g. from other macros, still need separating
+        let col = match span.column().saturating_sub(1) {
+            col if col < len => len + 1,
+            col => col,
+        };
+        while l.chars().count() < col {
+            l.push(' ');
+        }
+        self.spans.push((Position::new(line, col), span));
+        l.push_str(text);
+    }
+
+    /// Span of the last token starting at or before the position
+    fn span(&self, position: Option<Position>) -> Span {
+        let Some(position) = position else {
+            return Span::call_site();
+        };
+        self.spans.iter().rev()
+            .find(|(p, _)| *p <= position)
+            .map(|(_, s)| *s)
+            .unwrap_or_else(Span::call_site)
+    }
+
+    fn compile_error(&self, d: &Diagnostic) -> TokenStream {
+        let span = self.span(d.position);
+        let mut message = Literal::string(&d.message);
+        message.set_span(span);
+        let tokens: [TokenTree; 3] = [
+            Ident::new("compile_error", span).into(),
+            Punct::new('!', Spacing::Alone).into(),
+            Group::new(Delimiter::Brace, TokenTree::from(message).into()).into(),
+        ];
+        tokens.into_iter().map(|mut t| { t.set_span(span); t }).collect()
+    }
+}
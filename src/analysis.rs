@@ -0,0 +1,197 @@
+//! Reachability analyses of the state graph for `fan check --analyze`: the states the initial one
+//! never leads to, the states that never lead to NULL and the cycles of states.
+
+use crate::graph::{AutomataGraph, Graph};
+use crate::json::Json;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Analysis {
+    pub automata: String,
+    /// States no path of links leads to from the initial state
+    pub unreachable: Vec<String>,
+    /// States no path of links leads from to NULL, the automata entering them never finishes
+    pub non_terminating: Vec<String>,
+    /// Strongly connected components with a cycle, the states and the components in the declaration order
+    pub components: Vec<Vec<String>>,
+}
+
+impl Analysis {
+    pub fn new(a: &AutomataGraph) -> Self {
+        let names: Vec<&str> = a.states.iter().map(|s| s.name.as_str()).collect();
+        let index = |name: &str| names.iter().position(|n| *n == name);
+        let mut successors = vec![vec![]; names.len()];
+        let mut predecessors = vec![vec![]; names.len()];
+        let mut finishing = vec![];
+        for e in a.edges.iter() {
+            let Some(from) = index(&e.from) else { continue };
+            match e.to.as_deref().map(index) {
+                None => finishing.push(from),
+                Some(Some(to)) if !successors[from].contains(&to) => {
+                    successors[from].push(to);
+                    predecessors[to].push(from);
+                },
+                // links to the undefined states are errors of the checker
+                Some(_) => {},
+            }
+        }
+        let reachable = search(a.initial().and_then(|s| index(&s.name)).into_iter().collect(), &successors);
+        let terminating = search(finishing, &predecessors);
+        let states = |found: &[bool], expected: bool| {
+            names.iter().zip(found).filter(|(_, f)| **f == expected).map(|(n, _)| n.to_string()).collect()
+        };
+        let mut components: Vec<Vec<usize>> = Tarjan::components(&successors)
+            .into_iter()
+            .filter(|c| c.len() > 1 || successors[c[0]].contains(&c[0]))
+            .collect();
+        components.iter_mut().for_each(|c| c.sort());
+        components.sort();
+        Self {
+            automata: a.name.clone(),
+            unreachable: states(&reachable, false),
+            non_terminating: states(&terminating, false),
+            components: components.iter().map(|c| c.iter().map(|i| names[*i].to_string()).collect()).collect(),
+        }
+    }
+
+    pub fn to_json(&self) -> Json {
+        Json::object([
+            ("automata", self.automata.as_str().into()),
+            ("unreachable", self.unreachable.clone().into()),
+            ("non_terminating", self.non_terminating.clone().into()),
+            ("components", self.components.clone().into()),
+        ])
+    }
+}
+
+/// Findings of the automata, a line for each kind
+impl std::fmt::Display for Analysis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "automata {}", self.automata)?;
+        if !self.unreachable.is_empty() {
+            writeln!(f, "    unreachable from the initial state: {}", self.unreachable.join(", "))?;
+        }
+        if !self.non_terminating.is_empty() {
+            writeln!(f, "    never reaching NULL: {}", self.non_terminating.join(", "))?;
+        }
+        if !self.components.is_empty() {
+            let components: Vec<String> = self.components.iter().map(|c| format!("{{{}}}", c.join(", "))).collect();
+            writeln!(f, "    cycles: {}", components.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// States found from the start ones by following the edges
+fn search(start: Vec<usize>, edges: &[Vec<usize>]) -> Vec<bool> {
+    let mut found = vec![false; edges.len()];
+    let mut stack = start;
+    while let Some(i) = stack.pop() {
+        if !found[i] {
+            found[i] = true;
+            stack.extend(edges[i].iter().filter(|j| !found[**j]));
+        }
+    }
+    found
+}
+
+/// Tarjan's algorithm for the strongly connected components
+struct Tarjan<'e> {
+    successors: &'e [Vec<usize>],
+    /// Visit order and lowest reachable visit order of the states
+    order: Vec<Option<(usize, usize)>>,
+    stack: Vec<usize>,
+    on_stack: Vec<bool>,
+    visited: usize,
+    components: Vec<Vec<usize>>,
+}
+
+impl<'e> Tarjan<'e> {
+    fn components(successors: &'e [Vec<usize>]) -> Vec<Vec<usize>> {
+        let n = successors.len();
+        let mut tarjan = Self { successors, order: vec![None; n], stack: vec![], on_stack: vec![false; n], visited: 0, components: vec![] };
+        for i in 0..n {
+            if tarjan.order[i].is_none() {
+                tarjan.visit(i);
+            }
+        }
+        tarjan.components
+    }
+
+    fn visit(&mut self, i: usize) -> usize {
+        let index = self.visited;
+        let mut low = index;
+        self.visited += 1;
+        self.order[i] = Some((index, low));
+        self.stack.push(i);
+        self.on_stack[i] = true;
+        for &j in self.successors[i].iter() {
+            match self.order[j] {
+                None => low = low.min(self.visit(j)),
+                Some((order, _)) if self.on_stack[j] => low = low.min(order),
+                Some(_) => {},
+            }
+        }
+        self.order[i] = Some((index, low));
+        if low == index {
+            let mut component = vec![];
+            while let Some(j) = self.stack.pop() {
+                self.on_stack[j] = false;
+                component.push(j);
+                if j == i {
+                    break;
+                }
+            }
+            self.components.push(component);
+        }
+        low
+    }
+}
+
+pub fn analyze(graph: &Graph) -> Vec<Analysis> {
+    graph.automata.iter().map(Analysis::new).collect()
+}
+
+#[test]
+fn analysis_test() {
+    use crate::parser::Parser;
+
+    let module = Parser::parse_str("
+        automata Door {
+            state Closed<locked: bool> {
+                if locked { link self -> Closed<false>; } else { link self -> Open; }
+            }
+            state Open { link self -> Ajar; }
+            state Ajar { if true { link self -> Closed<true>; } else { link self -> Open; } }
+            state Broken { link self -> Spin; }
+            state Spin { link self -> Spin; }
+            state Done { }
+        }
+        automata Timer {
+            state Tick<n: int64> { if n > 0 { link self -> Tick<n - 1>; } }
+        }
+    ").unwrap();
+    let analyses = analyze(&Graph::new(&module));
+    assert_eq!(analyses[0], Analysis {
+        automata: "Door".to_string(),
+        unreachable: vec!["Broken".to_string(), "Spin".to_string(), "Done".to_string()],
+        non_terminating: vec!["Closed".to_string(), "Open".to_string(), "Ajar".to_string(), "Broken".to_string(), "Spin".to_string()],
+        components: vec![vec!["Closed".to_string(), "Open".to_string(), "Ajar".to_string()], vec!["Spin".to_string()]],
+    });
+    assert_eq!(analyses[0].to_string(), "\
+automata Door
+    unreachable from the initial state: Broken, Spin, Done
+    never reaching NULL: Closed, Open, Ajar, Broken, Spin
+    cycles: {Closed, Open, Ajar}, {Spin}
+");
+    assert_eq!(analyses[1].to_string(), "automata Timer\n    cycles: {Tick}\n");
+    assert_eq!(
+        analyses[1].to_json().to_string(),
+        r#"{"automata":"Timer","unreachable":[],"non_terminating":[],"components":[["Tick"]]}"#,
+    );
+
+    // two cycles joined by an edge and a state out of them
+    let successors = vec![vec![1], vec![0, 2], vec![3], vec![4], vec![2], vec![]];
+    let mut components = Tarjan::components(&successors);
+    components.iter_mut().for_each(|c| c.sort());
+    assert_eq!(components, vec![vec![2, 3, 4], vec![0, 1], vec![5]]);
+}
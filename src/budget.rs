@@ -0,0 +1,106 @@
+use std::time::{Duration, Instant};
+
+use crate::interpreter::{StateValue, MAX_CALL_DEPTH, MAX_RUN_DEPTH};
+use crate::lexer::Position;
+
+/// Limits of a single execution, counted from the top level `run` or call
+#[derive(Debug, Clone, PartialEq)]
+pub struct Budget {
+    /// Transitions of all automata, nested `run`s included
+    pub max_transitions: Option<usize>,
+    /// Iterations of all `for` and `while` loops
+    pub max_iterations: Option<usize>,
+    /// Nested `run`s, the top level automata counts too
+    pub max_run_depth: usize,
+    /// Nested function calls, each of them takes a few KiB of the native stack
+    pub max_call_depth: usize,
+    /// Wall clock time measured by the interpreter [`Clock`]
+    pub max_duration: Option<Duration>,
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Self {
+            max_transitions: None,
+            max_iterations: None,
+            max_run_depth: MAX_RUN_DEPTH,
+            max_call_depth: MAX_CALL_DEPTH,
+            max_duration: None,
+        }
+    }
+}
+
+/// The exhausted limit of the [`Budget`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Limit {
+    Transitions(usize),
+    Iterations(usize),
+    RunDepth(usize),
+    CallDepth(usize),
+    WallClock(Duration),
+}
+
+impl std::fmt::Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Limit::Transitions(n) => write!(f, "{} transition(s)", n),
+            Limit::Iterations(n) => write!(f, "{} loop iteration(s)", n),
+            Limit::RunDepth(n) => write!(f, "nested `run` depth {}", n),
+            Limit::CallDepth(n) => write!(f, "nested call depth {}", n),
+            Limit::WallClock(d) => write!(f, "{:?} of wall clock", d),
+        }
+    }
+}
+
+/// Where the execution was stopped
+#[derive(Debug, Clone, PartialEq)]
+pub struct TracePosition {
+    /// Names of the running automata, the outermost first
+    pub run_stack: Vec<String>,
+    /// Current state of the innermost automata
+    pub state: Option<StateValue>,
+    /// Transitions made by the innermost automata
+    pub steps: usize,
+    /// Last evaluated expression
+    pub source: Position,
+}
+
+impl std::fmt::Display for TracePosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)?;
+        if let Some(state) = &self.state {
+            write!(f, " in {} after {} step(s)", state, self.steps)?;
+        }
+        if !self.run_stack.is_empty() {
+            write!(f, " ({})", self.run_stack.join(" -> "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Source of time for the wall clock limit, injectable for tests and replays
+pub trait Clock {
+    /// Monotonic time since an arbitrary origin
+    fn now(&self) -> Duration;
+}
+
+/// [`Clock`] backed by [`Instant`]
+pub struct SystemClock(Instant);
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self(Instant::now())
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.0.elapsed()
+    }
+}
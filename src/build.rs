@@ -0,0 +1,141 @@
+//! Helpers for `build.rs` of the crates embedding FAN automata:
+//!
+//! ```no_run
+//! // main of build.rs
+//! fan_rs::build::compile_dir("automata/");
+//! ```
+//!
+//! `automata/traffic.fan` is then included with
+//! `include!(concat!(env!("OUT_DIR"), "/traffic.rs"));`,
+//! files of the subdirectories keep their relative paths.
+
+use std::path::{Path, PathBuf};
+
+use crate::codegen::to_rust;
+use crate::diagnostic::Diagnostic;
+use crate::parser::Parser;
+
+#[derive(Debug)]
+pub enum BuildError {
+    Io(PathBuf, std::io::Error),
+    /// The file does not parse, check or generate
+    Fan { file: PathBuf, source: String, diagnostics: Vec<Diagnostic> },
+}
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildError::Io(path, e) => write!(f, "error: {}: {}", path.display(), e),
+            BuildError::Fan { file, source, diagnostics } => {
+                for d in diagnostics {
+                    write!(f, "{}", d.render(&file.display().to_string(), source))?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+/// Compiles every `.fan` file of the directory into `OUT_DIR` and asks cargo to rerun on their changes.
+/// Prints the diagnostics and fails the build script on errors.
+pub fn compile_dir(dir: impl AsRef<Path>) -> Vec<PathBuf> {
+    let dir = dir.as_ref();
+    let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").expect("OUT_DIR is set by cargo for build scripts"));
+    println!("cargo:rerun-if-changed={}", dir.display());
+    let sources = match fan_files(dir) {
+        Ok(sources) => sources,
+        Err(e) => fail(&[e]),
+    };
+    for source in sources.iter() {
+        println!("cargo:rerun-if-changed={}", source.display());
+    }
+    match compile_files(dir, &sources, &out_dir) {
+        Ok(outputs) => outputs,
+        Err(errors) => fail(&errors),
+    }
+}
+
+fn fail(errors: &[BuildError]) -> ! {
+    for e in errors {
+        eprintln!("{}", e);
+    }
+    eprintln!("error: could not compile FAN automata due to {} previous error(s)", errors.len());
+    std::process::exit(1)
+}
+
+/// `.fan` files of the directory and its subdirectories in a stable order
+pub fn fan_files(dir: &Path) -> Result<Vec<PathBuf>, BuildError> {
+    let mut files = vec![];
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(d) = dirs.pop() {
+        let entries = std::fs::read_dir(&d).map_err(|e| BuildError::Io(d.clone(), e))?;
+        for entry in entries {
+            let path = entry.map_err(|e| BuildError::Io(d.clone(), e))?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|e| e == "fan") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Compiles the sources of `dir` into the same relative paths under `out_dir`, returns the written files.
+/// Every source is compiled, so all the errors are reported at once.
+pub fn compile_files(dir: &Path, sources: &[PathBuf], out_dir: &Path) -> Result<Vec<PathBuf>, Vec<BuildError>> {
+    let mut outputs = vec![];
+    let mut errors = vec![];
+    for source in sources {
+        let relative = source.strip_prefix(dir).unwrap_or(source);
+        let output = out_dir.join(relative).with_extension("rs");
+        match compile_file(source, &output) {
+            Ok(()) => outputs.push(output),
+            Err(e) => errors.push(e),
+        }
+    }
+    if errors.is_empty() { Ok(outputs) } else { Err(errors) }
+}
+
+/// Parses, checks and generates the Rust source of a single file
+pub fn compile_file(source: &Path, output: &Path) -> Result<(), BuildError> {
+    let text = std::fs::read_to_string(source).map_err(|e| BuildError::Io(source.to_path_buf(), e))?;
+    let generated = Parser::parse_str(&text)
+        .map_err(|e| vec![Diagnostic::from(&e)])
+        .and_then(|module| to_rust(&module).map_err(|e| Vec::from(&e)));
+    let generated = generated.map_err(|diagnostics| BuildError::Fan { file: source.to_path_buf(), source: text, diagnostics })?;
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| BuildError::Io(parent.to_path_buf(), e))?;
+    }
+    // unchanged output keeps the mtime, so cargo does not rebuild the crate
+    if std::fs::read_to_string(output).is_ok_and(|old| old == generated) {
+        return Ok(());
+    }
+    std::fs::write(output, generated).map_err(|e| BuildError::Io(output.to_path_buf(), e))
+}
+
+#[test]
+fn compile_dir_test() {
+    let root = std::env::temp_dir().join(format!("fan-build-{}", std::process::id()));
+    let (dir, out) = (root.join("automata"), root.join("out"));
+    std::fs::create_dir_all(dir.join("nested")).unwrap();
+    std::fs::write(dir.join("a.fan"), "fn one() -> int64 { 1 }").unwrap();
+    std::fs::write(dir.join("nested/b.fan"), "automata B { state S { } }").unwrap();
+    std::fs::write(dir.join("notes.txt"), "not FAN").unwrap();
+
+    let sources = fan_files(&dir).unwrap();
+    assert_eq!(sources, vec![dir.join("a.fan"), dir.join("nested/b.fan")]);
+    let outputs = compile_files(&dir, &sources, &out).unwrap();
+    assert_eq!(outputs, vec![out.join("a.rs"), out.join("nested/b.rs")]);
+    assert!(std::fs::read_to_string(out.join("a.rs")).unwrap().contains("pub fn one() -> i64"));
+
+    std::fs::write(dir.join("c.fan"), "fn f() {\n    g();\n}").unwrap();
+    let sources = fan_files(&dir).unwrap();
+    let errors = compile_files(&dir, &sources, &out).unwrap_err();
+    std::fs::remove_dir_all(&root).unwrap();
+    assert_eq!(errors.len(), 1);
+    let rendered = errors[0].to_string();
+    assert!(rendered.contains("c.fan:2:5"), "{}", rendered);
+    assert!(rendered.contains("2 |     g();\n  |     ^"), "{}", rendered);
+}
@@ -0,0 +1,765 @@
+use std::collections::HashMap;
+
+use crate::checker::{is_self, Checker, Type};
+use crate::interpreter::{ends_with_value, literal_value, ExecError, Value};
+use crate::lexer::Position;
+use crate::parser::{
+    AutomataDef, AutomataKind, Block, DefinitionExp, Expression, ExpressionType, FunctionDef, Module, Pattern,
+    ProceduralExp, ReturnableExp, SingleName, StateDef, Statement,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add, Sub, Mul, Div, Rem, BitAnd, BitOr, BitXor,
+    Eq, Ne, Lt, Gt, Le, Ge,
+}
+
+impl BinaryOp {
+    pub fn from_operator(op: &str) -> Option<Self> {
+        Some(match op {
+            "+" => BinaryOp::Add,
+            "-" => BinaryOp::Sub,
+            "*" => BinaryOp::Mul,
+            "/" => BinaryOp::Div,
+            "%" => BinaryOp::Rem,
+            "&" => BinaryOp::BitAnd,
+            "|" => BinaryOp::BitOr,
+            "^" => BinaryOp::BitXor,
+            "==" => BinaryOp::Eq,
+            "!=" => BinaryOp::Ne,
+            "<" => BinaryOp::Lt,
+            ">" => BinaryOp::Gt,
+            "<=" => BinaryOp::Le,
+            ">=" => BinaryOp::Ge,
+            _ => return None,
+        })
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::BitOr => "|",
+            BinaryOp::BitXor => "^",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Gt => ">",
+            BinaryOp::Le => "<=",
+            BinaryOp::Ge => ">=",
+        }
+    }
+
+    pub fn is_comparison(self) -> bool {
+        matches!(self, BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Gt | BinaryOp::Le | BinaryOp::Ge)
+    }
+}
+
+/// Instruction of the stack VM, indices point into the [`Program`] tables
+/// or to the local slots of the running [`Chunk`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instr {
+    Const(usize),
+    Unit,
+    Load(usize),
+    Store(usize),
+    Pop,
+    /// Exchanges the two top values
+    Swap,
+    Coerce(usize),
+    /// Builds the tuple of the top values
+    Tuple(usize),
+    Binary(BinaryOp),
+    Neg,
+    Not,
+    BitNot,
+    Member(usize),
+    Index,
+    /// `local[index]` without copying the whole local
+    LoadIndex(usize),
+    /// Pops the indices, then the value, and stores it into the tuple element of the slot
+    StorePath { slot: usize, depth: usize },
+    Jump(usize),
+    JumpIfFalse(usize),
+    /// Short-circuit of `&&` and `||`, the deciding value stays on the stack
+    JumpIfFalseOrPop(usize),
+    JumpIfTrueOrPop(usize),
+    /// Pops the iterable of `for` into the slot
+    Items(usize),
+    /// Stores the next item into `variable` or jumps to `exit`
+    ForNext { items: usize, index: usize, variable: usize, exit: usize },
+    /// Spends a loop iteration of the budget
+    Iteration,
+    Call { function: usize, argc: usize },
+    /// Calls the native function or constructor of the host
+    Native { name: usize, argc: usize },
+    /// Pops the arguments and the value of the uploaded type and calls its method
+    Method { name: usize, argc: usize },
+    Return,
+    Link { state: usize, argc: usize },
+    LinkNull,
+    Run { automata: usize, argc: usize },
+    /// Pops the value and pushes whether the pattern matches,
+    /// the bindings go to the consecutive slots from `first`
+    Test { pattern: usize, first: usize },
+    /// Pops the value no `match` arm accepted
+    NoMatch,
+    SelfState,
+    SelfPrevious,
+    SelfSteps,
+    SelfAutomata,
+    IsMe,
+    /// `self.previous.is_me()` without building the previous state
+    PreviousIsMe,
+    /// Raises the error the interpreter would raise at this point
+    Fail(usize),
+}
+
+/// Compiled body with the positions of its instructions
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Chunk {
+    pub code: Vec<Instr>,
+    pub positions: Vec<Position>,
+    /// Number of local slots, the arguments come first
+    pub slots: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledFunction {
+    pub name: String,
+    pub params: usize,
+    pub chunk: Chunk,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledState {
+    pub name: String,
+    /// Template parameters with the types the arguments are coerced to
+    pub params: Vec<(String, Type)>,
+    /// Declared type of the Mealy signal parameter
+    pub signal: Option<Type>,
+    pub consumes_signal: bool,
+    pub signal_slot: Option<usize>,
+    pub output_slot: Option<usize>,
+    pub chunk: Chunk,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledAutomata {
+    pub name: String,
+    pub mealy: bool,
+    pub initial: Option<usize>,
+    pub states: Vec<CompiledState>,
+}
+
+impl CompiledAutomata {
+    pub fn state(&self, name: &str) -> Option<usize> {
+        self.states.iter().position(|s| s.name == name)
+    }
+}
+
+/// Bytecode of the whole module
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    pub automata: Vec<CompiledAutomata>,
+    pub functions: Vec<CompiledFunction>,
+    /// Module level functions callable from the host
+    pub exports: HashMap<String, usize>,
+    pub constants: Vec<Value>,
+    pub types: Vec<Type>,
+    pub names: Vec<String>,
+    pub patterns: Vec<Pattern>,
+    pub errors: Vec<ExecError>,
+}
+
+impl Program {
+    /// Compiles the module accepted by the [`Checker`].
+    /// Whatever the interpreter rejects at runtime compiles to [`Instr::Fail`] at the same point.
+    pub fn compile(module: &Module) -> Program {
+        Compiler::new(module).compile()
+    }
+
+    pub fn find_automata(&self, name: &str) -> Option<usize> {
+        self.automata.iter().position(|a| a.name == name)
+    }
+
+    /// Total number of instructions
+    pub fn size(&self) -> usize {
+        self.functions.iter().map(|f| f.chunk.code.len()).sum::<usize>()
+            + self.automata.iter().flat_map(|a| a.states.iter()).map(|s| s.chunk.code.len()).sum::<usize>()
+    }
+}
+
+/// Locals of the chunk being compiled
+#[derive(Default)]
+struct ChunkBuilder {
+    chunk: Chunk,
+    scopes: Vec<HashMap<String, usize>>,
+}
+
+impl ChunkBuilder {
+    fn new() -> Self {
+        Self { chunk: Chunk::default(), scopes: vec![HashMap::new()] }
+    }
+
+    fn slot(&mut self) -> usize {
+        self.chunk.slots += 1;
+        self.chunk.slots - 1
+    }
+
+    fn define(&mut self, name: &str) -> usize {
+        let slot = self.slot();
+        self.scopes.last_mut().unwrap().insert(name.to_string(), slot);
+        slot
+    }
+
+    fn get(&self, name: &str) -> Option<usize> {
+        self.scopes.iter().rev().find_map(|s| s.get(name).copied())
+    }
+}
+
+struct Compiler<'m> {
+    module: &'m Module,
+    program: Program,
+    /// Functions of the automata, `None` stands for the module
+    functions: HashMap<(Option<&'m str>, &'m str), usize>,
+    automata: Option<&'m AutomataDef>,
+    /// Compiling function body: `return` is allowed, `link` is not
+    in_function: bool,
+    returns: Type,
+    builder: ChunkBuilder,
+    position: Position,
+}
+
+impl<'m> Compiler<'m> {
+    fn new(module: &'m Module) -> Self {
+        Self {
+            module,
+            program: Program::default(),
+            functions: HashMap::new(),
+            automata: None,
+            in_function: false,
+            returns: Type::Unknown,
+            builder: ChunkBuilder::new(),
+            position: Position::default(),
+        }
+    }
+
+    fn compile(mut self) -> Program {
+        let module = self.module;
+        // indices first, bodies may refer to anything declared later
+        let mut functions = vec![];
+        for f in module.functions() {
+            self.functions.entry((None, f.name.as_str())).or_insert(functions.len());
+            self.program.exports.entry(f.name.clone()).or_insert(functions.len());
+            functions.push((None, f));
+        }
+        let mut automata = vec![];
+        for a in module.automata() {
+            if self.program.find_automata(&a.name).is_some() {
+                continue;
+            }
+            for f in a.functions.iter() {
+                self.functions.entry((Some(a.name.as_str()), f.name.as_str())).or_insert(functions.len());
+                functions.push((Some(a), f));
+            }
+            self.program.automata.push(CompiledAutomata {
+                name: a.name.clone(),
+                mealy: a.kind != AutomataKind::Moore,
+                initial: a.initial_state().and_then(|s| a.states.iter().position(|x| std::ptr::eq(x, s))),
+                states: vec![],
+            });
+            automata.push(a);
+        }
+        for (a, f) in functions {
+            let function = self.function(a, f);
+            self.program.functions.push(function);
+        }
+        for (i, a) in automata.into_iter().enumerate() {
+            let states = a.states.iter().map(|s| self.state(a, s)).collect();
+            self.program.automata[i].states = states;
+        }
+        self.program
+    }
+
+    fn function(&mut self, automata: Option<&'m AutomataDef>, f: &'m FunctionDef) -> CompiledFunction {
+        self.automata = automata;
+        self.in_function = true;
+        self.builder = ChunkBuilder::new();
+        self.position = f.position;
+        for p in f.params.iter() {
+            self.builder.define(&p.name);
+        }
+        for (slot, p) in f.params.iter().enumerate() {
+            let ty = Type::from_annotation(&p.ty);
+            if ty != Type::Unknown {
+                self.emit(Instr::Load(slot));
+                self.coerce(ty);
+                self.emit(Instr::Store(slot));
+            }
+        }
+        self.returns = Type::result_of(f);
+        self.block(&f.body);
+        if f.returns.is_none() && !ends_with_value(&f.body) {
+            self.emit(Instr::Pop);
+            self.emit(Instr::Unit);
+        }
+        self.coerce(self.returns.clone());
+        self.emit(Instr::Return);
+        CompiledFunction { name: f.name.clone(), params: f.params.len(), chunk: std::mem::take(&mut self.builder.chunk) }
+    }
+
+    fn state(&mut self, a: &'m AutomataDef, s: &'m StateDef) -> CompiledState {
+        self.automata = Some(a);
+        self.in_function = false;
+        self.builder = ChunkBuilder::new();
+        self.position = s.position;
+        let params = Checker::template_params(a, &s.params);
+        let slots: Vec<usize> = params.iter().map(|_| self.builder.slot()).collect();
+        let (mut signal_slot, mut output_slot) = (None, None);
+        if let AutomataKind::Mealy(signal, output) = &a.kind {
+            signal_slot = Some(self.builder.define(signal));
+            output_slot = output.as_ref().map(|o| self.builder.define(o));
+        }
+        for (p, slot) in params.iter().zip(slots) {
+            self.builder.scopes[0].insert(p.name.clone(), slot);
+        }
+        self.block(&s.body);
+        self.emit(Instr::LinkNull);
+        CompiledState {
+            name: s.name.clone(),
+            params: params.iter().map(|p| (p.name.clone(), Type::from_annotation(&p.ty))).collect(),
+            signal: a.signal_param(s).map(|p| Type::from_annotation(&p.ty)),
+            consumes_signal: a.consumes_signal(s),
+            signal_slot,
+            output_slot,
+            chunk: std::mem::take(&mut self.builder.chunk),
+        }
+    }
+
+    fn emit(&mut self, instr: Instr) -> usize {
+        self.builder.chunk.code.push(instr);
+        self.builder.chunk.positions.push(self.position);
+        self.builder.chunk.code.len() - 1
+    }
+
+    fn here(&self) -> usize {
+        self.builder.chunk.code.len()
+    }
+
+    /// Points the jump at `at` to the next instruction
+    fn patch(&mut self, at: usize) {
+        let target = self.here();
+        match &mut self.builder.chunk.code[at] {
+            Instr::Jump(t) | Instr::JumpIfFalse(t) | Instr::JumpIfFalseOrPop(t) | Instr::JumpIfTrueOrPop(t) => *t = target,
+            Instr::ForNext { exit, .. } => *exit = target,
+            i => unreachable!("{:?} is not a jump", i),
+        }
+    }
+
+    fn constant(&mut self, v: Value) {
+        let i = self.program.constants.iter().position(|c| *c == v).unwrap_or_else(|| {
+            self.program.constants.push(v);
+            self.program.constants.len() - 1
+        });
+        self.emit(Instr::Const(i));
+    }
+
+    fn coerce(&mut self, ty: Type) {
+        if ty == Type::Unknown {
+            return;
+        }
+        let i = self.program.types.iter().position(|t| *t == ty).unwrap_or_else(|| {
+            self.program.types.push(ty);
+            self.program.types.len() - 1
+        });
+        self.emit(Instr::Coerce(i));
+    }
+
+    fn name(&mut self, name: &str) -> usize {
+        self.program.names.iter().position(|n| n == name).unwrap_or_else(|| {
+            self.program.names.push(name.to_string());
+            self.program.names.len() - 1
+        })
+    }
+
+    fn fail(&mut self, error: ExecError) {
+        self.program.errors.push(error);
+        self.emit(Instr::Fail(self.program.errors.len() - 1));
+    }
+
+    fn self_available(&self) -> bool {
+        !self.in_function && self.automata.is_some() && self.builder.get("self").is_none()
+    }
+
+    fn block(&mut self, block: &'m Block) {
+        self.builder.scopes.push(HashMap::new());
+        if block.block.is_empty() {
+            self.emit(Instr::Unit);
+        }
+        for (i, e) in block.block.iter().enumerate() {
+            if i > 0 {
+                self.emit(Instr::Pop);
+            }
+            self.expression(e);
+        }
+        self.builder.scopes.pop();
+    }
+
+    /// Compiles the expression leaving exactly one value on the stack
+    fn expression(&mut self, e: &'m Expression) {
+        let outer = std::mem::replace(&mut self.position, e.position);
+        match &e.kind {
+            ExpressionType::Definition(DefinitionExp::Define(d)) => {
+                self.expression(&d.value);
+                self.coerce(Type::from_annotation(&d.ty));
+                let slot = self.builder.define(&d.name);
+                self.emit(Instr::Store(slot));
+                self.emit(Instr::Unit);
+            },
+            ExpressionType::Import(_) | ExpressionType::Definition(_) => {
+                self.emit(Instr::Unit);
+            },
+            ExpressionType::Procedural(p) => self.procedural(p),
+            ExpressionType::Returnable(r) => self.returnable(r),
+        }
+        self.position = outer;
+    }
+
+    fn procedural(&mut self, p: &'m ProceduralExp) {
+        match p {
+            ProceduralExp::For(f) => {
+                self.expression(&f.iterable);
+                let items = self.builder.slot();
+                let index = self.builder.slot();
+                self.emit(Instr::Items(items));
+                self.constant(Value::Int(0));
+                self.emit(Instr::Store(index));
+                self.builder.scopes.push(HashMap::new());
+                let variable = self.builder.define(&f.variable);
+                let start = self.emit(Instr::ForNext { items, index, variable, exit: 0 });
+                self.emit(Instr::Iteration);
+                self.block(&f.body);
+                self.emit(Instr::Pop);
+                self.emit(Instr::Jump(start));
+                self.builder.scopes.pop();
+                self.patch(start);
+                self.emit(Instr::Unit);
+            },
+            ProceduralExp::While(w) => {
+                let start = self.here();
+                self.expression(&w.condition);
+                let exit = self.emit(Instr::JumpIfFalse(0));
+                self.emit(Instr::Iteration);
+                self.block(&w.body);
+                self.emit(Instr::Pop);
+                self.emit(Instr::Jump(start));
+                self.patch(exit);
+                self.emit(Instr::Unit);
+            },
+            ProceduralExp::Link(l) => {
+                let a = match self.automata {
+                    Some(a) if !self.in_function => a,
+                    _ => return self.fail(ExecError::LinkInFunction),
+                };
+                let Some(SingleName(target, template)) = &l.target else {
+                    self.emit(Instr::LinkNull);
+                    return;
+                };
+                let args = template.iter().flat_map(|t| t.args.iter());
+                let argc = args.clone().count();
+                for e in args {
+                    self.expression(e);
+                }
+                match a.states.iter().position(|s| s.name == *target) {
+                    Some(state) => {
+                        self.emit(Instr::Link { state, argc });
+                    },
+                    None => self.fail(ExecError::UndefinedState(target.clone())),
+                }
+            },
+            ProceduralExp::Return(value) => {
+                if !self.in_function {
+                    return self.fail(ExecError::ReturnOutsideFunction);
+                }
+                match value {
+                    Some(v) => self.expression(v),
+                    None => {
+                        self.emit(Instr::Unit);
+                    },
+                }
+                self.coerce(self.returns.clone());
+                self.emit(Instr::Return);
+            },
+        }
+    }
+
+    fn returnable(&mut self, r: &'m ReturnableExp) {
+        match r {
+            ReturnableExp::Statement(s) => match s {
+                Statement::Literal(l) => match literal_value(l) {
+                    Ok(v) => self.constant(v),
+                    Err(e) => self.fail(e),
+                },
+                Statement::Block(b) => self.block(b),
+                Statement::Name(n) => {
+                    let path = n.path();
+                    match self.builder.get(&path) {
+                        Some(slot) => {
+                            self.emit(Instr::Load(slot));
+                        },
+                        None if path == "self" && self.self_available() => {
+                            self.emit(Instr::SelfState);
+                        },
+                        None => self.fail(ExecError::UndefinedName(path)),
+                    }
+                },
+                Statement::Tuple(t) => {
+                    for x in t.tuple.iter() {
+                        self.expression(x);
+                    }
+                    self.emit(Instr::Tuple(t.tuple.len()));
+                },
+            },
+            ReturnableExp::FunctionCall(callee, args) => {
+                let name = match callee {
+                    Statement::Name(n) => n.path(),
+                    _ => return self.fail(ExecError::UndefinedFunction("<expression>".to_string())),
+                };
+                let function = self.automata
+                    .and_then(|a| self.functions.get(&(Some(a.name.as_str()), name.as_str())))
+                    .or_else(|| self.functions.get(&(None, name.as_str())))
+                    .copied();
+                for a in args.tuple.iter() {
+                    self.expression(a);
+                }
+                match function {
+                    Some(function) => {
+                        self.emit(Instr::Call { function, argc: args.tuple.len() });
+                    },
+                    // resolved by the host of the VM
+                    None => {
+                        let name = self.name(&name);
+                        self.emit(Instr::Native { name, argc: args.tuple.len() });
+                    },
+                }
+            },
+            ReturnableExp::AutomataCall(SingleName(name, template)) => {
+                let Some(automata) = self.program.find_automata(name) else {
+                    return self.fail(ExecError::UndefinedAutomata(name.clone()));
+                };
+                let args = template.iter().flat_map(|t| t.args.iter());
+                let argc = args.clone().count();
+                for e in args {
+                    self.expression(e);
+                }
+                self.emit(Instr::Run { automata, argc });
+            },
+            ReturnableExp::Match(m) => {
+                self.expression(&m.value);
+                let value = self.builder.slot();
+                self.emit(Instr::Store(value));
+                let mut ends = vec![];
+                for arm in m.arms.iter() {
+                    self.builder.scopes.push(HashMap::new());
+                    self.emit(Instr::Load(value));
+                    self.test(&arm.pattern);
+                    let next = self.emit(Instr::JumpIfFalse(0));
+                    self.expression(&arm.body);
+                    ends.push(self.emit(Instr::Jump(0)));
+                    self.builder.scopes.pop();
+                    self.patch(next);
+                }
+                self.emit(Instr::Load(value));
+                self.emit(Instr::NoMatch);
+                for end in ends {
+                    self.patch(end);
+                }
+            },
+            ReturnableExp::Is(value, pattern) => {
+                self.expression(value);
+                self.test(pattern);
+            },
+            ReturnableExp::BinaryOperator(b) => {
+                let op = b.operator.as_str();
+                match op {
+                    "=" | "+=" | "-=" | "*=" | "/=" => self.assign(&b.arg1, &b.arg2, BinaryOp::from_operator(&op[..op.len() - 1])),
+                    "&&" | "||" => {
+                        self.expression(&b.arg1);
+                        let skip = self.emit(if op == "&&" { Instr::JumpIfFalseOrPop(0) } else { Instr::JumpIfTrueOrPop(0) });
+                        self.expression(&b.arg2);
+                        self.patch(skip);
+                    },
+                    _ => {
+                        self.expression(&b.arg1);
+                        self.expression(&b.arg2);
+                        match BinaryOp::from_operator(op) {
+                            Some(op) => {
+                                self.emit(Instr::Binary(op));
+                            },
+                            None => self.fail(ExecError::UnsupportedOperator(op.to_string())),
+                        }
+                    },
+                }
+            },
+            ReturnableExp::UnaryyOperator(u) => {
+                self.expression(&u.arg);
+                let instr = match u.operator.as_str() {
+                    "-" => Instr::Neg,
+                    "!" => Instr::Not,
+                    "~" => Instr::BitNot,
+                    op => return self.fail(ExecError::UnsupportedOperator(op.to_string())),
+                };
+                self.emit(instr);
+            },
+            ReturnableExp::Member(value, member) => {
+                if is_self(value) && self.self_available() {
+                    let special = match member.as_str() {
+                        "previous" => Some(Instr::SelfPrevious),
+                        "steps" => Some(Instr::SelfSteps),
+                        "automata" => Some(Instr::SelfAutomata),
+                        _ => None,
+                    };
+                    if let Some(instr) = special {
+                        self.emit(instr);
+                        return;
+                    }
+                }
+                self.expression(value);
+                let name = self.name(member);
+                self.emit(Instr::Member(name));
+            },
+            ReturnableExp::MethodCall(value, method, args) => {
+                if let ExpressionType::Returnable(ReturnableExp::Member(base, member)) = &value.kind
+                    && method == "is_me" && member == "previous" && args.tuple.is_empty()
+                    && is_self(base) && self.self_available()
+                {
+                    self.emit(Instr::PreviousIsMe);
+                    return;
+                }
+                self.expression(value);
+                for a in args.tuple.iter() {
+                    self.expression(a);
+                }
+                match method.as_str() {
+                    "is_me" if args.tuple.is_empty() => {
+                        self.emit(Instr::IsMe);
+                    },
+                    "is_me" => self.fail(ExecError::ArityMismatch { name: method.clone(), expected: 0, found: args.tuple.len() }),
+                    _ => {
+                        let name = self.name(method);
+                        self.emit(Instr::Method { name, argc: args.tuple.len() });
+                    },
+                }
+            },
+            ReturnableExp::Index(value, index) => {
+                let local = match &value.kind {
+                    ExpressionType::Returnable(ReturnableExp::Statement(Statement::Name(n))) => self.builder.get(&n.path()),
+                    _ => None,
+                };
+                match local {
+                    Some(slot) => {
+                        self.expression(index);
+                        self.emit(Instr::LoadIndex(slot));
+                    },
+                    None => {
+                        self.expression(value);
+                        self.expression(index);
+                        self.emit(Instr::Index);
+                    },
+                }
+            },
+            ReturnableExp::If(i) => {
+                self.builder.scopes.push(HashMap::new());
+                self.expression(&i.condition);
+                let otherwise = self.emit(Instr::JumpIfFalse(0));
+                self.block(&i.then);
+                let end = self.emit(Instr::Jump(0));
+                self.builder.scopes.pop();
+                self.patch(otherwise);
+                match &i.otherwise {
+                    Some(o) => self.block(o),
+                    None => {
+                        self.emit(Instr::Unit);
+                    },
+                }
+                self.patch(end);
+            },
+        }
+    }
+
+    /// Tests the value on the stack, the bindings are defined in the current scope
+    fn test(&mut self, pattern: &'m Pattern) {
+        let mut names = vec![];
+        bindings(pattern, &mut names);
+        let first = self.builder.chunk.slots;
+        for name in names {
+            self.builder.define(name);
+        }
+        self.program.patterns.push(pattern.clone());
+        self.emit(Instr::Test { pattern: self.program.patterns.len() - 1, first });
+    }
+
+    fn assign(&mut self, target: &'m Expression, value: &'m Expression, op: Option<BinaryOp>) {
+        // path from the variable to the assigned element, the outermost first
+        let mut path = vec![];
+        let mut current = target;
+        let name = loop {
+            match &current.kind {
+                ExpressionType::Returnable(ReturnableExp::Statement(Statement::Name(n))) => break n.path(),
+                ExpressionType::Returnable(ReturnableExp::Member(v, m)) => {
+                    match m.parse::<usize>() {
+                        Ok(i) => path.push(Err(i)),
+                        Err(_) => return self.fail(ExecError::NoSuchMember(m.clone())),
+                    }
+                    current = v;
+                },
+                ExpressionType::Returnable(ReturnableExp::Index(v, i)) => {
+                    path.push(Ok(i.as_ref()));
+                    current = v;
+                },
+                _ => return self.fail(ExecError::InvalidAssignment),
+            }
+        };
+        let Some(slot) = self.builder.get(&name) else {
+            return self.fail(ExecError::UndefinedName(name));
+        };
+        if let Some(op) = op {
+            // the value is evaluated before the old target, as in the interpreter
+            self.expression(value);
+            self.expression(target);
+            self.emit(Instr::Swap);
+            self.emit(Instr::Binary(op));
+        } else {
+            self.expression(value);
+        }
+        if path.is_empty() {
+            self.emit(Instr::Store(slot));
+        } else {
+            let depth = path.len();
+            for step in path.into_iter().rev() {
+                match step {
+                    Ok(index) => self.expression(index),
+                    Err(i) => self.constant(Value::Int(i as i64)),
+                }
+            }
+            self.emit(Instr::StorePath { slot, depth });
+        }
+        self.emit(Instr::Unit);
+    }
+}
+
+/// Names bound by the pattern in the order of [`crate::interpreter::match_pattern`]
+fn bindings<'p>(pattern: &'p Pattern, names: &mut Vec<&'p str>) {
+    match pattern {
+        Pattern::Binding(name) => names.push(name),
+        Pattern::Tuple(patterns) | Pattern::State { args: Some(patterns), .. } => {
+            patterns.iter().for_each(|p| bindings(p, names));
+        },
+        Pattern::Wildcard | Pattern::Literal(_) | Pattern::State { args: None, .. } => {},
+    }
+}
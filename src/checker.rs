@@ -0,0 +1,935 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::host::Host;
+use crate::lexer::Position;
+use crate::parser::{
+    AutomataDef, AutomataKind, Block, DefinitionExp, Expression, ExpressionType, FANType, FunctionDef,
+    Literal, MatchExp, Module, Param, Pattern, ProceduralExp, ReturnableExp, SingleName, StateDef, Statement,
+};
+
+/// Resolved type of a value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Int,
+    Float,
+    Bool,
+    Char,
+    String,
+    Tuple(Vec<Type>),
+    Null,
+    /// Uploaded or otherwise opaque type
+    Named(String),
+    /// Could not be inferred statically
+    Unknown,
+}
+
+impl Type {
+    pub fn unit() -> Self {
+        Type::Tuple(vec![])
+    }
+
+    pub fn from_fan(t: &FANType) -> Self {
+        match t {
+            FANType::Named(n) => match n.as_str() {
+                "int" | "int8" | "int16" | "int32" | "int64" | "uint8" | "uint16" | "uint32" | "uint64"
+                | "i8" | "i16" | "i32" | "i64" | "u8" | "u16" | "u32" | "u64" | "isize" | "usize" => Type::Int,
+                "float" | "float32" | "float64" | "f32" | "f64" => Type::Float,
+                "bool" => Type::Bool,
+                "char" => Type::Char,
+                "string" | "str" | "String" => Type::String,
+                _ => Type::Named(n.clone()),
+            },
+            FANType::Tuple(v) => Type::Tuple(v.iter().map(Type::from_fan).collect()),
+        }
+    }
+
+    pub fn from_annotation(t: &Option<FANType>) -> Self {
+        t.as_ref().map(Type::from_fan).unwrap_or(Type::Unknown)
+    }
+
+    /// Declared result of the function, unit without `-> T`
+    pub fn result_of(f: &FunctionDef) -> Self {
+        f.returns.as_ref().map(Type::from_fan).unwrap_or(Type::unit())
+    }
+
+    /// Can a value of type `other` be used where `self` is expected
+    pub fn accepts(&self, other: &Type) -> bool {
+        match (self, other) {
+            (Type::Unknown, _) | (_, Type::Unknown) => true,
+            (Type::Float, Type::Int) => true,
+            (Type::Tuple(a), Type::Tuple(b)) => a.len() == b.len() && a.iter().zip(b).all(|(x, y)| x.accepts(y)),
+            (a, b) => a == b,
+        }
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Int => write!(f, "int64"),
+            Type::Float => write!(f, "float64"),
+            Type::Bool => write!(f, "bool"),
+            Type::Char => write!(f, "char"),
+            Type::String => write!(f, "string"),
+            Type::Tuple(v) => {
+                write!(f, "(")?;
+                for (i, t) in v.iter().enumerate() {
+                    if i > 0 { write!(f, ", ")?; }
+                    write!(f, "{}", t)?;
+                }
+                write!(f, ")")
+            },
+            Type::Null => write!(f, "NULL"),
+            Type::Named(n) => write!(f, "{}", n),
+            Type::Unknown => write!(f, "_"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CheckError {
+    DuplicateAutomata(String, Position),
+    DuplicateFunction(String, Position),
+    DuplicateState(String, Position),
+    DuplicateParam(String, Position),
+    DuplicateInitialState(String, Position),
+    UndefinedName(String, Position),
+    UndefinedFunction(String, Position),
+    UndefinedState(String, Position),
+    UndefinedAutomata(String, Position),
+    /// Only Moore automata can be `run` to the end
+    NotMoore(String, Position),
+    ArityMismatch { name: String, expected: usize, found: usize, position: Position },
+    TypeMismatch { expected: Type, found: Type, position: Position },
+    ReturnOutsideFunction(Position),
+    LinkInFunction(Position),
+    NoSuchMember(String, Position),
+    UndefinedMethod(String, Position),
+    /// `match` does not cover the listed cases
+    NonExhaustive { missing: Vec<String>, position: Position },
+}
+
+impl CheckError {
+    pub fn position(&self) -> Position {
+        match self {
+            CheckError::DuplicateAutomata(_, p)
+            | CheckError::DuplicateFunction(_, p)
+            | CheckError::DuplicateState(_, p)
+            | CheckError::DuplicateParam(_, p)
+            | CheckError::DuplicateInitialState(_, p)
+            | CheckError::UndefinedName(_, p)
+            | CheckError::UndefinedFunction(_, p)
+            | CheckError::UndefinedState(_, p)
+            | CheckError::UndefinedAutomata(_, p)
+            | CheckError::NotMoore(_, p)
+            | CheckError::NoSuchMember(_, p)
+            | CheckError::UndefinedMethod(_, p)
+            | CheckError::ReturnOutsideFunction(p)
+            | CheckError::LinkInFunction(p) => *p,
+            CheckError::ArityMismatch { position, .. }
+            | CheckError::TypeMismatch { position, .. }
+            | CheckError::NonExhaustive { position, .. } => *position,
+        }
+    }
+}
+
+impl std::fmt::Display for CheckError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: ", self.position())?;
+        match self {
+            CheckError::DuplicateAutomata(n, _) => write!(f, "automata `{}` is defined twice", n),
+            CheckError::DuplicateFunction(n, _) => write!(f, "function `{}` is defined twice", n),
+            CheckError::DuplicateState(n, _) => write!(f, "state `{}` is defined twice", n),
+            CheckError::DuplicateParam(n, _) => write!(f, "parameter `{}` is declared twice", n),
+            CheckError::DuplicateInitialState(n, _) => write!(f, "state `{}` is another initial state", n),
+            CheckError::UndefinedName(n, _) => write!(f, "undefined name `{}`", n),
+            CheckError::UndefinedFunction(n, _) => write!(f, "undefined function `{}`", n),
+            CheckError::UndefinedState(n, _) => write!(f, "undefined state `{}`", n),
+            CheckError::UndefinedAutomata(n, _) => write!(f, "undefined automata `{}`", n),
+            CheckError::NotMoore(n, _) => write!(f, "`{}` is not a Moore automata and can not be `run`", n),
+            CheckError::ArityMismatch { name, expected, found, .. } =>
+                write!(f, "`{}` takes {} argument(s) but {} given", name, expected, found),
+            CheckError::TypeMismatch { expected, found, .. } =>
+                write!(f, "expected `{}`, found `{}`", expected, found),
+            CheckError::ReturnOutsideFunction(_) => write!(f, "`return` outside of function"),
+            CheckError::LinkInFunction(_) => write!(f, "functions are pure and can not `link`"),
+            CheckError::NoSuchMember(m, _) => write!(f, "no member `{}`", m),
+            CheckError::UndefinedMethod(m, _) => write!(f, "undefined method `{}`", m),
+            CheckError::NonExhaustive { missing, .. } => write!(f, "`match` does not cover {}", missing.join(", ")),
+        }
+    }
+}
+
+/// Where the checked body lives
+#[derive(Clone, Copy)]
+enum Context<'m> {
+    Function(&'m FunctionDef),
+    State(&'m StateDef),
+}
+
+struct Scope {
+    vars: Vec<HashMap<String, Type>>,
+}
+
+impl Scope {
+    fn new() -> Self {
+        Self { vars: vec![HashMap::new()] }
+    }
+
+    fn get(&self, name: &str) -> Option<&Type> {
+        self.vars.iter().rev().find_map(|s| s.get(name))
+    }
+
+    fn set(&mut self, name: &str, t: Type) {
+        self.vars.last_mut().unwrap().insert(name.to_string(), t);
+    }
+
+    fn push(&mut self) {
+        self.vars.push(HashMap::new());
+    }
+
+    fn pop(&mut self) {
+        self.vars.pop();
+    }
+}
+
+/// Types of the checked expressions, valid while the module is borrowed
+#[derive(Debug, Default)]
+pub struct TypeTable<'m> {
+    types: HashMap<*const Expression, Type>,
+    module: std::marker::PhantomData<&'m Module>,
+}
+
+impl<'m> TypeTable<'m> {
+    pub fn get(&self, e: &'m Expression) -> &Type {
+        self.types.get(&(e as *const Expression)).unwrap_or(&Type::Unknown)
+    }
+}
+
+/// Name resolution and type checking of the parsed module
+pub struct Checker<'m> {
+    module: &'m Module,
+    uploaded: HashSet<&'m str>,
+    natives: HashSet<String>,
+    errors: Vec<CheckError>,
+    types: TypeTable<'m>,
+}
+
+impl<'m> Checker<'m> {
+    pub fn check(module: &'m Module) -> Result<(), Vec<CheckError>> {
+        Checker::check_types(module).map(|_| ())
+    }
+
+    /// Checks the module and returns the types of its expressions
+    pub fn check_types(module: &'m Module) -> Result<TypeTable<'m>, Vec<CheckError>> {
+        Checker::check_types_with_host(module, &Host::default())
+    }
+
+    /// Checks the module calling the native functions of the host
+    pub fn check_with_host(module: &'m Module, host: &Host) -> Result<(), Vec<CheckError>> {
+        Checker::check_types_with_host(module, host).map(|_| ())
+    }
+
+    pub fn check_types_with_host(module: &'m Module, host: &Host) -> Result<TypeTable<'m>, Vec<CheckError>> {
+        let mut checker = Checker {
+            module,
+            uploaded: module.imports().flat_map(|i| i.names.iter().map(|n| n.as_str())).collect(),
+            natives: host.function_names().map(str::to_string).collect(),
+            errors: vec![],
+            types: TypeTable::default(),
+        };
+        checker.module_level();
+        if checker.errors.is_empty() { Ok(checker.types) } else { Err(checker.errors) }
+    }
+
+    fn module_level(&mut self) {
+        let mut automata = HashSet::new();
+        for a in self.module.automata() {
+            if !automata.insert(a.name.as_str()) {
+                self.errors.push(CheckError::DuplicateAutomata(a.name.clone(), a.position));
+            }
+        }
+        self.duplicate_functions(self.module.functions());
+        for f in self.module.functions() {
+            self.function(None, f);
+        }
+        for a in self.module.automata() {
+            self.automata(a);
+        }
+    }
+
+    fn duplicate_functions(&mut self, functions: impl Iterator<Item = &'m FunctionDef>) {
+        let mut names = HashSet::new();
+        for f in functions {
+            if !names.insert(f.name.as_str()) {
+                self.errors.push(CheckError::DuplicateFunction(f.name.clone(), f.position));
+            }
+        }
+    }
+
+    fn params(&mut self, scope: &mut Scope, params: &[Param]) {
+        let mut names = HashSet::new();
+        for p in params {
+            if !names.insert(p.name.as_str()) {
+                self.errors.push(CheckError::DuplicateParam(p.name.clone(), p.position));
+            }
+            scope.set(&p.name, Type::from_annotation(&p.ty));
+        }
+    }
+
+    fn automata(&mut self, a: &'m AutomataDef) {
+        self.duplicate_functions(a.functions.iter());
+        let mut states = HashSet::new();
+        for s in a.states.iter() {
+            if !states.insert(s.name.as_str()) {
+                self.errors.push(CheckError::DuplicateState(s.name.clone(), s.position));
+            }
+        }
+        for s in a.states.iter().filter(|s| s.initial).skip(1) {
+            self.errors.push(CheckError::DuplicateInitialState(s.name.clone(), s.position));
+        }
+        for f in a.functions.iter() {
+            self.function(Some(a), f);
+        }
+        for s in a.states.iter() {
+            let mut scope = Scope::new();
+            scope.set("self", Type::Named(a.name.clone()));
+            if let AutomataKind::Mealy(signal, output) = &a.kind {
+                scope.set(signal, Type::Unknown);
+                if let Some(output) = output {
+                    scope.set(output, Type::Unknown);
+                }
+            }
+            self.params(&mut scope, &s.params);
+            self.block(Some(a), Context::State(s), &mut scope, &s.body);
+        }
+    }
+
+    fn function(&mut self, automata: Option<&'m AutomataDef>, f: &'m FunctionDef) {
+        let mut scope = Scope::new();
+        self.params(&mut scope, &f.params);
+        let result = self.block(automata, Context::Function(f), &mut scope, &f.body);
+        // implicit result of the last expression
+        if let Some(last) = f.body.block.last()
+            && matches!(last.kind, ExpressionType::Returnable(_)) {
+            self.expect(&Type::result_of(f), &result, last.position);
+        }
+    }
+
+    fn expect(&mut self, expected: &Type, found: &Type, position: Position) {
+        if !expected.accepts(found) {
+            self.errors.push(CheckError::TypeMismatch { expected: expected.clone(), found: found.clone(), position });
+        }
+    }
+
+    /// Arity of the link template: Mealy signal is bound by the input, not by the link
+    pub fn template_params<'a>(automata: &AutomataDef, params: &'a [Param]) -> Vec<&'a Param> {
+        params
+            .iter()
+            .filter(|p| !matches!(&automata.kind, AutomataKind::Mealy(signal, _) if *signal == p.name))
+            .collect()
+    }
+
+    fn block(&mut self, automata: Option<&'m AutomataDef>, context: Context<'m>, scope: &mut Scope, block: &'m Block) -> Type {
+        scope.push();
+        let mut last = Type::unit();
+        for e in block.block.iter() {
+            last = self.expression(automata, context, scope, e);
+        }
+        scope.pop();
+        last
+    }
+
+    fn find_function(&self, automata: Option<&'m AutomataDef>, name: &str) -> Option<&'m FunctionDef> {
+        automata.and_then(|a| a.function(name)).or_else(|| self.module.find_function(name))
+    }
+
+    fn expression(&mut self, automata: Option<&'m AutomataDef>, context: Context<'m>, scope: &mut Scope, e: &'m Expression) -> Type {
+        let t = self.expression_kind(automata, context, scope, e);
+        self.types.types.insert(e as *const Expression, t.clone());
+        t
+    }
+
+    fn expression_kind(&mut self, automata: Option<&'m AutomataDef>, context: Context<'m>, scope: &mut Scope, e: &'m Expression) -> Type {
+        match &e.kind {
+            ExpressionType::Import(_) => Type::unit(),
+            ExpressionType::Definition(DefinitionExp::Define(d)) => {
+                let value = self.expression(automata, context, scope, &d.value);
+                let t = match &d.ty {
+                    Some(ty) => {
+                        let declared = Type::from_fan(ty);
+                        self.expect(&declared, &value, d.value.position);
+                        declared
+                    },
+                    None => value,
+                };
+                scope.set(&d.name, t);
+                Type::unit()
+            },
+            ExpressionType::Definition(_) => Type::unit(),
+            ExpressionType::Procedural(p) => {
+                match p {
+                    ProceduralExp::For(f) => {
+                        let iterable = self.expression(automata, context, scope, &f.iterable);
+                        let item = match iterable {
+                            Type::String => Type::Char,
+                            Type::Int => Type::Int,
+                            _ => Type::Unknown,
+                        };
+                        scope.push();
+                        scope.set(&f.variable, item);
+                        self.block(automata, context, scope, &f.body);
+                        scope.pop();
+                    },
+                    ProceduralExp::While(w) => {
+                        let c = self.expression(automata, context, scope, &w.condition);
+                        self.expect(&Type::Bool, &c, w.condition.position);
+                        self.block(automata, context, scope, &w.body);
+                    },
+                    ProceduralExp::Link(l) => {
+                        if let Context::Function(_) = context {
+                            self.errors.push(CheckError::LinkInFunction(e.position));
+                        }
+                        if let Some(target) = &l.target {
+                            let args: Vec<(Type, Position)> = target.1.iter()
+                                .flat_map(|t| t.args.iter())
+                                .map(|a| (self.expression(automata, context, scope, a), a.position))
+                                .collect();
+                            match automata.and_then(|a| a.state(&target.0).map(|s| (a, s))) {
+                                Some((a, s)) => {
+                                    let params = Self::template_params(a, &s.params);
+                                    if params.len() != args.len() {
+                                        self.errors.push(CheckError::ArityMismatch {
+                                            name: target.0.clone(), expected: params.len(), found: args.len(), position: e.position,
+                                        });
+                                    } else {
+                                        for (p, (t, position)) in params.iter().zip(args.iter()) {
+                                            self.expect(&Type::from_annotation(&p.ty), t, *position);
+                                        }
+                                    }
+                                },
+                                None => self.errors.push(CheckError::UndefinedState(target.0.clone(), e.position)),
+                            }
+                        }
+                    },
+                    ProceduralExp::Return(value) => {
+                        let t = value.as_ref()
+                            .map(|v| self.expression(automata, context, scope, v))
+                            .unwrap_or(Type::unit());
+                        match context {
+                            Context::Function(f) => self.expect(&Type::result_of(f), &t, e.position),
+                            Context::State(_) => self.errors.push(CheckError::ReturnOutsideFunction(e.position)),
+                        }
+                    },
+                }
+                Type::unit()
+            },
+            ExpressionType::Returnable(r) => self.returnable(automata, context, scope, r, e.position),
+        }
+    }
+
+    fn returnable(&mut self, automata: Option<&'m AutomataDef>, context: Context<'m>, scope: &mut Scope, r: &'m ReturnableExp, position: Position) -> Type {
+        match r {
+            ReturnableExp::Statement(s) => match s {
+                Statement::Literal(l) => literal_type(l),
+                Statement::Block(b) => self.block(automata, context, scope, b),
+                Statement::Name(n) => {
+                    let path = n.path();
+                    if let Some(t) = scope.get(&path) {
+                        t.clone()
+                    } else if self.uploaded.contains(path.as_str()) {
+                        Type::Named(path)
+                    } else {
+                        self.errors.push(CheckError::UndefinedName(path, position));
+                        Type::Unknown
+                    }
+                },
+                Statement::Tuple(t) => Type::Tuple(
+                    t.tuple.iter().map(|e| self.expression(automata, context, scope, e)).collect()
+                ),
+            },
+            ReturnableExp::FunctionCall(callee, args) => {
+                let args: Vec<(Type, Position)> = args.tuple.iter()
+                    .map(|a| (self.expression(automata, context, scope, a), a.position))
+                    .collect();
+                let name = match callee {
+                    Statement::Name(n) => n.path(),
+                    _ => return Type::Unknown,
+                };
+                if let Some(f) = self.find_function(automata, &name) {
+                    if f.params.len() != args.len() {
+                        self.errors.push(CheckError::ArityMismatch {
+                            name, expected: f.params.len(), found: args.len(), position,
+                        });
+                    } else {
+                        for (p, (t, position)) in f.params.iter().zip(args.iter()) {
+                            self.expect(&Type::from_annotation(&p.ty), t, *position);
+                        }
+                    }
+                    Type::result_of(f)
+                } else if self.uploaded.contains(name.as_str()) {
+                    // opaque constructor of the uploaded type
+                    Type::Named(name)
+                } else if self.natives.contains(&name) {
+                    Type::Unknown
+                } else {
+                    self.errors.push(CheckError::UndefinedFunction(name, position));
+                    Type::Unknown
+                }
+            },
+            ReturnableExp::AutomataCall(SingleName(name, template)) => {
+                let args: Vec<(Type, Position)> = template.iter()
+                    .flat_map(|t| t.args.iter())
+                    .map(|a| (self.expression(automata, context, scope, a), a.position))
+                    .collect();
+                let Some(called) = self.module.find_automata(name) else {
+                    self.errors.push(CheckError::UndefinedAutomata(name.clone(), position));
+                    return Type::Unknown;
+                };
+                if called.kind != AutomataKind::Moore {
+                    self.errors.push(CheckError::NotMoore(name.clone(), position));
+                } else if let Some(initial) = called.initial_state() {
+                    let params = Self::template_params(called, &initial.params);
+                    if params.len() != args.len() {
+                        self.errors.push(CheckError::ArityMismatch {
+                            name: name.clone(), expected: params.len(), found: args.len(), position,
+                        });
+                    } else {
+                        for (p, (t, position)) in params.iter().zip(args.iter()) {
+                            self.expect(&Type::from_annotation(&p.ty), t, *position);
+                        }
+                    }
+                }
+                Type::Named(name.clone())
+            },
+            ReturnableExp::Match(m) => self.match_expression(automata, context, scope, m, position),
+            ReturnableExp::Is(value, pattern) => {
+                let t = self.expression(automata, context, scope, value);
+                self.pattern(automata, scope, pattern, &t, position);
+                Type::Bool
+            },
+            ReturnableExp::BinaryOperator(b) => {
+                // in the evaluation order, so the bindings of `is` on the left are visible on the right
+                let (left, right) = if matches!(b.operator.as_str(), "=" | "+=" | "-=" | "*=" | "/=") {
+                    let right = self.expression(automata, context, scope, &b.arg2);
+                    (self.expression(automata, context, scope, &b.arg1), right)
+                } else {
+                    let left = self.expression(automata, context, scope, &b.arg1);
+                    (left, self.expression(automata, context, scope, &b.arg2))
+                };
+                match b.operator.as_str() {
+                    "=" => {
+                        self.expect(&left, &right, b.arg2.position);
+                        Type::unit()
+                    },
+                    "+=" | "-=" | "*=" | "/=" => Type::unit(),
+                    "==" | "!=" | "<" | ">" | "<=" | ">=" => Type::Bool,
+                    "&&" | "||" => {
+                        self.expect(&Type::Bool, &left, b.arg1.position);
+                        self.expect(&Type::Bool, &right, b.arg2.position);
+                        Type::Bool
+                    },
+                    _ => match (left, right) {
+                        (Type::Int, Type::Int) => Type::Int,
+                        (Type::Float, Type::Int) | (Type::Int, Type::Float) | (Type::Float, Type::Float) => Type::Float,
+                        (Type::Bool, Type::Bool) => Type::Bool,
+                        (Type::Char, Type::Char) if b.operator.as_str() == "-" => Type::Int,
+                        (Type::String, _) if b.operator.as_str() == "+" => Type::String,
+                        _ => Type::Unknown,
+                    },
+                }
+            },
+            ReturnableExp::UnaryyOperator(u) => {
+                let t = self.expression(automata, context, scope, &u.arg);
+                match u.operator.as_str() {
+                    "!" => {
+                        self.expect(&Type::Bool, &t, u.arg.position);
+                        Type::Bool
+                    },
+                    _ => t,
+                }
+            },
+            ReturnableExp::Member(value, member) => {
+                if let (Context::State(state), Some(a)) = (context, automata)
+                    && is_self(value) && scope.get("self") == Some(&Type::Named(a.name.clone()))
+                {
+                    return self.self_member(a, state, member, position);
+                }
+                match (self.expression(automata, context, scope, value), member.parse::<usize>()) {
+                    (Type::Tuple(v), Ok(i)) => v.get(i).cloned().unwrap_or(Type::Unknown),
+                    _ => Type::Unknown,
+                }
+            },
+            ReturnableExp::MethodCall(value, method, args) => {
+                let t = self.expression(automata, context, scope, value);
+                for a in args.tuple.iter() {
+                    self.expression(automata, context, scope, a);
+                }
+                match method.as_str() {
+                    "is_me" => {
+                        if !args.tuple.is_empty() {
+                            self.errors.push(CheckError::ArityMismatch { name: method.clone(), expected: 0, found: args.tuple.len(), position });
+                        }
+                        if !matches!(t, Type::Named(_) | Type::Null | Type::Unknown) {
+                            self.errors.push(CheckError::TypeMismatch { expected: Type::Named("state".to_string()), found: t, position: value.position });
+                        }
+                        Type::Bool
+                    },
+                    // methods of the uploaded types are known to the host only
+                    _ if matches!(&t, Type::Named(n) if self.uploaded.contains(n.as_str())) || t == Type::Unknown => Type::Unknown,
+                    _ => {
+                        self.errors.push(CheckError::UndefinedMethod(method.clone(), position));
+                        Type::Unknown
+                    },
+                }
+            },
+            ReturnableExp::Index(value, index) => {
+                let i = self.expression(automata, context, scope, index);
+                self.expect(&Type::Int, &i, index.position);
+                let literal_index = match &index.kind {
+                    ExpressionType::Returnable(ReturnableExp::Statement(Statement::Literal(Literal::Digital(d)))) => d.parse::<usize>().ok(),
+                    _ => None,
+                };
+                match (self.expression(automata, context, scope, value), literal_index) {
+                    (Type::Tuple(v), Some(i)) => v.get(i).cloned().unwrap_or(Type::Unknown),
+                    (Type::String, _) => Type::Char,
+                    _ => Type::Unknown,
+                }
+            },
+            ReturnableExp::If(i) => {
+                // bindings of `is` are visible in `then` only
+                scope.push();
+                let c = self.expression(automata, context, scope, &i.condition);
+                self.expect(&Type::Bool, &c, i.condition.position);
+                let then = self.block(automata, context, scope, &i.then);
+                scope.pop();
+                match &i.otherwise {
+                    Some(o) => {
+                        let otherwise = self.block(automata, context, scope, o);
+                        if then == otherwise { then } else { Type::Unknown }
+                    },
+                    None => Type::Unknown,
+                }
+            },
+        }
+    }
+}
+
+impl<'m> Checker<'m> {
+    /// Members of `self` in the state body: the execution history or the state arguments
+    fn self_member(&mut self, a: &'m AutomataDef, state: &'m StateDef, member: &str, position: Position) -> Type {
+        match member {
+            "previous" => Type::Named(a.name.clone()),
+            "steps" => Type::Int,
+            "automata" => Type::String,
+            _ => {
+                let params = Checker::template_params(a, &state.params);
+                let param = match member.parse::<usize>() {
+                    Ok(i) => params.get(i).copied(),
+                    Err(_) => params.iter().find(|p| p.name == member).copied(),
+                };
+                match param {
+                    Some(p) => Type::from_annotation(&p.ty),
+                    None => {
+                        self.errors.push(CheckError::NoSuchMember(member.to_string(), position));
+                        Type::Unknown
+                    },
+                }
+            },
+        }
+    }
+
+    fn match_expression(&mut self, automata: Option<&'m AutomataDef>, context: Context<'m>, scope: &mut Scope, m: &'m MatchExp, position: Position) -> Type {
+        let t = self.expression(automata, context, scope, &m.value);
+        let mut result: Option<Type> = None;
+        for arm in m.arms.iter() {
+            scope.push();
+            self.pattern(automata, scope, &arm.pattern, &t, arm.position);
+            let body = self.expression(automata, context, scope, &arm.body);
+            scope.pop();
+            result = match result {
+                None => Some(body),
+                Some(r) if r == body => Some(r),
+                Some(_) => Some(Type::Unknown),
+            };
+        }
+        let missing = self.missing_cases(automata, &t, m.arms.iter().map(|a| &a.pattern).collect());
+        if !missing.is_empty() {
+            self.errors.push(CheckError::NonExhaustive { missing, position });
+        }
+        result.unwrap_or(Type::Unknown)
+    }
+
+    /// Automata the state pattern refers to
+    fn pattern_automata(&self, automata: Option<&'m AutomataDef>, explicit: &Option<String>, t: &Type, state: &str) -> Option<&'m AutomataDef> {
+        if let Some(a) = explicit {
+            return self.module.find_automata(a);
+        }
+        if let Type::Named(n) = t
+            && let Some(a) = self.module.find_automata(n) {
+            return Some(a);
+        }
+        automata
+            .filter(|a| a.state(state).is_some())
+            .or_else(|| self.module.automata().find(|a| a.state(state).is_some()))
+    }
+
+    fn pattern(&mut self, automata: Option<&'m AutomataDef>, scope: &mut Scope, pattern: &Pattern, t: &Type, position: Position) {
+        match pattern {
+            Pattern::Wildcard => {},
+            Pattern::Binding(name) => scope.set(name, t.clone()),
+            Pattern::Literal(l) => {
+                let lt = literal_type(l);
+                if !t.accepts(&lt) && !lt.accepts(t) {
+                    self.errors.push(CheckError::TypeMismatch { expected: t.clone(), found: lt, position });
+                }
+            },
+            Pattern::Tuple(patterns) => {
+                let types = match t {
+                    Type::Tuple(v) if v.len() == patterns.len() => v.clone(),
+                    Type::Unknown | Type::Named(_) => vec![Type::Unknown; patterns.len()],
+                    _ => {
+                        self.errors.push(CheckError::TypeMismatch {
+                            expected: t.clone(), found: Type::Tuple(vec![Type::Unknown; patterns.len()]), position,
+                        });
+                        vec![Type::Unknown; patterns.len()]
+                    },
+                };
+                for (p, t) in patterns.iter().zip(types.iter()) {
+                    self.pattern(automata, scope, p, t, position);
+                }
+            },
+            Pattern::State { automata: explicit, state, args } => {
+                let Some(a) = self.pattern_automata(automata, explicit, t, state) else {
+                    match explicit {
+                        Some(e) => self.errors.push(CheckError::UndefinedAutomata(e.clone(), position)),
+                        None => self.errors.push(CheckError::UndefinedState(state.clone(), position)),
+                    }
+                    return;
+                };
+                let Some(s) = a.state(state) else {
+                    self.errors.push(CheckError::UndefinedState(state.clone(), position));
+                    return;
+                };
+                match args {
+                    None => {},
+                    Some(patterns) if patterns.len() == s.params.len() => {
+                        for (p, param) in patterns.iter().zip(s.params.iter()) {
+                            self.pattern(automata, scope, p, &Type::from_annotation(&param.ty), position);
+                        }
+                    },
+                    Some(patterns) if patterns.len() == 1 && patterns[0].is_irrefutable() => {
+                        self.pattern(automata, scope, &patterns[0], &Type::Named(a.name.clone()), position);
+                    },
+                    Some(patterns) => self.errors.push(CheckError::ArityMismatch {
+                        name: state.clone(), expected: s.params.len(), found: patterns.len(), position,
+                    }),
+                }
+            },
+        }
+    }
+
+    /// Cases not covered by the patterns, empty when the match is exhaustive
+    fn missing_cases(&self, automata: Option<&'m AutomataDef>, t: &Type, patterns: Vec<&Pattern>) -> Vec<String> {
+        if patterns.iter().any(|p| p.is_irrefutable()) {
+            return vec![];
+        }
+        let states: Vec<&Pattern> = patterns.iter().copied().filter(|p| matches!(p, Pattern::State { .. })).collect();
+        if let Some(Pattern::State { automata: explicit, state, .. }) = states.first()
+            && let Some(a) = self.pattern_automata(automata, explicit, t, state) {
+            let covered: HashSet<&str> = states.iter().filter_map(|p| match p {
+                Pattern::State { state, args, .. } if args.as_ref().is_none_or(|v| v.iter().all(|p| p.is_irrefutable())) => {
+                    Some(state.as_str())
+                },
+                _ => None,
+            }).collect();
+            return terminal_states(a)
+                .into_iter()
+                .filter(|s| !covered.contains(s.name.as_str()))
+                .map(|s| format!("{}::{}", a.name, s.name))
+                .collect();
+        }
+        if *t == Type::Bool {
+            return [true, false].iter()
+                .filter(|b| !patterns.iter().any(|p| matches!(p, Pattern::Literal(Literal::Bool(x)) if x == *b)))
+                .map(|b| b.to_string())
+                .collect();
+        }
+        vec!["_".to_string()]
+    }
+}
+
+/// Does every path through the block end with `link`
+pub fn always_links(block: &Block) -> bool {
+    block.block.iter().any(expression_always_links)
+}
+
+fn expression_always_links(e: &Expression) -> bool {
+    match &e.kind {
+        ExpressionType::Procedural(ProceduralExp::Link(_)) => true,
+        ExpressionType::Returnable(ReturnableExp::Statement(Statement::Block(b))) => always_links(b),
+        ExpressionType::Returnable(ReturnableExp::If(i)) => {
+            always_links(&i.then) && i.otherwise.as_ref().is_some_and(always_links)
+        },
+        ExpressionType::Returnable(ReturnableExp::Match(m)) => {
+            !m.arms.is_empty() && m.arms.iter().all(|a| expression_always_links(&a.body))
+        },
+        _ => false,
+    }
+}
+
+/// States the automata can finish in: ones linking to NULL explicitly
+/// or by default, when some path of the body has no `link`
+pub fn terminal_states(a: &AutomataDef) -> Vec<&StateDef> {
+    a.states.iter().filter(|s| {
+        let mut links_null = false;
+        s.body.walk(&mut |e| {
+            if let ExpressionType::Procedural(ProceduralExp::Link(l)) = &e.kind {
+                links_null |= l.target.is_none();
+            }
+        });
+        links_null || !always_links(&s.body)
+    }).collect()
+}
+
+/// Is the expression the bare `self` of the state body
+pub fn is_self(e: &Expression) -> bool {
+    matches!(&e.kind, ExpressionType::Returnable(ReturnableExp::Statement(Statement::Name(n))) if n.path() == "self")
+}
+
+pub fn literal_type(l: &Literal) -> Type {
+    match l {
+        Literal::Char(_) => Type::Char,
+        Literal::String(_) => Type::String,
+        Literal::Digital(_) => Type::Int,
+        Literal::NumericalLexem(n) => {
+            if n.contains('.') || n.ends_with("f32") || n.ends_with("f64") { Type::Float } else { Type::Int }
+        },
+        Literal::Bool(_) => Type::Bool,
+        Literal::NULL => Type::Null,
+    }
+}
+
+#[test]
+fn check_functions_test() {
+    use crate::parser::Parser;
+    let ok = Parser::parse_str("
+        fn fact(n: int64) -> int64 {
+            if n <= 1 { return 1; }
+            n * fact(n - 1)
+        }
+        automata A {
+            state S<x: int64> {
+                let y: int64 = fact(x);
+                link self -> S<y>;
+            }
+        }
+    ").unwrap();
+    assert_eq!(Checker::check(&ok), Ok(()));
+
+    let bad = Parser::parse_str("
+        fn f(a: int64) -> char {
+            link self -> NULL;
+            return a;
+        }
+        automata A {
+            state S {
+                let z = g(1);
+                f(1, 2);
+                return 1;
+                link self -> T;
+            }
+        }
+    ").unwrap();
+    let errors = Checker::check(&bad).unwrap_err();
+    assert!(matches!(errors[0], CheckError::LinkInFunction(_)));
+    assert!(matches!(errors[1], CheckError::TypeMismatch { expected: Type::Char, found: Type::Int, .. }));
+    assert!(matches!(&errors[2], CheckError::UndefinedFunction(n, _) if n == "g"));
+    assert!(matches!(errors[3], CheckError::ArityMismatch { expected: 1, found: 2, .. }));
+    assert!(matches!(errors[4], CheckError::ReturnOutsideFunction(_)));
+    assert!(matches!(&errors[5], CheckError::UndefinedState(n, _) if n == "T"));
+
+    // functions without `-> T` return the unit
+    let unit = Parser::parse_str("
+        fn f() { return 5; }
+        fn g() { 1 }
+        fn h() { return; }
+    ").unwrap();
+    let errors = Checker::check(&unit).unwrap_err();
+    assert_eq!(errors.len(), 2);
+    assert!(matches!(&errors[0], CheckError::TypeMismatch { expected, found: Type::Int, .. } if *expected == Type::unit()));
+    assert!(matches!(&errors[1], CheckError::TypeMismatch { expected, found: Type::Int, .. } if *expected == Type::unit()));
+}
+
+#[test]
+fn check_match_test() {
+    use crate::parser::Parser;
+    let module = Parser::parse_str("
+        automata Other {
+            state Base<x: int64> {
+                if x > 1 { link self -> Verdict1<x, 2>; } else { link self -> Verdict2<x>; }
+            }
+            state Verdict1<arg1: int64, arg2: int64> { link self -> NULL; }
+            state Verdict2<arg1: int64> { }
+        }
+        fn full(v: Other) -> int64 {
+            match v {
+                Other::Verdict1(verd1) => verd1.arg1,
+                Other::Verdict2(a) => a,
+            }
+        }
+        fn partial(v: Other) -> int64 {
+            if v is Other::Verdict3 { return 1; }
+            match v {
+                Other::Verdict1(a, 1) => a,
+                Other::Verdict2(a, b) => 0,
+            }
+        }
+        fn flags(b: bool) -> int64 {
+            match b { true => 1 }
+        }
+    ").unwrap();
+    let errors = Checker::check(&module).unwrap_err();
+    assert_eq!(errors.len(), 4, "{:?}", errors);
+    assert!(matches!(&errors[0], CheckError::UndefinedState(s, _) if s == "Verdict3"));
+    assert!(matches!(&errors[1], CheckError::ArityMismatch { name, expected: 1, found: 2, .. } if name == "Verdict2"));
+    assert!(matches!(&errors[2], CheckError::NonExhaustive { missing, .. } if *missing == vec!["Other::Verdict1".to_string()]));
+    assert!(matches!(&errors[3], CheckError::NonExhaustive { missing, .. } if *missing == vec!["false".to_string()]));
+
+    // the bindings on the left of `&&` are visible on the right
+    let module = Parser::parse_str("
+        automata Ctx {
+            state V1<arg1: int64> { }
+        }
+        fn big(r: Ctx) -> bool {
+            if r is Ctx::V1(v) && v.arg1 > 3 { return true; }
+            false
+        }
+    ").unwrap();
+    assert_eq!(Checker::check(&module), Ok(()));
+}
+
+#[test]
+fn check_self_test() {
+    use crate::parser::Parser;
+    let module = Parser::parse_str("
+        automata History {
+            state Base<count: int64> {
+                let name: string = self.automata;
+                if self.previous.is_me() && self.steps > 1 && self.count < 10 {
+                    link self -> Base<self.count + 1>;
+                }
+                let bad: bool = self.steps;
+                self.history;
+                self.previous.was_me();
+            }
+        }
+        fn outside() -> int64 { self.steps }
+    ").unwrap();
+    let errors = Checker::check(&module).unwrap_err();
+    assert_eq!(errors.len(), 4, "{:?}", errors);
+    assert!(matches!(&errors[0], CheckError::UndefinedName(n, _) if n == "self"));
+    assert!(matches!(&errors[1], CheckError::TypeMismatch { expected: Type::Bool, found: Type::Int, .. }));
+    assert!(matches!(&errors[2], CheckError::NoSuchMember(m, _) if m == "history"));
+    assert!(matches!(&errors[3], CheckError::UndefinedMethod(m, _) if m == "was_me"));
+}
@@ -0,0 +1,716 @@
+//! The `fan` command line. Every subcommand reports in text for people or in JSON with `--format json`;
+//! the exit code is [`SUCCESS`], [`FAILURE`] when the FAN source or its execution has errors,
+//! and [`USAGE_ERROR`] for invalid arguments, unreadable files and invalid project configuration.
+
+use std::io::{BufRead, Write};
+
+use crate::analysis::{analyze, Analysis};
+use crate::bytecode::Program;
+use crate::checker::Checker;
+use crate::dap::DapServer;
+use crate::debugger::{Console, Debugger};
+use crate::diagnostic::Diagnostic;
+use crate::dot::{automata_to_dot, to_dot};
+use crate::format::{diff, format};
+use crate::graph::{AutomataGraph, Graph};
+use crate::import::{import, to_fan};
+use crate::interpreter::{parse_value, ExecError, Interpreter, MealyHalt, MealyRun, StateValue, Value};
+use crate::json::Json;
+use crate::lexer::{FANGrammarToken, Lexer};
+use crate::lint::{lint, Level, LintConfig};
+use crate::lsp::LspServer;
+use crate::mermaid::{automata_to_mermaid, to_mermaid};
+use crate::parser::{AutomataKind, FANType, Module, Param, ParseError, Parser};
+use crate::plantuml::{automata_to_plantuml, to_plantuml};
+use crate::table::{tables, to_csv, to_markdown, TransitionTable};
+use crate::trace::{trace_state, trace_value};
+use crate::vm::Vm;
+
+pub const SUCCESS: u8 = 0;
+pub const FAILURE: u8 = 1;
+pub const USAGE_ERROR: u8 = 2;
+
+pub const USAGE: &str = "\
+usage: fan <command> [--format text|json|dot|mermaid|plantuml|csv|markdown] [arguments]
+
+commands:
+    lex <file>                                      print the lexems
+    parse <file>                                    print the syntax tree, its declarations in JSON
+    check [--analyze] <file>...                     resolve names and check types, with `--analyze` report the states
+                                                    unreachable or never reaching NULL and the cycles of states
+    run [--vm] <file> <automata> [args...] [-- signals...]
+                                                    run the automata, arguments and signals are FAN expressions
+    lint <file>...                                  report suspicious code, the rule levels are set in the
+                                                    `lint` section of `fan.json` next to the files or above them
+    fmt [--check] <file>...                         reformat the files in place, with `--check` print the diff
+                                                    and fail when the files are not formatted
+    graph <file> [automata]                         print the transitions of the automata, `--format dot`, `mermaid`
+                                                    or `plantuml` for the state diagrams
+    table <file> [automata]                         print the links of the states with their guards and arguments,
+                                                    `--format csv` or `markdown` for the spreadsheets and the docs
+    import <file>                                   print FAN automata skeletons of the Mermaid `stateDiagram`
+                                                    or DOT `digraph` state diagrams
+    debug <file> <automata> [args...] [-- signals...]
+                                                    run the automata in the interactive debugger
+    dap                                             serve the Debug Adapter Protocol over stdio
+    lsp                                             serve the Language Server Protocol over stdio
+";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+    /// Graphviz, only for `graph`
+    Dot,
+    /// Mermaid `stateDiagram-v2`, only for `graph`
+    Mermaid,
+    /// PlantUML state diagram, only for `graph`
+    PlantUml,
+    /// Only for `table`
+    Csv,
+    /// Only for `table`
+    Markdown,
+}
+
+impl Format {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Format::Text => "text",
+            Format::Json => "json",
+            Format::Dot => "dot",
+            Format::Mermaid => "mermaid",
+            Format::PlantUml => "plantuml",
+            Format::Csv => "csv",
+            Format::Markdown => "markdown",
+        }
+    }
+
+    /// The only command supporting the format, `None` for the formats of every command
+    pub fn command(&self) -> Option<&'static str> {
+        match self {
+            Format::Text | Format::Json => None,
+            Format::Dot | Format::Mermaid | Format::PlantUml => Some("graph"),
+            Format::Csv | Format::Markdown => Some("table"),
+        }
+    }
+}
+
+/// Output of the subcommand in both formats
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub text: String,
+    pub json: Json,
+}
+
+impl Report {
+    pub fn new(text: impl Into<String>, json: Json) -> Self {
+        Self { text: text.into(), json }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CliError {
+    /// Invalid arguments or unreadable files, reported with the usage
+    Usage(String),
+    /// Invalid `fan.json`, reported alone since the arguments are fine
+    Config(String),
+    /// Errors of the FAN source or the execution, reported like the output
+    Failed(Report),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub format: Format,
+    /// `run` on the bytecode VM instead of the interpreter
+    pub vm: bool,
+    /// `fmt` reports the unformatted files instead of rewriting them
+    pub check: bool,
+    /// `check` reports the reachability of the states
+    pub analyze: bool,
+    pub positional: Vec<String>,
+    /// Arguments after `--`
+    pub rest: Vec<String>,
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Self, CliError> {
+        let mut options =
+            Options { format: Format::Text, vm: false, check: false, analyze: false, positional: vec![], rest: vec![] };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--" => {
+                    options.rest = args.cloned().collect();
+                    break;
+                },
+                "--vm" => options.vm = true,
+                "--check" => options.check = true,
+                "--analyze" => options.analyze = true,
+                "--format" => {
+                    let format = args.next().ok_or_else(|| CliError::Usage("`--format` needs a value".to_string()))?;
+                    options.format = Self::format(format)?;
+                },
+                _ if arg.starts_with("--format=") => options.format = Self::format(&arg["--format=".len()..])?,
+                _ if arg.starts_with("--") => return Err(CliError::Usage(format!("unknown option `{}`", arg))),
+                _ => options.positional.push(arg.clone()),
+            }
+        }
+        Ok(options)
+    }
+
+    fn format(value: &str) -> Result<Format, CliError> {
+        match value {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "dot" => Ok(Format::Dot),
+            "mermaid" => Ok(Format::Mermaid),
+            "plantuml" => Ok(Format::PlantUml),
+            "csv" => Ok(Format::Csv),
+            "markdown" => Ok(Format::Markdown),
+            _ => Err(CliError::Usage(format!(
+                "unknown format `{}`, expected `text`, `json`, `dot`, `mermaid`, `plantuml`, `csv` or `markdown`",
+                value
+            ))),
+        }
+    }
+}
+
+fn read(file: &str) -> Result<String, CliError> {
+    std::fs::read_to_string(file).map_err(|e| CliError::Usage(format!("cannot read `{}`: {}", file, e)))
+}
+
+fn failed(file: &str, source: &str, diagnostics: &[Diagnostic]) -> CliError {
+    CliError::Failed(Report::new(
+        diagnostics.iter().map(|d| d.render(file, source)).collect::<String>(),
+        Json::object([("file", file.into()), ("diagnostics", Json::Array(diagnostics.iter().map(Diagnostic::to_json).collect()))]),
+    ))
+}
+
+fn parse(file: &str, source: &str) -> Result<Module, CliError> {
+    Parser::parse_str(source).map_err(|e| failed(file, source, &[Diagnostic::from(&e)]))
+}
+
+fn checked(file: &str, source: &str) -> Result<Module, CliError> {
+    let module = parse(file, source)?;
+    match Checker::check(&module) {
+        Ok(()) => Ok(module),
+        Err(errors) => Err(failed(file, source, &errors.iter().map(Diagnostic::from).collect::<Vec<_>>())),
+    }
+}
+
+fn single<'a>(options: &'a Options, what: &str) -> Result<&'a str, CliError> {
+    match options.positional.as_slice() {
+        [file] => Ok(file),
+        _ => Err(CliError::Usage(format!("`{}` takes a single file", what))),
+    }
+}
+
+fn token_json(token: &FANGrammarToken) -> (&'static str, String) {
+    match token {
+        FANGrammarToken::Name(s) => ("name", s.clone()),
+        FANGrammarToken::Digital(s) => ("digital", s.clone()),
+        FANGrammarToken::NumericalLexem(s) => ("number", s.clone()),
+        FANGrammarToken::CharLiteral(c) => ("char", c.to_string()),
+        FANGrammarToken::StringLiteral(s) => ("string", s.clone()),
+        FANGrammarToken::Reserved(r) => ("reserved", r.as_str().to_string()),
+        FANGrammarToken::BlockSymbol(b) => ("block", b.as_char().to_string()),
+        FANGrammarToken::Operational(o) => ("operator", o.as_str().to_string()),
+        FANGrammarToken::TokBrk => ("break", " ".to_string()),
+    }
+}
+
+pub fn lex(options: &Options) -> Result<Report, CliError> {
+    let file = single(options, "lex")?;
+    let source = read(file)?;
+    let tokens = Lexer::lex_str(&source).map_err(|e| failed(file, &source, &[Diagnostic::from(&ParseError::Lex(e))]))?;
+    let mut text = String::new();
+    let mut json = vec![];
+    for (position, token) in tokens.iter() {
+        let (kind, lexem) = token_json(token);
+        text.push_str(&format!("{}\t{}\t{:?}\n", position, kind, lexem));
+        json.push(Json::object([
+            ("line", (position.line + 1).into()), ("column", (position.col + 1).into()),
+            ("kind", kind.into()), ("text", lexem.into()),
+        ]));
+    }
+    Ok(Report::new(text, Json::object([("tokens", Json::Array(json))])))
+}
+
+fn type_json(t: &Option<FANType>) -> Json {
+    t.as_ref().map(|t| t.to_string()).into()
+}
+
+fn params_json(params: &[Param]) -> Json {
+    Json::Array(params.iter().map(|p| Json::object([("name", p.name.as_str().into()), ("type", type_json(&p.ty))])).collect())
+}
+
+/// Declarations of the module, the bodies are left out
+pub fn module_json(module: &Module) -> Json {
+    let function = |f: &crate::parser::FunctionDef| Json::object([
+        ("name", f.name.as_str().into()),
+        ("params", params_json(&f.params)),
+        ("returns", type_json(&f.returns)),
+        ("line", (f.position.line + 1).into()),
+    ]);
+    let automata = module.automata().map(|a| {
+        let kind = match &a.kind {
+            AutomataKind::Moore => Json::object([("type", "moore".into())]),
+            AutomataKind::Mealy(signal, output) => Json::object([
+                ("type", "mealy".into()), ("signal", signal.as_str().into()), ("output", output.as_deref().into()),
+            ]),
+        };
+        let states = a.states.iter().map(|s| Json::object([
+            ("name", s.name.as_str().into()),
+            ("params", params_json(&s.params)),
+            ("initial", s.initial.into()),
+            ("line", (s.position.line + 1).into()),
+        ])).collect();
+        Json::object([
+            ("name", a.name.as_str().into()),
+            ("kind", kind),
+            ("states", Json::Array(states)),
+            ("functions", Json::Array(a.functions.iter().map(function).collect())),
+            ("line", (a.position.line + 1).into()),
+        ])
+    }).collect();
+    Json::object([
+        ("imports", Json::Array(module.imports().map(|i| Json::object([
+            ("names", i.names.clone().into()), ("from", i.from.as_str().into()),
+        ])).collect())),
+        ("functions", Json::Array(module.functions().map(function).collect())),
+        ("automata", Json::Array(automata)),
+    ])
+}
+
+pub fn parse_command(options: &Options) -> Result<Report, CliError> {
+    let file = single(options, "parse")?;
+    let source = read(file)?;
+    let module = parse(file, &source)?;
+    Ok(Report::new(format!("{:#?}\n", module), module_json(&module)))
+}
+
+pub fn check(options: &Options) -> Result<Report, CliError> {
+    if options.positional.is_empty() {
+        return Err(CliError::Usage("`check` needs files".to_string()));
+    }
+    let mut text = String::new();
+    let mut files = vec![];
+    let mut ok = true;
+    for file in options.positional.iter() {
+        let source = read(file)?;
+        match checked(file, &source) {
+            Ok(module) if options.analyze => {
+                let analyses = analyze(&Graph::new(&module));
+                text.push_str(&format!("{}\n", file));
+                text.extend(analyses.iter().map(Analysis::to_string));
+                files.push(Json::object([
+                    ("file", file.as_str().into()),
+                    ("diagnostics", Json::Array(vec![])),
+                    ("analysis", Json::Array(analyses.iter().map(Analysis::to_json).collect())),
+                ]));
+            },
+            Ok(_) => files.push(Json::object([("file", file.as_str().into()), ("diagnostics", Json::Array(vec![]))])),
+            Err(CliError::Failed(report)) => {
+                ok = false;
+                text.push_str(&report.text);
+                files.push(report.json);
+            },
+            Err(e) => return Err(e),
+        }
+    }
+    let report = Report::new(text, Json::object([("files", Json::Array(files))]));
+    if ok { Ok(report) } else { Err(CliError::Failed(report)) }
+}
+
+fn values(args: &[String]) -> Result<Vec<Value>, CliError> {
+    args.iter().map(|a| parse_value(a)).collect::<Result<_, _>>().map_err(CliError::Usage)
+}
+
+/// Runtime error of `run` and `debug`
+fn exec_failed(e: ExecError) -> CliError {
+    CliError::Failed(Report::new(format!("error: {}\n", e), Json::object([("error", e.to_string().into())])))
+}
+
+fn moore_report(state: StateValue) -> Result<Report, CliError> {
+    Ok(Report::new(format!("{}\n", state), Json::object([("state", trace_state(&state))])))
+}
+
+/// Outputs and the final state, the run fails when a signal is rejected
+fn mealy_report(run: MealyRun) -> Result<Report, CliError> {
+    let mut text: String = run.outputs.iter().map(|o| format!("{}\n", o)).collect();
+    let halt = match &run.halt {
+        MealyHalt::Consumed => Json::object([("type", "consumed".into())]),
+        MealyHalt::Rejected(v) => {
+            text.push_str(&format!("rejected {}\n", v));
+            Json::object([("type", "rejected".into()), ("signal", trace_value(v))])
+        },
+        MealyHalt::Null { remaining } => Json::object([("type", "null".into()), ("remaining", (*remaining).into())]),
+    };
+    text.push_str(&format!("{}\n", run.state));
+    let report = Report::new(text, Json::object([
+        ("state", trace_state(&run.state)),
+        ("outputs", Json::Array(run.outputs.iter().map(trace_value).collect())),
+        ("consumed", run.consumed.into()),
+        ("halt", halt),
+    ]));
+    if matches!(run.halt, MealyHalt::Rejected(_)) { Err(CliError::Failed(report)) } else { Ok(report) }
+}
+
+pub fn run(options: &Options) -> Result<Report, CliError> {
+    let [file, automata, args @ ..] = options.positional.as_slice() else {
+        return Err(CliError::Usage("`run` needs the file and the automata".to_string()));
+    };
+    let source = read(file)?;
+    let module = checked(file, &source)?;
+    let (args, signals) = (values(args)?, values(&options.rest)?);
+    let a = module.find_automata(automata).ok_or_else(|| CliError::Usage(format!("undefined automata `{}`", automata)))?;
+    let mealy = a.kind != AutomataKind::Moore;
+    let result = if options.vm {
+        let program = Program::compile(&module);
+        let mut vm = Vm::new(&program);
+        if mealy {
+            vm.run_mealy(automata, args, signals).map(mealy_report)
+        } else {
+            vm.run_automata(automata, args).map(moore_report)
+        }
+    } else {
+        let mut interpreter = Interpreter::new(&module);
+        if mealy {
+            interpreter.run_mealy(automata, args, signals).map(mealy_report)
+        } else {
+            interpreter.run_automata(automata, args).map(moore_report)
+        }
+    };
+    result.unwrap_or_else(|e| Err(exec_failed(e)))
+}
+
+pub fn lint_command(options: &Options) -> Result<Report, CliError> {
+    if options.positional.is_empty() {
+        return Err(CliError::Usage("`lint` needs files".to_string()));
+    }
+    let mut text = String::new();
+    let mut files = vec![];
+    let mut ok = true;
+    for file in options.positional.iter() {
+        let source = read(file)?;
+        let dir = std::fs::canonicalize(file).ok().and_then(|p| p.parent().map(|d| d.to_path_buf())).unwrap_or_default();
+        let (_, config) = LintConfig::find(&dir).map_err(CliError::Config)?;
+        match lint(&source, &config) {
+            Ok(lints) => {
+                ok &= lints.iter().all(|l| l.level != Level::Deny);
+                text.extend(lints.iter().map(|l| l.render(file, &source)));
+                files.push(Json::object([
+                    ("file", file.as_str().into()), ("lints", Json::Array(lints.iter().map(|l| l.to_json()).collect())),
+                ]));
+            },
+            Err(e) => {
+                ok = false;
+                let d = Diagnostic::from(&e);
+                text.push_str(&d.render(file, &source));
+                files.push(Json::object([("file", file.as_str().into()), ("diagnostics", Json::Array(vec![d.to_json()]))]));
+            },
+        }
+    }
+    let report = Report::new(text, Json::object([("files", Json::Array(files))]));
+    if ok { Ok(report) } else { Err(CliError::Failed(report)) }
+}
+
+pub fn fmt(options: &Options) -> Result<Report, CliError> {
+    if options.positional.is_empty() {
+        return Err(CliError::Usage("`fmt` needs files".to_string()));
+    }
+    let mut text = String::new();
+    let mut files = vec![];
+    let mut formatted_all = true;
+    for file in options.positional.iter() {
+        let source = read(file)?;
+        let formatted = format(&source).map_err(|e| failed(file, &source, &[Diagnostic::from(&e)]))?;
+        let changed = formatted != source;
+        let mut json = vec![("file", file.as_str().into()), ("changed", changed.into())];
+        if options.check {
+            let diff = diff(&source, &formatted);
+            if changed {
+                formatted_all = false;
+                text.push_str(&format!("--- {}\n+++ {}\n{}", file, file, diff));
+            }
+            json.push(("diff", diff.into()));
+        } else if changed {
+            std::fs::write(file, formatted).map_err(|e| CliError::Usage(format!("cannot write `{}`: {}", file, e)))?;
+            text.push_str(&format!("formatted {}\n", file));
+        }
+        files.push(Json::object(json));
+    }
+    let report = Report::new(text, Json::object([("files", Json::Array(files))]));
+    if formatted_all { Ok(report) } else { Err(CliError::Failed(report)) }
+}
+
+/// File and optional automata arguments of `graph` and `table`
+fn file_and_automata<'a>(options: &'a Options, what: &str) -> Result<(&'a str, Option<&'a str>), CliError> {
+    match options.positional.as_slice() {
+        [file] => Ok((file, None)),
+        [file, automata] => Ok((file, Some(automata))),
+        _ => Err(CliError::Usage(format!("`{}` needs the file and optionally the automata", what))),
+    }
+}
+
+fn find<'g>(graph: &'g Graph, automata: &str) -> Result<&'g AutomataGraph, CliError> {
+    graph.find(automata).ok_or_else(|| CliError::Usage(format!("undefined automata `{}`", automata)))
+}
+
+pub fn graph(options: &Options) -> Result<Report, CliError> {
+    let (file, automata) = file_and_automata(options, "graph")?;
+    let source = read(file)?;
+    let graph = Graph::new(&parse(file, &source)?);
+    if let Some(automata) = automata {
+        let a = find(&graph, automata)?;
+        let text = match options.format {
+            Format::Dot => automata_to_dot(&graph, a),
+            Format::Mermaid => automata_to_mermaid(a),
+            Format::PlantUml => automata_to_plantuml(a),
+            _ => a.to_string(),
+        };
+        return Ok(Report::new(text, Json::object([("automata", Json::Array(vec![a.to_json()]))])));
+    }
+    match options.format {
+        Format::Dot => Ok(Report::new(to_dot(&graph), Json::Null)),
+        Format::Mermaid => Ok(Report::new(to_mermaid(&graph), Json::Null)),
+        Format::PlantUml => Ok(Report::new(to_plantuml(&graph), Json::Null)),
+        _ => Ok(Report::new(graph.to_string(), graph.to_json())),
+    }
+}
+
+pub fn table(options: &Options) -> Result<Report, CliError> {
+    let (file, automata) = file_and_automata(options, "table")?;
+    let source = read(file)?;
+    let graph = Graph::new(&parse(file, &source)?);
+    let tables = match automata {
+        Some(automata) => vec![TransitionTable::new(find(&graph, automata)?)],
+        None => tables(&graph),
+    };
+    let text = match options.format {
+        Format::Csv => to_csv(&tables),
+        Format::Markdown => to_markdown(&tables),
+        _ => tables.iter().map(TransitionTable::to_string).collect(),
+    };
+    Ok(Report::new(text, Json::object([("automata", Json::Array(tables.iter().map(TransitionTable::to_json).collect()))])))
+}
+
+pub fn import_command(options: &Options) -> Result<Report, CliError> {
+    let file = single(options, "import")?;
+    let source = read(file)?;
+    let diagrams = import(&source).map_err(|e| failed(file, &source, &[Diagnostic::from(&e)]))?;
+    let name = std::path::Path::new(file).file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let fan = to_fan(&diagrams, &name);
+    let json = Json::object([("file", file.into()), ("source", fan.as_str().into())]);
+    Ok(Report::new(fan, json))
+}
+
+pub fn debug(options: &Options, input: &mut dyn BufRead, output: &mut dyn Write) -> Result<Report, CliError> {
+    let [file, automata, args @ ..] = options.positional.as_slice() else {
+        return Err(CliError::Usage("`debug` needs the file and the automata".to_string()));
+    };
+    let source = read(file)?;
+    let module = checked(file, &source)?;
+    let (args, signals) = (values(args)?, values(&options.rest)?);
+    let a = module.find_automata(automata).ok_or_else(|| CliError::Usage(format!("undefined automata `{}`", automata)))?;
+    let mut interpreter = Interpreter::new(&module).with_debugger(Debugger::new(&module, Console::new(input, output)));
+    let result = match a.kind {
+        AutomataKind::Moore => interpreter.run_automata(automata, args).map(moore_report),
+        AutomataKind::Mealy(..) => interpreter.run_mealy(automata, args, signals).map(mealy_report),
+    };
+    result.unwrap_or_else(|e| Err(exec_failed(e)))
+}
+
+/// Runs the command line without the program name, returns the exit code
+pub fn main(args: &[String], input: &mut dyn BufRead, output: &mut dyn Write, errors: &mut dyn Write) -> u8 {
+    let Some((command, args)) = args.split_first() else {
+        let _ = write!(errors, "{}", USAGE);
+        return USAGE_ERROR;
+    };
+    let options = match Options::parse(args) {
+        Ok(options) => options,
+        Err(e) => return report(Err(e), Format::Text, output, errors),
+    };
+    if options.format.command().is_some_and(|c| c != command) {
+        let e = CliError::Usage(format!("`{}` does not support `--format {}`", command, options.format.as_str()));
+        return report(Err(e), Format::Text, output, errors);
+    }
+    let server = |e: crate::rpc::RpcError| CliError::Failed(Report::new(format!("error: {}\n", e), Json::Null));
+    let result = match command.as_str() {
+        "lex" => lex(&options),
+        "parse" => parse_command(&options),
+        "check" => check(&options),
+        "run" => run(&options),
+        "lint" => lint_command(&options),
+        "fmt" => fmt(&options),
+        "graph" => graph(&options),
+        "table" => table(&options),
+        "import" => import_command(&options),
+        "debug" => debug(&options, input, output),
+        "dap" => DapServer::new(&mut *input, &mut *output).serve().map(|()| Report::new("", Json::Null)).map_err(server),
+        "lsp" => LspServer::new(&mut *input, &mut *output).serve().map(|()| Report::new("", Json::Null)).map_err(server),
+        "help" | "--help" | "-h" => Ok(Report::new(USAGE, Json::String(USAGE.to_string()))),
+        _ => Err(CliError::Usage(format!("unknown command `{}`", command))),
+    };
+    // the output of the servers is the protocol itself
+    let format = if matches!(command.as_str(), "dap" | "lsp") { Format::Text } else { options.format };
+    report(result, format, output, errors)
+}
+
+/// Writes the result in the format, returns the exit code
+fn report(report: Result<Report, CliError>, format: Format, output: &mut dyn Write, errors: &mut dyn Write) -> u8 {
+    let written = match (&report, format) {
+        (Ok(r), Format::Json) | (Err(CliError::Failed(r)), Format::Json) => writeln!(output, "{}", r.json),
+        (Ok(r), _) => write!(output, "{}", r.text),
+        (Err(CliError::Failed(r)), _) => write!(errors, "{}", r.text),
+        (Err(CliError::Usage(message)), _) => write!(errors, "error: {}\n\n{}", message, USAGE),
+        (Err(CliError::Config(message)), _) => writeln!(errors, "error: {}", message),
+    };
+    match (report, written) {
+        (_, Err(_)) => FAILURE,
+        (Ok(_), _) => SUCCESS,
+        (Err(CliError::Failed(_)), _) => FAILURE,
+        (Err(CliError::Usage(_) | CliError::Config(_)), _) => USAGE_ERROR,
+    }
+}
+
+#[test]
+fn cli_test() {
+    let dir = std::env::temp_dir().join(format!("cli_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = |name: &str, source: &str| {
+        let path = dir.join(name);
+        std::fs::write(&path, source).unwrap();
+        path.to_string_lossy().to_string()
+    };
+    let counter = file("counter.fan", "\
+automata Count {
+  state Up<n: int64> { if n < 3 { link self -> Up<n + 1>; } }
+}
+automata Echo: Mealy<signal, out> {
+    state Read<signal: int64> { out = signal * 2; link self -> Read; }
+}
+");
+    let broken = file("broken.fan", "fn f() -> int64 {\n    g()\n}\n");
+    let fan = |args: &[&str]| {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        let (mut output, mut errors) = (vec![], vec![]);
+        let code = main(&args, &mut "".as_bytes(), &mut output, &mut errors);
+        (code, String::from_utf8(output).unwrap(), String::from_utf8(errors).unwrap())
+    };
+
+    assert_eq!(fan(&["run", &counter, "Count", "0"]), (SUCCESS, "Count::Up<3>\n".to_string(), String::new()));
+    let (code, output, _) = fan(&["run", "--vm", "--format", "json", &counter, "Echo", "--", "1", "2"]);
+    assert_eq!(code, SUCCESS);
+    assert_eq!(output, concat!(
+        r#"{"state":{"automata":"Echo","state":"Read","args":[]},"outputs":[{"int":2},{"int":4}],"consumed":2,"#,
+        r#""halt":{"type":"consumed"}}"#, "\n",
+    ));
+    // a rejected signal fails the run
+    let (code, output, errors) = fan(&["run", &counter, "Echo", "--", "1", "'x'"]);
+    assert_eq!((code, output.as_str()), (FAILURE, ""));
+    assert_eq!(errors, "2\nrejected 'x'\nEcho::Read\n");
+    let (code, output, _) = fan(&["run", "--vm", "--format", "json", &counter, "Echo", "--", "'x'"]);
+    assert_eq!(code, FAILURE);
+    assert_eq!(output, concat!(
+        r#"{"state":{"automata":"Echo","state":"Read","args":[]},"outputs":[],"consumed":0,"#,
+        r#""halt":{"type":"rejected","signal":{"char":"x"}}}"#, "\n",
+    ));
+    assert_eq!(fan(&["check", &counter]).0, SUCCESS);
+    let (code, output, _) = fan(&["check", "--analyze", &counter]);
+    assert_eq!(code, SUCCESS);
+    assert_eq!(output, format!("{}\nautomata Count\n    cycles: {{Up}}\nautomata Echo\n    never reaching NULL: Read\n    cycles: {{Read}}\n", counter));
+    let (_, output, _) = fan(&["check", "--analyze", "--format", "json", &counter]);
+    assert!(output.contains(r#""analysis":[{"automata":"Count","unreachable":[],"non_terminating":[],"components":[["Up"]]},"#), "{}", output);
+    let (code, output, errors) = fan(&["check", &broken]);
+    assert_eq!((code, output.as_str()), (FAILURE, ""));
+    assert!(errors.starts_with("error: undefined function `g`\n"), "{}", errors);
+    let (code, output, _) = fan(&["check", "--format=json", &counter, &broken]);
+    assert_eq!(code, FAILURE);
+    let json = Json::parse(&output).unwrap();
+    let files = json.get("files").and_then(Json::as_array).unwrap();
+    assert_eq!(files[0].get("diagnostics").unwrap().to_string(), "[]");
+    assert_eq!(files[1].get("diagnostics").unwrap().to_string(), r#"[{"message":"undefined function `g`","line":2,"column":5}]"#);
+
+    let (code, output, _) = fan(&["lex", "--format", "json", &broken]);
+    assert_eq!(code, SUCCESS);
+    assert!(output.starts_with(r#"{"tokens":[{"line":1,"column":1,"kind":"reserved","text":"fn"},"#));
+    assert!(fan(&["lex", &broken]).1.starts_with("1:1\treserved\t\"fn\"\n1:4\tname\t\"f\"\n"));
+    let (_, output, _) = fan(&["parse", "--format", "json", &counter]);
+    assert!(output.contains(r#"{"name":"Up","params":[{"name":"n","type":"int64"}],"initial":false,"line":2}"#), "{}", output);
+    assert!(fan(&["parse", &counter]).1.starts_with("Module {"));
+    assert_eq!(fan(&["graph", &counter]).1, "automata Count\n    Up -> Up\n    Up -> NULL\nautomata Echo\n    Read -> Read\n");
+    let (code, output, _) = fan(&["graph", "--format", "dot", &counter]);
+    assert_eq!(code, SUCCESS);
+    assert!(output.starts_with("digraph \"Count\" {\n"));
+    assert!(output.contains("    \"Up\" -> \"Up\" [label=\"n < 3\"];\n    \"Up\" -> \"NULL\" [style=dashed];\n"), "{}", output);
+    assert_eq!(fan(&["check", "--format", "dot", &counter]).0, USAGE_ERROR);
+    let (code, output, _) = fan(&["graph", "--format", "mermaid", &counter, "Count"]);
+    assert_eq!(code, SUCCESS);
+    assert_eq!(output, "stateDiagram-v2\n    state \"Up#lt;n: int64#gt;\" as Up\n    [*] --> Up\n    Up --> Up : n #lt; 3\n    Up --> [*]\n");
+    let (code, output, _) = fan(&["graph", "--format=plantuml", &counter]);
+    assert_eq!(code, SUCCESS);
+    assert!(output.starts_with("@startuml Count\n") && output.ends_with("@startuml Echo\nstate \"Read<signal: int64>\" as Read\n[*] --> Read\nRead --> Read\n@enduml\n"), "{}", output);
+    assert_eq!(fan(&["graph", &counter, "Echo"]).1, "automata Echo\n    Read -> Read\n");
+    assert_eq!(fan(&["graph", &counter, "Missing"]).0, USAGE_ERROR);
+    assert_eq!(fan(&["lint", "--format", "mermaid", &counter]).0, USAGE_ERROR);
+    assert_eq!(fan(&["table", &counter, "Count"]).1, "automata Count\n    Up [n < 3] -> Up<n + 1>\n");
+    let (code, output, _) = fan(&["table", "--format", "csv", &counter]);
+    assert_eq!((code, output.as_str()), (SUCCESS, "automata,state,guard,target,arguments\r\nCount,Up,n < 3,Up,n + 1\r\nEcho,Read,,Read,\r\n"));
+    assert!(fan(&["table", "--format", "markdown", &counter]).1.starts_with("## Count\n\n| State | Guard | Target | Arguments |\n"));
+    let (_, output, _) = fan(&["table", "--format", "json", &counter, "Echo"]);
+    assert_eq!(output, "{\"automata\":[{\"automata\":\"Echo\",\"rows\":[{\"from\":\"Read\",\"guard\":\"\",\"to\":\"Read\",\"args\":[],\"line\":5}]}]}\n");
+    assert_eq!(fan(&["graph", "--format", "csv", &counter]).0, USAGE_ERROR);
+
+    let turnstile = file("turnstile.mmd", "stateDiagram-v2\n    [*] --> Locked\n    Locked --> Unlocked : coin\n");
+    let (code, output, _) = fan(&["import", &turnstile]);
+    assert_eq!(code, SUCCESS);
+    assert!(output.starts_with("automata turnstile {\n    state Locked {\n        if false { # coin\n"), "{}", output);
+    let imported = file("imported.fan", &output);
+    assert_eq!(fan(&["check", &imported]).0, SUCCESS);
+    let sketch = file("sketch.dot", "digraph {\n    a -> \n}\n");
+    let (code, output, errors) = fan(&["import", "--format", "json", &sketch]);
+    assert_eq!((code, errors.as_str()), (FAILURE, ""));
+    assert!(output.contains(r#""message":"expected an id, found `}`","line":3,"column":1"#), "{}", output);
+
+    let (code, output, errors) = fan(&["fmt", "--check", &counter]);
+    assert_eq!((code, output.as_str()), (FAILURE, ""));
+    assert!(errors.starts_with(&format!("--- {}\n+++ {}\n@@ -1,6 +1,14 @@\n automata Count {{\n-  state Up", counter, counter)), "{}", errors);
+    assert!(errors.contains("\n+    state Up<n: int64> {\n+        if n < 3 {\n"));
+    assert!(std::fs::read_to_string(&counter).unwrap().starts_with("automata Count {\n  state Up"));
+    assert_eq!(fan(&["fmt", &counter]).1, format!("formatted {}\n", counter));
+    assert!(std::fs::read_to_string(&counter).unwrap().contains("\n    state Up<n: int64>"));
+    assert_eq!(fan(&["fmt", "--format", "json", &counter]).1, format!("{{\"files\":[{{\"file\":\"{}\",\"changed\":false}}]}}\n", counter));
+    assert_eq!(
+        fan(&["fmt", "--check", "--format", "json", &counter]),
+        (SUCCESS, format!("{{\"files\":[{{\"file\":\"{}\",\"changed\":false,\"diff\":\"\"}}]}}\n", counter), String::new()),
+    );
+
+    assert_eq!(fan(&["lint", &counter]), (SUCCESS, String::new(), String::new()));
+    let null = file("null.fan", "automata A {\n    state S {\n        link self -> Null;\n    }\n}\n");
+    let (code, output, _) = fan(&["lint", &null]);
+    assert_eq!(code, SUCCESS);
+    assert!(output.starts_with("warning: `Null` should be written as `NULL` [null-spelling]\n"), "{}", output);
+    file("fan.json", r#"{"lint": {"null-spelling": "deny"}}"#);
+    let (code, output, _) = fan(&["lint", "--format", "json", &counter, &null]);
+    assert_eq!(code, FAILURE);
+    assert_eq!(output, format!(
+        "{{\"files\":[{{\"file\":\"{}\",\"lints\":[]}},{{\"file\":\"{}\",\"lints\":[{}]}}]}}\n", counter, null,
+        r#"{"rule":"null-spelling","level":"deny","message":"`Null` should be written as `NULL`","line":3,"column":22}"#,
+    ));
+    file("fan.json", r#"{"lint": {"null-spelling": "fatal"}}"#);
+    let (code, output, errors) = fan(&["lint", &null]);
+    assert_eq!((code, output.as_str()), (USAGE_ERROR, ""));
+    assert!(errors.starts_with("error: ") && errors.contains("fan.json") && !errors.contains(USAGE), "{}", errors);
+
+    assert_eq!(fan(&["run", &counter, "Missing"]).0, USAGE_ERROR);
+    assert_eq!(fan(&["run", &counter, "Count", "1 +"]).0, USAGE_ERROR);
+    assert_eq!(fan(&["check", "--format", "yaml", &counter]).0, USAGE_ERROR);
+    assert_eq!(fan(&["frobnicate"]).0, USAGE_ERROR);
+    assert_eq!(fan(&["check", &dir.join("none.fan").to_string_lossy()]).0, USAGE_ERROR);
+    assert_eq!(fan(&[]).0, USAGE_ERROR);
+    let (code, output, errors) = fan(&["run", &counter, "Count", "\"x\""]);
+    assert_eq!((code, output.as_str()), (FAILURE, ""));
+    assert!(errors.starts_with("error: "));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
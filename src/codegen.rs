@@ -0,0 +1,920 @@
+//! Rust code generator: every automata becomes an `enum` with a variant per state
+//! carrying its template arguments, and `start`/`step`/`run` functions implementing the links.
+//!
+//! Uploaded host types are used by name and have to be `Clone + Debug + PartialEq`,
+//! `T()` constructs them with `Default` and `T(args)` with `T::new(args)`.
+
+use crate::checker::{is_self, CheckError, Checker, Type, TypeTable};
+use crate::lexer::Position;
+use crate::parser::{
+    AutomataDef, AutomataKind, Block, DefinitionExp, Expression, ExpressionType, FANType, FunctionDef, Literal,
+    Module, Param, Pattern, ProceduralExp, ReturnableExp, SingleName, StateDef, Statement,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CodegenError {
+    Check(Vec<CheckError>),
+    /// Rust needs the type of the parameter or the Mealy output
+    Untyped { name: String, position: Position },
+    /// The construct has no Rust counterpart yet
+    Unsupported { what: String, position: Position },
+}
+
+impl std::fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CodegenError::Check(errors) => {
+                for (i, e) in errors.iter().enumerate() {
+                    if i > 0 { writeln!(f)?; }
+                    write!(f, "{}", e)?;
+                }
+                Ok(())
+            },
+            CodegenError::Untyped { name, position } => write!(f, "{}: `{}` needs a type to be generated", position, name),
+            CodegenError::Unsupported { what, position } => write!(f, "{}: {} can not be generated", position, what),
+        }
+    }
+}
+
+impl From<Vec<CheckError>> for CodegenError {
+    fn from(errors: Vec<CheckError>) -> Self {
+        CodegenError::Check(errors)
+    }
+}
+
+/// Checks the module and generates the Rust source of its functions and automata
+pub fn to_rust(module: &Module) -> Result<String, CodegenError> {
+    let types = Checker::check_types(module)?;
+    let generator = Generator { module, types };
+    let mut out = String::from("// generated from FAN, do not edit\n");
+    for f in module.functions() {
+        out.push('\n');
+        out.push_str(LINTS);
+        out.push_str(&generator.function(None, f)?);
+    }
+    for a in module.automata() {
+        out.push('\n');
+        out.push_str(&generator.automata(a)?);
+    }
+    Ok(out)
+}
+
+/// Generated code mirrors FAN, not hand written Rust
+const LINTS: &str = "#[allow(unused_mut, unused_variables, unused_parens, unreachable_code, unreachable_patterns, clippy::all)]\n";
+
+const KEYWORDS: [&str; 50] = [
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "false", "fn",
+    "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "self", "Self",
+    "static", "struct", "super", "trait", "true", "type", "unsafe", "use", "where", "while", "yield", "abstract",
+    "become", "box", "do", "final", "macro", "override", "priv", "try", "typeof", "gen",
+];
+
+/// FAN name as the Rust identifier
+fn ident(name: &str) -> String {
+    match name {
+        "self" | "Self" | "crate" | "super" => format!("{}_", name),
+        n if KEYWORDS.contains(&n) => format!("r#{}", n),
+        n => n.to_string(),
+    }
+}
+
+pub fn rust_type(t: &FANType) -> String {
+    match t {
+        FANType::Named(n) => match n.as_str() {
+            "int" | "int64" | "i64" => "i64",
+            "int8" | "i8" => "i8",
+            "int16" | "i16" => "i16",
+            "int32" | "i32" => "i32",
+            "uint8" | "u8" => "u8",
+            "uint16" | "u16" => "u16",
+            "uint32" | "u32" => "u32",
+            "uint64" | "u64" => "u64",
+            "isize" => "isize",
+            "usize" => "usize",
+            "float" | "float64" | "f64" => "f64",
+            "float32" | "f32" => "f32",
+            "bool" => "bool",
+            "char" => "char",
+            "string" | "str" | "String" => "String",
+            n => n,
+        }.to_string(),
+        FANType::Tuple(v) if v.len() == 1 => format!("({},)", rust_type(&v[0])),
+        FANType::Tuple(v) => format!("({})", v.iter().map(rust_type).collect::<Vec<_>>().join(", ")),
+    }
+}
+
+/// Rust type of the inferred FAN type, `None` when unknown
+fn inferred_type(t: &Type) -> Option<String> {
+    Some(match t {
+        Type::Int => "i64".to_string(),
+        Type::Float => "f64".to_string(),
+        Type::Bool => "bool".to_string(),
+        Type::Char => "char".to_string(),
+        Type::String => "String".to_string(),
+        Type::Tuple(v) if v.len() == 1 => format!("({},)", inferred_type(&v[0])?),
+        Type::Tuple(v) => format!("({})", v.iter().map(inferred_type).collect::<Option<Vec<_>>>()?.join(", ")),
+        Type::Named(n) => n.clone(),
+        Type::Null | Type::Unknown => return None,
+    })
+}
+
+fn is_copy(t: &Type) -> bool {
+    match t {
+        Type::Int | Type::Float | Type::Bool | Type::Char => true,
+        Type::Tuple(v) => v.iter().all(is_copy),
+        _ => false,
+    }
+}
+
+fn indent(code: &str) -> String {
+    code.lines().map(|l| if l.is_empty() { String::new() } else { format!("    {}\n", l) }).collect()
+}
+
+fn unsupported<T>(what: &str, position: Position) -> Result<T, CodegenError> {
+    Err(CodegenError::Unsupported { what: what.to_string(), position })
+}
+
+fn param_type(p: &Param) -> Result<&FANType, CodegenError> {
+    p.ty.as_ref().ok_or_else(|| CodegenError::Untyped { name: p.name.clone(), position: p.position })
+}
+
+/// Where the generated body lives
+#[derive(Clone, Copy)]
+struct Body<'m> {
+    automata: Option<&'m AutomataDef>,
+    state: Option<&'m StateDef>,
+    function: Option<&'m FunctionDef>,
+}
+
+impl<'m> Body<'m> {
+    fn output(&self) -> Option<&'m str> {
+        match self.automata.map(|a| &a.kind) {
+            Some(AutomataKind::Mealy(_, Some(o))) if self.state.is_some() => Some(o),
+            _ => None,
+        }
+    }
+
+    fn mealy(&self) -> bool {
+        matches!(self.automata.map(|a| &a.kind), Some(AutomataKind::Mealy(..)))
+    }
+}
+
+struct Generator<'m> {
+    module: &'m Module,
+    types: TypeTable<'m>,
+}
+
+impl<'m> Generator<'m> {
+    fn ty(&self, e: &'m Expression) -> &Type {
+        self.types.get(e)
+    }
+
+    fn function(&self, automata: Option<&'m AutomataDef>, f: &'m FunctionDef) -> Result<String, CodegenError> {
+        let params = f.params.iter()
+            .map(|p| Ok(format!("mut {}: {}", ident(&p.name), rust_type(param_type(p)?))))
+            .collect::<Result<Vec<_>, CodegenError>>()?;
+        let returns = f.returns.as_ref().map(|r| format!(" -> {}", rust_type(r))).unwrap_or_default();
+        let body = Body { automata, state: None, function: Some(f) };
+        let block = match &f.returns {
+            Some(r) => {
+                let last = f.body.block.last().filter(|e| matches!(e.kind, ExpressionType::Returnable(_)));
+                let code = self.statements(body, &f.body, last.is_some())?;
+                match last {
+                    Some(last) => self.coerce_block(code, self.ty(last), r),
+                    None => code,
+                }
+            },
+            None => self.statements(body, &f.body, false)?,
+        };
+        Ok(format!("pub fn {}({}){} {{\n{}}}\n", ident(&f.name), params.join(", "), returns, indent(&block)))
+    }
+
+    /// Coerces the value of the last line of the generated block
+    fn coerce_block(&self, code: String, from: &Type, to: &FANType) -> String {
+        let mut lines: Vec<&str> = code.lines().collect();
+        match lines.pop() {
+            Some(last) if !last.starts_with(' ') && !last.ends_with(';') && !last.ends_with('}') => {
+                let last = coerce(last.to_string(), from, to);
+                lines.push(&last);
+                lines.join("\n") + "\n"
+            },
+            _ => code,
+        }
+    }
+
+    fn automata(&self, a: &'m AutomataDef) -> Result<String, CodegenError> {
+        let Some(initial) = a.initial_state() else {
+            return unsupported("automata without states", a.position);
+        };
+        let name = ident(&a.name);
+        let mut variants = String::new();
+        for s in a.states.iter() {
+            let fields = Checker::template_params(a, &s.params).into_iter()
+                .map(|p| Ok(format!("{}: {}", ident(&p.name), rust_type(param_type(p)?))))
+                .collect::<Result<Vec<_>, CodegenError>>()?;
+            if fields.is_empty() {
+                variants.push_str(&format!("{},\n", ident(&s.name)));
+            } else {
+                variants.push_str(&format!("{} {{ {} }},\n", ident(&s.name), fields.join(", ")));
+            }
+        }
+        let mut items = String::new();
+        let start_params = Checker::template_params(a, &initial.params);
+        let args = start_params.iter()
+            .map(|p| Ok(format!("{}: {}", ident(&p.name), rust_type(param_type(p)?))))
+            .collect::<Result<Vec<_>, CodegenError>>()?;
+        items.push_str(&format!(
+            "/// Initial state with the context arguments\npub fn start({}) -> Self {{\n    {}\n}}\n",
+            args.join(", "),
+            variant(&initial.name, start_params.iter().map(|p| ident(&p.name)).collect()),
+        ));
+        for f in a.functions.iter() {
+            items.push('\n');
+            items.push_str(&self.function(Some(a), f)?);
+        }
+        let mut arms = String::new();
+        for s in a.states.iter() {
+            arms.push_str(&self.state(a, s)?);
+        }
+        match &a.kind {
+            AutomataKind::Moore => {
+                items.push_str(&format!(
+                    "\n/// Makes a single transition, `None` stands for NULL\npub fn step(&self, previous: Option<&Self>, steps: usize) -> Option<Self> {{\n    match self {{\n{}    }}\n}}\n",
+                    indent(&indent(&arms)),
+                ));
+                items.push_str(concat!(
+                    "\n/// Makes transitions until the link to NULL, returns the final state\n",
+                    "pub fn run(self) -> Self {\n",
+                    "    let (mut current, mut previous, mut steps) = (self, None, 0);\n",
+                    "    while let Some(next) = current.step(previous.as_ref(), steps) {\n",
+                    "        previous = Some(std::mem::replace(&mut current, next));\n",
+                    "        steps += 1;\n",
+                    "    }\n",
+                    "    current\n",
+                    "}\n",
+                ));
+            },
+            AutomataKind::Mealy(..) => {
+                let signal = self.signal_type(a)?;
+                let output = self.output_type(a)?;
+                let consumes = a.states.iter()
+                    .filter(|s| a.consumes_signal(s))
+                    .map(|s| format!("Self::{} {{ .. }}", ident(&s.name)))
+                    .collect::<Vec<_>>();
+                let consumes = if consumes.is_empty() { "false".to_string() } else { format!("matches!(self, {})", consumes.join(" | ")) };
+                items.push_str(&format!(
+                    "\n/// Does the state read the input signal\npub fn consumes(&self) -> bool {{\n    {}\n}}\n",
+                    consumes,
+                ));
+                let output_var = match &a.kind {
+                    AutomataKind::Mealy(_, Some(o)) => format!("    let mut {}: Option<{}> = None;\n", ident(o), output),
+                    _ => String::new(),
+                };
+                items.push_str(&format!(
+                    "\n/// Makes a single transition with the signal, `None` for states not reading the input.\n/// Returns the linked state, `None` stands for NULL, and the output.\npub fn step(&self, previous: Option<&Self>, steps: usize, signal: Option<{}>) -> (Option<Self>, Option<{}>) {{\n{}    match self {{\n{}    }}\n}}\n",
+                    signal, output, output_var, indent(&indent(&arms)),
+                ));
+                items.push_str(&format!(concat!(
+                    "\n/// Feeds the input until it ends or the link to NULL, returns the final state and an output per transition,\n",
+                    "/// `None` where the output is not assigned and the interpreter reports `()`\n",
+                    "pub fn run(self, input: impl IntoIterator<Item = {}>) -> (Self, Vec<Option<{}>>) {{\n",
+                    "    let mut input = input.into_iter();\n",
+                    "    let (mut current, mut previous, mut steps, mut outputs) = (self, None, 0, Vec::new());\n",
+                    "    loop {{\n",
+                    "        let signal = if current.consumes() {{\n",
+                    "            match input.next() {{\n",
+                    "                Some(signal) => Some(signal),\n",
+                    "                None => break,\n",
+                    "            }}\n",
+                    "        }} else {{\n",
+                    "            None\n",
+                    "        }};\n",
+                    "        let (next, output) = current.step(previous.as_ref(), steps, signal);\n",
+                    "        outputs.push(output);\n",
+                    "        match next {{\n",
+                    "            Some(next) => previous = Some(std::mem::replace(&mut current, next)),\n",
+                    "            None => break,\n",
+                    "        }}\n",
+                    "        steps += 1;\n",
+                    "    }}\n",
+                    "    (current, outputs)\n",
+                    "}}\n",
+                ), signal, output));
+            },
+        }
+        Ok(format!(
+            "#[derive(Debug, Clone, PartialEq)]\npub enum {} {{\n{}}}\n\n{}impl {} {{\n{}}}\n",
+            name, indent(&variants), LINTS, name, indent(&items),
+        ))
+    }
+
+    /// Type of the signal read by the Mealy states
+    fn signal_type(&self, a: &'m AutomataDef) -> Result<String, CodegenError> {
+        let mut signal = None;
+        for s in a.states.iter().filter(|s| a.consumes_signal(s)) {
+            match a.signal_param(s) {
+                Some(p) => {
+                    let t = rust_type(param_type(p)?);
+                    match &signal {
+                        Some(other) if *other != t => return unsupported("signals of different types", p.position),
+                        _ => signal = Some(t),
+                    }
+                },
+                None => return Err(CodegenError::Untyped {
+                    name: match &a.kind { AutomataKind::Mealy(s, _) => s.clone(), _ => String::new() },
+                    position: s.position,
+                }),
+            }
+        }
+        Ok(signal.unwrap_or("()".to_string()))
+    }
+
+    /// Type of the values assigned to the Mealy output
+    fn output_type(&self, a: &'m AutomataDef) -> Result<String, CodegenError> {
+        let AutomataKind::Mealy(_, Some(output)) = &a.kind else {
+            return Ok("()".to_string());
+        };
+        let mut found = None;
+        for s in a.states.iter() {
+            s.body.walk(&mut |e| {
+                if let ExpressionType::Returnable(ReturnableExp::BinaryOperator(b)) = &e.kind
+                    && b.operator.as_str() == "="
+                    && matches!(&b.arg1.kind, ExpressionType::Returnable(ReturnableExp::Statement(Statement::Name(n))) if n.path() == *output)
+                    && found.is_none()
+                {
+                    found = inferred_type(self.ty(&b.arg2));
+                }
+            });
+        }
+        found.ok_or_else(|| CodegenError::Untyped { name: output.clone(), position: a.position })
+    }
+
+    fn state(&self, a: &'m AutomataDef, s: &'m StateDef) -> Result<String, CodegenError> {
+        let body = Body { automata: Some(a), state: Some(s), function: None };
+        let params = Checker::template_params(a, &s.params);
+        let mut code = String::new();
+        if let AutomataKind::Mealy(signal, _) = &a.kind {
+            match a.signal_param(s) {
+                Some(p) if a.consumes_signal(s) => code.push_str(&format!(
+                    "let mut {}: {} = signal.expect(\"`{}` reads the signal\");\n", ident(signal), rust_type(param_type(p)?), s.name,
+                )),
+                _ => code.push_str(&format!("let mut {} = ();\n", ident(signal))),
+            }
+        }
+        for p in params.iter() {
+            code.push_str(&format!("let mut {} = __self_{}.clone();\n", ident(&p.name), p.name));
+        }
+        code.push_str(&self.statements(body, &s.body, false)?);
+        code.push_str(&self.link_null(body));
+        code.push('\n');
+        let fields = params.iter().map(|p| format!("{}: __self_{}", ident(&p.name), p.name)).collect::<Vec<_>>();
+        let pattern = if fields.is_empty() { format!("Self::{}", ident(&s.name)) } else { format!("Self::{} {{ {} }}", ident(&s.name), fields.join(", ")) };
+        Ok(format!("{} => {{\n{}}},\n", pattern, indent(&code)))
+    }
+
+    fn link_null(&self, body: Body<'m>) -> String {
+        match (body.mealy(), body.output()) {
+            (false, _) => "None".to_string(),
+            (true, Some(o)) => format!("(None, {})", ident(o)),
+            (true, None) => "(None, None)".to_string(),
+        }
+    }
+
+    /// Lines of the block, the last one is the value when `value` is set
+    fn statements(&self, body: Body<'m>, block: &'m Block, value: bool) -> Result<String, CodegenError> {
+        let mut code = String::new();
+        for (i, e) in block.block.iter().enumerate() {
+            let last = i + 1 == block.block.len();
+            match &e.kind {
+                ExpressionType::Definition(DefinitionExp::Define(d)) => {
+                    let v = self.expression(body, &d.value)?;
+                    match &d.ty {
+                        Some(t) => code.push_str(&format!("let mut {}: {} = {};\n", ident(&d.name), rust_type(t), coerce(v, self.ty(&d.value), t))),
+                        None => code.push_str(&format!("let mut {} = {};\n", ident(&d.name), v)),
+                    }
+                    if last && value {
+                        code.push_str("()\n");
+                    }
+                },
+                ExpressionType::Import(_) | ExpressionType::Definition(_) => {},
+                _ if last && value => {
+                    code.push_str(&self.expression(body, e)?);
+                    code.push('\n');
+                },
+                _ => {
+                    code.push_str(&self.statement(body, e)?);
+                    code.push('\n');
+                },
+            }
+        }
+        if block.block.is_empty() && value {
+            code.push_str("()\n");
+        }
+        Ok(code)
+    }
+
+    fn block(&self, body: Body<'m>, block: &'m Block, value: bool) -> Result<String, CodegenError> {
+        Ok(format!("{{\n{}}}", indent(&self.statements(body, block, value)?)))
+    }
+
+    /// Expression whose value is dropped
+    fn statement(&self, body: Body<'m>, e: &'m Expression) -> Result<String, CodegenError> {
+        match &e.kind {
+            ExpressionType::Procedural(ProceduralExp::For(f)) => {
+                let iterable = self.expression(body, &f.iterable)?;
+                let iterable = match self.ty(&f.iterable) {
+                    Type::Int => format!("0..{}", iterable),
+                    Type::String => format!("{}.chars()", iterable),
+                    _ => return unsupported("`for` over a tuple", f.iterable.position),
+                };
+                Ok(format!("for mut {} in {} {}", ident(&f.variable), iterable, self.block(body, &f.body, false)?))
+            },
+            ExpressionType::Procedural(ProceduralExp::While(w)) => {
+                Ok(format!("while {} {}", self.expression(body, &w.condition)?, self.block(body, &w.body, false)?))
+            },
+            ExpressionType::Returnable(ReturnableExp::If(i)) => self.if_expression(body, i, false),
+            ExpressionType::Returnable(ReturnableExp::Match(m)) => self.match_expression(body, m, false, e.position),
+            ExpressionType::Returnable(ReturnableExp::Statement(Statement::Block(b))) => self.block(body, b, false),
+            _ => Ok(format!("{};", self.expression(body, e)?)),
+        }
+    }
+
+    fn expression(&self, body: Body<'m>, e: &'m Expression) -> Result<String, CodegenError> {
+        match &e.kind {
+            ExpressionType::Procedural(p) => self.procedural(body, p, e.position),
+            ExpressionType::Returnable(r) => self.returnable(body, r, e),
+            ExpressionType::Import(_) | ExpressionType::Definition(_) => Ok("()".to_string()),
+        }
+    }
+
+    fn procedural(&self, body: Body<'m>, p: &'m ProceduralExp, position: Position) -> Result<String, CodegenError> {
+        match p {
+            ProceduralExp::For(_) | ProceduralExp::While(_) => unsupported("loop as a value", position),
+            ProceduralExp::Link(l) => {
+                let (Some(a), Some(_)) = (body.automata, body.state) else {
+                    return unsupported("`link` outside of state", position);
+                };
+                let Some(SingleName(target, template)) = &l.target else {
+                    return Ok(format!("return {}", self.link_null(body)));
+                };
+                let Some(s) = a.state(target) else {
+                    return unsupported("link to the undefined state", position);
+                };
+                let args = template.iter().flat_map(|t| t.args.iter()).collect::<Vec<_>>();
+                let params = Checker::template_params(a, &s.params);
+                let fields = params.iter().zip(args)
+                    .map(|(p, v)| Ok(format!("{}: {}", ident(&p.name), coerce(self.expression(body, v)?, self.ty(v), param_type(p)?))))
+                    .collect::<Result<Vec<_>, CodegenError>>()?;
+                let next = if fields.is_empty() {
+                    format!("Self::{}", ident(&s.name))
+                } else {
+                    format!("Self::{} {{ {} }}", ident(&s.name), fields.join(", "))
+                };
+                Ok(match (body.mealy(), body.output()) {
+                    (false, _) => format!("return Some({})", next),
+                    (true, Some(o)) => format!("return (Some({}), {})", next, ident(o)),
+                    (true, None) => format!("return (Some({}), None)", next),
+                })
+            },
+            ProceduralExp::Return(v) => {
+                let returns = body.function.and_then(|f| f.returns.as_ref());
+                match (v, returns) {
+                    (Some(v), Some(r)) => Ok(format!("return {}", coerce(self.expression(body, v)?, self.ty(v), r))),
+                    (Some(v), None) => Ok(format!("return {}", self.expression(body, v)?)),
+                    (None, _) => Ok("return".to_string()),
+                }
+            },
+        }
+    }
+
+    /// `local`, `local.0` and `local[1]` chains without cloning the whole local
+    fn place(&self, body: Body<'m>, e: &'m Expression) -> Option<String> {
+        match &e.kind {
+            ExpressionType::Returnable(ReturnableExp::Statement(Statement::Name(n))) => {
+                let name = n.path();
+                (name != "self" && Some(name.as_str()) != body.output()).then(|| ident(&name))
+            },
+            ExpressionType::Returnable(ReturnableExp::Member(v, m)) if m.parse::<usize>().is_ok() => {
+                matches!(self.ty(v), Type::Tuple(_)).then_some(())?;
+                Some(format!("{}.{}", self.place(body, v)?, m))
+            },
+            ExpressionType::Returnable(ReturnableExp::Index(v, i)) => {
+                let ExpressionType::Returnable(ReturnableExp::Statement(Statement::Literal(Literal::Digital(d)))) = &i.kind else {
+                    return None;
+                };
+                matches!(self.ty(v), Type::Tuple(_)).then_some(())?;
+                Some(format!("{}.{}", self.place(body, v)?, d))
+            },
+            _ => None,
+        }
+    }
+
+    fn returnable(&self, body: Body<'m>, r: &'m ReturnableExp, e: &'m Expression) -> Result<String, CodegenError> {
+        let position = e.position;
+        if let Some(place) = self.place(body, e) {
+            return Ok(if is_copy(self.ty(e)) { place } else { format!("{}.clone()", place) });
+        }
+        match r {
+            ReturnableExp::Statement(s) => match s {
+                Statement::Literal(l) => literal(l, position),
+                Statement::Block(b) => self.block(body, b, true),
+                Statement::Name(n) => match n.path().as_str() {
+                    "self" if body.state.is_some() => Ok("self.clone()".to_string()),
+                    name if Some(name) == body.output() => Ok(format!("{}.clone().expect(\"output is assigned\")", ident(name))),
+                    _ => unsupported("path", position),
+                },
+                Statement::Tuple(t) => {
+                    let items = t.tuple.iter().map(|x| self.expression(body, x)).collect::<Result<Vec<_>, _>>()?;
+                    Ok(match items.len() {
+                        1 => format!("({},)", items[0]),
+                        _ => format!("({})", items.join(", ")),
+                    })
+                },
+            },
+            ReturnableExp::FunctionCall(callee, args) => {
+                let Statement::Name(n) = callee else {
+                    return unsupported("call of an expression", position);
+                };
+                let name = n.path();
+                let f = body.automata.and_then(|a| a.function(&name).map(|f| (f, true)))
+                    .or_else(|| self.module.find_function(&name).map(|f| (f, false)));
+                let Some((f, associated)) = f else {
+                    let args = args.tuple.iter().map(|x| self.expression(body, x)).collect::<Result<Vec<_>, _>>()?;
+                    let uploaded = self.module.imports().any(|i| i.names.contains(&name));
+                    return match (uploaded, args.is_empty()) {
+                        (true, true) => Ok(format!("<{}>::default()", ident(&name))),
+                        (true, false) => Ok(format!("{}::new({})", ident(&name), args.join(", "))),
+                        (false, _) => unsupported("call of the undefined function", position),
+                    };
+                };
+                let args = f.params.iter().zip(args.tuple.iter())
+                    .map(|(p, x)| Ok(coerce(self.expression(body, x)?, self.ty(x), param_type(p)?)))
+                    .collect::<Result<Vec<_>, CodegenError>>()?;
+                let prefix = if associated { "Self::" } else { "" };
+                Ok(format!("{}{}({})", prefix, ident(&f.name), args.join(", ")))
+            },
+            ReturnableExp::AutomataCall(SingleName(name, template)) => {
+                let Some(a) = self.module.find_automata(name) else {
+                    return unsupported("run of the undefined automata", position);
+                };
+                if a.kind != AutomataKind::Moore {
+                    return unsupported("run of the Mealy automata", position);
+                }
+                let initial = a.initial_state().map(|s| Checker::template_params(a, &s.params)).unwrap_or_default();
+                let args = initial.iter().zip(template.iter().flat_map(|t| t.args.iter()))
+                    .map(|(p, x)| Ok(coerce(self.expression(body, x)?, self.ty(x), param_type(p)?)))
+                    .collect::<Result<Vec<_>, CodegenError>>()?;
+                Ok(format!("{}::start({}).run()", ident(name), args.join(", ")))
+            },
+            ReturnableExp::BinaryOperator(b) => self.binary(body, b, position),
+            ReturnableExp::UnaryyOperator(u) => {
+                let arg = self.expression(body, &u.arg)?;
+                match u.operator.as_str() {
+                    "-" => Ok(format!("-({})", arg)),
+                    "!" | "~" => Ok(format!("!({})", arg)),
+                    op => unsupported(&format!("operator `{}`", op), position),
+                }
+            },
+            ReturnableExp::Member(value, member) => self.member(body, value, member, e),
+            ReturnableExp::MethodCall(value, method, args) => {
+                if method != "is_me" || !args.tuple.is_empty() {
+                    return unsupported(&format!("method `{}`", method), position);
+                }
+                if let ExpressionType::Returnable(ReturnableExp::Member(base, m)) = &value.kind
+                    && is_self(base) && m == "previous"
+                {
+                    return Ok("previous.is_some_and(|p| std::mem::discriminant(p) == std::mem::discriminant(self))".to_string());
+                }
+                Ok(format!("std::mem::discriminant(&{}) == std::mem::discriminant(self)", self.expression(body, value)?))
+            },
+            ReturnableExp::Index(value, index) => {
+                let v = self.expression(body, value)?;
+                let i = self.expression(body, index)?;
+                match self.ty(value) {
+                    Type::String => Ok(format!("{}.chars().nth(({}) as usize).expect(\"index is in bounds\")", v, i)),
+                    Type::Tuple(_) => match &index.kind {
+                        ExpressionType::Returnable(ReturnableExp::Statement(Statement::Literal(Literal::Digital(d)))) => Ok(format!("{}.{}", v, d)),
+                        _ => unsupported("tuple index which is not a literal", position),
+                    },
+                    _ => unsupported("index of the untyped value", position),
+                }
+            },
+            ReturnableExp::If(i) => self.if_expression(body, i, true),
+            ReturnableExp::Match(m) => self.match_expression(body, m, true, position),
+            ReturnableExp::Is(value, pattern) => {
+                let mut names = vec![];
+                bindings(pattern, &mut names);
+                if !names.is_empty() {
+                    return unsupported("`is` with bindings outside of `if` condition", position);
+                }
+                Ok(format!("matches!({}, {})", self.expression(body, value)?, self.pattern(body, pattern, self.ty(value), position)?))
+            },
+        }
+    }
+
+    fn member(&self, body: Body<'m>, value: &'m Expression, member: &str, e: &'m Expression) -> Result<String, CodegenError> {
+        let position = e.position;
+        if is_self(value) && let (Some(a), Some(s)) = (body.automata, body.state) {
+            return match member {
+                "steps" => Ok("(steps as i64)".to_string()),
+                "automata" => Ok(format!("String::from({:?})", a.name)),
+                "previous" => unsupported("`self.previous` other than `self.previous.is_me()`", position),
+                _ => {
+                    let params = Checker::template_params(a, &s.params);
+                    let param = match member.parse::<usize>() {
+                        Ok(i) => params.get(i).copied(),
+                        Err(_) => params.iter().find(|p| p.name == member).copied(),
+                    };
+                    match param {
+                        Some(p) => Ok(format!("__self_{}.clone()", p.name)),
+                        None => unsupported(&format!("member `{}`", member), position),
+                    }
+                },
+            };
+        }
+        let v = self.expression(body, value)?;
+        match self.ty(value) {
+            Type::Tuple(_) => Ok(format!("{}.{}", v, member)),
+            Type::Named(n) => {
+                let Some(a) = self.module.find_automata(n) else {
+                    return unsupported("member of the host value", position);
+                };
+                // the state is known at runtime only
+                let mut arms = vec![];
+                for s in a.states.iter() {
+                    let params = Checker::template_params(a, &s.params);
+                    let param = match member.parse::<usize>() {
+                        Ok(i) => params.get(i).copied(),
+                        Err(_) => params.iter().find(|p| p.name == member).copied(),
+                    };
+                    if let Some(p) = param {
+                        arms.push(format!("{}::{} {{ {}: v, .. }} => v.clone(),", ident(&a.name), ident(&s.name), ident(&p.name)));
+                    }
+                }
+                arms.push(format!("_ => panic!(\"no member `{}`\"),", member));
+                Ok(format!("match &{} {{\n{}}}", v, indent(&arms.join("\n"))))
+            },
+            _ => unsupported("member of the untyped value", position),
+        }
+    }
+
+    fn binary(&self, body: Body<'m>, b: &'m crate::parser::BinaryOperator, position: Position) -> Result<String, CodegenError> {
+        let op = b.operator.as_str();
+        let (lt, rt) = (self.ty(&b.arg1), self.ty(&b.arg2));
+        if matches!(op, "=" | "+=" | "-=" | "*=" | "/=") {
+            if let ExpressionType::Returnable(ReturnableExp::Statement(Statement::Name(n))) = &b.arg1.kind
+                && Some(n.path().as_str()) == body.output()
+            {
+                if op != "=" {
+                    return unsupported("compound assignment of the output", position);
+                }
+                return Ok(format!("{} = Some({})", ident(&n.path()), self.expression(body, &b.arg2)?));
+            }
+            let Some(target) = self.place(body, &b.arg1) else {
+                return unsupported("assignment target", position);
+            };
+            let value = self.expression(body, &b.arg2)?;
+            return Ok(match (op, lt) {
+                ("+=", Type::String) => format!("{} = format!(\"{{}}{{}}\", {}, {})", target, target, value),
+                (_, Type::Float) if *rt == Type::Int => format!("{} {} ({}) as f64", target, op, value),
+                _ => format!("{} {} {}", target, op, value),
+            });
+        }
+        let left = self.expression(body, &b.arg1)?;
+        let right = self.expression(body, &b.arg2)?;
+        let (left, right) = match (lt, rt) {
+            (Type::Int, Type::Float) => (format!("({}) as f64", left), right),
+            (Type::Float, Type::Int) => (left, format!("({}) as f64", right)),
+            _ => (left, right),
+        };
+        match (op, lt, rt) {
+            ("-", Type::Char, Type::Char) => Ok(format!("(({}) as i64 - ({}) as i64)", left, right)),
+            ("+", Type::String, Type::String | Type::Char) => Ok(format!("format!(\"{{}}{{}}\", {}, {})", left, right)),
+            ("+" | "-" | "*" | "/" | "%" | "&" | "|" | "^" | "&&" | "||" | "==" | "!=" | "<" | ">" | "<=" | ">=", _, _) => {
+                Ok(format!("({} {} {})", left, op, right))
+            },
+            _ => unsupported(&format!("operator `{}`", op), position),
+        }
+    }
+
+    fn if_expression(&self, body: Body<'m>, i: &'m crate::parser::IfExp, value: bool) -> Result<String, CodegenError> {
+        let value = value && i.otherwise.is_some();
+        let condition = match &i.condition.kind {
+            ExpressionType::Returnable(ReturnableExp::Is(v, pattern)) => format!(
+                "let {} = {}",
+                self.pattern(body, pattern, self.ty(v), i.condition.position)?,
+                self.expression(body, v)?,
+            ),
+            _ => self.expression(body, &i.condition)?,
+        };
+        let mut code = format!("if {} {}", condition, self.block(body, &i.then, value)?);
+        if let Some(o) = &i.otherwise {
+            code.push_str(&format!(" else {}", self.block(body, o, value)?));
+        }
+        Ok(code)
+    }
+
+    fn match_expression(&self, body: Body<'m>, m: &'m crate::parser::MatchExp, value: bool, position: Position) -> Result<String, CodegenError> {
+        let t = self.ty(&m.value);
+        let mut arms = String::new();
+        for arm in m.arms.iter() {
+            let pattern = self.pattern(body, &arm.pattern, t, arm.position)?;
+            let code = if value { self.expression(body, &arm.body)? } else { self.statement(body, &arm.body)? };
+            arms.push_str(&format!("{} => {{\n{}}},\n", pattern, indent(&code)));
+        }
+        arms.push_str(&format!("_ => panic!(\"no pattern matches at {}\"),\n", position));
+        Ok(format!("match {} {{\n{}}}", self.expression(body, &m.value)?, indent(&arms)))
+    }
+
+    fn pattern(&self, body: Body<'m>, pattern: &'m Pattern, t: &Type, position: Position) -> Result<String, CodegenError> {
+        match pattern {
+            Pattern::Wildcard => Ok("_".to_string()),
+            Pattern::Binding(name) => Ok(format!("mut {}", ident(name))),
+            Pattern::Literal(Literal::String(_) | Literal::NumericalLexem(_) | Literal::NULL) => unsupported("literal pattern", position),
+            Pattern::Literal(l) => literal(l, position),
+            Pattern::Tuple(patterns) => {
+                let types = match t {
+                    Type::Tuple(v) if v.len() == patterns.len() => v.clone(),
+                    _ => vec![Type::Unknown; patterns.len()],
+                };
+                let items = patterns.iter().zip(types.iter())
+                    .map(|(p, t)| self.pattern(body, p, t, position))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(format!("({},)", items.join(", ")))
+            },
+            Pattern::State { automata, state, args } => {
+                let a = match (automata, t) {
+                    (Some(name), _) => self.module.find_automata(name),
+                    (None, Type::Named(name)) => self.module.find_automata(name),
+                    (None, _) => body.automata.filter(|a| a.state(state).is_some())
+                        .or_else(|| self.module.automata().find(|a| a.state(state).is_some())),
+                };
+                let Some((a, s)) = a.and_then(|a| a.state(state).map(|s| (a, s))) else {
+                    return unsupported("pattern of the undefined state", position);
+                };
+                let path = format!("{}::{}", ident(&a.name), ident(&s.name));
+                let params = Checker::template_params(a, &s.params);
+                match args {
+                    None => Ok(format!("{} {{ .. }}", path)),
+                    Some(patterns) if patterns.len() == params.len() => {
+                        let fields = params.iter().zip(patterns)
+                            .map(|(p, x)| Ok(format!("{}: {}", ident(&p.name), self.pattern(body, x, &Type::from_annotation(&p.ty), position)?)))
+                            .collect::<Result<Vec<_>, CodegenError>>()?;
+                        Ok(format!("{} {{ {} }}", path, fields.join(", ")))
+                    },
+                    Some(patterns) => match patterns.as_slice() {
+                        [Pattern::Binding(name)] => Ok(format!("mut {} @ {} {{ .. }}", ident(name), path)),
+                        [Pattern::Wildcard] => Ok(format!("{} {{ .. }}", path)),
+                        _ => unsupported("pattern with the wrong number of arguments", position),
+                    },
+                }
+            },
+        }
+    }
+}
+
+fn variant(state: &str, fields: Vec<String>) -> String {
+    if fields.is_empty() { format!("Self::{}", ident(state)) } else { format!("Self::{} {{ {} }}", ident(state), fields.join(", ")) }
+}
+
+/// Widens the int value to the declared float type
+fn coerce(code: String, from: &Type, to: &FANType) -> String {
+    match (from, Type::from_fan(to)) {
+        (Type::Int, Type::Float) => format!("({}) as {}", code, rust_type(to)),
+        _ => code,
+    }
+}
+
+fn literal(l: &Literal, position: Position) -> Result<String, CodegenError> {
+    const SUFFIXES: [(&str, &str); 4] = [("uint64", "u64"), ("int64", "i64"), ("float64", "f64"), ("float32", "f32")];
+    match l {
+        Literal::Char(c) => Ok(format!("{:?}", c)),
+        Literal::String(s) => Ok(format!("String::from({:?})", s)),
+        Literal::Digital(d) => Ok(d.clone()),
+        Literal::NumericalLexem(n) => {
+            let n = SUFFIXES.iter().find(|(fan, _)| n.ends_with(fan))
+                .map(|(fan, rust)| format!("{}{}", &n[..n.len() - fan.len()], rust))
+                .unwrap_or(n.clone());
+            // `1.f32` is not a Rust literal
+            Ok(n.replace(".f", ".0f").replace(".i", ".0i").replace(".u", ".0u"))
+        },
+        Literal::Bool(b) => Ok(b.to_string()),
+        Literal::NULL => unsupported("NULL value", position),
+    }
+}
+
+/// Names bound by the pattern
+fn bindings<'p>(pattern: &'p Pattern, names: &mut Vec<&'p str>) {
+    match pattern {
+        Pattern::Binding(name) => names.push(name),
+        Pattern::Tuple(patterns) | Pattern::State { args: Some(patterns), .. } => {
+            patterns.iter().for_each(|p| bindings(p, names));
+        },
+        Pattern::Wildcard | Pattern::Literal(_) | Pattern::State { args: None, .. } => {},
+    }
+}
+
+#[test]
+fn codegen_test() {
+    use crate::parser::Parser;
+    let module = Parser::parse_str("
+        fn fib(n: int64) -> int64 {
+            if n < 2 { return n; }
+            fib(n - 1) + fib(n - 2)
+        }
+        fn classify(v: (int64, char)) -> string {
+            match v {
+                (0, _) => \"zero\",
+                (n, 'a') => \"a\",
+                _ => \"other\",
+            }
+        }
+        fn sum(s: string) -> int64 {
+            let total = 0;
+            for c in s { total += c - '0'; }
+            let i = 0;
+            while i < 3 && total > 0 { i += 1; }
+            total * 10 + i
+        }
+        automata ContextAutomata {
+            state Base<context1: int64, context2: int64> {
+                if context1 > context2 {
+                    link self -> Verdict1<context1, context2>;
+                } else {
+                    link self -> Verdict2<context2>;
+                }
+            }
+            state Verdict1<arg1: int64, arg2: int64> { link self -> NULL; }
+            state Verdict2<arg1: int64> { }
+        }
+        automata MyAutomata {
+            state Base<context: (int64, int64, int64)> {
+                if self.previous.is_me() {
+                    context[0] += 1;
+                }
+                if context[0] == 10 {
+                    link self -> Verdict<context[1], context[2], self.steps>;
+                } else {
+                    link self -> Base<context>;
+                }
+            }
+            state Verdict<a: int64, b: int64, steps: int64> {
+                let r = run ContextAutomata<a, b>;
+                if r is ContextAutomata::Verdict1(verd1) {
+                    link self -> Done<verd1.arg1 - verd1.arg2, self.automata>;
+                } else {
+                    link self -> Done<0, self.automata>;
+                }
+            }
+            state Done<diff: int64, name: string> { }
+        }
+        automata Mathematica: Mealy<signal, out> {
+            state Number<signal: char, acc: int64> {
+                if signal == '=' {
+                    link self -> Result<acc>;
+                } else {
+                    out = acc * 10 + (signal - '0');
+                    link self -> Number<out>;
+                }
+            }
+            state Result<signal: (), acc: int64> {
+                out = acc;
+            }
+        }
+    ").unwrap();
+    let mut source = to_rust(&module).unwrap();
+    source.push_str(r#"
+fn main() {
+    println!("{}", fib(15));
+    println!("{} {} {}", classify((0, 'a')), classify((3, 'a')), classify((3, 'b')));
+    println!("{} {}", sum(String::from("123")), sum(String::new()));
+    println!("{:?}", MyAutomata::start((0, 7, 2)).run());
+    println!("{:?}", MyAutomata::start((0, 1, 2)).run());
+    println!("{:?}", Mathematica::start(0).run("12=".chars()));
+}
+"#);
+    let dir = std::env::temp_dir().join(format!("fan-codegen-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("main.rs"), &source).unwrap();
+    let rustc = std::env::var("RUSTC").unwrap_or("rustc".to_string());
+    let compiled = std::process::Command::new(rustc)
+        .args(["--edition", "2021", "-o"]).arg(dir.join("main")).arg(dir.join("main.rs"))
+        .output().unwrap();
+    assert!(compiled.status.success(), "{}\n{}", source, String::from_utf8_lossy(&compiled.stderr));
+    let output = std::process::Command::new(dir.join("main")).output().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), concat!(
+        "610\n",
+        "zero a other\n",
+        "63 0\n",
+        "Done { diff: 5, name: \"MyAutomata\" }\n",
+        "Done { diff: 0, name: \"MyAutomata\" }\n",
+        // an output per transition as in the interpreter, the link to `Result` assigns none
+        "(Result { acc: 12 }, [Some(1), Some(12), None, Some(12)])\n",
+    ));
+
+    let untyped = Parser::parse_str("automata A { state S<n> { } }").unwrap();
+    assert!(matches!(to_rust(&untyped), Err(CodegenError::Untyped { name, .. }) if name == "n"));
+    let null = Parser::parse_str("fn f() { let x = NULL; }").unwrap();
+    assert!(matches!(to_rust(&null), Err(CodegenError::Unsupported { .. })));
+}
@@ -0,0 +1,495 @@
+//! Debug Adapter Protocol over stdio for `fan dap`: the editor sets the breakpoints by source lines,
+//! the running automata are the stack frames and their template arguments and locals the variables.
+//! The program runs on the single thread `1`.
+
+use std::io::{BufRead, Write};
+
+use crate::debugger::{Breakpoint, Breakpoints, Command, DebugFrontend, Debugger, Stop, StopReason};
+use crate::interpreter::{parse_value, Interpreter, Value};
+use crate::json::Json;
+use crate::lexer::Position;
+use crate::parser::{AutomataKind, Block, Expression, ExpressionType, Module, Parser, ProceduralExp, ReturnableExp, Statement};
+use crate::rpc::{read_message, write_message, RpcError};
+
+const THREAD: i64 = 1;
+
+/// Breakpoint requested by the editor, lines are one based
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceBreakpoint {
+    pub line: usize,
+    pub condition: Option<String>,
+}
+
+/// Breakpoint of the debugger for the source line with the line it is shown on.
+/// The declaration of the state breaks on its entry, the `link` statements on their edge
+/// and the other statements of the state body before they are evaluated.
+pub fn resolve_line(module: &Module, breakpoint: &SourceBreakpoint) -> Result<(Breakpoint, usize), String> {
+    let line = breakpoint.line.checked_sub(1).ok_or_else(|| "no state at the line".to_string())?;
+    for a in module.automata() {
+        for s in a.states.iter() {
+            let mut last = s.position.line;
+            let mut link = None;
+            let mut statement = s.body.block.iter().any(|e| e.position.line == line);
+            s.body.walk(&mut |e| {
+                last = last.max(e.position.line);
+                if let ExpressionType::Procedural(ProceduralExp::Link(l)) = &e.kind
+                    && e.position.line == line
+                    && link.is_none()
+                {
+                    link = Some(l.target.as_ref().map_or("NULL".to_string(), |t| t.0.clone()));
+                }
+                statement |= nested_blocks(e).iter().flat_map(|b| b.block.iter()).any(|e| e.position.line == line);
+            });
+            if !(s.position.line..=last).contains(&line) {
+                continue;
+            }
+            let automata = Some(a.name.clone());
+            let condition = breakpoint.condition.clone();
+            return match link {
+                _ if line == s.position.line =>
+                    Ok((Breakpoint::State { automata, state: s.name.clone(), condition }, breakpoint.line)),
+                Some(to) if condition.is_none() =>
+                    Ok((Breakpoint::Link { automata, from: Some(s.name.clone()), to: Some(to) }, breakpoint.line)),
+                _ if statement => Ok((Breakpoint::Line { automata, state: s.name.clone(), line, condition }, breakpoint.line)),
+                _ => Err("no statement at the line".to_string()),
+            };
+        }
+    }
+    Err("no state at the line".to_string())
+}
+
+/// Blocks of the expression whose statements the debugger stops on
+fn nested_blocks(e: &Expression) -> Vec<&Block> {
+    match &e.kind {
+        ExpressionType::Procedural(ProceduralExp::For(f)) => vec![&f.body],
+        ExpressionType::Procedural(ProceduralExp::While(w)) => vec![&w.body],
+        ExpressionType::Returnable(ReturnableExp::Statement(Statement::Block(b))) => vec![b],
+        ExpressionType::Returnable(ReturnableExp::If(i)) => std::iter::once(&i.then).chain(i.otherwise.as_ref()).collect(),
+        _ => vec![],
+    }
+}
+
+struct Connection<R, W> {
+    input: R,
+    output: W,
+    seq: i64,
+    /// Client disconnected, the execution is aborted
+    disconnected: bool,
+}
+
+impl<R: BufRead, W: Write> Connection<R, W> {
+    fn send(&mut self, mut fields: Vec<(String, Json)>) -> Result<(), RpcError> {
+        self.seq += 1;
+        fields.insert(0, ("seq".to_string(), self.seq.into()));
+        write_message(&mut self.output, &Json::Object(fields))?;
+        Ok(())
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> Result<(), RpcError> {
+        self.send(vec![
+            ("type".to_string(), "event".into()), ("event".to_string(), event.into()), ("body".to_string(), body),
+        ])
+    }
+
+    fn respond(&mut self, request: &Json, result: Result<Json, String>) -> Result<(), RpcError> {
+        let mut fields = vec![
+            ("type".to_string(), "response".into()),
+            ("request_seq".to_string(), request.get("seq").cloned().unwrap_or(Json::Null)),
+            ("success".to_string(), result.is_ok().into()),
+            ("command".to_string(), request.get("command").cloned().unwrap_or(Json::Null)),
+        ];
+        match result {
+            Ok(body) => fields.push(("body".to_string(), body)),
+            Err(message) => fields.push(("message".to_string(), message.into())),
+        }
+        self.send(fields)
+    }
+
+    fn output(&mut self, category: &str, text: String) -> Result<(), RpcError> {
+        self.event("output", Json::object([("category", category.into()), ("output", text.into())]))
+    }
+}
+
+fn command(request: &Json) -> &str {
+    request.get("command").and_then(Json::as_str).unwrap_or_default()
+}
+
+fn arguments(request: &Json) -> &Json {
+    request.get("arguments").unwrap_or(&Json::Null)
+}
+
+fn source_breakpoints(request: &Json) -> (String, Vec<SourceBreakpoint>) {
+    let arguments = arguments(request);
+    let path = arguments.get("source").and_then(|s| s.get("path")).and_then(Json::as_str).unwrap_or_default();
+    let lines = arguments.get("breakpoints").and_then(Json::as_array).unwrap_or_default().iter()
+        .filter_map(|b| Some(SourceBreakpoint {
+            line: b.get("line")?.as_i64()? as usize,
+            condition: b.get("condition").and_then(Json::as_str).filter(|c| !c.trim().is_empty()).map(str::to_string),
+        }))
+        .collect();
+    (path.to_string(), lines)
+}
+
+/// Adds the breakpoints of the program source, replacing the previous ones
+fn set_breakpoints(breakpoints: &mut Breakpoints<'_>, module: &Module, lines: &[SourceBreakpoint]) -> Json {
+    let ids: Vec<usize> = breakpoints.iter().map(|(id, _)| id).collect();
+    for id in ids {
+        breakpoints.remove(id);
+    }
+    Json::Array(lines.iter().map(|b| {
+        let added = resolve_line(module, b)
+            .and_then(|(breakpoint, line)| Ok((breakpoints.add(&breakpoint).map_err(|e| e.to_string())?, line)));
+        match added {
+            Ok((id, line)) => Json::object([("id", id.into()), ("verified", true.into()), ("line", line.into())]),
+            Err(message) => Json::object([("verified", false.into()), ("line", b.line.into()), ("message", message.into())]),
+        }
+    }).collect())
+}
+
+/// Frontend of the debugger answering the requests of the client while the execution is stopped
+struct Session<'c, 'm, R, W> {
+    connection: &'c mut Connection<R, W>,
+    module: &'m Module,
+    path: String,
+    entry: bool,
+}
+
+impl<R: BufRead, W: Write> Session<'_, '_, R, W> {
+    fn frame_position(&self, stop: &Stop<'_>, index: usize) -> Position {
+        if index + 1 == stop.frames.len() {
+            return stop.position;
+        }
+        let current = &stop.frames[index].current;
+        self.module.find_automata(&current.automata).and_then(|a| a.state(&current.state))
+            .map_or(Position::default(), |s| s.position)
+    }
+
+    fn stack_trace(&self, stop: &Stop<'_>) -> Json {
+        let frames: Vec<Json> = (0..stop.frames.len()).rev().map(|i| {
+            let position = self.frame_position(stop, i);
+            Json::object([
+                ("id", i.into()),
+                ("name", stop.frames[i].current.to_string().into()),
+                ("source", Json::object([("path", self.path.as_str().into())])),
+                ("line", (position.line + 1).into()),
+                ("column", (position.col + 1).into()),
+            ])
+        }).collect();
+        Json::object([("totalFrames", frames.len().into()), ("stackFrames", Json::Array(frames))])
+    }
+
+    /// Template arguments of every frame and the locals of the innermost one
+    fn scopes(&self, stop: &Stop<'_>, frame: usize) -> Json {
+        let scope = |name: &str, reference: usize| Json::object([
+            ("name", name.into()), ("variablesReference", reference.into()), ("expensive", false.into()),
+        ]);
+        let mut scopes = vec![scope("Arguments", 2 * frame + 1)];
+        if frame + 1 == stop.frames.len() {
+            scopes.push(scope("Locals", 2 * frame + 2));
+        }
+        Json::object([("scopes", Json::Array(scopes))])
+    }
+
+    fn variables(&self, stop: &Stop<'_>, reference: usize) -> Json {
+        let frame = (reference.max(1) - 1) / 2;
+        let values: &[(String, Value)] = match stop.frames.get(frame) {
+            Some(f) if reference % 2 == 1 => &f.current.args,
+            Some(_) if frame + 1 == stop.frames.len() => &stop.locals,
+            _ => &[],
+        };
+        let variables = values.iter().map(|(name, v)| Json::object([
+            ("name", name.as_str().into()),
+            ("value", v.to_string().into()),
+            ("type", v.type_name().into()),
+            ("variablesReference", 0i64.into()),
+        ])).collect();
+        Json::object([("variables", Json::Array(variables))])
+    }
+
+    fn serve(&mut self, stop: &Stop<'_>, breakpoints: &mut Breakpoints<'_>) -> Result<Command, RpcError> {
+        let (reason, hit) = match &stop.reason {
+            _ if std::mem::take(&mut self.entry) => ("entry", vec![]),
+            StopReason::Step | StopReason::Statement => ("step", vec![]),
+            StopReason::Breakpoint(id) => ("breakpoint", vec![*id]),
+            StopReason::ConditionError(id, e) => {
+                self.connection.output("stderr", format!("breakpoint {} condition failed: {}\n", id, e))?;
+                ("breakpoint", vec![*id])
+            },
+        };
+        self.connection.event("stopped", Json::object([
+            ("reason", reason.into()), ("threadId", THREAD.into()), ("hitBreakpointIds", hit.into()),
+        ]))?;
+        loop {
+            let Some(request) = read_message(&mut self.connection.input)? else {
+                self.connection.disconnected = true;
+                return Ok(Command::Abort);
+            };
+            let resume = match command(&request) {
+                "continue" => Some(Command::Continue),
+                "next" | "stepIn" => Some(Command::StepStatement),
+                "stepOut" => Some(Command::StepTransition),
+                "disconnect" | "terminate" => {
+                    self.connection.disconnected = true;
+                    Some(Command::Abort)
+                },
+                _ => None,
+            };
+            if let Some(resume) = resume {
+                let body = match resume {
+                    Command::Continue => Json::object([("allThreadsContinued", true.into())]),
+                    _ => Json::object([]),
+                };
+                self.connection.respond(&request, Ok(body))?;
+                return Ok(resume);
+            }
+            let arguments = arguments(&request);
+            let result = match command(&request) {
+                "threads" => Ok(Json::object([("threads", Json::Array(vec![
+                    Json::object([("id", THREAD.into()), ("name", "main".into())]),
+                ]))])),
+                "stackTrace" => Ok(self.stack_trace(stop)),
+                "scopes" => arguments.get("frameId").and_then(Json::as_i64)
+                    .map(|frame| self.scopes(stop, frame as usize))
+                    .ok_or_else(|| "missing frameId".to_string()),
+                "variables" => arguments.get("variablesReference").and_then(Json::as_i64)
+                    .map(|reference| self.variables(stop, reference as usize))
+                    .ok_or_else(|| "missing variablesReference".to_string()),
+                "setBreakpoints" => {
+                    let (path, lines) = source_breakpoints(&request);
+                    let lines = if path == self.path { lines } else { vec![] };
+                    Ok(Json::object([("breakpoints", set_breakpoints(breakpoints, self.module, &lines))]))
+                },
+                "pause" => Err("the execution is already stopped".to_string()),
+                other => Err(format!("unsupported request `{}`", other)),
+            };
+            self.connection.respond(&request, result)?;
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> DebugFrontend for Session<'_, '_, R, W> {
+    fn stopped(&mut self, stop: &Stop<'_>, breakpoints: &mut Breakpoints<'_>) -> Command {
+        // the execution can not go on without the client
+        self.serve(stop, breakpoints).unwrap_or_else(|_| {
+            self.connection.disconnected = true;
+            Command::Abort
+        })
+    }
+}
+
+/// Debug adapter serving a single launch of the program
+pub struct DapServer<R, W> {
+    connection: Connection<R, W>,
+    /// Requested breakpoints by the source path
+    breakpoints: Vec<(String, Vec<SourceBreakpoint>)>,
+    launch: Option<Json>,
+    configured: bool,
+}
+
+impl<R: BufRead, W: Write> DapServer<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Self {
+            connection: Connection { input, output, seq: 0, disconnected: false },
+            breakpoints: vec![],
+            launch: None,
+            configured: false,
+        }
+    }
+
+    /// Serves the requests until the client disconnects or closes the input
+    pub fn serve(mut self) -> Result<(), RpcError> {
+        while let Some(request) = read_message(&mut self.connection.input)? {
+            let result = match command(&request) {
+                "initialize" => Ok(Json::object([
+                    ("supportsConfigurationDoneRequest", true.into()),
+                    ("supportsConditionalBreakpoints", true.into()),
+                ])),
+                "setBreakpoints" => {
+                    let (path, lines) = source_breakpoints(&request);
+                    let body = match std::fs::read_to_string(&path).ok().and_then(|s| Parser::parse_str(&s).ok()) {
+                        Some(module) => set_breakpoints(&mut Breakpoints::new(&module), &module, &lines),
+                        None => Json::Array(lines.iter().map(|b| Json::object([
+                            ("verified", false.into()), ("line", b.line.into()), ("message", "source does not parse".into()),
+                        ])).collect()),
+                    };
+                    self.breakpoints.retain(|(p, _)| *p != path);
+                    self.breakpoints.push((path, lines));
+                    Ok(Json::object([("breakpoints", body)]))
+                },
+                "setExceptionBreakpoints" => Ok(Json::object([("breakpoints", Json::Array(vec![]))])),
+                "threads" => Ok(Json::object([("threads", Json::Array(vec![
+                    Json::object([("id", THREAD.into()), ("name", "main".into())]),
+                ]))])),
+                "launch" => {
+                    self.launch = Some(arguments(&request).clone());
+                    Ok(Json::object([]))
+                },
+                "configurationDone" => {
+                    self.configured = true;
+                    Ok(Json::object([]))
+                },
+                "disconnect" | "terminate" => {
+                    self.connection.respond(&request, Ok(Json::object([])))?;
+                    return Ok(());
+                },
+                other => Err(format!("unsupported request `{}`", other)),
+            };
+            self.connection.respond(&request, result)?;
+            if command(&request) == "initialize" {
+                self.connection.event("initialized", Json::object([]))?;
+            }
+            if self.configured && let Some(launch) = self.launch.take() {
+                self.launch(&launch)?;
+                if self.connection.disconnected {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the program to the end, reporting the result as the output and the exit code
+    fn launch(&mut self, arguments: &Json) -> Result<(), RpcError> {
+        let result = self.execute(arguments);
+        if self.connection.disconnected {
+            return Ok(());
+        }
+        let code = match result? {
+            Ok(output) => {
+                self.connection.output("stdout", output)?;
+                0i64
+            },
+            Err(e) => {
+                self.connection.output("stderr", format!("error: {}\n", e))?;
+                1
+            },
+        };
+        self.connection.event("exited", Json::object([("exitCode", code.into())]))?;
+        self.connection.event("terminated", Json::object([]))
+    }
+
+    fn execute(&mut self, arguments: &Json) -> Result<Result<String, String>, RpcError> {
+        let string = |key: &str| arguments.get(key).and_then(Json::as_str).unwrap_or_default().to_string();
+        let values = |key: &str| arguments.get(key).and_then(Json::as_array).unwrap_or_default().iter()
+            .map(|v| v.as_str().map_or(Err(format!("`{}` are not strings", key)), parse_value))
+            .collect::<Result<Vec<_>, _>>();
+        let path = string("program");
+        let module = match std::fs::read_to_string(&path) {
+            Ok(source) => match Parser::parse_str(&source) {
+                Ok(module) => module,
+                Err(e) => return Ok(Err(format!("{}: {}", path, e))),
+            },
+            Err(e) => return Ok(Err(format!("{}: {}", path, e))),
+        };
+        let name = string("automata");
+        let Some(a) = module.find_automata(&name) else {
+            return Ok(Err(format!("undefined automata `{}`", name)));
+        };
+        let (args, signals) = match (values("args"), values("signals")) {
+            (Ok(args), Ok(signals)) => (args, signals),
+            (Err(e), _) | (_, Err(e)) => return Ok(Err(e)),
+        };
+        let entry = arguments.get("stopOnEntry").and_then(Json::as_bool).unwrap_or(false);
+        let session = Session { connection: &mut self.connection, module: &module, path: path.clone(), entry };
+        let mut debugger = Debugger::new(&module, session);
+        if !entry {
+            debugger = debugger.with_continue();
+        }
+        let lines = self.breakpoints.iter().find(|(p, _)| *p == path).map(|(_, l)| l.as_slice()).unwrap_or_default();
+        set_breakpoints(&mut debugger.breakpoints, &module, lines);
+        let mut interpreter = Interpreter::new(&module).with_debugger(debugger);
+        let result = match a.kind {
+            AutomataKind::Mealy(..) => interpreter.run_mealy(&name, args, signals).map(|run| {
+                run.outputs.iter().chain([&Value::State(Box::new(run.state))]).map(|v| format!("{}\n", v)).collect()
+            }),
+            AutomataKind::Moore => interpreter.run_automata(&name, args).map(|state| format!("{}\n", state)),
+        };
+        Ok(result.map_err(|e| e.to_string()))
+    }
+}
+
+#[test]
+fn dap_test() {
+    let source = "\
+automata Count {
+    state Up<n: int64> {
+        let next = n + 1;
+
+        if next < 4 { link self -> Up<next>; }
+    }
+}
+";
+    let path = std::env::temp_dir().join(format!("dap_test_{}.fan", std::process::id()));
+    std::fs::write(&path, source).unwrap();
+    let path = path.to_string_lossy().to_string();
+
+    let mut input = vec![];
+    let mut seq = 0i64;
+    let mut request = |command: &str, arguments: Json| {
+        seq += 1;
+        let request = Json::object([
+            ("seq", seq.into()), ("type", "request".into()), ("command", command.into()), ("arguments", arguments),
+        ]);
+        write_message(&mut input, &request).unwrap();
+    };
+    let breakpoint = |line: i64, condition: Option<&str>| Json::object([("line", line.into()), ("condition", condition.into())]);
+    let set_breakpoints = |breakpoints: Vec<Json>| Json::object([
+        ("source", Json::object([("path", path.as_str().into())])), ("breakpoints", Json::Array(breakpoints)),
+    ]);
+    request("initialize", Json::object([("adapterID", "fan".into())]));
+    request("setBreakpoints", set_breakpoints(vec![
+        breakpoint(3, Some("n == 2")), breakpoint(2, Some("n == 3")), breakpoint(4, None), breakpoint(9, None),
+    ]));
+    request("launch", Json::object([
+        ("program", path.as_str().into()), ("automata", "Count".into()), ("args", vec!["0"].into()),
+    ]));
+    request("configurationDone", Json::object([]));
+    // the condition breakpoint on the first statement of Up<2>
+    request("stackTrace", Json::object([("threadId", 1i64.into())]));
+    request("variables", Json::object([("variablesReference", 1i64.into())]));
+    request("next", Json::object([]));
+    request("variables", Json::object([("variablesReference", 2i64.into())]));
+    request("setBreakpoints", set_breakpoints(vec![breakpoint(5, None)]));
+    request("continue", Json::object([]));
+    // the link breakpoint of Up<2>
+    request("setBreakpoints", set_breakpoints(vec![]));
+    request("continue", Json::object([]));
+    request("disconnect", Json::object([]));
+
+    let mut output = vec![];
+    DapServer::new(input.as_slice(), &mut output).serve().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let mut reader = output.as_slice();
+    let mut messages = vec![];
+    while let Some(message) = read_message(&mut reader).unwrap() {
+        messages.push(message);
+    }
+    let kinds: Vec<String> = messages.iter().map(|m| {
+        let kind = m.get("event").or(m.get("command")).and_then(Json::as_str).unwrap();
+        format!("{} {}", m.get("type").and_then(Json::as_str).unwrap(), kind)
+    }).collect();
+    assert_eq!(kinds, [
+        "response initialize", "event initialized", "response setBreakpoints", "response launch",
+        "response configurationDone", "event stopped", "response stackTrace", "response variables",
+        "response next", "event stopped", "response variables", "response setBreakpoints", "response continue",
+        "event stopped", "response setBreakpoints", "response continue", "event output", "event exited",
+        "event terminated", "response disconnect",
+    ]);
+    let body = |i: usize| messages[i].get("body").unwrap().to_string();
+    assert_eq!(body(2), concat!(
+        r#"{"breakpoints":[{"id":1,"verified":true,"line":3},{"id":2,"verified":true,"line":2},"#,
+        r#"{"verified":false,"line":4,"message":"no statement at the line"},{"verified":false,"line":9,"message":"no state at the line"}]}"#,
+    ));
+    assert_eq!(body(5), r#"{"reason":"breakpoint","threadId":1,"hitBreakpointIds":[1]}"#);
+    assert!(body(6).contains(r#"{"id":0,"name":"Count::Up<2>","#) && body(6).contains(r#""line":3,"column":9}"#));
+    assert_eq!(body(7), r#"{"variables":[{"name":"n","value":"2","type":"int64","variablesReference":0}]}"#);
+    assert_eq!(body(9), r#"{"reason":"step","threadId":1,"hitBreakpointIds":[]}"#);
+    assert_eq!(body(10), concat!(
+        r#"{"variables":[{"name":"n","value":"2","type":"int64","variablesReference":0},"#,
+        r#"{"name":"next","value":"3","type":"int64","variablesReference":0}]}"#,
+    ));
+    assert_eq!(body(11), r#"{"breakpoints":[{"id":3,"verified":true,"line":5}]}"#);
+    assert_eq!(body(13), r#"{"reason":"breakpoint","threadId":1,"hitBreakpointIds":[3]}"#);
+    assert_eq!(body(16), r#"{"category":"stdout","output":"Count::Up<3>\n"}"#);
+    assert_eq!(body(17), r#"{"exitCode":0}"#);
+}
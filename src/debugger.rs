@@ -0,0 +1,539 @@
+//! Step debugger driven by the interpreter: breakpoints on state entry, on `link` edges and on conditions
+//! over the template arguments, stepping by transitions or statements.
+//! The [`DebugFrontend`] is asked what to do on every stop, `fan debug` reads the commands from stdin.
+
+use std::io::{BufRead, Write};
+
+use crate::checker::Checker;
+use crate::interpreter::{ExecError, Interpreter, RunFrame, StateValue, Value};
+use crate::lexer::Position;
+use crate::parser::{Module, ParseError, Parser};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Breakpoint {
+    /// Entry to the state, optionally when the condition over its template arguments holds
+    State { automata: Option<String>, state: String, condition: Option<String> },
+    /// `link` from the state to the state, `None` matches any and `NULL` the link to NULL
+    Link { automata: Option<String>, from: Option<String>, to: Option<String> },
+    /// Statements of the state body starting on the zero based line, optionally when the condition holds
+    Line { automata: Option<String>, state: String, line: usize, condition: Option<String> },
+}
+
+impl std::fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let prefix = |a: &Option<String>| a.as_ref().map(|a| format!("{}::", a)).unwrap_or_default();
+        match self {
+            Breakpoint::State { automata, state, condition } => {
+                write!(f, "{}{}", prefix(automata), state)?;
+                if let Some(c) = condition {
+                    write!(f, " if {}", c)?;
+                }
+                Ok(())
+            },
+            Breakpoint::Link { automata, from, to } => write!(
+                f, "{}{} -> {}", prefix(automata), from.as_deref().unwrap_or("*"), to.as_deref().unwrap_or("*"),
+            ),
+            Breakpoint::Line { automata, state, line, condition } => {
+                write!(f, "{}{}:{}", prefix(automata), state, line + 1)?;
+                if let Some(c) = condition {
+                    write!(f, " if {}", c)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DebugError {
+    UndefinedAutomata(String),
+    UndefinedState(String),
+    /// Condition is not a FAN expression
+    Condition(String),
+}
+
+impl std::fmt::Display for DebugError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DebugError::UndefinedAutomata(a) => write!(f, "undefined automata `{}`", a),
+            DebugError::UndefinedState(s) => write!(f, "undefined state `{}`", s),
+            DebugError::Condition(e) => write!(f, "invalid condition: {}", e),
+        }
+    }
+}
+
+/// Condition compiled into the module with the single function of the template arguments
+#[derive(Debug)]
+struct Condition {
+    params: Vec<String>,
+    module: Module,
+}
+
+impl Condition {
+    const FUNCTION: &'static str = "condition";
+
+    fn parse(condition: &str, params: Vec<String>) -> Result<Self, ParseError> {
+        let source = format!("fn {}({}) -> bool {{\n{}\n}}", Self::FUNCTION, params.join(", "), condition);
+        Ok(Self { params, module: Parser::parse_str(&source)? })
+    }
+
+    /// Errors of the condition do not stop the execution, they are reported by the stop instead
+    fn holds(&self, state: &StateValue) -> Result<bool, ExecError> {
+        let args = self.params.iter().map(|p| state.arg(p).cloned().unwrap_or(Value::Null)).collect();
+        Interpreter::new(&self.module).call_function(Self::FUNCTION, args)?.as_bool()
+    }
+}
+
+/// Breakpoints with their ids, the ids are not reused after removal
+#[derive(Debug)]
+pub struct Breakpoints<'m> {
+    module: &'m Module,
+    list: Vec<(usize, Breakpoint, Option<Condition>)>,
+    next_id: usize,
+}
+
+impl<'m> Breakpoints<'m> {
+    pub fn new(module: &'m Module) -> Self {
+        Self { module, list: vec![], next_id: 1 }
+    }
+
+    /// Breaks on the entry to the state, the automata is the first one defining the state when omitted
+    pub fn add_state(&mut self, automata: Option<&str>, state: &str, condition: Option<&str>) -> Result<usize, DebugError> {
+        let compiled = self.condition(automata, state, condition)?;
+        let breakpoint = Breakpoint::State {
+            automata: automata.map(str::to_string),
+            state: state.to_string(),
+            condition: condition.map(str::to_string),
+        };
+        Ok(self.push(breakpoint, compiled))
+    }
+
+    /// Breaks before the statements of the state body starting on the zero based line
+    pub fn add_line(&mut self, automata: Option<&str>, state: &str, line: usize, condition: Option<&str>) -> Result<usize, DebugError> {
+        let compiled = self.condition(automata, state, condition)?;
+        let breakpoint = Breakpoint::Line {
+            automata: automata.map(str::to_string),
+            state: state.to_string(),
+            line,
+            condition: condition.map(str::to_string),
+        };
+        Ok(self.push(breakpoint, compiled))
+    }
+
+    /// Checks the state is defined and compiles the condition over its template arguments
+    fn condition(&self, automata: Option<&str>, state: &str, condition: Option<&str>) -> Result<Option<Condition>, DebugError> {
+        let a = match automata {
+            Some(name) => self.module.find_automata(name).ok_or_else(|| DebugError::UndefinedAutomata(name.to_string()))?,
+            None => self.module.automata().find(|a| a.state(state).is_some())
+                .ok_or_else(|| DebugError::UndefinedState(state.to_string()))?,
+        };
+        let s = a.state(state).ok_or_else(|| DebugError::UndefinedState(state.to_string()))?;
+        condition.map(|c| {
+            let params = Checker::template_params(a, &s.params).iter().map(|p| p.name.clone()).collect();
+            Condition::parse(c, params).map_err(|e| DebugError::Condition(e.to_string()))
+        }).transpose()
+    }
+
+    /// Breaks on the `link` edge, `None` matches any state and `NULL` the link to NULL
+    pub fn add_link(&mut self, automata: Option<&str>, from: Option<&str>, to: Option<&str>) -> Result<usize, DebugError> {
+        let automata_def = automata
+            .map(|name| self.module.find_automata(name).ok_or_else(|| DebugError::UndefinedAutomata(name.to_string())))
+            .transpose()?;
+        for state in from.into_iter().chain(to.filter(|t| *t != "NULL")) {
+            let defined = match automata_def {
+                Some(a) => a.state(state).is_some(),
+                None => self.module.automata().any(|a| a.state(state).is_some()),
+            };
+            if !defined {
+                return Err(DebugError::UndefinedState(state.to_string()));
+            }
+        }
+        let breakpoint = Breakpoint::Link {
+            automata: automata.map(str::to_string),
+            from: from.map(str::to_string),
+            to: to.map(str::to_string),
+        };
+        Ok(self.push(breakpoint, None))
+    }
+
+    pub fn add(&mut self, breakpoint: &Breakpoint) -> Result<usize, DebugError> {
+        match breakpoint {
+            Breakpoint::State { automata, state, condition } =>
+                self.add_state(automata.as_deref(), state, condition.as_deref()),
+            Breakpoint::Link { automata, from, to } =>
+                self.add_link(automata.as_deref(), from.as_deref(), to.as_deref()),
+            Breakpoint::Line { automata, state, line, condition } =>
+                self.add_line(automata.as_deref(), state, *line, condition.as_deref()),
+        }
+    }
+
+    fn push(&mut self, breakpoint: Breakpoint, condition: Option<Condition>) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.list.push((id, breakpoint, condition));
+        id
+    }
+
+    pub fn remove(&mut self, id: usize) -> bool {
+        let before = self.list.len();
+        self.list.retain(|(i, ..)| *i != id);
+        self.list.len() != before
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Breakpoint)> {
+        self.list.iter().map(|(id, b, _)| (*id, b))
+    }
+
+    /// Breakpoint hit by entering the state, with the error of its condition if any
+    fn entry(&self, state: &StateValue) -> Option<(usize, Option<ExecError>)> {
+        self.list.iter().find_map(|(id, b, condition)| match b {
+            Breakpoint::State { automata, state: name, .. }
+                if *name == state.state && automata.as_ref().is_none_or(|a| *a == state.automata) =>
+            {
+                Self::hit(*id, condition.as_ref(), state)
+            },
+            _ => None,
+        })
+    }
+
+    /// Breakpoint hit by the statement on the line of the state body, with the error of its condition if any
+    fn statement(&self, state: &StateValue, position: Position) -> Option<(usize, Option<ExecError>)> {
+        self.list.iter().find_map(|(id, b, condition)| match b {
+            Breakpoint::Line { automata, state: name, line, .. }
+                if *line == position.line && *name == state.state && automata.as_ref().is_none_or(|a| *a == state.automata) =>
+            {
+                Self::hit(*id, condition.as_ref(), state)
+            },
+            _ => None,
+        })
+    }
+
+    fn hit(id: usize, condition: Option<&Condition>, state: &StateValue) -> Option<(usize, Option<ExecError>)> {
+        match condition.map(|c| c.holds(state)) {
+            None | Some(Ok(true)) => Some((id, None)),
+            Some(Ok(false)) => None,
+            Some(Err(e)) => Some((id, Some(e))),
+        }
+    }
+
+    fn link(&self, from: &StateValue, to: Option<&StateValue>) -> Option<usize> {
+        self.list.iter().find_map(|(id, b, _)| match b {
+            Breakpoint::Link { automata, from: f, to: t }
+                if automata.as_ref().is_none_or(|a| *a == from.automata)
+                    && f.as_ref().is_none_or(|f| *f == from.state)
+                    && t.as_ref().is_none_or(|t| Some(t.as_str()) == to.map(|s| s.state.as_str()).or(Some("NULL"))) =>
+            {
+                Some(*id)
+            },
+            _ => None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopReason {
+    /// Stepping by transitions reached the state entry
+    Step,
+    /// Stepping by statements reached the statement
+    Statement,
+    Breakpoint(usize),
+    /// Condition of the breakpoint failed, the debugger stops to show the error
+    ConditionError(usize, ExecError),
+}
+
+/// What the debugged execution looks like at the stop
+#[derive(Debug)]
+pub struct Stop<'a> {
+    pub reason: StopReason,
+    /// Running automata, the outermost first
+    pub frames: &'a [RunFrame],
+    /// Locals of the innermost body, the template arguments and the signal included.
+    /// The locals of the state body are out of scope by its `link`.
+    pub locals: Vec<(String, Value)>,
+    /// Link about to be made, `Some(None)` stands for NULL
+    pub link: Option<Option<StateValue>>,
+    /// Statement about to be evaluated or the position of the state
+    pub position: Position,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    /// Runs to the next breakpoint
+    Continue,
+    /// Stops at the next state entry
+    StepTransition,
+    /// Stops at the next statement
+    StepStatement,
+    /// Stops the execution with [`ExecError::Aborted`]
+    Abort,
+}
+
+pub trait DebugFrontend {
+    /// Called on every stop, breakpoints can be changed before resuming
+    fn stopped(&mut self, stop: &Stop<'_>, breakpoints: &mut Breakpoints<'_>) -> Command;
+}
+
+impl<F: FnMut(&Stop<'_>, &mut Breakpoints<'_>) -> Command> DebugFrontend for F {
+    fn stopped(&mut self, stop: &Stop<'_>, breakpoints: &mut Breakpoints<'_>) -> Command {
+        self(stop, breakpoints)
+    }
+}
+
+/// State of the debugging session, owned by the interpreter
+pub struct Debugger<'m> {
+    pub breakpoints: Breakpoints<'m>,
+    mode: Command,
+    frontend: Box<dyn DebugFrontend + 'm>,
+}
+
+impl<'m> Debugger<'m> {
+    /// The debugger stops on the first state entry
+    pub fn new(module: &'m Module, frontend: impl DebugFrontend + 'm) -> Self {
+        Self { breakpoints: Breakpoints::new(module), mode: Command::StepTransition, frontend: Box::new(frontend) }
+    }
+
+    /// Runs to the first breakpoint instead of stopping on the first state entry
+    pub fn with_continue(mut self) -> Self {
+        self.mode = Command::Continue;
+        self
+    }
+
+    fn stop(&mut self, stop: Stop<'_>) -> Result<(), ExecError> {
+        self.mode = self.frontend.stopped(&stop, &mut self.breakpoints);
+        match self.mode {
+            Command::Abort => Err(ExecError::Aborted),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn on_entry(&mut self, frames: &[RunFrame], locals: Vec<(String, Value)>, position: Position) -> Result<(), ExecError> {
+        let current = &frames.last().expect("entered state runs in a frame").current;
+        let reason = match self.breakpoints.entry(current) {
+            Some((id, None)) => StopReason::Breakpoint(id),
+            Some((id, Some(e))) => StopReason::ConditionError(id, e),
+            None if self.mode != Command::Continue => StopReason::Step,
+            None => return Ok(()),
+        };
+        self.stop(Stop { reason, frames, locals, link: None, position })
+    }
+
+    pub(crate) fn on_link(&mut self, frames: &[RunFrame], locals: Vec<(String, Value)>, to: Option<&StateValue>, position: Position) -> Result<(), ExecError> {
+        let from = &frames.last().expect("linked state runs in a frame").current;
+        let Some(id) = self.breakpoints.link(from, to) else {
+            return Ok(());
+        };
+        self.stop(Stop { reason: StopReason::Breakpoint(id), frames, locals, link: Some(to.cloned()), position })
+    }
+
+    pub(crate) fn on_statement(&mut self, frames: &[RunFrame], locals: Vec<(String, Value)>, position: Position) -> Result<(), ExecError> {
+        let reason = match frames.last().and_then(|f| self.breakpoints.statement(&f.current, position)) {
+            Some((id, None)) => StopReason::Breakpoint(id),
+            Some((id, Some(e))) => StopReason::ConditionError(id, e),
+            None if self.mode == Command::StepStatement => StopReason::Statement,
+            None => return Ok(()),
+        };
+        self.stop(Stop { reason, frames, locals, link: None, position })
+    }
+}
+
+/// Line based frontend of `fan debug`, reads the commands and prints the stops
+pub struct Console<R, W> {
+    pub input: R,
+    pub output: W,
+}
+
+impl<R: BufRead, W: Write> Console<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Self { input, output }
+    }
+
+    fn print(&mut self, text: std::fmt::Arguments<'_>) {
+        // the debugged program keeps running when the terminal is gone
+        let _ = self.output.write_fmt(text);
+        let _ = self.output.flush();
+    }
+
+    fn describe(&mut self, stop: &Stop<'_>) {
+        let current = &stop.frames.last().expect("stops happen in a frame").current;
+        let reason = match &stop.reason {
+            StopReason::Step => "step".to_string(),
+            StopReason::Statement => "statement".to_string(),
+            StopReason::Breakpoint(id) => format!("breakpoint {}", id),
+            StopReason::ConditionError(id, e) => format!("breakpoint {}, condition failed: {}", id, e),
+        };
+        self.print(format_args!("{} at {} ({})\n", current, stop.position, reason));
+        if let Some(to) = &stop.link {
+            let to = to.as_ref().map_or("NULL".to_string(), ToString::to_string);
+            self.print(format_args!("  link -> {}\n", to));
+        }
+    }
+
+    fn breakpoint(&mut self, spec: &str, breakpoints: &mut Breakpoints<'_>) {
+        let (spec, condition) = match spec.split_once(" if ") {
+            Some((spec, condition)) => (spec.trim(), Some(condition.trim())),
+            None => (spec.trim(), None),
+        };
+        let (automata, spec) = match spec.split_once("::") {
+            Some((a, spec)) => (Some(a.trim()), spec.trim()),
+            None => (None, spec),
+        };
+        fn any(s: &str) -> Option<&str> {
+            Some(s.trim()).filter(|s| *s != "*")
+        }
+        let added = match spec.split_once("->") {
+            Some((from, to)) => breakpoints.add_link(automata, any(from), any(to)),
+            None => breakpoints.add_state(automata, spec, condition),
+        };
+        match added {
+            Ok(id) => self.print(format_args!("breakpoint {} at {}\n", id, spec)),
+            Err(e) => self.print(format_args!("error: {}\n", e)),
+        }
+    }
+}
+
+const HELP: &str = "\
+break [Automata::]State [if condition]   break on the entry to the state
+break [Automata::]From -> To             break on the link, `*` matches any state and NULL the link to NULL
+delete ID                                remove the breakpoint
+breakpoints                              list the breakpoints
+continue, c                              run to the next breakpoint
+step, s                                  stop at the next state entry
+next, n                                  stop at the next statement
+locals                                   print the local variables
+stack                                    print the running automata
+quit, q                                  abort the execution
+";
+
+impl<R: BufRead, W: Write> DebugFrontend for Console<R, W> {
+    fn stopped(&mut self, stop: &Stop<'_>, breakpoints: &mut Breakpoints<'_>) -> Command {
+        self.describe(stop);
+        loop {
+            self.print(format_args!("(fan) "));
+            let mut line = String::new();
+            match self.input.read_line(&mut line) {
+                Ok(0) | Err(_) => return Command::Abort,
+                Ok(_) => {},
+            }
+            let line = line.trim();
+            let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+            match command {
+                "" => {},
+                "continue" | "c" => return Command::Continue,
+                "step" | "s" => return Command::StepTransition,
+                "next" | "n" => return Command::StepStatement,
+                "quit" | "q" => return Command::Abort,
+                "break" | "b" => self.breakpoint(rest, breakpoints),
+                "delete" | "d" => match rest.trim().parse() {
+                    Ok(id) if breakpoints.remove(id) => {},
+                    _ => self.print(format_args!("error: no breakpoint `{}`\n", rest.trim())),
+                },
+                "breakpoints" => {
+                    let list: Vec<_> = breakpoints.iter().map(|(id, b)| format!("{} {}\n", id, b)).collect();
+                    self.print(format_args!("{}", list.concat()));
+                },
+                "locals" => {
+                    let locals: Vec<_> = stop.locals.iter().map(|(name, v)| format!("{} = {}\n", name, v)).collect();
+                    self.print(format_args!("{}", locals.concat()));
+                },
+                "stack" => {
+                    let frames: Vec<_> = stop.frames.iter().rev()
+                        .map(|f| format!("{} (steps: {})\n", f.current, f.steps))
+                        .collect();
+                    self.print(format_args!("{}", frames.concat()));
+                },
+                "help" | "h" => self.print(format_args!("{}", HELP)),
+                _ => self.print(format_args!("error: unknown command `{}`, see `help`\n", command)),
+            }
+        }
+    }
+}
+
+#[test]
+fn debugger_test() {
+    let source = "
+        automata Counter {
+            state Count<n: int64> {
+                let next = n + 1;
+                if next < 5 { link self -> Count<next>; } else { link self -> Done<next>; }
+            }
+            state Done<n: int64> { }
+        }
+    ";
+    let module = Parser::parse_str(source).unwrap();
+    let mut stops = vec![];
+    let mut commands = vec![
+        Command::StepStatement, Command::StepStatement, Command::Continue,
+        Command::Continue, Command::StepTransition, Command::Continue,
+    ].into_iter();
+    let frontend = |stop: &Stop<'_>, breakpoints: &mut Breakpoints<'_>| {
+        if stops.is_empty() {
+            assert_eq!(breakpoints.add_state(None, "Count", Some("n == 3")), Ok(1));
+            assert_eq!(breakpoints.add_link(Some("Counter"), Some("Count"), Some("Done")), Ok(2));
+        }
+        let state = stop.frames.last().unwrap();
+        stops.push((stop.reason.clone(), state.current.to_string(), stop.locals.clone(), stop.link.clone(), stop.position.line));
+        commands.next().unwrap()
+    };
+    let debugger = Debugger::new(&module, frontend);
+    let done = Interpreter::new(&module).with_debugger(debugger).run_automata("Counter", vec![Value::Int(0)]).unwrap();
+    assert_eq!(done.to_string(), "Counter::Done<5>");
+
+    let n = |i| ("n".to_string(), Value::Int(i));
+    let count = |i: i64| format!("Counter::Count<{}>", i);
+    let done_state = Interpreter::new(&module).enter(module.find_automata("Counter").unwrap(), "Done", vec![Value::Int(5)]).unwrap();
+    assert_eq!(stops, vec![
+        (StopReason::Step, count(0), vec![n(0)], None, 2),
+        (StopReason::Statement, count(0), vec![n(0)], None, 3),
+        (StopReason::Statement, count(0), vec![n(0), ("next".to_string(), Value::Int(1))], None, 4),
+        (StopReason::Breakpoint(1), count(3), vec![n(3)], None, 2),
+        (StopReason::Breakpoint(2), count(4), vec![n(4)], Some(Some(done_state)), 4),
+        (StopReason::Step, "Counter::Done<5>".to_string(), vec![n(5)], None, 6),
+    ]);
+
+    let mut breakpoints = Breakpoints::new(&module);
+    assert_eq!(breakpoints.add_state(Some("Counter"), "Missing", None), Err(DebugError::UndefinedState("Missing".into())));
+    assert_eq!(breakpoints.add_link(Some("Other"), None, None), Err(DebugError::UndefinedAutomata("Other".into())));
+    assert!(matches!(breakpoints.add_state(None, "Count", Some("n ==")), Err(DebugError::Condition(_))));
+    assert_eq!(breakpoints.add_link(None, Some("Done"), Some("NULL")), Ok(1));
+    assert_eq!(breakpoints.add_line(Some("Counter"), "Count", 2, Some("n == 1")), Ok(2));
+    assert_eq!(breakpoints.iter().map(|(_, b)| b.to_string()).collect::<Vec<_>>(), ["Done -> NULL", "Counter::Count:3 if n == 1"]);
+    assert!(breakpoints.remove(1) && !breakpoints.remove(1));
+
+    let mut reasons = vec![];
+    let frontend = |stop: &Stop<'_>, _: &mut Breakpoints<'_>| {
+        reasons.push(stop.reason.clone());
+        if reasons.len() < 3 { Command::Continue } else { Command::Abort }
+    };
+    let mut debugger = Debugger::new(&module, frontend).with_continue();
+    debugger.breakpoints.add_state(None, "Count", Some("n / 0 == 1")).unwrap();
+    let aborted = Interpreter::new(&module).with_debugger(debugger).run_automata("Counter", vec![Value::Int(0)]);
+    assert_eq!(aborted, Err(ExecError::Aborted));
+    assert_eq!(reasons, vec![StopReason::ConditionError(1, ExecError::DivisionByZero); 3]);
+}
+
+#[test]
+fn console_test() {
+    let module = Parser::parse_str("
+        automata Count {
+            state Up<n: int64> { if n < 3 { link self -> Up<n + 1>; } }
+        }
+    ").unwrap();
+    let input = "break Up if n == 2\nbreak Count::Up -> NULL\nbreak Nowhere\nc\nlocals\nstack\nc\nbreakpoints\ndelete 2\nc\n";
+    let mut output = vec![];
+    let debugger = Debugger::new(&module, Console::new(input.as_bytes(), &mut output));
+    let done = Interpreter::new(&module).with_debugger(debugger).run_automata("Count", vec![Value::Int(0)]).unwrap();
+    assert_eq!(done.to_string(), "Count::Up<3>");
+    assert_eq!(String::from_utf8(output).unwrap(), "\
+Count::Up<0> at 3:19 (step)
+(fan) breakpoint 1 at Up
+(fan) breakpoint 2 at Up -> NULL
+(fan) error: undefined state `Nowhere`
+(fan) Count::Up<2> at 3:19 (breakpoint 1)
+(fan) n = 2
+(fan) Count::Up<2> (steps: 2)
+(fan) Count::Up<3> at 3:41 (breakpoint 2)
+  link -> NULL
+(fan) 1 Up if n == 2
+2 Count::Up -> NULL
+(fan) (fan) ");
+}
@@ -0,0 +1,100 @@
+use crate::checker::CheckError;
+use crate::codegen::CodegenError;
+use crate::import::ImportError;
+use crate::json::Json;
+use crate::lexer::Position;
+use crate::parser::ParseError;
+
+/// Error message pointing into the FAN source
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub position: Option<Position>,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>, position: Option<Position>) -> Self {
+        Self { message: message.into(), position }
+    }
+
+    /// Renders the message with the source line and a caret under the position
+    pub fn render(&self, file: &str, source: &str) -> String {
+        self.render_as("error", file, source)
+    }
+
+    /// Same as [`Diagnostic::render`] with another severity, `warning`
+    pub fn render_as(&self, severity: &str, file: &str, source: &str) -> String {
+        let Some(position) = self.position else {
+            return format!("{}: {}\n --> {}\n", severity, self.message, file);
+        };
+        let line = source.lines().nth(position.line).unwrap_or_default();
+        let number = (position.line + 1).to_string();
+        let gutter = " ".repeat(number.len());
+        let caret: String = line.chars().take(position.col).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+        format!(
+            "{}: {}\n{}--> {}:{}\n{} |\n{} | {}\n{} | {}^\n",
+            severity, self.message, gutter, file, position, gutter, number, line, gutter, caret,
+        )
+    }
+
+    /// Machine readable form, the line and the column are one based as in the rendered message
+    pub fn to_json(&self) -> Json {
+        Json::object([
+            ("message", self.message.as_str().into()),
+            ("line", self.position.map(|p| p.line + 1).into()),
+            ("column", self.position.map(|p| p.col + 1).into()),
+        ])
+    }
+}
+
+/// Message without the `line:col: ` prefix of the error display
+fn message(display: String, position: Position) -> String {
+    display.strip_prefix(&format!("{}: ", position)).map(str::to_string).unwrap_or(display)
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(e: &ParseError) -> Self {
+        match e.position() {
+            Some(p) => Diagnostic::new(message(e.to_string(), p), Some(p)),
+            None => Diagnostic::new(e.to_string(), None),
+        }
+    }
+}
+
+impl From<&CheckError> for Diagnostic {
+    fn from(e: &CheckError) -> Self {
+        Diagnostic::new(message(e.to_string(), e.position()), Some(e.position()))
+    }
+}
+
+impl From<&ImportError> for Diagnostic {
+    fn from(e: &ImportError) -> Self {
+        Diagnostic::new(e.message.clone(), Some(e.position))
+    }
+}
+
+impl From<&CodegenError> for Vec<Diagnostic> {
+    fn from(e: &CodegenError) -> Self {
+        match e {
+            CodegenError::Check(errors) => errors.iter().map(Diagnostic::from).collect(),
+            CodegenError::Untyped { position, .. } | CodegenError::Unsupported { position, .. } => {
+                vec![Diagnostic::new(message(e.to_string(), *position), Some(*position))]
+            },
+        }
+    }
+}
+
+#[test]
+fn render_test() {
+    let source = "fn f() {\n    g();\n}";
+    let d = Diagnostic::new("undefined function `g`", Some(Position::new(1, 4)));
+    assert_eq!(d.render("a.fan", source), concat!(
+        "error: undefined function `g`\n",
+        " --> a.fan:2:5\n",
+        "  |\n",
+        "2 |     g();\n",
+        "  |     ^\n",
+    ));
+    let e = crate::parser::Parser::parse_str("fn f( {").unwrap_err();
+    assert!(!Diagnostic::from(&e).message.starts_with("1:"));
+}
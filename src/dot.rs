@@ -0,0 +1,125 @@
+//! Graphviz DOT of the automata graphs for `fan graph --format dot`, one `digraph` per automata.
+//! Automata started by `run` in a state are drawn inside it as the clusters of their own states.
+
+use crate::format::INDENT;
+use crate::graph::{AutomataGraph, Graph};
+
+/// DOT string literal
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// `digraph` of an automata of the graph, the graph has the automata it runs
+pub fn automata_to_dot(graph: &Graph, a: &AutomataGraph) -> String {
+    let mut out = format!("digraph {} {{\n", quote(&a.name));
+    out.push_str(&format!("{}rankdir=LR;\n", INDENT));
+    out.push_str(&format!("{}node [shape=box, style=rounded];\n", INDENT));
+    body(&mut out, graph, a, "", 1, &mut vec![a.name.as_str()]);
+    out.push_str("}\n");
+    out
+}
+
+/// `digraph` of every automata of the graph
+pub fn to_dot(graph: &Graph) -> String {
+    graph.automata.iter().map(|a| automata_to_dot(graph, a)).collect()
+}
+
+/// Nodes and edges of the automata, the node ids start with the prefix of the enclosing `run`s.
+/// `running` are the automata of the enclosing clusters, recursive `run`s are not expanded again.
+fn body<'g>(out: &mut String, graph: &'g Graph, a: &'g AutomataGraph, prefix: &str, depth: usize, running: &mut Vec<&'g str>) {
+    let indent = INDENT.repeat(depth);
+    let id = |state: Option<&str>| quote(&format!("{}{}", prefix, state.unwrap_or("NULL")));
+    if depth == 1 && let Some(initial) = a.initial() {
+        let start = quote(&format!("{}.start", prefix));
+        out.push_str(&format!("{}{} [shape=point];\n", indent, start));
+        out.push_str(&format!("{}{} -> {};\n", indent, start, id(Some(&initial.name))));
+    }
+    for s in a.states.iter() {
+        out.push_str(&format!("{}{} [label={}];\n", indent, id(Some(&s.name)), quote(&s.label())));
+    }
+    if a.edges.iter().any(|e| e.to.is_none()) {
+        out.push_str(&format!("{}{} [shape=doublecircle, label=\"NULL\"];\n", indent, id(None)));
+    }
+    for (e, label) in a.labelled_edges() {
+        let mut attributes = vec![];
+        if !label.is_empty() {
+            attributes.push(format!("label={}", quote(&label)));
+        }
+        if e.implicit {
+            attributes.push("style=dashed".to_string());
+        }
+        out.push_str(&format!("{}{} -> {}", indent, id(Some(&e.from)), id(e.to.as_deref())));
+        if !attributes.is_empty() {
+            out.push_str(&format!(" [{}]", attributes.join(", ")));
+        }
+        out.push_str(";\n");
+    }
+    for s in a.states.iter() {
+        for run in s.runs.iter() {
+            let Some(called) = graph.find(run).filter(|c| !running.contains(&c.name.as_str())) else { continue };
+            let nested = format!("{}{}/{}/", prefix, s.name, run);
+            out.push_str(&format!("{}subgraph {} {{\n", indent, quote(&format!("cluster_{}", nested))));
+            out.push_str(&format!("{}{}label={};\n", indent, INDENT, quote(&format!("run {}", run))));
+            out.push_str(&format!("{}{}style=dashed;\n", indent, INDENT));
+            running.push(&called.name);
+            body(out, graph, called, &nested, depth + 1, running);
+            running.pop();
+            out.push_str(&format!("{}}}\n", indent));
+            if let Some(initial) = called.initial() {
+                let target = quote(&format!("{}{}", nested, initial.name));
+                out.push_str(&format!("{}{} -> {} [label=\"run\", style=dotted];\n", indent, id(Some(&s.name)), target));
+            }
+        }
+    }
+}
+
+#[test]
+fn dot_test() {
+    use crate::parser::Parser;
+
+    let module = Parser::parse_str("
+        automata Door {
+            state Closed<locked: bool> {
+                if locked { link self -> Closed<false>; } else if self.previous.is_me() { link self -> Open; }
+            }
+            state Open { let _ = run Timer<3>; link self -> Closed<true>; }
+        }
+        automata Timer {
+            state Tick<n: int64> { if n > 0 { link self -> Tick<n - 1>; } else { let _ = run Timer<0>; } }
+        }
+    ").unwrap();
+    assert_eq!(to_dot(&Graph::new(&module)), r#"digraph "Door" {
+    rankdir=LR;
+    node [shape=box, style=rounded];
+    ".start" [shape=point];
+    ".start" -> "Closed";
+    "Closed" [label="Closed<locked: bool>"];
+    "Open" [label="Open"];
+    "NULL" [shape=doublecircle, label="NULL"];
+    "Closed" -> "Closed" [label="locked"];
+    "Closed" -> "Open" [label="!locked && self.previous.is_me()"];
+    "Closed" -> "NULL" [style=dashed];
+    "Open" -> "Closed";
+    subgraph "cluster_Open/Timer/" {
+        label="run Timer";
+        style=dashed;
+        "Open/Timer/Tick" [label="Tick<n: int64>"];
+        "Open/Timer/NULL" [shape=doublecircle, label="NULL"];
+        "Open/Timer/Tick" -> "Open/Timer/Tick" [label="n > 0"];
+        "Open/Timer/Tick" -> "Open/Timer/NULL" [style=dashed];
+    }
+    "Open" -> "Open/Timer/Tick" [label="run", style=dotted];
+}
+digraph "Timer" {
+    rankdir=LR;
+    node [shape=box, style=rounded];
+    ".start" [shape=point];
+    ".start" -> "Tick";
+    "Tick" [label="Tick<n: int64>"];
+    "NULL" [shape=doublecircle, label="NULL"];
+    "Tick" -> "Tick" [label="n > 0"];
+    "Tick" -> "NULL" [style=dashed];
+}
+"#);
+    assert_eq!(quote("say \"hi\" \\"), r#""say \"hi\" \\""#);
+}
@@ -0,0 +1,773 @@
+//! Canonical layout of the FAN source for `fan fmt`: the module is parsed and printed back
+//! with four spaces indentation, spaced binary operators and one statement per line.
+//! Comments are kept, own line ones before the next statement and the end of line ones
+//! after the line their code is printed on.
+
+use crate::lexer::{BlockSymbol, FANGrammarToken, Lexer, Position};
+use crate::parser::{
+    AutomataDef, AutomataKind, Block, DefinitionExp, Expression, ExpressionType, FANType, FunctionDef, IfExp, Imports,
+    Literal, MatchExp, Name, Param, ParseError, Parser, Pattern, ProceduralExp, ReturnableExp, SingleName, StateDef,
+    Statement, TypeTemplate, ASSIGNMENTS, COMPARISON_PRECEDENCE, PRECEDENCE, TEMPLATE_PRECEDENCE,
+};
+
+pub const INDENT: &str = "    ";
+
+/// Position after the end of any source
+const END: Position = Position { line: usize::MAX, col: 0 };
+
+/// Binding strength of the printed expressions, brackets are added around the looser ones
+const ASSIGNMENT: usize = 0;
+const UNARY: usize = PRECEDENCE.len() + 1;
+const POSTFIX: usize = UNARY + 1;
+
+/// Formats the source, the source must parse
+pub fn format(source: &str) -> Result<String, ParseError> {
+    let tokens = Lexer::lex_str(source)?;
+    let comments = Lexer::comments(source)?;
+    let mut opened = vec![];
+    let mut braces = vec![];
+    for (position, token) in tokens.iter() {
+        match token {
+            FANGrammarToken::BlockSymbol(BlockSymbol::BlockBracketOpen) => {
+                opened.push(braces.len());
+                braces.push((*position, END));
+            },
+            FANGrammarToken::BlockSymbol(BlockSymbol::BlockBracketClose) => {
+                if let Some(i) = opened.pop() {
+                    braces[i].1 = *position;
+                }
+            },
+            _ => {},
+        }
+    }
+    let module = Parser::parse_positioned(tokens)?;
+    let mut printer = Printer::new(source.lines().collect(), comments, braces);
+    let mut previous_upload = false;
+    for e in module.expressions.iter() {
+        let upload = matches!(e.kind, ExpressionType::Import(_));
+        printer.leading(e.position, !(upload && previous_upload));
+        printer.item(e);
+        printer.out.push('\n');
+        previous_upload = upload;
+    }
+    printer.flush(END, !module.expressions.is_empty());
+    Ok(printer.out)
+}
+
+/// Canonical source of the expression without the comments,
+/// the same for the expressions that differ only in the layout. Statements keep their `;`.
+pub fn expression_source(e: &Expression) -> String {
+    let mut printer = Printer::new(vec![], vec![], vec![]);
+    match &e.kind {
+        ExpressionType::Returnable(_) => printer.expression(e, ASSIGNMENT),
+        _ => printer.statement_body(e, false),
+    }
+    printer.out
+}
+
+/// Same as [`expression_source`] for the block
+pub fn block_source(b: &Block) -> String {
+    let mut printer = Printer::new(vec![], vec![], vec![]);
+    printer.block(b, Position::default(), false);
+    printer.out
+}
+
+struct Printer<'s> {
+    out: String,
+    depth: usize,
+    /// Nothing is printed in the current block yet
+    fresh: bool,
+    /// Source line of the last printed node or comment
+    line: usize,
+    lines: Vec<&'s str>,
+    comments: Vec<(Position, String)>,
+    next_comment: usize,
+    /// Positions of the `{` and its `}` in the source order
+    braces: Vec<(Position, Position)>,
+    next_brace: usize,
+}
+
+impl<'s> Printer<'s> {
+    fn new(lines: Vec<&'s str>, comments: Vec<(Position, String)>, braces: Vec<(Position, Position)>) -> Self {
+        Self { out: String::new(), depth: 0, fresh: true, line: 0, lines, comments, next_comment: 0, braces, next_brace: 0 }
+    }
+
+    fn indent(&mut self) {
+        self.out.push_str(&INDENT.repeat(self.depth));
+    }
+
+    fn blank_before(&self, line: usize) -> bool {
+        line > 0 && self.lines.get(line - 1).is_some_and(|l| l.trim().is_empty())
+    }
+
+    /// Empty line before the next printed line: always when `separate`, otherwise where the source has it
+    fn gap(&mut self, line: usize, separate: bool) {
+        let blank = line != self.line && self.blank_before(line);
+        if !self.fresh && (separate || blank) && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+        self.line = line;
+    }
+
+    /// Prints the comments before the position, returns whether the `separate` empty line is still due
+    fn flush(&mut self, before: Position, mut separate: bool) -> bool {
+        while let Some((position, text)) = self.comments.get(self.next_comment).filter(|(p, _)| *p < before).cloned() {
+            self.next_comment += 1;
+            let code_before = self.lines[position.line].chars().take(position.col).any(|c| !c.is_whitespace());
+            if code_before && self.out.ends_with('\n') {
+                self.out.pop();
+                self.out.push_str(&format!(" #{}\n", text.trim_end()));
+            } else {
+                self.gap(position.line, separate);
+                separate = false;
+                self.indent();
+                self.out.push_str(&format!("#{}\n", text.trim_end()));
+                self.fresh = false;
+            }
+        }
+        separate
+    }
+
+    /// Comments and the empty line before the line of the node at the position
+    fn leading(&mut self, position: Position, separate: bool) {
+        let separate = self.flush(position, separate);
+        self.gap(position.line, separate);
+        self.fresh = false;
+    }
+
+    fn has_comment_before(&self, position: Position) -> bool {
+        self.comments.get(self.next_comment).is_some_and(|(p, _)| *p < position)
+    }
+
+    /// Takes the first `{` of the source at or after the position, returns the position of its `}`
+    fn open(&mut self, after: Position) -> Position {
+        while self.braces.get(self.next_brace).is_some_and(|(open, _)| *open < after) {
+            self.next_brace += 1;
+        }
+        let close = self.braces.get(self.next_brace).map(|(_, close)| *close).unwrap_or(END);
+        self.next_brace += 1;
+        self.out.push('{');
+        close
+    }
+
+    /// Starts the lines of the block, returns `false` for the block with nothing to print that stays `{}`
+    fn open_lines(&mut self, empty: bool, close: Position) -> bool {
+        if empty && !self.has_comment_before(close) {
+            self.out.push('}');
+            return false;
+        }
+        self.out.push('\n');
+        self.depth += 1;
+        self.fresh = true;
+        true
+    }
+
+    fn close(&mut self, close: Position) {
+        self.flush(close, false);
+        self.depth -= 1;
+        self.indent();
+        self.out.push('}');
+        self.fresh = false;
+    }
+
+    /* ---------------------------- declarations ----------------------------- */
+
+    fn item(&mut self, e: &Expression) {
+        self.indent();
+        match &e.kind {
+            ExpressionType::Import(i) => self.upload(i),
+            ExpressionType::Definition(DefinitionExp::Automata(a)) => self.automata(a),
+            ExpressionType::Definition(DefinitionExp::Function(f)) => self.function(f),
+            ExpressionType::Definition(DefinitionExp::AutomataState(s)) => self.state(s),
+            _ => self.statement_body(e, false),
+        }
+    }
+
+    fn upload(&mut self, i: &Imports) {
+        self.out.push_str(&format!("upload {} from ", i.names.join(", ")));
+        if is_raw_path(&i.from) {
+            self.out.push_str(&i.from);
+        } else {
+            self.out.push_str(&quote(&i.from, '"'));
+        }
+    }
+
+    fn automata(&mut self, a: &AutomataDef) {
+        self.out.push_str(&format!("automata {}", a.name));
+        if let AutomataKind::Mealy(signal, output) = &a.kind {
+            self.out.push_str(&format!(": Mealy<{}", signal));
+            if let Some(output) = output {
+                self.out.push_str(&format!(", {}", output));
+            }
+            self.out.push('>');
+        }
+        self.out.push(' ');
+        let close = self.open(a.position);
+        let mut members: Vec<(Position, Option<&StateDef>, Option<&FunctionDef>)> = a.states.iter().map(|s| (s.position, Some(s), None))
+            .chain(a.functions.iter().map(|f| (f.position, None, Some(f))))
+            .collect();
+        members.sort_by_key(|m| m.0);
+        if self.open_lines(members.is_empty(), close) {
+            for (position, state, function) in members {
+                self.leading(position, false);
+                self.indent();
+                if let Some(s) = state {
+                    self.state(s);
+                }
+                if let Some(f) = function {
+                    self.function(f);
+                }
+                self.out.push('\n');
+            }
+            self.close(close);
+        }
+    }
+
+    fn params(&mut self, params: &[Param]) {
+        let params: Vec<String> = params.iter().map(|p| match &p.ty {
+            Some(t) => format!("{}: {}", p.name, t),
+            None => p.name.clone(),
+        }).collect();
+        self.out.push_str(&params.join(", "));
+    }
+
+    fn state(&mut self, s: &StateDef) {
+        if s.initial {
+            self.out.push_str("initial ");
+        }
+        self.out.push_str(&format!("state {}", s.name));
+        if !s.params.is_empty() {
+            self.out.push('<');
+            self.params(&s.params);
+            self.out.push('>');
+        }
+        self.out.push(' ');
+        self.block(&s.body, s.position, false);
+    }
+
+    fn function(&mut self, f: &FunctionDef) {
+        self.out.push_str(&format!("fn {}(", f.name));
+        self.params(&f.params);
+        self.out.push(')');
+        if let Some(t) = &f.returns {
+            self.out.push_str(&format!(" -> {}", t));
+        }
+        self.out.push(' ');
+        let value = f.returns.as_ref().is_some_and(|t| *t != FANType::unit());
+        self.block(&f.body, f.position, value);
+    }
+
+    /* ------------------------------ statements ----------------------------- */
+
+    /// Prints the block starting at the first `{` after the position, returns the position of its `}`.
+    /// The last statement of the `value` block is printed without `;`.
+    fn block(&mut self, b: &Block, after: Position, value: bool) -> Position {
+        let close = self.open(after);
+        if self.open_lines(b.block.is_empty(), close) {
+            for (i, e) in b.block.iter().enumerate() {
+                self.leading(e.position, false);
+                self.indent();
+                self.statement_body(e, value && i + 1 == b.block.len());
+                self.out.push('\n');
+            }
+            self.close(close);
+        }
+        close
+    }
+
+    fn statement_body(&mut self, e: &Expression, tail: bool) {
+        match &e.kind {
+            ExpressionType::Definition(DefinitionExp::Define(d)) => {
+                self.out.push_str(&format!("let {}", d.name));
+                if let Some(t) = &d.ty {
+                    self.out.push_str(&format!(": {}", t));
+                }
+                self.out.push_str(" = ");
+                self.expression(&d.value, ASSIGNMENT);
+                self.out.push(';');
+            },
+            ExpressionType::Definition(_) | ExpressionType::Import(_) => self.item(e),
+            ExpressionType::Procedural(ProceduralExp::Link(l)) => {
+                self.out.push_str(&format!("link {} -> ", l.from));
+                match &l.target {
+                    Some(target) => self.single_name(target),
+                    None => self.out.push_str("NULL"),
+                }
+                self.out.push(';');
+            },
+            ExpressionType::Procedural(ProceduralExp::Return(v)) => {
+                self.out.push_str("return");
+                if let Some(v) = v {
+                    self.out.push(' ');
+                    self.expression(v, ASSIGNMENT);
+                }
+                self.out.push(';');
+            },
+            ExpressionType::Procedural(ProceduralExp::While(w)) => {
+                self.out.push_str("while ");
+                self.expression(&w.condition, ASSIGNMENT + 1);
+                self.out.push(' ');
+                self.block(&w.body, e.position, false);
+            },
+            ExpressionType::Procedural(ProceduralExp::For(f)) => {
+                self.out.push_str(&format!("for {} in ", f.variable));
+                self.expression(&f.iterable, ASSIGNMENT + 1);
+                self.out.push(' ');
+                self.block(&f.body, e.position, false);
+            },
+            ExpressionType::Returnable(ReturnableExp::If(i)) => self.if_expression(i, e.position, tail),
+            ExpressionType::Returnable(ReturnableExp::Match(m)) => self.match_expression(m, e.position),
+            ExpressionType::Returnable(ReturnableExp::Statement(Statement::Block(b))) => {
+                self.block(b, e.position, tail);
+            },
+            ExpressionType::Returnable(r) => {
+                self.expression(e, ASSIGNMENT);
+                let assignment = matches!(r, ReturnableExp::BinaryOperator(b) if ASSIGNMENTS.contains(&b.operator.as_str()));
+                if assignment || !tail {
+                    self.out.push(';');
+                }
+            },
+        }
+    }
+
+    fn if_expression(&mut self, i: &IfExp, position: Position, value: bool) {
+        self.out.push_str("if ");
+        self.expression(&i.condition, ASSIGNMENT + 1);
+        self.out.push(' ');
+        let close = self.block(&i.then, position, value);
+        if let Some(otherwise) = &i.otherwise {
+            self.out.push_str(" else ");
+            match otherwise.block.as_slice() {
+                [Expression { kind: ExpressionType::Returnable(ReturnableExp::If(nested)), position }] => {
+                    self.if_expression(nested, *position, value);
+                },
+                _ => {
+                    self.block(otherwise, close, value);
+                },
+            }
+        }
+    }
+
+    /// Arms are values, the blocks of the arms are printed as value blocks
+    fn match_expression(&mut self, m: &MatchExp, position: Position) {
+        self.out.push_str("match ");
+        self.expression(&m.value, ASSIGNMENT + 1);
+        self.out.push(' ');
+        let close = self.open(position);
+        if self.open_lines(m.arms.is_empty(), close) {
+            for arm in m.arms.iter() {
+                self.leading(arm.position, false);
+                self.indent();
+                self.pattern(&arm.pattern);
+                self.out.push_str(" => ");
+                match &arm.body.kind {
+                    ExpressionType::Returnable(ReturnableExp::Statement(Statement::Block(b))) => {
+                        self.block(b, arm.body.position, true);
+                    },
+                    _ => {
+                        self.expression(&arm.body, ASSIGNMENT);
+                        self.out.push(',');
+                    },
+                }
+                self.out.push('\n');
+            }
+            self.close(close);
+        }
+    }
+
+    /* ----------------------------- expressions ----------------------------- */
+
+    /// Prints the expression, in brackets when it binds looser than `min`
+    fn expression(&mut self, e: &Expression, min: usize) {
+        let bracket = binding(e) < min;
+        if bracket {
+            self.out.push('(');
+        }
+        match &e.kind {
+            ExpressionType::Returnable(r) => self.returnable(r, e.position),
+            _ => unreachable!("the parser does not nest declarations in expressions"),
+        }
+        if bracket {
+            self.out.push(')');
+        }
+    }
+
+    fn returnable(&mut self, r: &ReturnableExp, position: Position) {
+        match r {
+            ReturnableExp::Statement(s) => self.statement(s, position),
+            ReturnableExp::FunctionCall(s, args) => {
+                self.statement(s, position);
+                self.tuple(&args.tuple, false);
+            },
+            ReturnableExp::AutomataCall(call) => {
+                self.out.push_str("run ");
+                self.single_name(call);
+            },
+            ReturnableExp::BinaryOperator(b) => {
+                let op = b.operator.as_str();
+                let (left, right) = if ASSIGNMENTS.contains(&op) {
+                    (ASSIGNMENT + 1, ASSIGNMENT)
+                } else {
+                    let level = PRECEDENCE.iter().position(|ops| ops.contains(&op)).unwrap_or_default() + 1;
+                    (level, level + 1)
+                };
+                self.expression(&b.arg1, left);
+                self.out.push_str(&format!(" {} ", op));
+                self.expression(&b.arg2, right);
+            },
+            ReturnableExp::UnaryyOperator(u) => {
+                self.out.push_str(u.operator.as_str());
+                // `- -x` would be lexed as `--`
+                let nested = matches!(u.arg.kind, ExpressionType::Returnable(ReturnableExp::UnaryyOperator(_)));
+                self.expression(&u.arg, if nested { POSTFIX } else { UNARY });
+            },
+            ReturnableExp::Member(v, member) => {
+                self.expression(v, POSTFIX);
+                self.out.push_str(&format!(".{}", member));
+            },
+            ReturnableExp::MethodCall(v, method, args) => {
+                self.expression(v, POSTFIX);
+                self.out.push_str(&format!(".{}", method));
+                self.tuple(&args.tuple, false);
+            },
+            ReturnableExp::Index(v, i) => {
+                self.expression(v, POSTFIX);
+                self.out.push('[');
+                self.expression(i, ASSIGNMENT);
+                self.out.push(']');
+            },
+            ReturnableExp::If(i) => self.if_expression(i, position, true),
+            ReturnableExp::Match(m) => self.match_expression(m, position),
+            ReturnableExp::Is(v, p) => {
+                self.expression(v, COMPARISON_PRECEDENCE + 1);
+                self.out.push_str(" is ");
+                self.pattern(p);
+            },
+        }
+    }
+
+    fn statement(&mut self, s: &Statement, position: Position) {
+        match s {
+            Statement::Literal(l) => self.out.push_str(&literal(l)),
+            Statement::Block(b) => {
+                self.block(b, position, true);
+            },
+            Statement::Name(Name::SingleName(n)) => self.single_name(n),
+            Statement::Name(Name::NamespaceName(v)) => {
+                for (i, n) in v.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str("::");
+                    }
+                    self.single_name(n);
+                }
+            },
+            Statement::Tuple(t) => self.tuple(&t.tuple, true),
+        }
+    }
+
+    /// `(a, b)`, the tuple of one element keeps the trailing comma
+    fn tuple(&mut self, v: &[Expression], is_tuple: bool) {
+        self.out.push('(');
+        for (i, e) in v.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            self.expression(e, ASSIGNMENT);
+        }
+        if is_tuple && v.len() == 1 {
+            self.out.push(',');
+        }
+        self.out.push(')');
+    }
+
+    fn single_name(&mut self, n: &SingleName) {
+        self.out.push_str(&n.0);
+        if let Some(TypeTemplate { args }) = &n.1 {
+            self.out.push('<');
+            for (i, a) in args.iter().enumerate() {
+                if i > 0 {
+                    self.out.push_str(", ");
+                }
+                self.expression(a, TEMPLATE_PRECEDENCE + 1);
+            }
+            self.out.push('>');
+        }
+    }
+
+    fn pattern(&mut self, p: &Pattern) {
+        match p {
+            Pattern::Wildcard => self.out.push('_'),
+            Pattern::Binding(n) => self.out.push_str(n),
+            Pattern::Literal(l) => self.out.push_str(&literal(l)),
+            Pattern::Tuple(v) => self.patterns(v, true),
+            Pattern::State { automata, state, args } => {
+                if let Some(a) = automata {
+                    self.out.push_str(&format!("{}::", a));
+                }
+                self.out.push_str(state);
+                if let Some(args) = args {
+                    self.patterns(args, false);
+                }
+            },
+        }
+    }
+
+    fn patterns(&mut self, v: &[Pattern], is_tuple: bool) {
+        self.out.push('(');
+        for (i, p) in v.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            self.pattern(p);
+        }
+        if is_tuple && v.len() == 1 {
+            self.out.push(',');
+        }
+        self.out.push(')');
+    }
+}
+
+/// Binding strength of the expression, see [`ASSIGNMENT`]
+fn binding(e: &Expression) -> usize {
+    match &e.kind {
+        ExpressionType::Returnable(ReturnableExp::BinaryOperator(b)) => {
+            let op = b.operator.as_str();
+            match PRECEDENCE.iter().position(|ops| ops.contains(&op)) {
+                Some(level) => level + 1,
+                None => ASSIGNMENT,
+            }
+        },
+        ExpressionType::Returnable(ReturnableExp::Is(..)) => COMPARISON_PRECEDENCE + 1,
+        ExpressionType::Returnable(ReturnableExp::UnaryyOperator(_)) => UNARY,
+        _ => POSTFIX,
+    }
+}
+
+fn literal(l: &Literal) -> String {
+    match l {
+        Literal::Char(c) => quote(&c.to_string(), '\''),
+        Literal::String(s) => quote(s, '"'),
+        Literal::Digital(d) | Literal::NumericalLexem(d) => d.clone(),
+        Literal::Bool(b) => b.to_string(),
+        Literal::NULL => "NULL".to_string(),
+    }
+}
+
+/// Literal in the quotes with the escape sequences the lexer reads back
+fn quote(s: &str, q: char) -> String {
+    let mut r = String::from(q);
+    for c in s.chars() {
+        match c {
+            '\\' => r.push_str("\\\\"),
+            '\n' => r.push_str("\\n"),
+            '\t' => r.push_str("\\t"),
+            '\r' => r.push_str("\\r"),
+            '\0' => r.push_str("\\0"),
+            c if c == q => {
+                r.push('\\');
+                r.push(c);
+            },
+            c => r.push(c),
+        }
+    }
+    r.push(q);
+    r
+}
+
+/// Upload path that reads back the same without the quotes, `lib/contexts.fan`
+fn is_raw_path(path: &str) -> bool {
+    let Ok(tokens) = Lexer::lex_str(path) else { return false };
+    let mut raw = String::new();
+    for (_, t) in tokens.iter() {
+        match t {
+            FANGrammarToken::Name(s) | FANGrammarToken::Digital(s) | FANGrammarToken::NumericalLexem(s) => raw.push_str(s),
+            FANGrammarToken::Operational(o) if o.as_str() != ";" => raw.push_str(o.as_str()),
+            _ => return false,
+        }
+    }
+    raw == path
+}
+
+/// Line diff of the unified format with three lines of context, empty when the texts are equal
+pub fn diff(old: &str, new: &str) -> String {
+    const CONTEXT: usize = 3;
+    let (a, b): (Vec<&str>, Vec<&str>) = (old.lines().collect(), new.lines().collect());
+    // common[i][j] is the longest common subsequence of a[i..] and b[j..]
+    let mut common = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            common[i][j] = if a[i] == b[j] { common[i + 1][j + 1] + 1 } else { common[i + 1][j].max(common[i][j + 1]) };
+        }
+    }
+    // (' ' | '-' | '+', line of a, line of b)
+    let mut edits = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            edits.push((' ', i, j));
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || common[i + 1][j] >= common[i][j + 1]) {
+            edits.push(('-', i, j));
+            i += 1;
+        } else {
+            edits.push(('+', i, j));
+            j += 1;
+        }
+    }
+    let changed: Vec<usize> = (0..edits.len()).filter(|&k| edits[k].0 != ' ').collect();
+    let mut out = String::new();
+    let mut k = 0;
+    while k < changed.len() {
+        let start = changed[k].saturating_sub(CONTEXT);
+        let mut end = changed[k];
+        while k < changed.len() && changed[k] <= end + 2 * CONTEXT {
+            end = changed[k];
+            k += 1;
+        }
+        let end = (end + CONTEXT + 1).min(edits.len());
+        let hunk = &edits[start..end];
+        let old_count = hunk.iter().filter(|e| e.0 != '+').count();
+        let new_count = hunk.iter().filter(|e| e.0 != '-').count();
+        let line = |first: usize, count: usize| if count == 0 { first } else { first + 1 };
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            line(hunk[0].1, old_count), old_count, line(hunk[0].2, new_count), new_count,
+        ));
+        for (kind, i, j) in hunk.iter() {
+            let text = if *kind == '+' { b[*j] } else { a[*i] };
+            out.push_str(&format!("{}{}\n", kind, text));
+        }
+    }
+    if out.is_empty() && old != new {
+        out.push_str("\\ line endings differ\n");
+    }
+    out
+}
+
+#[test]
+fn format_test() {
+    let source = "\
+upload ContextType1,ContextType2 from contexts.fan
+upload Other from \"my lib/other.fan\";
+# counts up
+automata Count{# no input
+  state Up < n:int64 > {   # tuples!
+      if n<3{link self->Up<n+1>;}
+        else if (n == 3) { link self -> Down< ( n*2 , 1 ) >; }
+      else { if n>10 {return;} }
+
+
+      let t = ( n, ) ; let u=(1+2)*3-(4-5);
+      t[0]+=-(-1) ; x=!(a||b)&&c;
+
+
+      # trailing state comment
+  } # end of Up
+  fn helper(a: int64, b) -> int64 { a*b }
+  initial state Down<p: (int64, int64)> {
+    match p { (0, _) => 1,
+      (-1, 'a')=>{ f(p.0, 'x') }
+      (n, c) =>n, }
+    let s = \"say \\\"hi\\\"\\n\";
+    let r = run Count<1 + 2, (1 > 2)>;
+    if r is Count::Up(v) { link self -> NULL } else {}
+    while v .x<3 { v = { 1; 2 } ; }
+  }
+  state Empty { }
+  state Todo { # later
+  }
+}
+fn unit() { print(1) }
+";
+    let expected = "\
+upload ContextType1, ContextType2 from contexts.fan
+upload Other from \"my lib/other.fan\"
+
+# counts up
+automata Count { # no input
+    state Up<n: int64> { # tuples!
+        if n < 3 {
+            link self -> Up<n + 1>;
+        } else if n == 3 {
+            link self -> Down<(n * 2, 1)>;
+        } else if n > 10 {
+            return;
+        }
+
+        let t = (n,);
+        let u = (1 + 2) * 3 - (4 - 5);
+        t[0] += -(-1);
+        x = !(a || b) && c;
+
+        # trailing state comment
+    } # end of Up
+    fn helper(a: int64, b) -> int64 {
+        a * b
+    }
+    initial state Down<p: (int64, int64)> {
+        match p {
+            (0, _) => 1,
+            (-1, 'a') => {
+                f(p.0, 'x')
+            }
+            (n, c) => n,
+        }
+        let s = \"say \\\"hi\\\"\\n\";
+        let r = run Count<1 + 2, (1 > 2)>;
+        if r is Count::Up(v) {
+            link self -> NULL;
+        } else {}
+        while v.x < 3 {
+            v = {
+                1;
+                2
+            };
+        }
+    }
+    state Empty {}
+    state Todo { # later
+    }
+}
+
+fn unit() {
+    print(1);
+}
+";
+    let formatted = format(source).unwrap();
+    assert_eq!(formatted, expected, "\n{}", diff(expected, &formatted));
+    assert_eq!(format(&formatted).unwrap(), formatted);
+    assert_eq!(format("# only a comment\n\n\n").unwrap(), "# only a comment\n");
+    assert!(matches!(format("automata A { state S { link self } }"), Err(ParseError::Expected(..))));
+    assert!(matches!(format("let s = \"open"), Err(ParseError::Lex(_))));
+    let module = Parser::parse_str("fn f() { if a {x=( 1 );} else { # other\n x = 1 } }").unwrap();
+    let ExpressionType::Returnable(ReturnableExp::If(i)) = &module.find_function("f").unwrap().body.block[0].kind else { panic!() };
+    assert_eq!(block_source(&i.then), "{\n    x = 1;\n}");
+    assert_eq!(block_source(&i.then), block_source(i.otherwise.as_ref().unwrap()));
+    assert_eq!(expression_source(&module.expressions[0]), "fn f() {\n    if a {\n        x = 1;\n    } else {\n        x = 1;\n    }\n}");
+}
+
+#[test]
+fn diff_test() {
+    assert_eq!(diff("a\nb\n", "a\nb\n"), "");
+    let old = "1\n2\n3\n4\n5\nold\n7\n8\n9\n10\n11\n12\n13\n14\n15\n16\n";
+    let new = "1\n2\n3\n4\n5\nnew\n7\n8\n9\n10\n11\n12\n13\n14\n15\n16\nadded\n";
+    assert_eq!(diff(old, new), "\
+@@ -3,7 +3,7 @@
+ 3
+ 4
+ 5
+-old
++new
+ 7
+ 8
+ 9
+@@ -14,3 +14,4 @@
+ 14
+ 15
+ 16
++added
+");
+    assert_eq!(diff("a", "a\n"), "\\ line endings differ\n");
+}
+
@@ -0,0 +1,272 @@
+//! Transition graph of the automata: the states and the `link` edges between them.
+//! The exports and the analyses of the module are built on it.
+
+use crate::checker::always_links;
+use crate::format::expression_source;
+use crate::json::Json;
+use crate::lexer::Position;
+use crate::parser::{AutomataDef, AutomataKind, Block, Expression, ExpressionType, Module, ProceduralExp, ReturnableExp, Statement};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateNode {
+    pub name: String,
+    /// Template parameters as written, `n: int64`
+    pub params: Vec<String>,
+    pub initial: bool,
+    /// Automata started by `run` in the body
+    pub runs: Vec<String>,
+    pub position: Position,
+}
+
+impl StateNode {
+    /// Name with the template parameters, `Closed<locked: bool>`
+    pub fn label(&self) -> String {
+        if self.params.is_empty() { self.name.clone() } else { format!("{}<{}>", self.name, self.params.join(", ")) }
+    }
+}
+
+/// `link` of the state body, `to` is `None` for NULL
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edge {
+    pub from: String,
+    pub to: Option<String>,
+    /// Conditions of the `if`s around the link, the outer first, `!(c)` in the `else` branch
+    /// and `(a || b)` for the disjunctions
+    pub guard: Vec<String>,
+    /// Template arguments of the target as written
+    pub args: Vec<String>,
+    /// Default link to NULL of the body paths without `link`
+    pub implicit: bool,
+    pub position: Position,
+}
+
+impl Edge {
+    /// Conjunction of the guard, empty for the unconditional edges
+    pub fn condition(&self) -> String {
+        self.guard.join(" && ")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AutomataGraph {
+    pub name: String,
+    pub kind: AutomataKind,
+    pub states: Vec<StateNode>,
+    /// In the order of the `link`s in the source, the implicit ones after the state's own
+    pub edges: Vec<Edge>,
+}
+
+impl AutomataGraph {
+    pub fn new(a: &AutomataDef) -> Self {
+        let initial = a.initial_state().map(|s| s.name.as_str());
+        let mut states = vec![];
+        let mut edges = vec![];
+        for s in a.states.iter() {
+            let mut runs = vec![];
+            let guards = guards(&s.body);
+            s.body.walk(&mut |e| match &e.kind {
+                ExpressionType::Procedural(ProceduralExp::Link(l)) => edges.push(Edge {
+                    from: s.name.clone(),
+                    to: l.target.as_ref().map(|t| t.0.clone()),
+                    guard: guards.iter().find(|(link, _)| std::ptr::eq(*link, e)).map(|(_, g)| g.clone()).unwrap_or_default(),
+                    args: l.target.iter().flat_map(|t| t.1.iter()).flat_map(|t| t.args.iter()).map(expression_source).collect(),
+                    implicit: false,
+                    position: e.position,
+                }),
+                ExpressionType::Returnable(ReturnableExp::AutomataCall(call)) if !runs.contains(&call.0) => {
+                    runs.push(call.0.clone());
+                },
+                _ => {},
+            });
+            if !always_links(&s.body) {
+                edges.push(Edge { from: s.name.clone(), to: None, guard: vec![], args: vec![], implicit: true, position: s.position });
+            }
+            states.push(StateNode {
+                name: s.name.clone(),
+                params: s.params.iter().map(|p| match &p.ty {
+                    Some(t) => format!("{}: {}", p.name, t),
+                    None => p.name.clone(),
+                }).collect(),
+                initial: initial == Some(s.name.as_str()),
+                runs,
+                position: s.position,
+            });
+        }
+        Self { name: a.name.clone(), kind: a.kind.clone(), states, edges }
+    }
+
+    pub fn initial(&self) -> Option<&StateNode> {
+        self.states.iter().find(|s| s.initial)
+    }
+
+    /// Distinct edges by their ends, in the order of the first occurrence
+    pub fn transitions(&self) -> Vec<(&str, Option<&str>)> {
+        let mut transitions = vec![];
+        for e in self.edges.iter() {
+            let t = (e.from.as_str(), e.to.as_deref());
+            if !transitions.contains(&t) {
+                transitions.push(t);
+            }
+        }
+        transitions
+    }
+
+    /// Distinct edges by their ends and guards, with the guards joined by `&&`
+    pub fn labelled_edges(&self) -> Vec<(&Edge, String)> {
+        let mut edges: Vec<(&Edge, String)> = vec![];
+        for e in self.edges.iter() {
+            let label = e.condition();
+            if !edges.iter().any(|(d, l)| d.from == e.from && d.to == e.to && *l == label) {
+                edges.push((e, label));
+            }
+        }
+        edges
+    }
+
+    pub fn to_json(&self) -> Json {
+        let kind = match &self.kind {
+            AutomataKind::Moore => Json::object([("type", "moore".into())]),
+            AutomataKind::Mealy(signal, output) => Json::object([
+                ("type", "mealy".into()), ("signal", signal.as_str().into()), ("output", output.as_deref().into()),
+            ]),
+        };
+        let states = self.states.iter().map(|s| Json::object([
+            ("name", s.name.as_str().into()),
+            ("params", s.params.clone().into()),
+            ("initial", s.initial.into()),
+            ("runs", s.runs.clone().into()),
+            ("line", (s.position.line + 1).into()),
+        ])).collect();
+        let edges = self.edges.iter().map(|e| Json::object([
+            ("from", e.from.as_str().into()),
+            ("to", e.to.as_deref().into()),
+            ("guard", e.guard.clone().into()),
+            ("args", e.args.clone().into()),
+            ("implicit", e.implicit.into()),
+            ("line", (e.position.line + 1).into()),
+        ])).collect();
+        Json::object([
+            ("name", self.name.as_str().into()), ("kind", kind), ("states", Json::Array(states)), ("edges", Json::Array(edges)),
+        ])
+    }
+}
+
+/// Conditions guarding every `link` of the state body
+fn guards(body: &Block) -> Vec<(&Expression, Vec<String>)> {
+    let mut guards: Vec<(&Expression, Vec<String>)> = vec![];
+    body.walk(&mut |e| {
+        if let ExpressionType::Procedural(ProceduralExp::Link(_)) = &e.kind {
+            guards.push((e, vec![]));
+        }
+    });
+    // `walk` visits the outer `if` first
+    body.walk(&mut |e| {
+        let ExpressionType::Returnable(ReturnableExp::If(i)) = &e.kind else { return };
+        let condition = match &i.condition.kind {
+            ExpressionType::Returnable(ReturnableExp::BinaryOperator(b)) if b.operator.as_str() == "||" => {
+                format!("({})", expression_source(&i.condition))
+            },
+            _ => expression_source(&i.condition),
+        };
+        let negated = match &i.condition.kind {
+            ExpressionType::Returnable(
+                ReturnableExp::Statement(Statement::Name(_)) | ReturnableExp::FunctionCall(..)
+                | ReturnableExp::Member(..) | ReturnableExp::MethodCall(..) | ReturnableExp::Index(..)
+            ) => format!("!{}", condition),
+            _ if condition.starts_with('(') => format!("!{}", condition),
+            _ => format!("!({})", condition),
+        };
+        let branches = [(Some(&i.then), condition), (i.otherwise.as_ref(), negated)];
+        for (block, condition) in branches {
+            block.iter().for_each(|b| b.walk(&mut |link| {
+                if let Some((_, g)) = guards.iter_mut().find(|(l, _)| std::ptr::eq(*l, link)) {
+                    g.push(condition.clone());
+                }
+            }));
+        }
+    });
+    guards
+}
+
+/// Edge list of every automata, NULL included
+impl std::fmt::Display for AutomataGraph {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "automata {}", self.name)?;
+        for (from, to) in self.transitions() {
+            writeln!(f, "    {} -> {}", from, to.unwrap_or("NULL"))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Graph {
+    pub automata: Vec<AutomataGraph>,
+}
+
+impl Graph {
+    pub fn new(module: &Module) -> Self {
+        Self { automata: module.automata().map(AutomataGraph::new).collect() }
+    }
+
+    pub fn find(&self, name: &str) -> Option<&AutomataGraph> {
+        self.automata.iter().find(|a| a.name == name)
+    }
+
+    pub fn to_json(&self) -> Json {
+        Json::object([("automata", Json::Array(self.automata.iter().map(AutomataGraph::to_json).collect()))])
+    }
+}
+
+impl std::fmt::Display for Graph {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.automata.iter().try_for_each(|a| write!(f, "{}", a))
+    }
+}
+
+#[test]
+fn graph_test() {
+    use crate::parser::Parser;
+
+    let module = Parser::parse_str("
+        automata Door {
+            state Closed<locked: bool> {
+                if locked { link self -> Closed<false>; } else { link self -> Open; }
+            }
+            state Open { let _ = run Timer<3>; link self -> Closed<true>; link self -> Closed<false>; }
+            initial state Broken { }
+        }
+        automata Timer {
+            state Tick<n: int64> { if n > 0 { link self -> Tick<n - 1>; } else { link self -> NULL; } }
+        }
+    ").unwrap();
+    let graph = Graph::new(&module);
+    assert_eq!(graph.to_string(), "\
+automata Door
+    Closed -> Closed
+    Closed -> Open
+    Open -> Closed
+    Broken -> NULL
+automata Timer
+    Tick -> Tick
+    Tick -> NULL
+");
+    let door = &graph.automata[0];
+    assert_eq!(door.initial().map(|s| s.name.as_str()), Some("Broken"));
+    assert_eq!(door.states[0].params, ["locked: bool"]);
+    assert_eq!(door.states[0].label(), "Closed<locked: bool>");
+    assert_eq!(door.labelled_edges().len(), 4);
+    assert_eq!(door.states[1].runs, ["Timer"]);
+    assert!(door.edges.iter().any(|e| e.implicit && e.from == "Broken"));
+    assert!(!graph.automata[1].edges.iter().any(|e| e.implicit));
+    assert_eq!(door.edges[1].guard, ["!locked"]);
+    assert!(door.edges[2].guard.is_empty());
+    assert_eq!(
+        graph.automata[1].to_json().to_string(),
+        concat!(
+            r#"{"name":"Timer","kind":{"type":"moore"},"states":[{"name":"Tick","params":["n: int64"],"initial":true,"runs":[],"line":10}],"#,
+            r#""edges":[{"from":"Tick","to":"Tick","guard":["n > 0"],"args":["n - 1"],"implicit":false,"line":10},"#,
+            r#"{"from":"Tick","to":null,"guard":["!(n > 0)"],"args":[],"implicit":false,"line":10}]}"#,
+        ),
+    );
+}
@@ -0,0 +1,216 @@
+//! Embedding API: Rust types uploaded into FAN and native functions callable from FAN bodies.
+//!
+//! `upload Counter from host.fan` declares the type, `Counter(1)` constructs it with
+//! [`FanValue::construct`], `c.count` and `c.add(2)` dispatch to [`FanValue::member`] and [`FanValue::method`].
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::interpreter::{ExecError, Value};
+
+/// Rust type usable as the uploaded FAN type
+pub trait FanValue: std::fmt::Debug + Clone + PartialEq + 'static {
+    /// Name of the type in the `upload` declaration
+    const NAME: &'static str;
+
+    /// `Name(args)` constructor
+    fn construct(args: Vec<Value>) -> Result<Self, ExecError>;
+
+    /// `value.member`
+    fn member(&self, member: &str) -> Result<Value, ExecError> {
+        Err(ExecError::NoSuchMember(member.to_string()))
+    }
+
+    /// `value.method(args)`, values are immutable in FAN, so changes are returned as the new value
+    fn method(&self, method: &str, _args: Vec<Value>) -> Result<Value, ExecError> {
+        Err(ExecError::UndefinedMethod(method.to_string()))
+    }
+}
+
+/// Object safe side of [`FanValue`]
+trait HostObject: std::fmt::Debug {
+    fn type_name(&self) -> &'static str;
+    fn as_any(&self) -> &dyn Any;
+    fn equals(&self, other: &dyn HostObject) -> bool;
+    fn member(&self, member: &str) -> Result<Value, ExecError>;
+    fn method(&self, method: &str, args: Vec<Value>) -> Result<Value, ExecError>;
+}
+
+impl<T: FanValue> HostObject for T {
+    fn type_name(&self) -> &'static str {
+        T::NAME
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn equals(&self, other: &dyn HostObject) -> bool {
+        other.as_any().downcast_ref::<T>() == Some(self)
+    }
+
+    fn member(&self, member: &str) -> Result<Value, ExecError> {
+        FanValue::member(self, member)
+    }
+
+    fn method(&self, method: &str, args: Vec<Value>) -> Result<Value, ExecError> {
+        FanValue::method(self, method, args)
+    }
+}
+
+/// Value of the uploaded type, cheap to clone
+#[derive(Debug, Clone)]
+pub struct HostValue(Rc<dyn HostObject>);
+
+impl HostValue {
+    pub fn new<T: FanValue>(value: T) -> Self {
+        Self(Rc::new(value))
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        self.0.type_name()
+    }
+
+    pub fn downcast_ref<T: FanValue>(&self) -> Option<&T> {
+        self.0.as_any().downcast_ref()
+    }
+
+    pub fn member(&self, member: &str) -> Result<Value, ExecError> {
+        self.0.member(member)
+    }
+
+    pub fn method(&self, method: &str, args: Vec<Value>) -> Result<Value, ExecError> {
+        self.0.method(method, args)
+    }
+}
+
+impl std::fmt::Display for HostValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl PartialEq for HostValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.equals(other.0.as_ref())
+    }
+}
+
+type Native = Rc<dyn Fn(Vec<Value>) -> Result<Value, ExecError>>;
+
+/// Registry of the uploaded types and native functions shared by the interpreter, the VM and the checker
+#[derive(Clone, Default)]
+pub struct Host {
+    functions: HashMap<String, Native>,
+}
+
+impl Host {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the constructor of the uploaded type
+    pub fn with_type<T: FanValue>(self) -> Self {
+        self.with_function(T::NAME, |args| T::construct(args).map(|v| Value::Host(HostValue::new(v))))
+    }
+
+    /// Registers the native function, FAN functions of the same name take precedence
+    pub fn with_function(mut self, name: &str, f: impl Fn(Vec<Value>) -> Result<Value, ExecError> + 'static) -> Self {
+        self.functions.insert(name.to_string(), Rc::new(f));
+        self
+    }
+
+    pub fn has_function(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    pub fn function_names(&self) -> impl Iterator<Item = &str> {
+        self.functions.keys().map(|n| n.as_str())
+    }
+
+    /// Calls the native function or the constructor
+    pub fn call(&self, name: &str, args: Vec<Value>) -> Result<Value, ExecError> {
+        let f = self.functions.get(name).ok_or_else(|| ExecError::UndefinedFunction(name.to_string()))?;
+        f(args)
+    }
+}
+
+impl std::fmt::Debug for Host {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut names: Vec<_> = self.function_names().collect();
+        names.sort();
+        f.debug_struct("Host").field("functions", &names).finish()
+    }
+}
+
+#[test]
+fn host_test() {
+    use crate::bytecode::Program;
+    use crate::checker::{CheckError, Checker};
+    use crate::interpreter::Interpreter;
+    use crate::parser::Parser;
+    use crate::vm::Vm;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Counter(i64);
+
+    impl FanValue for Counter {
+        const NAME: &'static str = "Counter";
+
+        fn construct(args: Vec<Value>) -> Result<Self, ExecError> {
+            match args.as_slice() {
+                [] => Ok(Counter(0)),
+                [Value::Int(i)] => Ok(Counter(*i)),
+                _ => Err(ExecError::ArityMismatch { name: Self::NAME.to_string(), expected: 1, found: args.len() }),
+            }
+        }
+
+        fn member(&self, member: &str) -> Result<Value, ExecError> {
+            match member {
+                "count" => Ok(Value::Int(self.0)),
+                _ => Err(ExecError::NoSuchMember(member.to_string())),
+            }
+        }
+
+        fn method(&self, method: &str, args: Vec<Value>) -> Result<Value, ExecError> {
+            match (method, args.as_slice()) {
+                ("add", [Value::Int(i)]) => Ok(Value::Host(HostValue::new(Counter(self.0 + i)))),
+                _ => Err(ExecError::UndefinedMethod(method.to_string())),
+            }
+        }
+    }
+
+    let module = Parser::parse_str("
+        upload Counter from host.fan
+        automata Count {
+            state Start { link self -> Step<Counter(), twice(1)>; }
+            state Step<c: Counter, by: int64> {
+                if c.count < 10 {
+                    link self -> Step<c.add(by), by>;
+                }
+            }
+        }
+    ").unwrap();
+    let host = Host::new()
+        .with_type::<Counter>()
+        .with_function("twice", |args| match args.as_slice() {
+            [Value::Int(i)] => Ok(Value::Int(i * 2)),
+            _ => Err(ExecError::ArityMismatch { name: "twice".to_string(), expected: 1, found: args.len() }),
+        });
+    assert_eq!(Checker::check_with_host(&module, &host), Ok(()));
+    assert!(matches!(Checker::check(&module).unwrap_err().as_slice(), [CheckError::UndefinedFunction(f, _)] if f == "twice"));
+
+    let done = Interpreter::new(&module).with_host(host.clone()).run_automata("Count", vec![]).unwrap();
+    let counter = match done.arg("c") {
+        Some(Value::Host(h)) => h.downcast_ref::<Counter>().cloned(),
+        _ => None,
+    };
+    assert_eq!(counter, Some(Counter(10)));
+    let program = Program::compile(&module);
+    assert_eq!(Vm::new(&program).with_host(host).run_automata("Count", vec![]), Ok(done));
+    assert_eq!(
+        Interpreter::new(&module).run_automata("Count", vec![]),
+        Err(ExecError::UndefinedFunction("Counter".to_string())),
+    );
+}
@@ -0,0 +1,715 @@
+//! Importers of the state diagrams sketched in Mermaid `stateDiagram` or Graphviz DOT for `fan import`.
+//! Every diagram becomes an `automata` skeleton with a `state` per node and a `link` per edge,
+//! guarded by an `if false` placeholder commented with the label of the edge.
+
+use std::collections::HashMap;
+
+use crate::format::INDENT;
+use crate::lexer::{FANReserved, Position};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportError {
+    pub message: String,
+    pub position: Position,
+}
+
+impl ImportError {
+    fn new(message: impl Into<String>, position: Position) -> Self {
+        Self { message: message.into(), position }
+    }
+}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.position, self.message)
+    }
+}
+
+/// Edge of the diagram, `to` is `None` for the final state
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transition {
+    pub from: String,
+    pub to: Option<String>,
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Diagram {
+    /// Name of the DOT digraph, Mermaid diagrams have none
+    pub name: Option<String>,
+    /// Node ids in the order of their first appearance, without the start and the final nodes
+    pub states: Vec<String>,
+    /// Target of the start node
+    pub initial: Option<String>,
+    pub transitions: Vec<Transition>,
+}
+
+impl Diagram {
+    fn state(&mut self, id: &str) {
+        if !self.states.iter().any(|s| s == id) {
+            self.states.push(id.to_string());
+        }
+    }
+
+    fn start(&mut self, id: &str, position: Position) -> Result<(), ImportError> {
+        self.state(id);
+        match &self.initial {
+            Some(initial) if initial != id => {
+                Err(ImportError::new(format!("second initial state `{}`, the first is `{}`", id, initial), position))
+            },
+            _ => {
+                self.initial = Some(id.to_string());
+                Ok(())
+            },
+        }
+    }
+
+    fn transition(&mut self, from: &str, to: Option<&str>, label: Option<String>) {
+        self.state(from);
+        if let Some(to) = to {
+            self.state(to);
+        }
+        let label = label.filter(|l| !l.is_empty());
+        self.transitions.push(Transition { from: from.to_string(), to: to.map(str::to_string), label });
+    }
+
+    /// FAN source of the automata skeleton, `name` is used when the diagram has none
+    pub fn to_fan(&self, name: &str) -> String {
+        let mut ids = Identifiers::default();
+        let states: HashMap<&str, String> = self.states.iter().map(|s| (s.as_str(), ids.get(s))).collect();
+        let mut out = format!("automata {} {{\n", identifier(self.name.as_deref().unwrap_or(name)));
+        for (i, s) in self.states.iter().enumerate() {
+            if i > 0 {
+                out.push('\n');
+            }
+            let initial = i > 0 && self.initial.as_ref() == Some(s);
+            out.push_str(&format!("{}{}state {} {{", INDENT, if initial { "initial " } else { "" }, states[s.as_str()]));
+            let transitions: Vec<&Transition> = self.transitions.iter().filter(|t| &t.from == s).collect();
+            if transitions.is_empty() {
+                out.push_str("}\n");
+                continue;
+            }
+            out.push('\n');
+            for (j, t) in transitions.iter().enumerate() {
+                let indent = INDENT.repeat(2);
+                out.push_str(&if j == 0 { format!("{}if false {{", indent) } else { " else if false {".to_string() });
+                if let Some(label) = &t.label {
+                    out.push_str(&format!(" # {}", label.split_whitespace().collect::<Vec<_>>().join(" ")));
+                }
+                let to = t.to.as_ref().map_or("NULL", |to| states[to.as_str()].as_str());
+                out.push_str(&format!("\n{}{}link self -> {};\n{}}}", indent, INDENT, to, indent));
+            }
+            out.push_str(&format!("\n{}}}\n", INDENT));
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+/// FAN names of the diagram ids, distinct ids get distinct names
+#[derive(Default)]
+struct Identifiers {
+    used: Vec<String>,
+}
+
+impl Identifiers {
+    fn get(&mut self, id: &str) -> String {
+        let base = identifier(id);
+        let mut name = base.clone();
+        for n in 2.. {
+            if !self.used.contains(&name) {
+                break;
+            }
+            name = format!("{}{}", base, n);
+        }
+        self.used.push(name.clone());
+        name
+    }
+}
+
+/// FAN name for the id: the other chars are replaced by `_`, the reserved words get a `_` suffix
+fn identifier(id: &str) -> String {
+    let mut name: String = id.chars().map(|c| if c.is_alphanumeric() || c == '_' { c } else { '_' }).collect();
+    if !name.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+        name.insert(0, 'S');
+    }
+    if FANReserved::try_from(&name).is_some() || matches!(name.as_str(), "self" | "true" | "false") {
+        name.push('_');
+    }
+    name
+}
+
+/// FAN sources of the diagrams separated by an empty line, the unnamed ones are named after `name`
+pub fn to_fan(diagrams: &[Diagram], name: &str) -> String {
+    let mut names = Identifiers::default();
+    diagrams
+        .iter()
+        .map(|d| d.to_fan(&names.get(d.name.as_deref().unwrap_or(name))))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Diagrams of the Mermaid or DOT source, Mermaid when the first line starts with `stateDiagram`
+pub fn import(source: &str) -> Result<Vec<Diagram>, ImportError> {
+    let first = source.lines().map(str::trim).find(|l| !l.is_empty() && !l.starts_with("%%"));
+    if first.is_some_and(|l| l.starts_with("stateDiagram")) { parse_mermaid(source) } else { parse_dot(source) }
+}
+
+/// Text of the Mermaid entity codes: `#lt;`, `#gt;`, `#quot;`, `#amp;` and the decimal ones as `#59;`
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('#') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let entity = rest[1..].find(';').map(|end| &rest[1..end + 1]);
+        let c = entity.and_then(|e| match e {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "amp" => Some('&'),
+            _ => e.parse::<u32>().ok().and_then(char::from_u32),
+        });
+        match (entity, c) {
+            (Some(e), Some(c)) => {
+                out.push(c);
+                rest = &rest[e.len() + 2..];
+            },
+            _ => {
+                out.push('#');
+                rest = &rest[1..];
+            },
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Diagrams of the `stateDiagram` blocks: the transitions `A --> B : label` with `[*]` for the start
+/// and the end, the declarations `state "description" as A`, `state A` and `A : description`.
+/// Notes, directions and styles are skipped, composite states and concurrent regions are errors.
+pub fn parse_mermaid(source: &str) -> Result<Vec<Diagram>, ImportError> {
+    let mut diagrams: Vec<Diagram> = vec![];
+    let mut note = false;
+    for (n, line) in source.lines().enumerate() {
+        let trimmed = line.trim();
+        let position = Position::new(n, line.chars().take_while(|c| c.is_whitespace()).count());
+        if note {
+            note = trimmed != "end note";
+            continue;
+        }
+        if trimmed.is_empty() || trimmed.starts_with("%%") {
+            continue;
+        }
+        if trimmed == "stateDiagram" || trimmed == "stateDiagram-v2" {
+            diagrams.push(Diagram::default());
+            continue;
+        }
+        let Some(diagram) = diagrams.last_mut() else {
+            return Err(ImportError::new("expected `stateDiagram`", position));
+        };
+        let keyword = trimmed.split_whitespace().next().unwrap_or_default();
+        if matches!(keyword, "direction" | "classDef" | "class" | "style" | "click") {
+            continue;
+        }
+        if keyword == "note" {
+            note = !trimmed.contains(':');
+            continue;
+        }
+        if trimmed == "--" {
+            return Err(ImportError::new("concurrent regions are not supported", position));
+        }
+        if trimmed.ends_with('{') || trimmed == "}" {
+            return Err(ImportError::new("composite states are not supported", position));
+        }
+        let node = |s: &str| {
+            let s = s.trim();
+            if s.is_empty() || s.contains(char::is_whitespace) {
+                Err(ImportError::new(format!("expected a state, found `{}`", s), position))
+            } else {
+                Ok(s.to_string())
+            }
+        };
+        if let Some((from, rest)) = trimmed.split_once("-->") {
+            let (to, label) = match rest.split_once(':') {
+                Some((to, label)) => (to, Some(unescape(label.trim()))),
+                None => (rest, None),
+            };
+            match (node(from)?.as_str(), node(to)?.as_str()) {
+                ("[*]", "[*]") => return Err(ImportError::new("transition from the start to the end", position)),
+                ("[*]", to) => diagram.start(to, position)?,
+                (from, "[*]") => diagram.transition(from, None, label),
+                (from, to) => diagram.transition(from, Some(to), label),
+            }
+        } else if let Some(rest) = trimmed.strip_prefix("state ") {
+            let rest = rest.trim();
+            let id = match rest.strip_prefix('"') {
+                Some(quoted) => quoted.split_once('"').and_then(|(_, id)| id.trim().strip_prefix("as ")).unwrap_or_default(),
+                None => rest.split_whitespace().next().unwrap_or_default(),
+            };
+            diagram.state(&node(id)?);
+        } else {
+            let id = trimmed.split_once(':').map_or(trimmed, |(id, _)| id);
+            diagram.state(&node(id)?);
+        }
+    }
+    if diagrams.is_empty() {
+        return Err(ImportError::new("expected `stateDiagram`", Position::default()));
+    }
+    Ok(diagrams)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum DotToken {
+    /// Name, numeral, quoted or HTML string
+    Id(String),
+    Symbol(&'static str),
+}
+
+impl std::fmt::Display for DotToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DotToken::Id(id) => write!(f, "`{}`", id),
+            DotToken::Symbol(s) => write!(f, "`{}`", s),
+        }
+    }
+}
+
+const DOT_SYMBOLS: [&str; 10] = ["->", "--", "{", "}", "[", "]", ";", ",", "=", ":"];
+
+/// Chars of the DOT source with the position of the current one
+struct DotScanner {
+    chars: Vec<char>,
+    index: usize,
+    position: Position,
+}
+
+impl DotScanner {
+    fn peek(&self, k: usize) -> Option<char> {
+        self.chars.get(self.index + k).copied()
+    }
+
+    fn at(&self, s: &str) -> bool {
+        s.chars().enumerate().all(|(k, c)| self.peek(k) == Some(c))
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek(0)?;
+        self.index += 1;
+        if c == '\n' {
+            self.position = Position::new(self.position.line + 1, 0);
+        } else {
+            self.position.col += 1;
+        }
+        Some(c)
+    }
+
+    /// The current char starts the line, `#` lines are the output of the C preprocessor
+    fn line_start(&self) -> bool {
+        self.chars[..self.index].iter().rev().take_while(|c| **c != '\n').all(|c| c.is_whitespace())
+    }
+
+    /// Quoted string after the `"`, `\"` is a quote and a backslash before the line end continues the line
+    fn string(&mut self, position: Position) -> Result<String, ImportError> {
+        let mut id = String::new();
+        loop {
+            match self.bump() {
+                None => return Err(ImportError::new("string is not closed", position)),
+                Some('"') => return Ok(id),
+                Some('\\') => match self.bump() {
+                    Some('\n') => {},
+                    Some(c @ ('"' | '\\')) => id.push(c),
+                    Some(c) => {
+                        id.push('\\');
+                        id.push(c);
+                    },
+                    None => return Err(ImportError::new("string is not closed", position)),
+                },
+                Some(c) => id.push(c),
+            }
+        }
+    }
+
+    /// HTML string after the `<`, up to the matching `>`
+    fn html(&mut self, position: Position) -> Result<String, ImportError> {
+        let (mut depth, mut id) = (1, String::new());
+        loop {
+            match self.bump() {
+                None => return Err(ImportError::new("HTML string is not closed", position)),
+                Some('>') if depth == 1 => return Ok(id),
+                Some(c) => {
+                    match c {
+                        '<' => depth += 1,
+                        '>' => depth -= 1,
+                        _ => {},
+                    }
+                    id.push(c);
+                },
+            }
+        }
+    }
+}
+
+fn dot_tokens(source: &str) -> Result<Vec<(Position, DotToken)>, ImportError> {
+    let mut scanner = DotScanner { chars: source.chars().collect(), index: 0, position: Position::default() };
+    let mut tokens = vec![];
+    let id_char = |c: char| c.is_alphanumeric() || c == '_' || c == '.';
+    while let Some(c) = scanner.peek(0) {
+        let position = scanner.position;
+        if c.is_whitespace() {
+            scanner.bump();
+        } else if scanner.at("//") || (c == '#' && scanner.line_start()) {
+            while scanner.peek(0).is_some_and(|c| c != '\n') {
+                scanner.bump();
+            }
+        } else if scanner.at("/*") {
+            while !scanner.at("*/") {
+                scanner.bump().ok_or_else(|| ImportError::new("comment is not closed", position))?;
+            }
+            scanner.bump();
+            scanner.bump();
+        } else if c == '"' {
+            scanner.bump();
+            tokens.push((position, DotToken::Id(scanner.string(position)?)));
+        } else if c == '<' {
+            scanner.bump();
+            tokens.push((position, DotToken::Id(scanner.html(position)?)));
+        } else if id_char(c) || (c == '-' && scanner.peek(1).is_some_and(|c| c.is_ascii_digit() || c == '.')) {
+            let mut id = scanner.bump().map(String::from).unwrap_or_default();
+            while let Some(c) = scanner.peek(0).filter(|c| id_char(*c)) {
+                id.push(c);
+                scanner.bump();
+            }
+            tokens.push((position, DotToken::Id(id)));
+        } else if let Some(symbol) = DOT_SYMBOLS.iter().find(|s| scanner.at(s)) {
+            for _ in 0..symbol.len() {
+                scanner.bump();
+            }
+            tokens.push((position, DotToken::Symbol(symbol)));
+        } else {
+            return Err(ImportError::new(format!("unexpected `{}`", c), position));
+        }
+    }
+    Ok(tokens)
+}
+
+/// `name=value` pairs of the attribute lists
+type Attributes = Vec<(String, String)>;
+
+/// Statements of a digraph with the subgraphs flattened
+#[derive(Default)]
+struct DotGraph {
+    /// Node ids in the order of their first appearance with their attributes
+    nodes: Vec<(String, Attributes)>,
+    edges: Vec<(Position, String, String, Attributes)>,
+}
+
+impl DotGraph {
+    fn node(&mut self, id: &str, attributes: &[(String, String)]) {
+        match self.nodes.iter_mut().find(|(n, _)| n == id) {
+            Some((_, a)) => a.extend_from_slice(attributes),
+            None => self.nodes.push((id.to_string(), attributes.to_vec())),
+        }
+    }
+
+    fn attribute(&self, id: &str, name: &str) -> Option<&str> {
+        let (_, attributes) = self.nodes.iter().find(|(n, _)| n == id)?;
+        attributes.iter().rev().find(|(a, _)| a == name).map(|(_, value)| value.as_str())
+    }
+}
+
+struct DotParser {
+    tokens: Vec<(Position, DotToken)>,
+    index: usize,
+    /// End of the source for the errors at the end
+    end: Position,
+}
+
+impl DotParser {
+    fn peek(&self) -> Option<&DotToken> {
+        self.tokens.get(self.index).map(|(_, t)| t)
+    }
+
+    fn position(&self) -> Position {
+        self.tokens.get(self.index).map_or(self.end, |(p, _)| *p)
+    }
+
+    fn eat(&mut self, symbol: &'static str) -> bool {
+        let found = self.peek() == Some(&DotToken::Symbol(symbol));
+        if found {
+            self.index += 1;
+        }
+        found
+    }
+
+    fn error(&self, expected: &str) -> ImportError {
+        match self.peek() {
+            Some(t) => ImportError::new(format!("expected {}, found {}", expected, t), self.position()),
+            None => ImportError::new(format!("expected {}, found the end", expected), self.position()),
+        }
+    }
+
+    fn expect(&mut self, symbol: &'static str) -> Result<(), ImportError> {
+        if self.eat(symbol) { Ok(()) } else { Err(self.error(&format!("`{}`", symbol))) }
+    }
+
+    fn id(&mut self) -> Result<String, ImportError> {
+        match self.peek() {
+            Some(DotToken::Id(id)) => {
+                let id = id.clone();
+                self.index += 1;
+                Ok(id)
+            },
+            _ => Err(self.error("an id")),
+        }
+    }
+
+    fn keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(DotToken::Id(id)) if id.eq_ignore_ascii_case(keyword))
+    }
+
+    fn graph(&mut self) -> Result<(Option<String>, DotGraph), ImportError> {
+        if self.keyword("strict") {
+            self.index += 1;
+        }
+        if self.keyword("graph") {
+            return Err(ImportError::new("undirected graphs are not supported", self.position()));
+        }
+        if !self.keyword("digraph") {
+            return Err(self.error("`digraph`"));
+        }
+        self.index += 1;
+        let name = if matches!(self.peek(), Some(DotToken::Id(_))) { Some(self.id()?) } else { None };
+        self.expect("{")?;
+        let mut graph = DotGraph::default();
+        self.statements(&mut graph)?;
+        Ok((name, graph))
+    }
+
+    /// Statements up to and with the closing `}`
+    fn statements(&mut self, graph: &mut DotGraph) -> Result<(), ImportError> {
+        while !self.eat("}") {
+            if self.eat(";") || self.eat(",") {
+                continue;
+            }
+            if self.keyword("subgraph") || self.peek() == Some(&DotToken::Symbol("{")) {
+                if self.keyword("subgraph") {
+                    self.index += 1;
+                    if matches!(self.peek(), Some(DotToken::Id(_))) {
+                        self.index += 1;
+                    }
+                }
+                self.expect("{")?;
+                self.statements(graph)?;
+                if matches!(self.peek(), Some(DotToken::Symbol("->" | "--"))) {
+                    return Err(ImportError::new("edges of subgraphs are not supported", self.position()));
+                }
+                continue;
+            }
+            if (self.keyword("graph") || self.keyword("node") || self.keyword("edge"))
+                && self.tokens.get(self.index + 1).map(|(_, t)| t) == Some(&DotToken::Symbol("["))
+            {
+                self.index += 1;
+                self.attributes()?;
+                continue;
+            }
+            let position = self.position();
+            let mut chain = vec![self.node()?];
+            if self.eat("=") {
+                self.id()?;
+                continue;
+            }
+            while self.eat("->") {
+                chain.push(self.node()?);
+            }
+            if self.peek() == Some(&DotToken::Symbol("--")) {
+                return Err(ImportError::new("undirected edges are not supported", self.position()));
+            }
+            let attributes = self.attributes()?;
+            if chain.len() == 1 {
+                graph.node(&chain[0], &attributes);
+            }
+            for pair in chain.windows(2) {
+                graph.node(&pair[0], &[]);
+                graph.node(&pair[1], &[]);
+                graph.edges.push((position, pair[0].clone(), pair[1].clone(), attributes.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Node id without the port
+    fn node(&mut self) -> Result<String, ImportError> {
+        let id = self.id()?;
+        while self.eat(":") {
+            self.id()?;
+        }
+        Ok(id)
+    }
+
+    /// Attribute lists `[a=b, c=d][e=f]`, attributes without value are `true`
+    fn attributes(&mut self) -> Result<Attributes, ImportError> {
+        let mut attributes = vec![];
+        while self.eat("[") {
+            while !self.eat("]") {
+                if self.eat(",") || self.eat(";") {
+                    continue;
+                }
+                let name = self.id()?;
+                let value = if self.eat("=") { self.id()? } else { "true".to_string() };
+                attributes.push((name, value));
+            }
+        }
+        Ok(attributes)
+    }
+}
+
+/// Diagrams of the `digraph`s. Nodes with `shape=point` are the start, the `NULL` node and the nodes
+/// with `shape=doublecircle` are the end, the `label`s of the edges are the guards.
+/// Subgraphs are flattened, undirected graphs are errors.
+pub fn parse_dot(source: &str) -> Result<Vec<Diagram>, ImportError> {
+    let end = Position::new(source.lines().count().saturating_sub(1), source.lines().last().unwrap_or_default().chars().count());
+    let mut parser = DotParser { tokens: dot_tokens(source)?, index: 0, end };
+    let mut diagrams = vec![];
+    while parser.peek().is_some() || diagrams.is_empty() {
+        let (name, graph) = parser.graph()?;
+        let start = |id: &str| graph.attribute(id, "shape") == Some("point");
+        let end = |id: &str| FANReserved::try_from(id) == Some(FANReserved::NULL) || graph.attribute(id, "shape") == Some("doublecircle");
+        let mut diagram = Diagram { name, ..Diagram::default() };
+        for (id, _) in graph.nodes.iter().filter(|(id, _)| !start(id) && !end(id)) {
+            diagram.state(id);
+        }
+        for (position, from, to, attributes) in graph.edges.iter() {
+            let label = attributes.iter().rev().find(|(a, _)| a == "label").map(|(_, l)| l.replace("\\n", " "));
+            match (start(from) || end(from), start(to) || end(to)) {
+                (true, _) if end(from) => {
+                    return Err(ImportError::new(format!("transition from the end node `{}`", from), *position));
+                },
+                (_, true) if start(to) => {
+                    return Err(ImportError::new(format!("transition to the start node `{}`", to), *position));
+                },
+                (true, true) => return Err(ImportError::new("transition from the start to the end", *position)),
+                (true, false) => diagram.start(to, *position)?,
+                (false, true) => diagram.transition(from, None, label),
+                (false, false) => diagram.transition(from, Some(to), label),
+            }
+        }
+        diagrams.push(diagram);
+    }
+    Ok(diagrams)
+}
+
+#[test]
+fn import_test() {
+    use crate::format::format;
+    use crate::graph::Graph;
+    use crate::mermaid::to_mermaid;
+    use crate::parser::Parser;
+
+    let diagrams = import("
+        %% turnstile
+        stateDiagram-v2
+            direction LR
+            state \"Locked #lt;coin#gt;\" as Locked
+            [*] --> Locked
+            Locked --> Unlocked : coin
+            Unlocked --> Locked : push #59; pass
+            Unlocked : the arm turns
+            note right of Unlocked
+                free pass
+            end note
+            Unlocked --> [*] : broken
+            Locked --> Locked
+    ").unwrap();
+    assert_eq!(diagrams, vec![Diagram {
+        name: None,
+        states: vec!["Locked".to_string(), "Unlocked".to_string()],
+        initial: Some("Locked".to_string()),
+        transitions: vec![
+            Transition { from: "Locked".to_string(), to: Some("Unlocked".to_string()), label: Some("coin".to_string()) },
+            Transition { from: "Unlocked".to_string(), to: Some("Locked".to_string()), label: Some("push ; pass".to_string()) },
+            Transition { from: "Unlocked".to_string(), to: None, label: Some("broken".to_string()) },
+            Transition { from: "Locked".to_string(), to: Some("Locked".to_string()), label: None },
+        ],
+    }]);
+    let fan = to_fan(&diagrams, "Turnstile");
+    assert_eq!(fan, "\
+automata Turnstile {
+    state Locked {
+        if false { # coin
+            link self -> Unlocked;
+        } else if false {
+            link self -> Locked;
+        }
+    }
+
+    state Unlocked {
+        if false { # push ; pass
+            link self -> Locked;
+        } else if false { # broken
+            link self -> NULL;
+        }
+    }
+}
+");
+    assert_eq!(format(&fan).unwrap(), fan);
+    let graph = Graph::new(&Parser::parse_str(&fan).unwrap());
+    assert_eq!(graph.automata[0].transitions(), vec![
+        ("Locked", Some("Unlocked")),
+        ("Locked", Some("Locked")),
+        ("Locked", None),
+        ("Unlocked", Some("Locked")),
+        ("Unlocked", None),
+    ]);
+
+    let diagrams = import(r#"
+        /* sketch */
+        strict digraph "Traffic light" {
+            rankdir=LR; node [shape=box]
+            start [shape=point]
+            start -> red
+            red -> green -> "yellow!" [label="timer"]
+            # preprocessor line
+            subgraph cluster_end { done [shape=doublecircle, label=<<b>end</b>>] }
+            "yellow!":e -> red; "yellow!" -> done [label="off \"now\""]
+            "if"; "2way" -> NULL
+        }
+        digraph { a -> b }
+    "#).unwrap();
+    assert_eq!(diagrams[0].name.as_deref(), Some("Traffic light"));
+    assert_eq!(diagrams[0].states, vec!["red", "green", "yellow!", "if", "2way"]);
+    assert_eq!(diagrams[0].transitions[3], Transition { from: "yellow!".to_string(), to: None, label: Some("off \"now\"".to_string()) });
+    let fan = to_fan(&diagrams, "Light");
+    assert!(fan.starts_with("automata Traffic_light {\n    state red {\n"), "{}", fan);
+    assert!(fan.contains("    state yellow_ {\n        if false {\n            link self -> red;\n        } else if false { # off \"now\"\n"), "{}", fan);
+    assert!(fan.contains("    state if_ {}\n\n    state S2way {\n        if false {\n            link self -> NULL;\n"), "{}", fan);
+    assert!(fan.ends_with("\nautomata Light {\n    state a {\n        if false {\n            link self -> b;\n        }\n    }\n\n    state b {}\n}\n"), "{}", fan);
+    assert_eq!(format(&fan).unwrap(), fan);
+    Parser::parse_str(&fan).unwrap();
+
+    // the exported diagrams import back with their transitions as the explicit links
+    let module = Parser::parse_str("
+        automata Door {
+            state Closed<locked: bool> { if locked { link self -> Closed<false>; } else { link self -> Open; } }
+            initial state Open { let _ = run Timer<3>; link self -> Closed<true>; }
+        }
+        automata Timer { state Tick<n: int64> { if n > 0 { link self -> Tick<n - 1>; } } }
+    ").unwrap();
+    let graph = Graph::new(&module);
+    let fan = to_fan(&import(&to_mermaid(&graph)).unwrap(), "Door");
+    assert!(fan.contains("    initial state Open {\n"), "{}", fan);
+    assert!(fan.contains("automata Door2 {\n"), "{}", fan);
+    let imported = Graph::new(&Parser::parse_str(&fan).unwrap());
+    for (i, a) in imported.automata.iter().enumerate() {
+        let links: Vec<_> = a.edges.iter().filter(|e| !e.implicit).map(|e| (e.from.as_str(), e.to.as_deref())).collect();
+        assert_eq!(links, graph.automata[i].transitions());
+    }
+
+    assert_eq!(unescape("#lt;a#gt; #35; #x; #"), "<a> # #x; #");
+    assert_eq!(import("stateDiagram\n    state A {\n").unwrap_err(), ImportError::new("composite states are not supported", Position::new(1, 4)));
+    assert_eq!(import("stateDiagram\n  [*] --> A\n  [*] --> B").unwrap_err().to_string(), "3:3: second initial state `B`, the first is `A`");
+    assert_eq!(import("A -> B").unwrap_err().to_string(), "1:1: expected `digraph`, found `A`");
+    assert_eq!(import("graph { a -- b }").unwrap_err().to_string(), "1:1: undirected graphs are not supported");
+    assert_eq!(import("digraph { a -> }").unwrap_err().to_string(), "1:16: expected an id, found `}`");
+    assert_eq!(import("digraph {\n a -> \"b").unwrap_err().to_string(), "2:7: string is not closed");
+}
@@ -0,0 +1,1644 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use crate::budget::{Budget, Clock, Limit, SystemClock, TracePosition};
+use crate::checker::{is_self, Checker, Type};
+use crate::debugger::Debugger;
+use crate::host::{Host, HostValue};
+use crate::json::Json;
+use crate::lexer::Position;
+use crate::parser::{
+    AutomataDef, AutomataKind, Block, DefinitionExp, Expression, ExpressionType, FunctionDef, Literal, Module, Parser,
+    Pattern, ProceduralExp, ReturnableExp, SingleName, StateDef, Statement,
+};
+use crate::snapshot::{value_from_json, Snapshot, SnapshotError};
+use crate::trace::{Divergence, TraceEvent, Tracer};
+
+/// Default limit of nested function calls, see [`Budget::max_call_depth`]
+pub const MAX_CALL_DEPTH: usize = 1000;
+/// Default limit of nested `run`s, see [`Budget::max_run_depth`]
+pub const MAX_RUN_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+    String(String),
+    /// `()` is the unit value
+    Tuple(Vec<Value>),
+    /// State of the automata with its template arguments
+    State(Box<StateValue>),
+    /// Value of the uploaded Rust type
+    Host(HostValue),
+    Null,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateValue {
+    pub automata: String,
+    pub state: String,
+    /// (parameter name, value)
+    pub args: Vec<(String, Value)>,
+}
+
+impl StateValue {
+    pub fn arg(&self, name: &str) -> Option<&Value> {
+        self.args.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+}
+
+impl std::fmt::Display for StateValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}::{}", self.automata, self.state)?;
+        if !self.args.is_empty() {
+            write!(f, "<")?;
+            for (i, (_, v)) in self.args.iter().enumerate() {
+                if i > 0 { write!(f, ", ")?; }
+                write!(f, "{}", v)?;
+            }
+            write!(f, ">")?;
+        }
+        Ok(())
+    }
+}
+
+impl Value {
+    pub fn unit() -> Self {
+        Value::Tuple(vec![])
+    }
+
+    pub fn type_name(&self) -> String {
+        match self {
+            Value::Int(_) => "int64".to_string(),
+            Value::Float(_) => "float64".to_string(),
+            Value::Bool(_) => "bool".to_string(),
+            Value::Char(_) => "char".to_string(),
+            Value::String(_) => "string".to_string(),
+            Value::Tuple(v) => format!("({})", v.iter().map(|x| x.type_name()).collect::<Vec<_>>().join(", ")),
+            Value::State(s) => s.automata.clone(),
+            Value::Host(h) => h.type_name().to_string(),
+            Value::Null => "NULL".to_string(),
+        }
+    }
+
+    /// Converts the value to the declared type: ints are widened to floats,
+    /// anything else has to match exactly. Opaque types accept any value.
+    pub fn coerce(self, ty: &Type) -> Result<Value, ExecError> {
+        match (ty, self) {
+            (Type::Unknown | Type::Named(_), v) => Ok(v),
+            (Type::Int, v @ Value::Int(_)) => Ok(v),
+            (Type::Float, Value::Int(i)) => Ok(Value::Float(i as f64)),
+            (Type::Float, v @ Value::Float(_)) => Ok(v),
+            (Type::Bool, v @ Value::Bool(_)) => Ok(v),
+            (Type::Char, v @ Value::Char(_)) => Ok(v),
+            (Type::String, v @ Value::String(_)) => Ok(v),
+            (Type::Null, v @ Value::Null) => Ok(v),
+            (Type::Tuple(t), Value::Tuple(v)) if t.len() == v.len() => Ok(Value::Tuple(
+                t.iter().zip(v).map(|(t, v)| v.coerce(t)).collect::<Result<_, _>>()?
+            )),
+            (t, v) => Err(ExecError::TypeMismatch { expected: t.to_string(), found: v.type_name() }),
+        }
+    }
+
+    pub(crate) fn as_bool(&self) -> Result<bool, ExecError> {
+        match self {
+            Value::Bool(b) => Ok(*b),
+            v => Err(ExecError::TypeMismatch { expected: "bool".to_string(), found: v.type_name() }),
+        }
+    }
+
+    pub(crate) fn as_index(&self) -> Result<usize, ExecError> {
+        match self {
+            Value::Int(i) if *i >= 0 => Ok(*i as usize),
+            Value::Int(i) => Err(ExecError::IndexOutOfBounds(*i)),
+            v => Err(ExecError::TypeMismatch { expected: "int64".to_string(), found: v.type_name() }),
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Char(c) => write!(f, "{:?}", c),
+            Value::String(s) => write!(f, "{:?}", s),
+            Value::Tuple(v) => {
+                write!(f, "(")?;
+                for (i, x) in v.iter().enumerate() {
+                    if i > 0 { write!(f, ", ")?; }
+                    write!(f, "{}", x)?;
+                }
+                if v.len() == 1 { write!(f, ",")?; }
+                write!(f, ")")
+            },
+            Value::State(s) => write!(f, "{}", s),
+            Value::Host(h) => write!(f, "{}", h),
+            Value::Null => write!(f, "NULL"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExecError {
+    UndefinedName(String),
+    UndefinedFunction(String),
+    ArityMismatch { name: String, expected: usize, found: usize },
+    TypeMismatch { expected: String, found: String },
+    UnsupportedOperator(String),
+    InvalidLiteral(String),
+    InvalidAssignment,
+    IndexOutOfBounds(i64),
+    NoSuchMember(String),
+    UndefinedMethod(String),
+    DivisionByZero,
+    Overflow,
+    LinkInFunction,
+    ReturnOutsideFunction,
+    /// No `match` arm accepted the value
+    NoMatch(String),
+    UndefinedAutomata(String),
+    UndefinedState(String),
+    /// Automata has no states to start from
+    NoStates(String),
+    /// Moore automata executed as Mealy or vice versa
+    UnexpectedAutomataKind(String),
+    /// A limit of the [`Budget`] was reached
+    BudgetExhausted { limit: Limit, position: Box<TracePosition> },
+    /// Execution stopped to take the snapshot, [`Interpreter::run_for`] and [`Interpreter::resume`] return it instead
+    Paused,
+    /// Snapshot does not belong to the program
+    InvalidSnapshot(SnapshotError),
+    /// Trace could not be written or read
+    Trace(String),
+    /// Replay did not reproduce the recorded trace
+    Diverged(Box<Divergence>),
+    /// Error of the host call, as recorded in the trace
+    Host(String),
+    /// Debugger frontend stopped the execution
+    Aborted,
+}
+
+impl std::fmt::Display for ExecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecError::UndefinedName(n) => write!(f, "undefined name `{}`", n),
+            ExecError::UndefinedFunction(n) => write!(f, "undefined function `{}`", n),
+            ExecError::ArityMismatch { name, expected, found } =>
+                write!(f, "`{}` takes {} argument(s) but {} given", name, expected, found),
+            ExecError::TypeMismatch { expected, found } => write!(f, "expected `{}`, found `{}`", expected, found),
+            ExecError::UnsupportedOperator(o) => write!(f, "unsupported operator `{}`", o),
+            ExecError::InvalidLiteral(l) => write!(f, "invalid literal `{}`", l),
+            ExecError::InvalidAssignment => write!(f, "left side of the assignment is not assignable"),
+            ExecError::IndexOutOfBounds(i) => write!(f, "index {} is out of bounds", i),
+            ExecError::NoSuchMember(m) => write!(f, "no member `{}`", m),
+            ExecError::UndefinedMethod(m) => write!(f, "undefined method `{}`", m),
+            ExecError::DivisionByZero => write!(f, "division by zero"),
+            ExecError::Overflow => write!(f, "integer overflow"),
+            ExecError::LinkInFunction => write!(f, "functions are pure and can not `link`"),
+            ExecError::ReturnOutsideFunction => write!(f, "`return` outside of function"),
+            ExecError::NoMatch(v) => write!(f, "no pattern matches `{}`", v),
+            ExecError::UndefinedAutomata(n) => write!(f, "undefined automata `{}`", n),
+            ExecError::UndefinedState(n) => write!(f, "undefined state `{}`", n),
+            ExecError::NoStates(n) => write!(f, "automata `{}` has no states", n),
+            ExecError::UnexpectedAutomataKind(n) => write!(f, "automata `{}` is of the other kind", n),
+            ExecError::BudgetExhausted { limit, position } => write!(f, "budget of {} exhausted at {}", limit, position),
+            ExecError::Paused => write!(f, "execution is paused"),
+            ExecError::InvalidSnapshot(e) => write!(f, "invalid snapshot: {}", e),
+            ExecError::Trace(e) => write!(f, "trace: {}", e),
+            ExecError::Diverged(d) => write!(f, "{}", d),
+            ExecError::Host(e) => write!(f, "{}", e),
+            ExecError::Aborted => write!(f, "execution aborted by the debugger"),
+        }
+    }
+}
+
+/// How the evaluation of the expression ended
+#[derive(Debug, Clone)]
+pub enum Flow {
+    Next(Value),
+    Return(Value),
+    /// `link` was executed, `None` stands for NULL
+    Link(Option<StateValue>),
+}
+
+/// Local variables of the running body
+#[derive(Debug, Default)]
+pub struct Env {
+    scopes: Vec<HashMap<String, Value>>,
+}
+
+impl Env {
+    pub fn new() -> Self {
+        Self { scopes: vec![HashMap::new()] }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.scopes.iter().rev().find_map(|s| s.get(name))
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Value> {
+        self.scopes.iter_mut().rev().find_map(|s| s.get_mut(name))
+    }
+
+    pub fn define(&mut self, name: &str, v: Value) {
+        self.scopes.last_mut().unwrap().insert(name.to_string(), v);
+    }
+
+    fn push(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn pop(&mut self) {
+        self.scopes.pop();
+    }
+
+    /// Visible variables sorted by name, the inner scopes shadow the outer ones
+    pub fn locals(&self) -> Vec<(String, Value)> {
+        let mut locals: Vec<_> = self.scopes.iter().rev().flatten().map(|(k, v)| (k.clone(), v.clone())).collect();
+        locals.sort_by(|(a, _), (b, _)| a.cmp(b));
+        locals.dedup_by(|(a, _), (b, _)| a == b);
+        locals
+    }
+}
+
+macro_rules! next {
+    ($e:expr) => {
+        match $e? {
+            Flow::Next(v) => v,
+            flow => return Ok(flow),
+        }
+    };
+}
+
+/// Value of the FAN expression as written on the command line, names other than the builtins are undefined
+pub fn parse_value(text: &str) -> Result<Value, String> {
+    let module = Parser::parse_str(&format!("fn value() {{\n{}\n}}", text)).map_err(|e| format!("`{}`: {}", text, e))?;
+    let body = &module.find_function("value").expect("parsed above").body;
+    match Interpreter::new(&module).block(None, &mut Env::new(), body) {
+        Ok(Flow::Next(v)) => Ok(v),
+        Ok(_) => Err(format!("`{}` is not a value", text)),
+        Err(e) => Err(format!("`{}`: {}", text, e)),
+    }
+}
+
+pub fn literal_value(l: &Literal) -> Result<Value, ExecError> {
+    const SUFFIXES: [&str; 14] = [
+        "i8", "i16", "i32", "i64", "u8", "u16", "u32", "u64", "isize", "usize", "int64", "uint64", "f32", "f64",
+    ];
+    match l {
+        Literal::Char(c) => Ok(Value::Char(*c)),
+        Literal::String(s) => Ok(Value::String(s.clone())),
+        Literal::Digital(d) => d.parse().map(Value::Int).map_err(|_| ExecError::InvalidLiteral(d.clone())),
+        Literal::NumericalLexem(n) => {
+            let suffix = SUFFIXES.iter().find(|s| n.ends_with(*s)).copied().unwrap_or("");
+            let number = &n[..n.len() - suffix.len()];
+            if number.contains('.') || suffix.starts_with('f') {
+                number.parse().map(Value::Float).map_err(|_| ExecError::InvalidLiteral(n.clone()))
+            } else {
+                number.parse().map(Value::Int).map_err(|_| ExecError::InvalidLiteral(n.clone()))
+            }
+        },
+        Literal::Bool(b) => Ok(Value::Bool(*b)),
+        Literal::NULL => Ok(Value::Null),
+    }
+}
+
+pub(crate) fn arithmetic(op: &str, a: Value, b: Value) -> Result<Value, ExecError> {
+    let mismatch = |a: &Value, b: &Value| ExecError::TypeMismatch { expected: a.type_name(), found: b.type_name() };
+    match (a, b) {
+        (Value::Int(x), Value::Int(y)) => {
+            let r = match op {
+                "+" => x.checked_add(y),
+                "-" => x.checked_sub(y),
+                "*" => x.checked_mul(y),
+                "/" | "%" if y == 0 => return Err(ExecError::DivisionByZero),
+                "/" => x.checked_div(y),
+                "%" => x.checked_rem(y),
+                "&" => Some(x & y),
+                "|" => Some(x | y),
+                "^" => Some(x ^ y),
+                _ => return Err(ExecError::UnsupportedOperator(op.to_string())),
+            };
+            r.map(Value::Int).ok_or(ExecError::Overflow)
+        },
+        (Value::Int(x), Value::Float(y)) => arithmetic(op, Value::Float(x as f64), Value::Float(y)),
+        (Value::Float(x), Value::Int(y)) => arithmetic(op, Value::Float(x), Value::Float(y as f64)),
+        (Value::Float(x), Value::Float(y)) => match op {
+            "+" => Ok(Value::Float(x + y)),
+            "-" => Ok(Value::Float(x - y)),
+            "*" => Ok(Value::Float(x * y)),
+            "/" => Ok(Value::Float(x / y)),
+            "%" => Ok(Value::Float(x % y)),
+            _ => Err(ExecError::UnsupportedOperator(op.to_string())),
+        },
+        (Value::Bool(x), Value::Bool(y)) => match op {
+            "&" => Ok(Value::Bool(x & y)),
+            "|" => Ok(Value::Bool(x | y)),
+            "^" => Ok(Value::Bool(x ^ y)),
+            _ => Err(ExecError::UnsupportedOperator(op.to_string())),
+        },
+        (Value::String(x), Value::String(y)) if op == "+" => Ok(Value::String(x + &y)),
+        (Value::String(mut x), Value::Char(y)) if op == "+" => {
+            x.push(y);
+            Ok(Value::String(x))
+        },
+        // distance between chars, `signal - '0'`
+        (Value::Char(x), Value::Char(y)) if op == "-" => Ok(Value::Int(x as i64 - y as i64)),
+        (a, b) if a.type_name() == b.type_name() => Err(ExecError::UnsupportedOperator(op.to_string())),
+        (a, b) => Err(mismatch(&a, &b)),
+    }
+}
+
+pub(crate) fn compare(op: &str, a: &Value, b: &Value) -> Result<Value, ExecError> {
+    use std::cmp::Ordering;
+    let ordering = match (a, b) {
+        (Value::Int(x), Value::Int(y)) => Some(x.cmp(y)),
+        (Value::Int(x), Value::Float(y)) => (*x as f64).partial_cmp(y),
+        (Value::Float(x), Value::Int(y)) => x.partial_cmp(&(*y as f64)),
+        (Value::Float(x), Value::Float(y)) => x.partial_cmp(y),
+        (Value::Char(x), Value::Char(y)) => Some(x.cmp(y)),
+        (Value::String(x), Value::String(y)) => Some(x.cmp(y)),
+        (Value::Bool(x), Value::Bool(y)) => Some(x.cmp(y)),
+        _ => None,
+    };
+    let equal = ordering.map(|o| o == Ordering::Equal).unwrap_or(a == b);
+    match op {
+        "==" => Ok(Value::Bool(equal)),
+        "!=" => Ok(Value::Bool(!equal)),
+        _ => {
+            let o = ordering.ok_or(ExecError::TypeMismatch { expected: a.type_name(), found: b.type_name() })?;
+            Ok(Value::Bool(match op {
+                "<" => o == Ordering::Less,
+                ">" => o == Ordering::Greater,
+                "<=" => o != Ordering::Greater,
+                _ => o != Ordering::Less,
+            }))
+        },
+    }
+}
+
+/// Why the Mealy automata stopped
+#[derive(Debug, Clone, PartialEq)]
+pub enum MealyHalt {
+    /// Input is over, the automata waits for the next signal
+    Consumed,
+    /// Current state does not accept the signal type
+    Rejected(Value),
+    /// Automata linked to NULL, `remaining` signals were not read
+    Null { remaining: usize },
+}
+
+/// Result of feeding the input to the Mealy automata
+#[derive(Debug, Clone, PartialEq)]
+pub struct MealyRun {
+    pub halt: MealyHalt,
+    /// State waiting for the input, rejecting it or linked to NULL
+    pub state: StateValue,
+    /// Value of the output variable after every transition
+    pub outputs: Vec<Value>,
+    /// Number of read signals, the rejected one is not counted
+    pub consumed: usize,
+}
+
+/// Outcome of [`Interpreter::replay`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Replayed {
+    Moore(StateValue),
+    Mealy(MealyRun),
+}
+
+/// Outcome of [`Interpreter::run_for`] and [`Interpreter::resume`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Progress {
+    /// Automata linked to NULL in the final state
+    Done(StateValue),
+    Paused(Box<Snapshot>),
+}
+
+/// Running automata with its execution history, visible as `self` in the state body
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunFrame {
+    pub current: StateValue,
+    /// State the automata came from, `self.previous`
+    pub previous: Option<StateValue>,
+    /// Transitions made so far, `self.steps`
+    pub steps: usize,
+    /// Nested `run`s started by the body of the current state
+    pub runs: usize,
+}
+
+impl RunFrame {
+    pub fn new(current: StateValue) -> Self {
+        Self { current, previous: None, steps: 0, runs: 0 }
+    }
+}
+
+/// Tree-walking evaluator of FAN bodies
+pub struct Interpreter<'m> {
+    module: &'m Module,
+    depth: usize,
+    /// Evaluating function body: `return` is allowed, `link` is not
+    in_function: bool,
+    /// Automata being `run`, the outermost first
+    frames: Vec<RunFrame>,
+    budget: Budget,
+    clock: Box<dyn Clock>,
+    /// Spent since the top level `run` or call
+    transitions: usize,
+    iterations: usize,
+    started: std::time::Duration,
+    /// Last evaluated expression
+    position: Position,
+    host: Host,
+    /// Transitions after which [`Interpreter::run_for`] pauses
+    pause_at: Option<usize>,
+    paused: Option<Snapshot>,
+    /// Nested frames of the resumed snapshot with their index among the `run`s of the parent, the innermost first
+    resuming: Vec<(usize, RunFrame)>,
+    tracer: Option<Tracer>,
+    debugger: Option<Debugger<'m>>,
+}
+
+impl<'m> Interpreter<'m> {
+    pub fn new(module: &'m Module) -> Self {
+        Self {
+            module,
+            depth: 0,
+            in_function: false,
+            frames: vec![],
+            budget: Budget::default(),
+            clock: Box::new(SystemClock::new()),
+            transitions: 0,
+            iterations: 0,
+            started: std::time::Duration::ZERO,
+            position: Position::default(),
+            host: Host::default(),
+            pause_at: None,
+            paused: None,
+            resuming: vec![],
+            tracer: None,
+            debugger: None,
+        }
+    }
+
+    /// Uploaded Rust types and native functions callable from FAN
+    pub fn with_host(mut self, host: Host) -> Self {
+        self.host = host;
+        self
+    }
+
+    /// Limits the number of nested `run`s, the top level automata counts too
+    pub fn with_max_run_depth(mut self, depth: usize) -> Self {
+        self.budget.max_run_depth = depth;
+        self
+    }
+
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Replaces the clock measuring the wall clock limit
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Resets the spent budget when nothing is executed
+    fn begin(&mut self) {
+        if self.frames.is_empty() && self.depth == 0 {
+            self.transitions = 0;
+            self.iterations = 0;
+            self.started = self.clock.now();
+        }
+    }
+
+    fn exhausted(&self, limit: Limit) -> ExecError {
+        let frame = self.frames.last();
+        ExecError::BudgetExhausted {
+            limit,
+            position: Box::new(TracePosition {
+                run_stack: self.run_stack(),
+                state: frame.map(|f| f.current.clone()),
+                steps: frame.map_or(0, |f| f.steps),
+                source: self.position,
+            }),
+        }
+    }
+
+    fn check_clock(&self) -> Result<(), ExecError> {
+        match self.budget.max_duration {
+            Some(max) if self.clock.now().saturating_sub(self.started) > max => Err(self.exhausted(Limit::WallClock(max))),
+            _ => Ok(()),
+        }
+    }
+
+    fn spend_transition(&mut self) -> Result<(), ExecError> {
+        self.transitions += 1;
+        match self.budget.max_transitions {
+            Some(max) if self.transitions > max => Err(self.exhausted(Limit::Transitions(max))),
+            _ => self.check_clock(),
+        }
+    }
+
+    fn spend_iteration(&mut self) -> Result<(), ExecError> {
+        self.iterations += 1;
+        match self.budget.max_iterations {
+            Some(max) if self.iterations > max => Err(self.exhausted(Limit::Iterations(max))),
+            _ => self.check_clock(),
+        }
+    }
+
+    pub fn module(&self) -> &'m Module {
+        self.module
+    }
+
+    fn find_function(&self, automata: Option<&'m AutomataDef>, name: &str) -> Option<&'m FunctionDef> {
+        automata.and_then(|a| a.function(name)).or_else(|| self.module.find_function(name))
+    }
+
+    /// Calls the module level function
+    pub fn call_function(&mut self, name: &str, args: Vec<Value>) -> Result<Value, ExecError> {
+        let f = self.find_function(None, name).ok_or_else(|| ExecError::UndefinedFunction(name.to_string()))?;
+        self.begin();
+        self.call(None, f, args)
+    }
+
+    fn call(&mut self, automata: Option<&'m AutomataDef>, f: &'m FunctionDef, args: Vec<Value>) -> Result<Value, ExecError> {
+        if f.params.len() != args.len() {
+            return Err(ExecError::ArityMismatch { name: f.name.clone(), expected: f.params.len(), found: args.len() });
+        }
+        if self.depth >= self.budget.max_call_depth {
+            return Err(self.exhausted(Limit::CallDepth(self.budget.max_call_depth)));
+        }
+        self.check_clock()?;
+        let mut env = Env::new();
+        for (p, v) in f.params.iter().zip(args) {
+            env.define(&p.name, v.coerce(&Type::from_annotation(&p.ty))?);
+        }
+        self.depth += 1;
+        let outer = std::mem::replace(&mut self.in_function, true);
+        let result = self.block(automata, &mut env, &f.body);
+        self.in_function = outer;
+        self.depth -= 1;
+        let value = match result? {
+            Flow::Next(_) if f.returns.is_none() && !ends_with_value(&f.body) => Value::unit(),
+            Flow::Next(v) | Flow::Return(v) => v,
+            Flow::Link(_) => return Err(ExecError::LinkInFunction),
+        };
+        value.coerce(&Type::result_of(f))
+    }
+
+    pub fn block(&mut self, automata: Option<&'m AutomataDef>, env: &mut Env, block: &'m Block) -> Result<Flow, ExecError> {
+        env.push();
+        let mut last = Ok(Flow::Next(Value::unit()));
+        for e in block.block.iter() {
+            if let Some(debugger) = &mut self.debugger
+                && let Err(error) = debugger.on_statement(&self.frames, env.locals(), e.position)
+            {
+                last = Err(error);
+                break;
+            }
+            last = self.eval(automata, env, e);
+            if !matches!(last, Ok(Flow::Next(_))) {
+                break;
+            }
+        }
+        env.pop();
+        last
+    }
+
+    pub fn eval(&mut self, automata: Option<&'m AutomataDef>, env: &mut Env, e: &'m Expression) -> Result<Flow, ExecError> {
+        self.position = e.position;
+        match &e.kind {
+            ExpressionType::Import(_) => Ok(Flow::Next(Value::unit())),
+            ExpressionType::Definition(DefinitionExp::Define(d)) => {
+                let value = next!(self.eval(automata, env, &d.value));
+                let value = value.coerce(&Type::from_annotation(&d.ty))?;
+                env.define(&d.name, value);
+                Ok(Flow::Next(Value::unit()))
+            },
+            ExpressionType::Definition(_) => Ok(Flow::Next(Value::unit())),
+            ExpressionType::Procedural(p) => self.procedural(automata, env, p),
+            ExpressionType::Returnable(r) => self.returnable(automata, env, r),
+        }
+    }
+
+    fn procedural(&mut self, automata: Option<&'m AutomataDef>, env: &mut Env, p: &'m ProceduralExp) -> Result<Flow, ExecError> {
+        match p {
+            ProceduralExp::For(f) => {
+                // ranges are not built up front, the iteration budget is spent per item
+                let items: Box<dyn Iterator<Item = Value>> = match next!(self.eval(automata, env, &f.iterable)) {
+                    Value::Tuple(v) => Box::new(v.into_iter()),
+                    Value::String(s) => Box::new(s.chars().map(Value::Char).collect::<Vec<_>>().into_iter()),
+                    Value::Int(n) => Box::new((0..n).map(Value::Int)),
+                    v => return Err(ExecError::TypeMismatch { expected: "tuple".to_string(), found: v.type_name() }),
+                };
+                for item in items {
+                    self.spend_iteration()?;
+                    env.push();
+                    env.define(&f.variable, item);
+                    let flow = self.block(automata, env, &f.body);
+                    env.pop();
+                    next!(flow);
+                }
+                Ok(Flow::Next(Value::unit()))
+            },
+            ProceduralExp::While(w) => {
+                while next!(self.eval(automata, env, &w.condition)).as_bool()? {
+                    self.spend_iteration()?;
+                    next!(self.block(automata, env, &w.body));
+                }
+                Ok(Flow::Next(Value::unit()))
+            },
+            ProceduralExp::Link(l) => {
+                let a = match automata {
+                    Some(a) if !self.in_function => a,
+                    _ => return Err(ExecError::LinkInFunction),
+                };
+                let Some(SingleName(target, template)) = &l.target else {
+                    return Ok(Flow::Link(None));
+                };
+                let mut args = vec![];
+                for e in template.iter().flat_map(|t| t.args.iter()) {
+                    args.push(next!(self.eval(automata, env, e)));
+                }
+                Ok(Flow::Link(Some(self.enter(a, target, args)?)))
+            },
+            ProceduralExp::Return(value) => {
+                if !self.in_function {
+                    return Err(ExecError::ReturnOutsideFunction);
+                }
+                let v = match value {
+                    Some(v) => next!(self.eval(automata, env, v)),
+                    None => Value::unit(),
+                };
+                Ok(Flow::Return(v))
+            },
+        }
+    }
+
+    fn returnable(&mut self, automata: Option<&'m AutomataDef>, env: &mut Env, r: &'m ReturnableExp) -> Result<Flow, ExecError> {
+        let value = match r {
+            ReturnableExp::Statement(s) => match s {
+                Statement::Literal(l) => literal_value(l)?,
+                Statement::Block(b) => return self.block(automata, env, b),
+                Statement::Name(n) => {
+                    let path = n.path();
+                    match (env.get(&path), self.self_frame()) {
+                        (Some(v), _) => v.clone(),
+                        (None, Some(frame)) if path == "self" => Value::State(Box::new(frame.current.clone())),
+                        (None, _) => return Err(ExecError::UndefinedName(path)),
+                    }
+                },
+                Statement::Tuple(t) => {
+                    let mut v = Vec::with_capacity(t.tuple.len());
+                    for x in t.tuple.iter() {
+                        v.push(next!(self.eval(automata, env, x)));
+                    }
+                    Value::Tuple(v)
+                },
+            },
+            ReturnableExp::FunctionCall(callee, args) => {
+                let name = match callee {
+                    Statement::Name(n) => n.path(),
+                    _ => return Err(ExecError::UndefinedFunction("<expression>".to_string())),
+                };
+                let f = self.find_function(automata, &name);
+                // replayed host calls are answered by the trace
+                let replaying = matches!(self.tracer, Some(Tracer::Replay { .. }));
+                if f.is_none() && !self.host.has_function(&name) && !replaying {
+                    return Err(ExecError::UndefinedFunction(name));
+                }
+                let mut values = Vec::with_capacity(args.tuple.len());
+                for a in args.tuple.iter() {
+                    values.push(next!(self.eval(automata, env, a)));
+                }
+                match f {
+                    Some(f) => self.call(automata, f, values)?,
+                    None => self.host_call(name.clone(), values, |host, args| host.call(&name, args))?,
+                }
+            },
+            ReturnableExp::AutomataCall(SingleName(name, template)) => {
+                let a = self.find_automata(name)?;
+                let mut args = vec![];
+                for e in template.iter().flat_map(|t| t.args.iter()) {
+                    args.push(next!(self.eval(automata, env, e)));
+                }
+                Value::State(Box::new(self.run_moore(a, args)?))
+            },
+            ReturnableExp::Match(m) => {
+                let value = next!(self.eval(automata, env, &m.value));
+                for arm in m.arms.iter() {
+                    let mut bindings = vec![];
+                    if match_pattern(&arm.pattern, &value, &mut bindings)? {
+                        env.push();
+                        for (name, v) in bindings {
+                            env.define(&name, v);
+                        }
+                        let flow = self.eval(automata, env, &arm.body);
+                        env.pop();
+                        return flow;
+                    }
+                }
+                return Err(ExecError::NoMatch(value.to_string()));
+            },
+            ReturnableExp::Is(value, pattern) => {
+                let value = next!(self.eval(automata, env, value));
+                let mut bindings = vec![];
+                let matched = match_pattern(pattern, &value, &mut bindings)?;
+                // the bindings of a partial match are dropped
+                if matched {
+                    for (name, v) in bindings {
+                        env.define(&name, v);
+                    }
+                }
+                Value::Bool(matched)
+            },
+            ReturnableExp::BinaryOperator(b) => {
+                let op = b.operator.as_str();
+                match op {
+                    "=" | "+=" | "-=" | "*=" | "/=" => {
+                        let mut value = next!(self.eval(automata, env, &b.arg2));
+                        if op != "=" {
+                            let old = next!(self.eval(automata, env, &b.arg1));
+                            value = arithmetic(&op[..1], old, value)?;
+                        }
+                        self.assign(automata, env, &b.arg1, value)?;
+                        Value::unit()
+                    },
+                    "&&" | "||" => {
+                        let left = next!(self.eval(automata, env, &b.arg1)).as_bool()?;
+                        if left == (op == "||") {
+                            Value::Bool(left)
+                        } else {
+                            Value::Bool(next!(self.eval(automata, env, &b.arg2)).as_bool()?)
+                        }
+                    },
+                    _ => {
+                        let left = next!(self.eval(automata, env, &b.arg1));
+                        let right = next!(self.eval(automata, env, &b.arg2));
+                        match op {
+                            "==" | "!=" | "<" | ">" | "<=" | ">=" => compare(op, &left, &right)?,
+                            _ => arithmetic(op, left, right)?,
+                        }
+                    },
+                }
+            },
+            ReturnableExp::UnaryyOperator(u) => {
+                match (u.operator.as_str(), next!(self.eval(automata, env, &u.arg))) {
+                    ("-", Value::Int(i)) => Value::Int(i.checked_neg().ok_or(ExecError::Overflow)?),
+                    ("-", Value::Float(x)) => Value::Float(-x),
+                    ("!", Value::Bool(b)) => Value::Bool(!b),
+                    ("~", Value::Int(i)) => Value::Int(!i),
+                    (op, v) => return Err(ExecError::TypeMismatch { expected: format!("operand of `{}`", op), found: v.type_name() }),
+                }
+            },
+            ReturnableExp::Member(value, member) => {
+                if is_self(value) && env.get("self").is_none()
+                    && let Some(frame) = self.self_frame()
+                {
+                    match member.as_str() {
+                        "previous" => return Ok(Flow::Next(frame.previous.clone().map_or(Value::Null, |p| Value::State(Box::new(p))))),
+                        "steps" => return Ok(Flow::Next(Value::Int(frame.steps as i64))),
+                        "automata" => return Ok(Flow::Next(Value::String(frame.current.automata.clone()))),
+                        _ => {},
+                    }
+                }
+                let v = next!(self.eval(automata, env, value));
+                member_of(v, member)?
+            },
+            ReturnableExp::MethodCall(value, method, args) => {
+                let v = next!(self.eval(automata, env, value));
+                let mut values = Vec::with_capacity(args.tuple.len());
+                for a in args.tuple.iter() {
+                    values.push(next!(self.eval(automata, env, a)));
+                }
+                self.method(v, method, values)?
+            },
+            ReturnableExp::Index(value, index) => {
+                let v = next!(self.eval(automata, env, value));
+                let i = next!(self.eval(automata, env, index));
+                index_of(v, &i)?
+            },
+            ReturnableExp::If(i) => {
+                // bindings of `is` in the condition live until the end of `then`
+                env.push();
+                let then = match self.eval(automata, env, &i.condition) {
+                    Ok(Flow::Next(c)) => match c.as_bool() {
+                        Ok(true) => self.block(automata, env, &i.then).map(Some),
+                        Ok(false) => Ok(None),
+                        Err(e) => Err(e),
+                    },
+                    flow => flow.map(Some),
+                };
+                env.pop();
+                if let Some(flow) = then? {
+                    return Ok(flow);
+                } else if let Some(o) = &i.otherwise {
+                    return self.block(automata, env, o);
+                }
+                Value::unit()
+            },
+        };
+        Ok(Flow::Next(value))
+    }
+
+    /// Builds the state value checking the template arguments
+    pub fn enter(&self, a: &'m AutomataDef, state: &str, args: Vec<Value>) -> Result<StateValue, ExecError> {
+        let s = a.state(state).ok_or_else(|| ExecError::UndefinedState(state.to_string()))?;
+        let params = Checker::template_params(a, &s.params);
+        if params.len() != args.len() {
+            return Err(ExecError::ArityMismatch { name: s.name.clone(), expected: params.len(), found: args.len() });
+        }
+        Ok(StateValue {
+            automata: a.name.clone(),
+            state: s.name.clone(),
+            args: params.iter()
+                .zip(args)
+                .map(|(p, v)| Ok((p.name.clone(), v.coerce(&Type::from_annotation(&p.ty))?)))
+                .collect::<Result<_, ExecError>>()?,
+        })
+    }
+
+    pub fn find_automata(&self, name: &str) -> Result<&'m AutomataDef, ExecError> {
+        self.module.find_automata(name).ok_or_else(|| ExecError::UndefinedAutomata(name.to_string()))
+    }
+
+    /// Initial state of the automata with the context arguments
+    pub fn start(&self, a: &'m AutomataDef, args: Vec<Value>) -> Result<StateValue, ExecError> {
+        let initial = a.initial_state().ok_or_else(|| ExecError::NoStates(a.name.clone()))?;
+        self.enter(a, &initial.name, args)
+    }
+
+    fn state_def(&self, a: &'m AutomataDef, current: &StateValue) -> Result<&'m StateDef, ExecError> {
+        a.state(&current.state).ok_or_else(|| ExecError::UndefinedState(current.state.clone()))
+    }
+
+    /// Evaluates the body of the state in the prepared environment.
+    /// Returns the linked state, `None` stands for NULL which is also the default link.
+    fn state_body(&mut self, a: &'m AutomataDef, state: &'m StateDef, env: &mut Env) -> Result<Option<StateValue>, ExecError> {
+        let outer = std::mem::replace(&mut self.in_function, false);
+        let result = self.block(Some(a), env, &state.body);
+        self.in_function = outer;
+        let next = match result? {
+            Flow::Link(next) => next,
+            Flow::Next(_) => None,
+            Flow::Return(_) => return Err(ExecError::ReturnOutsideFunction),
+        };
+        if let Some(debugger) = &mut self.debugger {
+            debugger.on_link(&self.frames, env.locals(), next.as_ref(), self.position)?;
+        }
+        Ok(next)
+    }
+
+    /// Makes a single transition of the Moore automata outside of any run,
+    /// `self.previous` is NULL for it
+    pub fn step(&mut self, a: &'m AutomataDef, current: &StateValue) -> Result<Option<StateValue>, ExecError> {
+        self.begin();
+        self.frames.push(RunFrame::new(current.clone()));
+        let result = self.frame_step(a, None);
+        self.frames.pop();
+        result.map(|(next, _)| next)
+    }
+
+    /// Evaluates the current state of the innermost frame, binding the signal for Mealy.
+    /// Returns the linked state and the output of the transition.
+    fn frame_step(&mut self, a: &'m AutomataDef, signal: Option<Value>) -> Result<(Option<StateValue>, Option<Value>), ExecError> {
+        let current = &self.frames.last().expect("frame is pushed by the caller").current;
+        let state = self.state_def(a, current)?;
+        let mut env = Env::new();
+        let mut output = None;
+        if let AutomataKind::Mealy(signal_name, output_name) = &a.kind {
+            let signal = signal.unwrap_or(Value::unit());
+            env.define(signal_name, signal.coerce(&Type::from_annotation(&a.signal_param(state).and_then(|p| p.ty.clone())))?);
+            if let Some(o) = output_name {
+                env.define(o, Value::unit());
+                output = Some(o);
+            }
+        }
+        for (name, v) in current.args.iter() {
+            env.define(name, v.clone());
+        }
+        if let Some(debugger) = &mut self.debugger {
+            debugger.on_entry(&self.frames, env.locals(), state.position)?;
+        }
+        let next = self.state_body(a, state, &mut env)?;
+        Ok((next, output.and_then(|o| env.get(o).cloned())))
+    }
+
+    /// Moves the innermost frame to the linked state
+    fn advance(&mut self, next: StateValue) -> Result<(), ExecError> {
+        self.spend_transition()?;
+        self.trace(|| TraceEvent::Link { to: Some(next.clone()) })?;
+        let frame = self.frames.last_mut().expect("frame is pushed by the caller");
+        frame.previous = Some(std::mem::replace(&mut frame.current, next));
+        frame.steps += 1;
+        frame.runs = 0;
+        // the bodies replayed up to the resumed `run` do not pause
+        if self.pause_at.is_some_and(|at| self.transitions >= at) && self.resuming.is_empty() {
+            self.paused = Some(Snapshot::new(self.module, self.frames.clone()));
+            return Err(ExecError::Paused);
+        }
+        Ok(())
+    }
+
+    fn push_frame(&mut self, a: &'m AutomataDef, args: Vec<Value>) -> Result<(), ExecError> {
+        if self.frames.len() >= self.budget.max_run_depth {
+            let mut error = self.exhausted(Limit::RunDepth(self.budget.max_run_depth));
+            if let ExecError::BudgetExhausted { position, .. } = &mut error {
+                position.run_stack.push(a.name.clone());
+            }
+            return Err(error);
+        }
+        self.begin();
+        let index = self.frames.last_mut().map(|parent| {
+            parent.runs += 1;
+            parent.runs - 1
+        });
+        // the nested run the snapshot was taken in continues instead of starting over
+        if let Some((at, frame)) = self.resuming.last()
+            && Some(*at) == index
+        {
+            if frame.current.automata != a.name {
+                return Err(ExecError::InvalidSnapshot(SnapshotError::Mismatch(format!(
+                    "expected `run {}`, found `run {}`", frame.current.automata, a.name,
+                ))));
+            }
+            let (_, frame) = self.resuming.pop().expect("checked above");
+            self.frames.push(frame);
+            return Ok(());
+        }
+        if self.tracer.is_some() {
+            self.trace(|| TraceEvent::Run { automata: a.name.clone(), args: args.clone() })?;
+        }
+        let initial = self.start(a, args)?;
+        self.trace(|| TraceEvent::Enter { state: initial.clone() })?;
+        self.frames.push(RunFrame::new(initial));
+        Ok(())
+    }
+
+    /// Names of the automata being run, the outermost first
+    pub fn run_stack(&self) -> Vec<String> {
+        self.frames.iter().map(|f| f.current.automata.clone()).collect()
+    }
+
+    /// Runs the Moore automata from its initial state until it links to NULL.
+    /// Returns the final state with its arguments.
+    pub fn run_automata(&mut self, name: &str, args: Vec<Value>) -> Result<StateValue, ExecError> {
+        let a = self.find_automata(name)?;
+        self.run_moore(a, args)
+    }
+
+    fn run_moore(&mut self, a: &'m AutomataDef, args: Vec<Value>) -> Result<StateValue, ExecError> {
+        if a.kind != AutomataKind::Moore {
+            return Err(ExecError::UnexpectedAutomataKind(a.name.clone()));
+        }
+        self.push_frame(a, args)?;
+        let result = self.run_moore_states(a);
+        let frame = self.frames.pop().expect("frame is pushed above");
+        result.map(|_| frame.current)
+    }
+
+    fn run_moore_states(&mut self, a: &'m AutomataDef) -> Result<(), ExecError> {
+        while let (Some(next), _) = self.frame_step(a, None)? {
+            self.advance(next)?;
+        }
+        self.trace(|| TraceEvent::Link { to: None })
+    }
+
+    /// Makes a single transition of the Mealy automata with the given signal outside of any run.
+    /// Returns the linked state and the output of the transition.
+    pub fn step_mealy(&mut self, a: &'m AutomataDef, current: &StateValue, signal: Value) -> Result<(Option<StateValue>, Option<Value>), ExecError> {
+        if a.kind == AutomataKind::Moore {
+            return Err(ExecError::UnexpectedAutomataKind(a.name.clone()));
+        }
+        self.begin();
+        self.frames.push(RunFrame::new(current.clone()));
+        let result = self.frame_step(a, Some(signal));
+        self.frames.pop();
+        result
+    }
+
+    /// Feeds the signals to the Mealy automata started with the context arguments.
+    /// States with the unit signal type do not read the input.
+    pub fn run_mealy(&mut self, name: &str, args: Vec<Value>, input: impl IntoIterator<Item = Value>) -> Result<MealyRun, ExecError> {
+        let a = self.find_automata(name)?;
+        if a.kind == AutomataKind::Moore {
+            return Err(ExecError::UnexpectedAutomataKind(a.name.clone()));
+        }
+        self.push_frame(a, args)?;
+        let result = self.run_mealy_states(a, input);
+        let frame = self.frames.pop().expect("frame is pushed above");
+        result.map(|(halt, outputs, consumed)| MealyRun { halt, state: frame.current, outputs, consumed })
+    }
+
+    fn run_mealy_states(&mut self, a: &'m AutomataDef, input: impl IntoIterator<Item = Value>) -> Result<(MealyHalt, Vec<Value>, usize), ExecError> {
+        let mut input = input.into_iter().peekable();
+        let mut outputs = vec![];
+        let mut consumed = 0;
+        loop {
+            let state = self.state_def(a, &self.frames.last().expect("frame is pushed by the caller").current)?;
+            let signal = if a.consumes_signal(state) {
+                match input.peek() {
+                    Some(signal) => signal.clone(),
+                    None => return Ok((MealyHalt::Consumed, outputs, consumed)),
+                }
+            } else {
+                Value::unit()
+            };
+            let accepted = a.signal_param(state).is_none_or(|p| signal.clone().coerce(&Type::from_annotation(&p.ty)).is_ok());
+            if !accepted {
+                return Ok((MealyHalt::Rejected(signal), outputs, consumed));
+            }
+            if a.consumes_signal(state) {
+                self.trace(|| TraceEvent::Signal { value: signal.clone() })?;
+            }
+            let (next, output) = self.frame_step(a, Some(signal))?;
+            if a.consumes_signal(state) {
+                input.next();
+                consumed += 1;
+            }
+            outputs.extend(output);
+            match next {
+                Some(next) => self.advance(next)?,
+                None => {
+                    self.trace(|| TraceEvent::Link { to: None })?;
+                    return Ok((MealyHalt::Null { remaining: input.count() }, outputs, consumed));
+                },
+            }
+        }
+    }
+
+    /// Snapshot of the Moore or Mealy automata in its initial state
+    pub fn start_snapshot(&mut self, name: &str, args: Vec<Value>) -> Result<Snapshot, ExecError> {
+        let a = self.find_automata(name)?;
+        Ok(Snapshot::new(self.module, vec![RunFrame::new(self.start(a, args)?)]))
+    }
+
+    /// Runs the Moore automata like [`Interpreter::run_automata`] but pauses after the given number of transitions,
+    /// nested `run`s included
+    pub fn run_for(&mut self, name: &str, args: Vec<Value>, transitions: usize) -> Result<Progress, ExecError> {
+        let snapshot = self.start_snapshot(name, args)?;
+        self.resume(&snapshot, Some(transitions))
+    }
+
+    /// Continues the Moore automata from the snapshot, pausing after the given number of transitions.
+    /// Bodies of the states running the nested automata are evaluated again up to their `run`,
+    /// so native functions called before it are called again.
+    pub fn resume(&mut self, snapshot: &Snapshot, transitions: Option<usize>) -> Result<Progress, ExecError> {
+        let a = self.resume_frames(snapshot)?;
+        if a.kind != AutomataKind::Moore {
+            return Err(ExecError::UnexpectedAutomataKind(a.name.clone()));
+        }
+        self.pause_at = transitions;
+        let result = self.run_moore_states(a);
+        self.pause_at = None;
+        self.resuming.clear();
+        let frame = self.frames.pop();
+        self.frames.clear();
+        match result {
+            Ok(()) => Ok(Progress::Done(frame.expect("frame is pushed by resume_frames").current)),
+            Err(ExecError::Paused) => Ok(Progress::Paused(Box::new(self.paused.take().expect("paused in advance")))),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Feeds the signals to the Mealy automata restored from the snapshot.
+    /// Returns the run and the snapshot to continue from with the following input.
+    pub fn resume_mealy(&mut self, snapshot: &Snapshot, input: impl IntoIterator<Item = Value>) -> Result<(MealyRun, Snapshot), ExecError> {
+        let a = self.resume_frames(snapshot)?;
+        if a.kind == AutomataKind::Moore {
+            self.frames.clear();
+            self.resuming.clear();
+            return Err(ExecError::UnexpectedAutomataKind(a.name.clone()));
+        }
+        let result = self.run_mealy_states(a, input);
+        self.resuming.clear();
+        let frame = self.frames.pop().expect("frame is pushed by resume_frames");
+        let snapshot = Snapshot::new(self.module, vec![frame.clone()]);
+        result.map(|(halt, outputs, consumed)| (MealyRun { halt, state: frame.current, outputs, consumed }, snapshot))
+    }
+
+    /// Pushes the outermost frame of the checked snapshot, the nested ones wait for their `run`s
+    fn resume_frames(&mut self, snapshot: &Snapshot) -> Result<&'m AutomataDef, ExecError> {
+        snapshot.check(self.module).map_err(ExecError::InvalidSnapshot)?;
+        let a = self.find_automata(&snapshot.frames[0].current.automata)?;
+        self.begin();
+        // the parents count their `run`s again while evaluating the bodies up to the nested ones
+        let mut frames = snapshot.frames.clone();
+        let runs: Vec<usize> = frames.iter_mut().map(|f| std::mem::take(&mut f.runs)).collect();
+        let nested = frames.split_off(1);
+        self.resuming = runs.into_iter().zip(nested).map(|(runs, f)| (runs - 1, f)).rev().collect();
+        self.frames.extend(frames);
+        Ok(a)
+    }
+
+    /// Stops the execution on the breakpoints and steps of the debugger
+    pub fn with_debugger(mut self, debugger: Debugger<'m>) -> Self {
+        self.debugger = Some(debugger);
+        self
+    }
+
+    /// Records every run, entered state, read signal, link and host call into the JSON Lines writer
+    pub fn with_trace(mut self, writer: impl Write + 'static) -> Self {
+        self.tracer = Some(Tracer::Record(Box::new(writer)));
+        self
+    }
+
+    fn trace(&mut self, event: impl FnOnce() -> TraceEvent) -> Result<(), ExecError> {
+        match &mut self.tracer {
+            Some(tracer) => tracer.event(event()),
+            None => Ok(()),
+        }
+    }
+
+    /// Calls into the host, recording the result or taking it from the replayed trace
+    fn host_call(&mut self, function: String, args: Vec<Value>, call: impl FnOnce(&Host, Vec<Value>) -> Result<Value, ExecError>) -> Result<Value, ExecError> {
+        let Some(tracer) = &mut self.tracer else {
+            return call(&self.host, args);
+        };
+        if let Some(result) = tracer.replayed_call(&function, &args)? {
+            return result;
+        }
+        let result = call(&self.host, args.clone());
+        let recorded = result.clone().map_err(|e| e.to_string());
+        tracer.event(TraceEvent::HostCall { function, args, result: recorded })?;
+        result
+    }
+
+    /// Re-executes the recorded trace: the automata and its arguments come from the first event,
+    /// the input of the Mealy automata from the signal events and the results of the host calls from the trace.
+    /// Fails with [`ExecError::Diverged`] on the first event the execution does not reproduce.
+    pub fn replay(&mut self, events: Vec<Json>) -> Result<Replayed, ExecError> {
+        let malformed = || ExecError::Trace("trace does not start with the run event".to_string());
+        let first = events.first().filter(|e| e.get("event").and_then(Json::as_str) == Some("run")).ok_or_else(malformed)?;
+        let name = first.get("automata").and_then(Json::as_str).ok_or_else(malformed)?.to_string();
+        let values = |json: Option<&Json>| json.into_iter().flat_map(|v| v.as_array().unwrap_or_default())
+            .map(|v| value_from_json(v).map_err(|e| ExecError::Trace(e.to_string())))
+            .collect::<Result<Vec<_>, _>>();
+        let args = values(first.get("args"))?;
+        let input = values(Some(&Json::Array(events.iter()
+            .filter(|e| e.get("event").and_then(Json::as_str) == Some("signal"))
+            .filter_map(|e| e.get("value").cloned())
+            .collect())))?;
+        let a = self.find_automata(&name)?;
+        let outer = self.tracer.replace(Tracer::Replay { events, next: 0 });
+        let result = match a.kind {
+            AutomataKind::Moore => self.run_automata(&name, args).map(Replayed::Moore),
+            AutomataKind::Mealy(..) => self.run_mealy(&name, args, input).map(Replayed::Mealy),
+        };
+        let tracer = std::mem::replace(&mut self.tracer, outer).expect("replay tracer is set above");
+        let result = result?;
+        tracer.finish()?;
+        Ok(result)
+    }
+
+    /// Innermost running automata, visible as `self` in state bodies only
+    fn self_frame(&self) -> Option<&RunFrame> {
+        if self.in_function { None } else { self.frames.last() }
+    }
+
+    fn method(&mut self, v: Value, method: &str, args: Vec<Value>) -> Result<Value, ExecError> {
+        match method {
+            "is_me" => {
+                if !args.is_empty() {
+                    return Err(ExecError::ArityMismatch { name: method.to_string(), expected: 0, found: args.len() });
+                }
+                let current = self.self_frame().map(|f| &f.current);
+                Ok(Value::Bool(match (v, current) {
+                    (Value::State(s), c) => c.is_some_and(|c| s.automata == c.automata && s.state == c.state),
+                    (Value::Null, _) => false,
+                    (v, _) => return Err(ExecError::TypeMismatch { expected: "state".to_string(), found: v.type_name() }),
+                }))
+            },
+            _ => match v {
+                Value::Host(h) => self.host_call(format!("{}.{}", h.type_name(), method), args, |_, args| h.method(method, args)),
+                _ => Err(ExecError::UndefinedMethod(method.to_string())),
+            },
+        }
+    }
+
+    /// Stores the value into the variable, tuple field or tuple index
+    fn assign(&mut self, automata: Option<&'m AutomataDef>, env: &mut Env, target: &'m Expression, value: Value) -> Result<(), ExecError> {
+        // path from the variable to the assigned element
+        let mut path = vec![];
+        let mut current = target;
+        let name = loop {
+            match &current.kind {
+                ExpressionType::Returnable(ReturnableExp::Statement(Statement::Name(n))) => break n.path(),
+                ExpressionType::Returnable(ReturnableExp::Member(v, m)) => {
+                    path.push(m.parse::<usize>().map_err(|_| ExecError::NoSuchMember(m.clone()))?);
+                    current = v;
+                },
+                ExpressionType::Returnable(ReturnableExp::Index(v, i)) => {
+                    let i = match self.eval(automata, env, i)? {
+                        Flow::Next(i) => i.as_index()?,
+                        _ => return Err(ExecError::InvalidAssignment),
+                    };
+                    path.push(i);
+                    current = v;
+                },
+                _ => return Err(ExecError::InvalidAssignment),
+            }
+        };
+        let mut slot = env.get_mut(&name).ok_or(ExecError::UndefinedName(name))?;
+        for i in path.into_iter().rev() {
+            slot = match slot {
+                Value::Tuple(v) => v.get_mut(i).ok_or(ExecError::IndexOutOfBounds(i as i64))?,
+                v => return Err(ExecError::TypeMismatch { expected: "tuple".to_string(), found: v.type_name() }),
+            };
+        }
+        *slot = value;
+        Ok(())
+    }
+}
+
+/// Does the last expression of the block produce the value of the block
+pub(crate) fn ends_with_value(block: &Block) -> bool {
+    matches!(block.block.last().map(|e| &e.kind), Some(ExpressionType::Returnable(_)))
+}
+
+pub fn member_of(v: Value, member: &str) -> Result<Value, ExecError> {
+    match (v, member.parse::<usize>()) {
+        (Value::Tuple(mut t), Ok(i)) if i < t.len() => Ok(t.swap_remove(i)),
+        (Value::State(mut s), Ok(i)) if i < s.args.len() => Ok(s.args.swap_remove(i).1),
+        (Value::State(s), Err(_)) => s.arg(member).cloned().ok_or_else(|| ExecError::NoSuchMember(member.to_string())),
+        (Value::Host(h), _) => h.member(member),
+        _ => Err(ExecError::NoSuchMember(member.to_string())),
+    }
+}
+
+/// Tries the pattern against the value, collecting the bindings on success
+pub fn match_pattern(pattern: &Pattern, value: &Value, bindings: &mut Vec<(String, Value)>) -> Result<bool, ExecError> {
+    match pattern {
+        Pattern::Wildcard => Ok(true),
+        Pattern::Binding(name) => {
+            bindings.push((name.clone(), value.clone()));
+            Ok(true)
+        },
+        Pattern::Literal(l) => Ok(compare("==", &literal_value(l)?, value)? == Value::Bool(true)),
+        Pattern::Tuple(patterns) => match value {
+            Value::Tuple(v) if v.len() == patterns.len() => {
+                for (p, x) in patterns.iter().zip(v) {
+                    if !match_pattern(p, x, bindings)? { return Ok(false); }
+                }
+                Ok(true)
+            },
+            _ => Ok(false),
+        },
+        Pattern::State { automata, state, args } => {
+            let s = match value {
+                Value::State(s) if s.state == *state && automata.as_ref().is_none_or(|a| *a == s.automata) => s,
+                _ => return Ok(false),
+            };
+            match args {
+                None => Ok(true),
+                Some(patterns) if patterns.len() == s.args.len() => {
+                    for (p, (_, x)) in patterns.iter().zip(s.args.iter()) {
+                        if !match_pattern(p, x, bindings)? { return Ok(false); }
+                    }
+                    Ok(true)
+                },
+                Some(patterns) if patterns.len() == 1 && patterns[0].is_irrefutable() => {
+                    match_pattern(&patterns[0], value, bindings)
+                },
+                Some(patterns) => Err(ExecError::ArityMismatch {
+                    name: state.clone(), expected: s.args.len(), found: patterns.len(),
+                }),
+            }
+        },
+    }
+}
+
+pub fn index_of(v: Value, i: &Value) -> Result<Value, ExecError> {
+    let i = i.as_index()?;
+    match v {
+        Value::Tuple(mut t) if i < t.len() => Ok(t.swap_remove(i)),
+        Value::String(s) => s.chars().nth(i).map(Value::Char).ok_or(ExecError::IndexOutOfBounds(i as i64)),
+        Value::Tuple(_) => Err(ExecError::IndexOutOfBounds(i as i64)),
+        v => Err(ExecError::TypeMismatch { expected: "tuple".to_string(), found: v.type_name() }),
+    }
+}
+
+#[test]
+fn function_call_test() {
+    use crate::parser::Parser;
+    let module = Parser::parse_str("
+        fn fib(n: int64) -> int64 {
+            if n < 2 { return n; }
+            fib(n - 1) + fib(n - 2)
+        }
+        fn swap(t: (int64, char)) -> (char, int64) {
+            let r = (t.1, t[0]);
+            r[1] += 1;
+            return r;
+        }
+        fn half(x: float64) -> float64 { x / 2 }
+        fn forever(n: int64) -> int64 { forever(n + 1) }
+    ").unwrap();
+    let mut interpreter = Interpreter::new(&module);
+    assert_eq!(interpreter.call_function("fib", vec![Value::Int(15)]), Ok(Value::Int(610)));
+    assert_eq!(
+        interpreter.call_function("swap", vec![Value::Tuple(vec![Value::Int(1), Value::Char('a')])]),
+        Ok(Value::Tuple(vec![Value::Char('a'), Value::Int(2)]))
+    );
+    assert_eq!(interpreter.call_function("half", vec![Value::Int(3)]), Ok(Value::Float(1.5)));
+    assert_eq!(
+        interpreter.call_function("half", vec![Value::Char('3')]),
+        Err(ExecError::TypeMismatch { expected: "float64".to_string(), found: "char".to_string() })
+    );
+    let mut interpreter = Interpreter::new(&module).with_budget(Budget { max_call_depth: 16, ..Budget::default() });
+    match interpreter.call_function("forever", vec![Value::Int(0)]) {
+        Err(ExecError::BudgetExhausted { limit: Limit::CallDepth(16), .. }) => {},
+        r => panic!("{:?}", r),
+    }
+}
+
+#[test]
+fn recursion_test() {
+    // debug builds take more of the native stack per call than the test threads have
+    std::thread::Builder::new().stack_size(256 << 20).spawn(|| {
+        let module = Parser::parse_str("
+            fn sum(n: int64) -> int64 { if n == 0 { return 0; } return n + sum(n - 1); }
+        ").unwrap();
+        let mut interpreter = Interpreter::new(&module);
+        assert_eq!(interpreter.call_function("sum", vec![Value::Int(100)]), Ok(Value::Int(5050)));
+        assert_eq!(interpreter.call_function("sum", vec![Value::Int(999)]), Ok(Value::Int(499500)));
+        match interpreter.call_function("sum", vec![Value::Int(MAX_CALL_DEPTH as i64)]) {
+            Err(ExecError::BudgetExhausted { limit: Limit::CallDepth(MAX_CALL_DEPTH), .. }) => {},
+            r => panic!("{:?}", r),
+        }
+    }).unwrap().join().unwrap();
+}
+
+#[test]
+fn match_test() {
+    use crate::parser::Parser;
+    let module = Parser::parse_str("
+        fn classify(t: (int64, char)) -> int64 {
+            match t {
+                (0, _) => 1,
+                (-1, 'a') => { 2 }
+                (n, c) => n * 10,
+            }
+        }
+        fn verdict(v: Other) -> int64 {
+            if v is Other::Verdict1(verd1) {
+                return verd1.arg1 + verd1.arg2;
+            }
+            match v {
+                Other::Verdict2(x) => x,
+                _ => 0,
+            }
+        }
+        fn pick(t: (int64, int64)) -> int64 {
+            let a = 0;
+            if (t is (a, 1)) || true { return a; }
+            a
+        }
+        fn after(t: (int64, int64)) -> int64 {
+            if t is (b, _) { }
+            b
+        }
+    ").unwrap();
+    let mut interpreter = Interpreter::new(&module);
+    let t = |i, c| Value::Tuple(vec![Value::Int(i), Value::Char(c)]);
+    assert_eq!(interpreter.call_function("classify", vec![t(0, 'x')]), Ok(Value::Int(1)));
+    assert_eq!(interpreter.call_function("classify", vec![t(-1, 'a')]), Ok(Value::Int(2)));
+    assert_eq!(interpreter.call_function("classify", vec![t(-1, 'b')]), Ok(Value::Int(-10)));
+    let state = |name: &str, args: Vec<(&str, i64)>| Value::State(Box::new(StateValue {
+        automata: "Other".to_string(),
+        state: name.to_string(),
+        args: args.into_iter().map(|(n, v)| (n.to_string(), Value::Int(v))).collect(),
+    }));
+    assert_eq!(interpreter.call_function("verdict", vec![state("Verdict1", vec![("arg1", 2), ("arg2", 3)])]), Ok(Value::Int(5)));
+    assert_eq!(interpreter.call_function("verdict", vec![state("Verdict2", vec![("arg1", 7)])]), Ok(Value::Int(7)));
+    assert_eq!(interpreter.call_function("verdict", vec![state("Base", vec![])]), Ok(Value::Int(0)));
+
+    let pair = |a, b| vec![Value::Tuple(vec![Value::Int(a), Value::Int(b)])];
+    assert_eq!(interpreter.call_function("pick", pair(5, 1)), Ok(Value::Int(5)));
+    // a failed `is` binds nothing, `a` is still the outer one
+    assert_eq!(interpreter.call_function("pick", pair(5, 2)), Ok(Value::Int(0)));
+    // bindings of the condition end with the `if`
+    assert_eq!(interpreter.call_function("after", pair(5, 2)), Err(ExecError::UndefinedName("b".to_string())));
+}
+
+#[test]
+fn moore_automata_test() {
+    use crate::parser::Parser;
+    let module = Parser::parse_str("
+        automata Counter {
+            state Base<context: (int64, char)> {
+                context[0] += 1;
+                if context[0] == 10 {
+                    link self -> Done<context[0] * 2, context[1]>;
+                } else {
+                    link self -> Base<context>;
+                }
+            }
+            initial state Start<first: int64> {
+                link self -> Base<(first, 'c')>;
+            }
+            state Done<total: int64, tag: char> {
+                # default link is NULL
+            }
+        }
+    ").unwrap();
+    let mut interpreter = Interpreter::new(&module);
+    let done = interpreter.run_automata("Counter", vec![Value::Int(3)]).unwrap();
+    assert_eq!(done.state, "Done");
+    assert_eq!(done.args, vec![("total".to_string(), Value::Int(20)), ("tag".to_string(), Value::Char('c'))]);
+    assert_eq!(
+        interpreter.run_automata("Counter", vec![]),
+        Err(ExecError::ArityMismatch { name: "Start".to_string(), expected: 1, found: 0 })
+    );
+}
+
+#[test]
+fn mealy_automata_test() {
+    use crate::parser::Parser;
+    let module = Parser::parse_str("
+        automata Mathematica: Mealy<signal, out> {
+            state Number<signal: char, acc: int64> {
+                if signal == '=' {
+                    link self -> Result<acc>;
+                } else {
+                    out = acc * 10 + (signal - '0');
+                    link self -> Number<out>;
+                }
+            }
+            state Result<signal: (), acc: int64> {
+                out = acc;
+                link self -> NULL;
+            }
+        }
+    ").unwrap();
+    let mut interpreter = Interpreter::new(&module);
+    let chars = |s: &str| s.chars().map(Value::Char).collect::<Vec<_>>();
+
+    let run = interpreter.run_mealy("Mathematica", vec![Value::Int(0)], chars("42=7")).unwrap();
+    assert_eq!(run.halt, MealyHalt::Null { remaining: 1 });
+    assert_eq!(run.state.state, "Result");
+    assert_eq!(run.outputs, vec![Value::Int(4), Value::Int(42), Value::unit(), Value::Int(42)]);
+    assert_eq!(run.consumed, 3);
+
+    let run = interpreter.run_mealy("Mathematica", vec![Value::Int(0)], chars("12")).unwrap();
+    assert_eq!(run.halt, MealyHalt::Consumed);
+    assert_eq!(run.state.args, vec![("acc".to_string(), Value::Int(12))]);
+
+    let run = interpreter.run_mealy("Mathematica", vec![Value::Int(0)], vec![Value::Char('1'), Value::Int(2)]).unwrap();
+    assert_eq!(run.halt, MealyHalt::Rejected(Value::Int(2)));
+    assert_eq!(run.consumed, 1);
+}
+
+#[test]
+fn nested_run_test() {
+    use crate::parser::Parser;
+    let module = Parser::parse_str("
+        automata ContextAutomata {
+            state Base<context1: int64, context2: int64> {
+                if context1 > context2 {
+                    link self -> Verdict1<context1, context2>;
+                } else {
+                    link self -> Verdict2<context2>;
+                }
+            }
+            state Verdict1<arg1: int64, arg2: int64> { link self -> NULL; }
+            state Verdict2<arg1: int64> { link self -> NULL; }
+        }
+        automata MyAutomata {
+            state Base<context: (int64, int64)> {
+                let con_autom_result = run ContextAutomata<context[0], context[1]>;
+                if con_autom_result is ContextAutomata::Verdict1(verd1) {
+                    link self -> Done<verd1.arg1 - verd1.arg2>;
+                } else {
+                    link self -> Done<0>;
+                }
+            }
+            state Done<diff: int64> { }
+        }
+        automata Runaway {
+            state Again { let r = run Runaway; }
+        }
+    ").unwrap();
+    let mut interpreter = Interpreter::new(&module);
+    let pair = |a, b| vec![Value::Tuple(vec![Value::Int(a), Value::Int(b)])];
+    assert_eq!(interpreter.run_automata("MyAutomata", pair(7, 2)).unwrap().args[0].1, Value::Int(5));
+    assert_eq!(interpreter.run_automata("MyAutomata", pair(1, 2)).unwrap().args[0].1, Value::Int(0));
+
+    let mut interpreter = Interpreter::new(&module).with_max_run_depth(3);
+    match interpreter.run_automata("Runaway", vec![]) {
+        Err(ExecError::BudgetExhausted { limit: Limit::RunDepth(3), position }) =>
+            assert_eq!(position.run_stack, vec!["Runaway".to_string(); 4]),
+        r => panic!("{:?}", r),
+    }
+    // the stack is unwound after the error
+    assert_eq!(interpreter.run_automata("ContextAutomata", vec![Value::Int(1), Value::Int(1)]).unwrap().state, "Verdict2");
+}
+
+#[test]
+fn self_introspection_test() {
+    use crate::parser::Parser;
+    let module = Parser::parse_str("
+        automata History {
+            state Base<context: (int64, int64)> {
+                if self.previous.is_me() {
+                    context[0] += 1;
+                }
+                if context[0] == 3 {
+                    link self -> Done<self.steps, self.automata, self.context[1]>;
+                } else {
+                    link self -> Base<context>;
+                }
+            }
+            initial state Start<first: int64> {
+                let none = self.previous.is_me();
+                link self -> Base<(first, self.first + 1)>;
+            }
+            state Done<steps: int64, name: string, second: int64> { }
+        }
+    ").unwrap();
+    let mut interpreter = Interpreter::new(&module);
+    let done = interpreter.run_automata("History", vec![Value::Int(0)]).unwrap();
+    assert_eq!(done.args, vec![
+        ("steps".to_string(), Value::Int(4)),
+        ("name".to_string(), Value::String("History".to_string())),
+        ("second".to_string(), Value::Int(1)),
+    ]);
+    let a = module.find_automata("History").unwrap();
+    let start = interpreter.enter(a, "Start", vec![Value::Int(5)]).unwrap();
+    let next = interpreter.step(a, &start).unwrap().unwrap();
+    assert_eq!(next.args, vec![("context".to_string(), Value::Tuple(vec![Value::Int(5), Value::Int(6)]))]);
+}
+
+#[test]
+fn budget_test() {
+    use crate::parser::Parser;
+    use std::cell::Cell;
+    use std::time::Duration;
+
+    /// Every reading takes a millisecond
+    struct TickingClock(Cell<u64>);
+    impl Clock for TickingClock {
+        fn now(&self) -> Duration {
+            self.0.set(self.0.get() + 1);
+            Duration::from_millis(self.0.get())
+        }
+    }
+
+    let module = Parser::parse_str("
+        automata MyAutomata {
+            state Base<context: (int64, int64)> {
+                context[1] += 1; # the counter is never checked
+                link self -> Base<context>;
+            }
+        }
+        automata Spin {
+            state Base<n: int64> {
+                while n >= 0 { n += 1; }
+            }
+        }
+        fn count(n: int64) -> int64 {
+            let total = 0;
+            for i in n { total += i; }
+            total
+        }
+    ").unwrap();
+    let budget = Budget { max_transitions: Some(100), max_iterations: Some(1000), ..Budget::default() };
+    let mut interpreter = Interpreter::new(&module).with_budget(budget.clone());
+    match interpreter.run_automata("MyAutomata", vec![Value::Tuple(vec![Value::Int(0), Value::Int(0)])]) {
+        Err(ExecError::BudgetExhausted { limit: Limit::Transitions(100), position }) => {
+            assert_eq!(position.run_stack, vec!["MyAutomata".to_string()]);
+            assert_eq!(position.steps, 100);
+            let state = position.state.unwrap();
+            assert_eq!(state.state, "Base");
+            assert_eq!(state.args[0].1, Value::Tuple(vec![Value::Int(0), Value::Int(100)]));
+            assert_eq!(position.source, Position::new(4, 34));
+        },
+        r => panic!("{:?}", r),
+    }
+    assert!(matches!(
+        interpreter.run_automata("Spin", vec![Value::Int(0)]),
+        Err(ExecError::BudgetExhausted { limit: Limit::Iterations(1000), .. })
+    ));
+    // the budget is renewed for every top level call
+    assert_eq!(interpreter.call_function("count", vec![Value::Int(1000)]), Ok(Value::Int(499500)));
+    assert_eq!(interpreter.call_function("count", vec![Value::Int(1000)]), Ok(Value::Int(499500)));
+    // the range is iterated lazily, so an over budget one stops instead of being allocated
+    assert!(matches!(
+        interpreter.call_function("count", vec![Value::Int(10_000_000_000)]),
+        Err(ExecError::BudgetExhausted { limit: Limit::Iterations(1000), .. })
+    ));
+
+    let budget = Budget { max_duration: Some(Duration::from_millis(50)), ..Budget::default() };
+    let mut interpreter = Interpreter::new(&module).with_budget(budget).with_clock(TickingClock(Cell::new(0)));
+    assert!(matches!(
+        interpreter.run_automata("MyAutomata", vec![Value::Tuple(vec![Value::Int(0), Value::Int(0)])]),
+        Err(ExecError::BudgetExhausted { limit: Limit::WallClock(_), position }) if position.steps == 50
+    ));
+}
@@ -0,0 +1,329 @@
+//! Minimal JSON document model for the snapshots, traces and the tooling protocols
+
+/// JSON value, objects keep the order of their keys
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    /// Number as written, so `i64` and `f64` round trip exactly
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonError {
+    pub message: String,
+    /// Byte offset in the parsed text
+    pub offset: usize,
+}
+
+impl std::fmt::Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at byte {}", self.message, self.offset)
+    }
+}
+
+impl Json {
+    pub fn object(fields: impl IntoIterator<Item = (&'static str, Json)>) -> Self {
+        Json::Object(fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Json::Number(n) => n.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(n) => n.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Json::Null
+    }
+
+    pub fn parse(text: &str) -> Result<Json, JsonError> {
+        let mut parser = JsonParser { text: text.as_bytes(), offset: 0 };
+        let value = parser.value()?;
+        parser.whitespace();
+        if parser.offset < text.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Self {
+        Json::Bool(b)
+    }
+}
+
+impl From<i64> for Json {
+    fn from(i: i64) -> Self {
+        Json::Number(i.to_string())
+    }
+}
+
+impl From<usize> for Json {
+    fn from(i: usize) -> Self {
+        Json::Number(i.to_string())
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Self {
+        Json::String(s.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Self {
+        Json::String(s)
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(v: Option<T>) -> Self {
+        v.map_or(Json::Null, Into::into)
+    }
+}
+
+impl<T: Into<Json>> From<Vec<T>> for Json {
+    fn from(v: Vec<T>) -> Self {
+        Json::Array(v.into_iter().map(Into::into).collect())
+    }
+}
+
+fn write_string(f: &mut std::fmt::Formatter<'_>, s: &str) -> std::fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Compact single line form, as used by JSON Lines
+impl std::fmt::Display for Json {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(n) => write!(f, "{}", n),
+            Json::String(s) => write_string(f, s),
+            Json::Array(v) => {
+                write!(f, "[")?;
+                for (i, x) in v.iter().enumerate() {
+                    if i > 0 { write!(f, ",")?; }
+                    write!(f, "{}", x)?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in fields.iter().enumerate() {
+                    if i > 0 { write!(f, ",")?; }
+                    write_string(f, k)?;
+                    write!(f, ":{}", v)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+struct JsonParser<'t> {
+    text: &'t [u8],
+    offset: usize,
+}
+
+impl JsonParser<'_> {
+    fn error(&self, message: &str) -> JsonError {
+        JsonError { message: message.to_string(), offset: self.offset }
+    }
+
+    fn whitespace(&mut self) {
+        while self.text.get(self.offset).is_some_and(|c| c.is_ascii_whitespace()) {
+            self.offset += 1;
+        }
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        self.whitespace();
+        let found = self.text.get(self.offset) == Some(&c);
+        if found {
+            self.offset += 1;
+        }
+        found
+    }
+
+    fn keyword(&mut self, word: &str, value: Json) -> Result<Json, JsonError> {
+        if self.text[self.offset..].starts_with(word.as_bytes()) {
+            self.offset += word.len();
+            Ok(value)
+        } else {
+            Err(self.error("unknown keyword"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, JsonError> {
+        self.whitespace();
+        match self.text.get(self.offset) {
+            None => Err(self.error("unexpected end")),
+            Some(b'n') => self.keyword("null", Json::Null),
+            Some(b't') => self.keyword("true", Json::Bool(true)),
+            Some(b'f') => self.keyword("false", Json::Bool(false)),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'[') => {
+                self.offset += 1;
+                let mut items = vec![];
+                if !self.eat(b']') {
+                    loop {
+                        items.push(self.value()?);
+                        if self.eat(b']') { break; }
+                        if !self.eat(b',') { return Err(self.error("expected `,` or `]`")); }
+                    }
+                }
+                Ok(Json::Array(items))
+            },
+            Some(b'{') => {
+                self.offset += 1;
+                let mut fields = vec![];
+                if !self.eat(b'}') {
+                    loop {
+                        self.whitespace();
+                        if self.text.get(self.offset) != Some(&b'"') {
+                            return Err(self.error("expected key"));
+                        }
+                        let key = self.string()?;
+                        if !self.eat(b':') { return Err(self.error("expected `:`")); }
+                        fields.push((key, self.value()?));
+                        if self.eat(b'}') { break; }
+                        if !self.eat(b',') { return Err(self.error("expected `,` or `}`")); }
+                    }
+                }
+                Ok(Json::Object(fields))
+            },
+            Some(c) if *c == b'-' || c.is_ascii_digit() => {
+                let start = self.offset;
+                self.offset += 1;
+                while self.text.get(self.offset).is_some_and(|c| c.is_ascii_digit() || b".eE+-".contains(c)) {
+                    self.offset += 1;
+                }
+                let number = std::str::from_utf8(&self.text[start..self.offset]).expect("digits are ASCII");
+                if number.parse::<f64>().is_err() {
+                    self.offset = start;
+                    return Err(self.error("invalid number"));
+                }
+                Ok(Json::Number(number.to_string()))
+            },
+            Some(_) => Err(self.error("unexpected character")),
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, JsonError> {
+        let digits = self.text.get(self.offset..self.offset + 4).ok_or_else(|| self.error("unexpected end"))?;
+        let code = std::str::from_utf8(digits).ok().and_then(|d| u32::from_str_radix(d, 16).ok())
+            .ok_or_else(|| self.error("invalid escape"))?;
+        self.offset += 4;
+        Ok(code)
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.offset += 1;
+        let mut bytes = vec![];
+        loop {
+            let c = *self.text.get(self.offset).ok_or_else(|| self.error("unterminated string"))?;
+            self.offset += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    let e = *self.text.get(self.offset).ok_or_else(|| self.error("unterminated string"))?;
+                    self.offset += 1;
+                    let c = match e {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.hex4()?;
+                            // surrogate pair
+                            if (0xd800..0xdc00).contains(&code) && self.text[self.offset..].starts_with(b"\\u") {
+                                self.offset += 2;
+                                let low = self.hex4()?;
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+                            }
+                            char::from_u32(code).ok_or_else(|| self.error("invalid escape"))?
+                        },
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                },
+                c => bytes.push(c),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| self.error("invalid UTF-8"))
+    }
+}
+
+#[test]
+fn json_test() {
+    let text = r#"{"a":[1,-2.5e3,true,null],"b":"q\"\\\né😀","c":{}}"#;
+    let json = Json::parse(text).unwrap();
+    assert_eq!(json.get("a").and_then(|a| a.as_array()).map(|a| a.len()), Some(4));
+    assert_eq!(json.get("a").unwrap().as_array().unwrap()[1].as_f64(), Some(-2500.0));
+    assert_eq!(json.get("b").and_then(Json::as_str), Some("q\"\\\né😀"));
+    assert_eq!(Json::parse(&json.to_string()), Ok(json));
+    assert_eq!(Json::from(i64::MAX).to_string().parse::<i64>(), Ok(i64::MAX));
+
+    assert_eq!(Json::parse(" [1, 2] ").unwrap(), Json::from(vec![1i64, 2]));
+    for bad in ["", "[1,", "{\"a\" 1}", "tru", "\"abc", "1 2", "-"] {
+        assert!(Json::parse(bad).is_err(), "{}", bad);
+    }
+}
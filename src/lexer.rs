@@ -19,9 +19,29 @@ impl TokenFlag {
     }
 }
 
-type Token = (TokenFlag, String);
+/// (flag, token text, column of the first char)
+type Token = (TokenFlag, String, usize);
 type TokenLine = Vec<Token>;
 
+/// Position of a token in the source, both zero based
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Position {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Position {
+    pub fn new(line: usize, col: usize) -> Self {
+        Self { line, col }
+    }
+}
+
+impl std::fmt::Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line + 1, self.col + 1)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum LexError {
     UnknownError,
@@ -30,7 +50,7 @@ pub enum LexError {
     LiteralEndNotFound
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum BlockSymbol {
     BlockBracketOpen,
     BlockBracketClose,
@@ -43,7 +63,7 @@ pub enum BlockSymbol {
 }
 
 impl BlockSymbol {
-    pub fn try_from<'a>(c: &'a char) -> Option<Self> {
+    pub fn try_from(c: &char) -> Option<Self> {
         match c {
             // '"' => Some(Self::Quote),
             // '\'' => Some(Self::SQuote),
@@ -59,14 +79,30 @@ impl BlockSymbol {
             _ => None
         }
     }
+
+    pub fn as_char(&self) -> char {
+        match self {
+            Self::BlockBracketOpen => '{',
+            Self::BlockBracketClose => '}',
+            Self::TupleBracketOpen => '(',
+            Self::TupleBracketClose => ')',
+            Self::IndexBracketOpen => '[',
+            Self::IndexBracketClose => ']',
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum FANReserved {
     AutomataDeclare,
     StateDeclare,
     LocVarDeclare,
     LinkDeclare,
+    FunctionDeclare,
+    Return,
+    Match,
+    Is,
+    Run,
     NULL,
     If,
     Else,
@@ -77,12 +113,17 @@ pub enum FANReserved {
 }
 
 impl FANReserved {
-    pub fn try_from<'a>(s: &'a str) -> Option<Self> {
+    pub fn try_from(s: &str) -> Option<Self> {
         match s {
             "automata" => Some(Self::AutomataDeclare),
             "state" => Some(Self::StateDeclare),
             "let" => Some(Self::LocVarDeclare),
             "link" => Some(Self::LinkDeclare),
+            "fn" => Some(Self::FunctionDeclare),
+            "return" => Some(Self::Return),
+            "match" => Some(Self::Match),
+            "is" => Some(Self::Is),
+            "run" => Some(Self::Run),
             "NULL" | "null" | "Null" => Some(Self::NULL),
             "if" => Some(Self::If),
             "else" => Some(Self::Else),
@@ -93,22 +134,44 @@ impl FANReserved {
             _ => None
         }
     }
+
+    /// Canonical spelling of the keyword
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::AutomataDeclare => "automata",
+            Self::StateDeclare => "state",
+            Self::LocVarDeclare => "let",
+            Self::LinkDeclare => "link",
+            Self::FunctionDeclare => "fn",
+            Self::Return => "return",
+            Self::Match => "match",
+            Self::Is => "is",
+            Self::Run => "run",
+            Self::NULL => "NULL",
+            Self::If => "if",
+            Self::Else => "else",
+            Self::For => "for",
+            Self::While => "while",
+            Self::Upload => "upload",
+            Self::From => "from",
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Operational(String);
 
 impl Operational {
-    const AVALS: [&'static str; 29] = [
+    const AVALS: [&'static str; 31] = [
         "=", "+=", "-=", "/=", "*=",
         "+", "-", "*", "/", "&", "->",
         "^", ":", "::", ".", ",", "%",
         "~", "==", "!=", ";", "&&", "|", "||",
         "<", ">", ">=", "<=",
-        "\\"
+        "\\", "!", "=>"
     ];
 
-    pub fn try_expand<'a>(&self, s: &'a str) -> Option<Self> {
+    pub fn try_expand(&self, s: &str) -> Option<Self> {
         let n = self.0.clone() + s;
         if Self::AVALS.iter().any(|&e| e.eq(&n)) {
             Some(Self(n))
@@ -116,9 +179,13 @@ impl Operational {
             None
         }
     }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum FANGrammarToken {
     Name(String),
     Digital(String),
@@ -137,7 +204,8 @@ pub struct Lexer {}
 #[derive(Debug, PartialEq)]
 pub struct LexStackItem {
     kind: LexStackItemType,
-    data: String
+    data: String,
+    start: usize,
 }
 
 #[derive(Debug, PartialEq)]
@@ -146,7 +214,7 @@ pub enum LexStackItemType {
     Comment
 }
 impl LexStackItemType {
-    pub fn try_from<'a>(c: &'a char) -> Option<Self> {
+    pub fn try_from(c: &char) -> Option<Self> {
         match c {
             '"' =>  Some(Self::StringLiteral(StringLiteralType::String)),
             '\'' => Some(Self::StringLiteral(StringLiteralType::Char)),
@@ -156,84 +224,92 @@ impl LexStackItemType {
     }
 }
 
+/// Replaces escape sequences of the literal body
+fn unescape(data: &str) -> String {
+    let mut r = String::with_capacity(data.len());
+    let mut chars = data.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => r.push('\n'),
+                Some('t') => r.push('\t'),
+                Some('r') => r.push('\r'),
+                Some('0') => r.push('\0'),
+                Some(x) => r.push(x),
+                None => r.push('\\'),
+            }
+        } else {
+            r.push(c);
+        }
+    }
+    r
+}
+
+/// Quote is escaped when it is preceded by an odd number of backslashes
+fn is_escaped(data: &str) -> bool {
+    data.chars().rev().take_while(|&c| c == '\\').count() % 2 == 1
+}
+
 impl Lexer {
     // pub fn new() -> Self {
     //     Self{}
     // }
 
-    pub fn split_line<'a>(line: &'a str, stack: &mut Vec<LexStackItem>) -> Result<TokenLine, LexError> {
-        let (tokens, stack) = line.chars().try_fold(
-            (TokenLine::new(), stack), 
-            |(mut tokenline, stack), char| {
+    pub fn split_line(line: &str, stack: &mut Vec<LexStackItem>) -> Result<TokenLine, LexError> {
+        Self::split_line_commented(line, stack).map(|(tokens, _)| tokens)
+    }
+
+    /// Same as [`Lexer::split_line`], also returns the column and the text after `#` of the line comment
+    fn split_line_commented(line: &str, stack: &mut Vec<LexStackItem>) -> Result<(TokenLine, Option<(usize, String)>), LexError> {
+        let (tokens, stack) = line.chars().enumerate().try_fold(
+            (TokenLine::new(), stack),
+            |(mut tokenline, stack), (col, char)| {
                 if let Some(lastst) = stack.last_mut() {
                     // stack is not empty
                     let this=LexStackItemType::try_from(&char);
                     match &mut lastst.kind {
                         LexStackItemType::StringLiteral(stritt) => {
-                            match stritt {
-                                StringLiteralType::Char => {
-                                    if let Some(LexStackItemType::StringLiteral(StringLiteralType::Char)) = this {
-                                        // close
-                                        if lastst.data.chars().last().unwrap_or(' ').eq(&'\\') {
-                                            // ignore
-                                            lastst.data.push(char);
-                                        } else {
-                                            stack.pop();
-                                        }
-                                    } else {
-                                        // check if able to set char value
-                                        if lastst.data.len() == 0 || lastst.data.len() == 1 && lastst.data.chars().last().unwrap_or(' ').eq(&'\\') {
-                                            lastst.data = char.to_string();
-                                        } else {
-                                            return Err(LexError::InvalidCharLiteralValue);
-                                        }
-                                    }
-                                },
-                                StringLiteralType::String => {
-                                    if let Some(LexStackItemType::StringLiteral(StringLiteralType::String)) = this {
-                                        // close
-                                        if lastst.data.chars().last().unwrap_or(' ').eq(&'\\') {
-                                            // ignore
-                                            lastst.data.push(char);
-                                        } else {
-                                            stack.pop();
-                                        }
-                                    } else {
-                                        // regular string
-                                        lastst.data.push(char);
-                                    }
-                                },
+                            if this == Some(LexStackItemType::StringLiteral(stritt.clone())) && !is_escaped(&lastst.data) {
+                                // close
+                                let value = unescape(&lastst.data);
+                                if *stritt == StringLiteralType::Char && value.chars().count() != 1 {
+                                    return Err(LexError::InvalidCharLiteralValue);
+                                }
+                                tokenline.push((TokenFlag::StringLiteral(stritt.clone()), value, lastst.start));
+                                stack.pop();
+                            } else {
+                                // regular literal body
+                                lastst.data.push(char);
                             }
                         },
-                        LexStackItemType::Comment => { /* PASS */ },
+                        LexStackItemType::Comment => lastst.data.push(char),
                     }
                 } else {
                     // stack is empty
-                    // println!(">>> {}", char);
                     if char.is_ascii_whitespace() {
                         // split
                         if let Some(x) = tokenline.last_mut() {
                             if let TokenFlag::TokBrk = x.0 { /* PASS */}
                             else {
-                                tokenline.push((TokenFlag::TokBrk, " ".to_string()));    
+                                tokenline.push((TokenFlag::TokBrk, " ".to_string(), col));
                             }
                         } else {
-                            tokenline.push((TokenFlag::TokBrk, " ".to_string()));    
+                            tokenline.push((TokenFlag::TokBrk, " ".to_string(), col));
                         }
                     } else {
                         match LexStackItemType::try_from(&char) {
                             Some(lst) => {
-                                // println!("StackType found: {:?}", lst);
                                 stack.push(
                                     LexStackItem {
                                         kind: lst,
                                         data: "".to_string(),
+                                        start: col,
                                     }
                                 );
                             },
                             None => {
                                 match tokenline.last_mut() {
-                                    Some((TokenFlag::Literal, last_tok_str)) => {
+                                    Some((TokenFlag::Literal, last_tok_str, _)) => {
                                         // last token exist
                                         if TokenFlag::is_literal(char) {
                                             last_tok_str.push(char);
@@ -241,7 +317,8 @@ impl Lexer {
                                             tokenline.push(
                                                 (
                                                     TokenFlag::Operational,
-                                                    char.to_string()
+                                                    char.to_string(),
+                                                    col
                                                 )
                                             );
                                         }
@@ -251,14 +328,16 @@ impl Lexer {
                                             tokenline.push(
                                                 (
                                                     TokenFlag::Literal,
-                                                    char.to_string()
+                                                    char.to_string(),
+                                                    col
                                                 )
                                             );
                                         } else {
                                             tokenline.push(
                                                 (
                                                     TokenFlag::Operational,
-                                                    char.to_string()
+                                                    char.to_string(),
+                                                    col
                                                 )
                                             );
                                         }
@@ -266,93 +345,98 @@ impl Lexer {
                                 }
                             },
                         }
-                        // append 
+                        // append
                     }
                 }
                 Ok((tokenline, stack))
             }
         )?;
-        if let Some(LexStackItem{data:_, kind: LexStackItemType::Comment}) = stack.last() {
-            stack.pop();
+        let mut comment = None;
+        if let Some(LexStackItem{kind: LexStackItemType::Comment, ..}) = stack.last() {
+            comment = stack.pop().map(|c| (c.start, c.data));
         }
         if stack.iter().filter(|x| {x.kind == LexStackItemType::Comment}).count() != 0 {
             Err(LexError::UnknownError)
         } else {
-            Ok(tokens)
+            Ok((tokens, comment))
         }
     }
 
-    pub fn lex_line<'a>(line: &'a str, stack: &mut Vec<LexStackItem>) -> Result<Vec<FANGrammarToken>, LexError> {
+    pub fn lex_line(line: &str, stack: &mut Vec<LexStackItem>) -> Result<Vec<FANGrammarToken>, LexError> {
+        Ok(
+            Self::lex_line_positioned(line, stack)?
+                .into_iter()
+                .map(|(_, t)| t)
+                .collect()
+        )
+    }
+
+    /// Same as [`Lexer::lex_line`], but every lexem is paired with its column
+    pub fn lex_line_positioned(line: &str, stack: &mut Vec<LexStackItem>) -> Result<Vec<(usize, FANGrammarToken)>, LexError> {
         let tokens = Self::split_line(line, stack)?;
         let lexems = tokens.iter().try_fold(
             vec![],
-            |mut lexems, (flag, token)| {
-                if token.is_empty() { return Ok(lexems); }
+            |mut lexems: Vec<(usize, FANGrammarToken)>, (flag, token, col)| {
+                // `""` is the only lexem with an empty value
+                if token.is_empty() && !matches!(flag, TokenFlag::StringLiteral(_)) { return Ok(lexems); }
+                let col = *col;
                 match flag {
                     TokenFlag::Literal => {
                         // check is reserved
-                        if let Some(rsrv) = FANReserved::try_from(&token) {
+                        if let Some(rsrv) = FANReserved::try_from(token) {
                             lexems.push(
-                                FANGrammarToken::Reserved(rsrv)
+                                (col, FANGrammarToken::Reserved(rsrv))
                             );
-                        } else if token.chars().nth(0).unwrap().is_numeric() {
+                        } else if token.chars().next().unwrap().is_numeric() {
                             if token.chars().all(|c| c.is_numeric()) {
                                 lexems.push(
-                                    FANGrammarToken::Digital(token.clone())
+                                    (col, FANGrammarToken::Digital(token.clone()))
                                 );
                             } else {
                                 lexems.push(
-                                    FANGrammarToken::NumericalLexem(token.clone())
+                                    (col, FANGrammarToken::NumericalLexem(token.clone()))
                                 );
                             }
                         } else {
                             lexems.push(
-                                FANGrammarToken::Name(token.clone())
+                                (col, FANGrammarToken::Name(token.clone()))
                             );
                         }
                     },
                     TokenFlag::Operational => {
                         // check operator expansion
-                        if let Some(FANGrammarToken::Operational(operational)) = lexems.last_mut() {
-                            if let Some(y) = operational.try_expand(&token) {
-                                operational.0 = y.0;
-                                return Ok(lexems);
-                            }
+                        if let Some((_, FANGrammarToken::Operational(operational))) = lexems.last_mut()
+                            && let Some(y) = operational.try_expand(token) {
+                            operational.0 = y.0;
+                            return Ok(lexems);
                         }
                         // check brackets etc
                         if token.len() > 1 {
-                            println!(">> {:?}", token);
                             return Err(LexError::InvalidOperatorToken);
+                        } else if let Some(blocksymb) = BlockSymbol::try_from(&token.chars().next().unwrap()) {
+                            lexems.push((col, FANGrammarToken::BlockSymbol(blocksymb)));
+                        } else if let Some(op) = Operational("".to_string()).try_expand(token) {
+                            lexems.push((col, FANGrammarToken::Operational(op)));
                         } else {
-                            if let Some(blocksymb) = BlockSymbol::try_from(&token.chars().nth(0).unwrap()) {
-                                lexems.push(FANGrammarToken::BlockSymbol(blocksymb)); 
-                            } else {
-                                if let Some(op) = Operational("".to_string()).try_expand(&token) {
-                                    lexems.push(FANGrammarToken::Operational(op));
-                                } else {
-                                    println!(">> 2");
-                                    return Err(LexError::InvalidOperatorToken);
-                                }
-                            }
+                            return Err(LexError::InvalidOperatorToken);
                         }
                     },
                     TokenFlag::StringLiteral(string_literal_type) => {
-                        lexems.push(match string_literal_type {
-                            StringLiteralType::Char => { FANGrammarToken::CharLiteral(token.chars().nth(0).unwrap()) },
+                        lexems.push((col, match string_literal_type {
+                            StringLiteralType::Char => { FANGrammarToken::CharLiteral(token.chars().next().unwrap()) },
                             StringLiteralType::String => { FANGrammarToken::StringLiteral(token.clone()) },
-                        })
+                        }))
                     },
                     TokenFlag::TokBrk => {
                         // Break last token lexing
-                        lexems.push(FANGrammarToken::TokBrk);
+                        lexems.push((col, FANGrammarToken::TokBrk));
                     },
                 };
                 Ok(lexems)
             }
         )?;
-        Ok(lexems.into_iter().to_owned().filter(
-            |x|
-                if let FANGrammarToken::TokBrk = x {false} else {true}
+        Ok(lexems.into_iter().filter(
+            |(_, x)| !matches!(x, FANGrammarToken::TokBrk)
             ).collect()
         )
     }
@@ -364,11 +448,45 @@ impl Lexer {
             (vec![], vec![]),
             |(mut r, mut stack), l| {
                 let s = Lexer::lex_line(l, &mut stack)?;
-                if s.len() > 0 {r.push(s);}
+                if !s.is_empty() {r.push(s);}
                 Ok((r, stack))
             }
         )?;
-        if s.len() > 0 {
+        if !s.is_empty() {
+            Err(LexError::LiteralEndNotFound)
+        } else {
+            Ok(r)
+        }
+    }
+
+    /// Line comments of the source, the position of `#` and the text after it
+    pub fn comments(data: &str) -> Result<Vec<(Position, String)>, LexError> {
+        let mut stack = vec![];
+        let mut r = vec![];
+        for (line, l) in data.lines().enumerate() {
+            if let (_, Some((col, text))) = Lexer::split_line_commented(l, &mut stack)? {
+                r.push((Position::new(line, col), text));
+            }
+        }
+        if !stack.is_empty() {
+            Err(LexError::LiteralEndNotFound)
+        } else {
+            Ok(r)
+        }
+    }
+
+    /// Lexes the whole source into a flat stream of positioned lexems
+    pub fn lex_str(data: &str) -> Result<Vec<(Position, FANGrammarToken)>, LexError> {
+        let mut stack = vec![];
+        let mut r = vec![];
+        for (line, l) in data.lines().enumerate() {
+            r.extend(
+                Lexer::lex_line_positioned(l, &mut stack)?
+                    .into_iter()
+                    .map(|(col, t)| (Position::new(line, col), t))
+            );
+        }
+        if !stack.is_empty() {
             Err(LexError::LiteralEndNotFound)
         } else {
             Ok(r)
@@ -380,7 +498,7 @@ impl Lexer {
 fn tokenize_test() {
     let data = "automata Mathematica: Mealy<signal>{# define signal name
          state AddAssign<signal: ()> { # no need to use context
-    
+
             link self -> NULL;
         }
 
@@ -396,5 +514,20 @@ fn tokenize_test() {
     }";
     println!(
         "{:?}", Lexer::lex_buf(data.split("\n"))
-    ); 
-}
\ No newline at end of file
+    );
+}
+
+#[test]
+fn literals_and_positions_test() {
+    let lexems = Lexer::lex_str("let s = \"a # b\";\n  if c == '\\'' { }").unwrap();
+    assert_eq!(lexems[3], (Position::new(0, 8), FANGrammarToken::StringLiteral("a # b".to_string())));
+    assert_eq!(lexems[5], (Position::new(1, 2), FANGrammarToken::Reserved(FANReserved::If)));
+    assert_eq!(lexems[8].1, FANGrammarToken::CharLiteral('\''));
+    assert!(Lexer::lex_str("let c = 'ab';").is_err());
+    assert_eq!(Lexer::lex_str("s = \"\";").unwrap()[2], (Position::new(0, 4), FANGrammarToken::StringLiteral(String::new())));
+    assert!(Lexer::lex_str("let c = '';").is_err());
+    assert_eq!(
+        Lexer::comments("let s = \"a # b\"; # c\n#d").unwrap(),
+        [(Position::new(0, 17), " c".to_string()), (Position::new(1, 0), "d".to_string())],
+    );
+}
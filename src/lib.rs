@@ -1,3 +1,28 @@
-
 pub mod lexer;
 pub mod parser;
+pub mod checker;
+pub mod interpreter;
+pub mod budget;
+pub mod bytecode;
+pub mod vm;
+pub mod codegen;
+pub mod diagnostic;
+pub mod build;
+pub mod host;
+pub mod json;
+pub mod snapshot;
+pub mod trace;
+pub mod debugger;
+pub mod rpc;
+pub mod dap;
+pub mod lsp;
+pub mod graph;
+pub mod dot;
+pub mod mermaid;
+pub mod plantuml;
+pub mod import;
+pub mod table;
+pub mod analysis;
+pub mod format;
+pub mod lint;
+pub mod cli;
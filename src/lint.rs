@@ -0,0 +1,391 @@
+//! `fan lint`: rules over the parsed module for code that is valid but likely wrong.
+//! The level of every rule can be changed in the `lint` section of the project file:
+//!
+//! ```json
+//! { "lint": { "unused-let": "allow", "self-loop": "deny" } }
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use crate::checker::Checker;
+use crate::diagnostic::Diagnostic;
+use crate::format::block_source;
+use crate::json::Json;
+use crate::lexer::{FANGrammarToken, FANReserved, Lexer, Position};
+use crate::parser::{
+    AutomataDef, Block, DefinitionExp, Expression, ExpressionType, Name, ParseError, Parser, ProceduralExp,
+    ReturnableExp, StateDef, Statement, ASSIGNMENTS,
+};
+
+/// Project file looked up in the directory of the linted file and its ancestors
+pub const PROJECT_FILE: &str = "fan.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Allow,
+    Warn,
+    /// Fails `fan lint`
+    Deny,
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Allow => "allow",
+            Level::Warn => "warn",
+            Level::Deny => "deny",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "allow" => Some(Level::Allow),
+            "warn" => Some(Level::Warn),
+            "deny" => Some(Level::Deny),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rule {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub level: Level,
+}
+
+pub const RULES: [Rule; 6] = [
+    Rule { name: "unreachable-state", description: "state is not initial and no other state links to it", level: Level::Warn },
+    Rule { name: "unused-param", description: "template parameter of the state is never read", level: Level::Warn },
+    Rule { name: "unused-let", description: "`let` variable is never read", level: Level::Warn },
+    Rule { name: "identical-branches", description: "both branches of `if` are the same", level: Level::Warn },
+    Rule { name: "self-loop", description: "state links to itself with unchanged arguments and never finishes", level: Level::Warn },
+    Rule { name: "null-spelling", description: "`NULL` is written as `null` or `Null`", level: Level::Warn },
+];
+
+/// Levels of the rules, the defaults of [`RULES`] unless overridden
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LintConfig {
+    pub levels: Vec<(&'static str, Level)>,
+}
+
+impl LintConfig {
+    pub fn with_level(mut self, rule: &str, level: Level) -> Result<Self, String> {
+        let rule = RULES.iter().find(|r| r.name == rule).ok_or_else(|| format!("unknown lint rule `{}`", rule))?;
+        self.levels.retain(|(name, _)| *name != rule.name);
+        self.levels.push((rule.name, level));
+        Ok(self)
+    }
+
+    pub fn level(&self, rule: &str) -> Level {
+        match self.levels.iter().find(|(name, _)| *name == rule) {
+            Some((_, level)) => *level,
+            None => RULES.iter().find(|r| r.name == rule).map(|r| r.level).unwrap_or(Level::Warn),
+        }
+    }
+
+    /// Reads the `lint` section of the project file, the file without it keeps the defaults
+    pub fn from_json(project: &Json) -> Result<Self, String> {
+        let mut config = LintConfig::default();
+        let Some(lint) = project.get("lint") else {
+            return Ok(config);
+        };
+        let Json::Object(fields) = lint else {
+            return Err("`lint` must be an object of the rule levels".to_string());
+        };
+        for (rule, level) in fields.iter() {
+            let level = level.as_str().and_then(Level::parse)
+                .ok_or_else(|| format!("level of `{}` must be \"allow\", \"warn\" or \"deny\"", rule))?;
+            config = config.with_level(rule, level)?;
+        }
+        Ok(config)
+    }
+
+    /// Loads the project file of the directory or of its closest ancestor, the defaults without one
+    pub fn find(dir: &Path) -> Result<(Option<PathBuf>, Self), String> {
+        let Some(path) = dir.ancestors().map(|d| d.join(PROJECT_FILE)).find(|p| p.is_file()) else {
+            return Ok((None, LintConfig::default()));
+        };
+        let text = std::fs::read_to_string(&path).map_err(|e| format!("cannot read `{}`: {}", path.display(), e))?;
+        let config = Json::parse(&text)
+            .map_err(|e| e.to_string())
+            .and_then(|json| LintConfig::from_json(&json))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok((Some(path), config))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lint {
+    pub rule: &'static str,
+    pub level: Level,
+    pub message: String,
+    pub position: Position,
+}
+
+impl Lint {
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::new(format!("{} [{}]", self.message, self.rule), Some(self.position))
+    }
+
+    /// `warning` or `error` with the source line
+    pub fn render(&self, file: &str, source: &str) -> String {
+        let severity = if self.level == Level::Deny { "error" } else { "warning" };
+        self.diagnostic().render_as(severity, file, source)
+    }
+
+    pub fn to_json(&self) -> Json {
+        Json::object([
+            ("rule", self.rule.into()),
+            ("level", self.level.as_str().into()),
+            ("message", self.message.as_str().into()),
+            ("line", (self.position.line + 1).into()),
+            ("column", (self.position.col + 1).into()),
+        ])
+    }
+}
+
+/// Lints of the source at their configured levels in the source order, the allowed ones are left out
+pub fn lint(source: &str, config: &LintConfig) -> Result<Vec<Lint>, ParseError> {
+    let tokens = Lexer::lex_str(source)?;
+    let mut found = null_spelling(source, &tokens);
+    let module = Parser::parse_positioned(tokens)?;
+    for a in module.automata() {
+        found.extend(unreachable_states(a));
+        for s in a.states.iter() {
+            found.extend(unused_params(a, s));
+            found.extend(self_loops(a, s));
+        }
+    }
+    for e in module.expressions.iter() {
+        e.walk(&mut |e| {
+            if let ExpressionType::Returnable(ReturnableExp::If(i)) = &e.kind
+                && let Some(otherwise) = &i.otherwise
+                && block_source(&i.then) == block_source(otherwise) {
+                found.push(("identical-branches", "both branches of `if` are the same".to_string(), e.position));
+            }
+        });
+        for b in bodies(e).into_iter().flat_map(blocks) {
+            found.extend(unused_lets(b));
+        }
+    }
+    let mut lints: Vec<Lint> = found.into_iter()
+        .map(|(rule, message, position)| Lint { rule, level: config.level(rule), message, position })
+        .filter(|l| l.level != Level::Allow)
+        .collect();
+    lints.sort_by_key(|l| l.position);
+    Ok(lints)
+}
+
+type Found = (&'static str, String, Position);
+
+fn null_spelling(source: &str, tokens: &[(Position, FANGrammarToken)]) -> Vec<Found> {
+    let lines: Vec<&str> = source.lines().collect();
+    tokens.iter().filter(|(_, t)| *t == FANGrammarToken::Reserved(FANReserved::NULL)).filter_map(|(p, _)| {
+        let written: String = lines[p.line].chars().skip(p.col).take(FANReserved::NULL.as_str().len()).collect();
+        (written != FANReserved::NULL.as_str())
+            .then(|| ("null-spelling", format!("`{}` should be written as `NULL`", written), *p))
+    }).collect()
+}
+
+fn unreachable_states(a: &AutomataDef) -> Vec<Found> {
+    let initial = a.initial_state().map(|s| s.name.as_str());
+    let mut linked = vec![];
+    for s in a.states.iter() {
+        s.body.walk(&mut |e| {
+            if let ExpressionType::Procedural(ProceduralExp::Link(l)) = &e.kind
+                && let Some(target) = &l.target
+                && target.0 != s.name {
+                linked.push(target.0.as_str());
+            }
+        });
+    }
+    a.states.iter()
+        .filter(|s| Some(s.name.as_str()) != initial && !linked.contains(&s.name.as_str()))
+        .map(|s| ("unreachable-state", format!("state `{}` is never linked to", s.name), s.position))
+        .collect()
+}
+
+fn unused_params(a: &AutomataDef, s: &StateDef) -> Vec<Found> {
+    let read = reads(&s.body.block);
+    Checker::template_params(a, &s.params).into_iter()
+        .filter(|p| !p.name.starts_with('_') && !read.contains(&p.name.as_str()))
+        .map(|p| ("unused-param", format!("parameter `{}` of state `{}` is never read", p.name, s.name), p.position))
+        .collect()
+}
+
+/// `link self -> S<a, b>` in `state S<a, b>` that does not change `a` or `b` enters the state
+/// again in the same way. Mealy states reading the signal get a new input every time.
+fn self_loops(a: &AutomataDef, s: &StateDef) -> Vec<Found> {
+    if a.consumes_signal(s) {
+        return vec![];
+    }
+    let params = Checker::template_params(a, &s.params);
+    let mut assigned = vec![];
+    s.body.walk(&mut |e| {
+        if let ExpressionType::Returnable(ReturnableExp::BinaryOperator(b)) = &e.kind
+            && ASSIGNMENTS.contains(&b.operator.as_str())
+            && let Some(name) = variable(&b.arg1) {
+            assigned.push(name);
+        }
+    });
+    let mut found = vec![];
+    s.body.walk(&mut |e| {
+        let ExpressionType::Procedural(ProceduralExp::Link(l)) = &e.kind else { return };
+        let Some(target) = l.target.as_ref().filter(|t| t.0 == s.name) else { return };
+        let args = target.1.as_ref().map(|t| t.args.as_slice()).unwrap_or_default();
+        let unchanged = args.len() == params.len()
+            && args.iter().zip(params.iter()).all(|(arg, p)| variable(arg) == Some(p.name.as_str()) && !assigned.contains(&p.name.as_str()));
+        if unchanged {
+            found.push(("self-loop", format!("`link self -> {}` with the same arguments never finishes", s.name), e.position));
+        }
+    });
+    found
+}
+
+/// `let` of the block that is not read by the following statements up to the next `let` of the same name
+fn unused_lets(b: &Block) -> Vec<Found> {
+    let mut found = vec![];
+    for (i, e) in b.block.iter().enumerate() {
+        let ExpressionType::Definition(DefinitionExp::Define(d)) = &e.kind else { continue };
+        if d.name.starts_with('_') {
+            continue;
+        }
+        let mut read = false;
+        for next in b.block[i + 1..].iter() {
+            if let ExpressionType::Definition(DefinitionExp::Define(shadow)) = &next.kind
+                && shadow.name == d.name {
+                read = reads(std::slice::from_ref(&shadow.value)).contains(&d.name.as_str());
+                break;
+            }
+            if reads(std::slice::from_ref(next)).contains(&d.name.as_str()) {
+                read = true;
+                break;
+            }
+        }
+        if !read {
+            found.push(("unused-let", format!("variable `{}` is never read", d.name), e.position));
+        }
+    }
+    found
+}
+
+/// Plain variable name of the expression
+fn variable(e: &Expression) -> Option<&str> {
+    match &e.kind {
+        ExpressionType::Returnable(ReturnableExp::Statement(Statement::Name(Name::SingleName(n)))) if n.1.is_none() => Some(&n.0),
+        _ => None,
+    }
+}
+
+/// Variables read by the expressions, the targets of `=` are written, not read
+fn reads(expressions: &[Expression]) -> Vec<&str> {
+    let mut targets: Vec<&Expression> = vec![];
+    let mut names = vec![];
+    for e in expressions.iter() {
+        e.walk(&mut |e| {
+            if let ExpressionType::Returnable(ReturnableExp::BinaryOperator(b)) = &e.kind
+                && b.operator.as_str() == "=" {
+                targets.push(&b.arg1);
+            }
+            if let Some(name) = variable(e) {
+                names.push((e, name));
+            }
+        });
+    }
+    names.into_iter().filter(|(e, _)| !targets.iter().any(|t| std::ptr::eq(*t, *e))).map(|(_, name)| name).collect()
+}
+
+/// Bodies of the functions and the states of the item
+fn bodies(e: &Expression) -> Vec<&Block> {
+    match &e.kind {
+        ExpressionType::Definition(DefinitionExp::Function(f)) => vec![&f.body],
+        ExpressionType::Definition(DefinitionExp::Automata(a)) => {
+            a.functions.iter().map(|f| &f.body).chain(a.states.iter().map(|s| &s.body)).collect()
+        },
+        ExpressionType::Definition(DefinitionExp::AutomataState(s)) => vec![&s.body],
+        _ => vec![],
+    }
+}
+
+/// The block and the blocks nested in it
+fn blocks(b: &Block) -> Vec<&Block> {
+    let mut v = vec![b];
+    b.walk(&mut |e| match &e.kind {
+        ExpressionType::Returnable(ReturnableExp::Statement(Statement::Block(b))) => v.push(b),
+        ExpressionType::Returnable(ReturnableExp::If(i)) => {
+            v.push(&i.then);
+            v.extend(i.otherwise.iter());
+        },
+        ExpressionType::Procedural(ProceduralExp::While(w)) => v.push(&w.body),
+        ExpressionType::Procedural(ProceduralExp::For(f)) => v.push(&f.body),
+        _ => {},
+    });
+    v
+}
+
+#[test]
+fn lint_test() {
+    let source = "\
+automata Door {
+    state Closed<locked: bool, unused: int64, _spare: int64> {
+        let tries = 0;
+        let opened = 1;
+        opened = 2;
+        if locked { link self -> Opened<1>; } else { link self -> Opened<1>; }
+    }
+    state Opened<n: int64> { link self -> Null; }
+    state Spin<n: int64> {
+        let n2 = n;
+        let n2 = n + 1;
+        if n2 > 3 { link self -> Spin<n>; } else { link self -> Spin<n2>; }
+    }
+    state Count<n: int64> { n += 1; link self -> Count<n>; }
+}
+automata Echo: Mealy<signal> {
+    state Read<signal: int64> { link self -> Read; }
+}
+";
+    let lints = lint(source, &LintConfig::default()).unwrap();
+    let summary: Vec<(usize, &str, &str)> = lints.iter().map(|l| (l.position.line + 1, l.rule, l.message.as_str())).collect();
+    assert_eq!(summary, [
+        (2, "unused-param", "parameter `unused` of state `Closed` is never read"),
+        (3, "unused-let", "variable `tries` is never read"),
+        (4, "unused-let", "variable `opened` is never read"),
+        (6, "identical-branches", "both branches of `if` are the same"),
+        (8, "unused-param", "parameter `n` of state `Opened` is never read"),
+        (8, "null-spelling", "`Null` should be written as `NULL`"),
+        (9, "unreachable-state", "state `Spin` is never linked to"),
+        (10, "unused-let", "variable `n2` is never read"),
+        (12, "self-loop", "`link self -> Spin` with the same arguments never finishes"),
+        (14, "unreachable-state", "state `Count` is never linked to"),
+    ]);
+    assert!(lints.iter().all(|l| l.level == Level::Warn));
+    assert_eq!(
+        lints[0].render("door.fan", source).lines().next(),
+        Some("warning: parameter `unused` of state `Closed` is never read [unused-param]"),
+    );
+
+    let project = Json::parse(r#"{"name": "door", "lint": {"unreachable-state": "allow", "self-loop": "deny"}}"#).unwrap();
+    let config = LintConfig::from_json(&project).unwrap();
+    let lints = lint(source, &config).unwrap();
+    assert!(!lints.iter().any(|l| l.rule == "unreachable-state"));
+    assert_eq!(lints.iter().filter(|l| l.level == Level::Deny).count(), 1);
+    assert!(lints[7].render("door.fan", source).starts_with("error: "));
+    assert_eq!(lints[7].to_json().to_string(), concat!(
+        r#"{"rule":"self-loop","level":"deny","message":"`link self -> Spin` with the same arguments never finishes","#,
+        r#""line":12,"column":21}"#,
+    ));
+    assert_eq!(LintConfig::from_json(&Json::parse(r#"{"lint": {"no-such": "deny"}}"#).unwrap()), Err("unknown lint rule `no-such`".to_string()));
+    assert!(LintConfig::from_json(&Json::parse(r#"{"lint": {"self-loop": "error"}}"#).unwrap()).is_err());
+    assert_eq!(LintConfig::from_json(&Json::parse("{}").unwrap()), Ok(LintConfig::default()));
+
+    let dir = std::env::temp_dir().join(format!("lint_test_{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("nested")).unwrap();
+    std::fs::write(dir.join(PROJECT_FILE), r#"{"lint": {"unused-let": "deny"}}"#).unwrap();
+    let (path, config) = LintConfig::find(&dir.join("nested")).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(path, Some(dir.join(PROJECT_FILE)));
+    assert_eq!(config.level("unused-let"), Level::Deny);
+    assert_eq!(config.level("unused-param"), Level::Warn);
+}
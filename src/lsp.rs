@@ -0,0 +1,687 @@
+//! Language Server Protocol over stdio for `fan lsp`.
+//! Navigation works on the lexems rather than the AST, so it keeps working while the edited source does not parse.
+
+use std::collections::HashSet;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use crate::build::fan_files;
+use crate::checker::Checker;
+use crate::diagnostic::Diagnostic;
+use crate::json::Json;
+use crate::lexer::{BlockSymbol, FANGrammarToken, FANReserved, Lexer, Position};
+use crate::parser::Parser;
+use crate::rpc::{read_message, write_message, RpcError};
+
+/// What the name refers to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Symbol {
+    Automata(String),
+    /// State of the automata
+    State(String, String),
+    /// Type brought by `upload`
+    Uploaded(String),
+}
+
+/// Name in the source resolved to its symbol
+#[derive(Debug, Clone, PartialEq)]
+pub struct Occurrence {
+    pub symbol: Symbol,
+    pub position: Position,
+    pub length: usize,
+    pub declaration: bool,
+    /// End of the declared block, the end of the name otherwise
+    pub end: Position,
+}
+
+impl Occurrence {
+    fn contains(&self, p: Position) -> bool {
+        p.line == self.position.line && (self.position.col..=self.position.col + self.length).contains(&p.col)
+    }
+}
+
+fn is_op(token: Option<&(Position, FANGrammarToken)>, op: &str) -> bool {
+    matches!(token, Some((_, FANGrammarToken::Operational(o))) if o.as_str() == op)
+}
+
+fn is_reserved(token: Option<&(Position, FANGrammarToken)>, reserved: FANReserved) -> bool {
+    matches!(token, Some((_, FANGrammarToken::Reserved(r))) if *r == reserved)
+}
+
+fn name(token: Option<&(Position, FANGrammarToken)>) -> Option<&str> {
+    match token {
+        Some((_, FANGrammarToken::Name(n))) => Some(n),
+        _ => None,
+    }
+}
+
+/// Number of characters the lexem takes in the source, escaped literals are counted as written unescaped
+pub fn token_length(token: &FANGrammarToken) -> usize {
+    match token {
+        FANGrammarToken::Name(s) | FANGrammarToken::Digital(s) | FANGrammarToken::NumericalLexem(s) => s.chars().count(),
+        FANGrammarToken::StringLiteral(s) => s.chars().count() + 2,
+        FANGrammarToken::CharLiteral(_) => 3,
+        FANGrammarToken::Reserved(r) => r.as_str().len(),
+        FANGrammarToken::Operational(o) => o.as_str().len(),
+        FANGrammarToken::BlockSymbol(_) | FANGrammarToken::TokBrk => 1,
+    }
+}
+
+/// End of the block opened after the lexem, `None` when the source ends first
+fn block_end(tokens: &[(Position, FANGrammarToken)], from: usize) -> Option<Position> {
+    let mut depth = 0;
+    for (p, t) in tokens[from..].iter() {
+        match t {
+            FANGrammarToken::BlockSymbol(BlockSymbol::BlockBracketOpen) => depth += 1,
+            FANGrammarToken::BlockSymbol(BlockSymbol::BlockBracketClose) if depth == 1 => {
+                return Some(Position::new(p.line, p.col + 1));
+            },
+            FANGrammarToken::BlockSymbol(BlockSymbol::BlockBracketClose) => depth -= 1,
+            FANGrammarToken::Operational(o) if depth == 0 && o.as_str() == ";" => return None,
+            _ => {},
+        }
+    }
+    None
+}
+
+/// Names of the automata, states and uploaded types with the `upload`ed names and their paths
+#[derive(Debug, Default)]
+pub struct Index {
+    pub occurrences: Vec<Occurrence>,
+    pub uploads: Vec<(String, String)>,
+}
+
+impl Index {
+    pub fn new(tokens: &[(Position, FANGrammarToken)]) -> Self {
+        let mut index = Index::default();
+        // the uploaded names are known before their uses
+        let mut i = 0;
+        while i < tokens.len() {
+            if is_reserved(tokens.get(i), FANReserved::Upload) {
+                let mut names = vec![];
+                i += 1;
+                while let Some(n) = name(tokens.get(i)) {
+                    names.push((i, n.to_string()));
+                    i += 1 + is_op(tokens.get(i + 1), ",") as usize;
+                }
+                let mut path = String::new();
+                if is_reserved(tokens.get(i), FANReserved::From) {
+                    i += 1;
+                    while let Some((_, t)) = tokens.get(i) {
+                        match t {
+                            FANGrammarToken::StringLiteral(s) => path.push_str(s),
+                            FANGrammarToken::Name(s) | FANGrammarToken::Digital(s) | FANGrammarToken::NumericalLexem(s) => path.push_str(s),
+                            FANGrammarToken::Operational(o) if o.as_str() != ";" => path.push_str(o.as_str()),
+                            _ => break,
+                        }
+                        i += 1;
+                    }
+                }
+                for (at, n) in names {
+                    let (position, token) = &tokens[at];
+                    index.push(Symbol::Uploaded(n.clone()), *position, token_length(token), true, None);
+                    index.uploads.push((n, path.clone()));
+                }
+            } else {
+                i += 1;
+            }
+        }
+        let uploaded: HashSet<&str> = index.uploads.iter().map(|(n, _)| n.as_str()).collect();
+        let mut automata: Option<String> = None;
+        let declared: HashSet<Position> = index.occurrences.iter().map(|o| o.position).collect();
+        let mut found = vec![];
+        for (i, (position, token)) in tokens.iter().enumerate() {
+            let FANGrammarToken::Name(n) = token else {
+                continue;
+            };
+            let prev = |k: usize| i.checked_sub(k).and_then(|j| tokens.get(j));
+            let (symbol, declaration) = if is_reserved(prev(1), FANReserved::AutomataDeclare) {
+                automata = Some(n.clone());
+                (Symbol::Automata(n.clone()), true)
+            } else if is_reserved(prev(1), FANReserved::StateDeclare) && let Some(a) = &automata {
+                (Symbol::State(a.clone(), n.clone()), true)
+            } else if is_reserved(prev(1), FANReserved::Run) || (is_op(tokens.get(i + 1), "::") && name(tokens.get(i + 2)).is_some()) {
+                (Symbol::Automata(n.clone()), false)
+            } else if is_op(prev(1), "->") && name(prev(2)) == Some("self") && is_reserved(prev(3), FANReserved::LinkDeclare)
+                && let Some(a) = &automata
+            {
+                (Symbol::State(a.clone(), n.clone()), false)
+            } else if is_op(prev(1), "::") && let Some(a) = name(prev(2)) {
+                (Symbol::State(a.to_string(), n.clone()), false)
+            } else if uploaded.contains(n.as_str()) && !declared.contains(position) {
+                (Symbol::Uploaded(n.clone()), false)
+            } else {
+                continue;
+            };
+            let end = if declaration { block_end(tokens, i) } else { None };
+            found.push((symbol, *position, token_length(token), declaration, end));
+        }
+        for (symbol, position, length, declaration, end) in found {
+            index.push(symbol, position, length, declaration, end);
+        }
+        index
+    }
+
+    fn push(&mut self, symbol: Symbol, position: Position, length: usize, declaration: bool, end: Option<Position>) {
+        let end = end.unwrap_or(Position::new(position.line, position.col + length));
+        self.occurrences.push(Occurrence { symbol, position, length, declaration, end });
+    }
+
+    pub fn at(&self, p: Position) -> Option<&Occurrence> {
+        self.occurrences.iter().find(|o| o.contains(p))
+    }
+
+    pub fn declaration(&self, symbol: &Symbol) -> Option<&Occurrence> {
+        self.occurrences.iter().find(|o| o.declaration && o.symbol == *symbol)
+    }
+
+    /// Automata declared last before the position
+    fn automata_at(&self, p: Position) -> Option<&str> {
+        self.occurrences.iter()
+            .filter(|o| o.declaration && o.position <= p)
+            .filter_map(|o| match &o.symbol {
+                Symbol::Automata(a) => Some(a.as_str()),
+                _ => None,
+            })
+            .next_back()
+    }
+}
+
+/// Path of the `file://` URI
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let bytes = path.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 3 <= bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            },
+            c => {
+                decoded.push(c);
+                i += 1;
+            },
+        }
+    }
+    String::from_utf8(decoded).ok().map(PathBuf::from)
+}
+
+pub fn path_to_uri(path: &Path) -> String {
+    let mut uri = "file://".to_string();
+    for c in path.to_string_lossy().chars() {
+        match c {
+            ' ' => uri.push_str("%20"),
+            '%' => uri.push_str("%25"),
+            '#' => uri.push_str("%23"),
+            c => uri.push(c),
+        }
+    }
+    uri
+}
+
+/// Column of the position in UTF-16 code units, the default position encoding of LSP
+fn utf16_col(text: &str, p: Position) -> usize {
+    let line = text.lines().nth(p.line).unwrap_or_default();
+    line.chars().take(p.col).map(char::len_utf16).sum::<usize>() + p.col.saturating_sub(line.chars().count())
+}
+
+/// Character column of the UTF-16 column sent by the client
+fn char_col(text: &str, line: usize, character: usize) -> usize {
+    let mut units = 0;
+    let mut chars = 0;
+    for c in text.lines().nth(line).unwrap_or_default().chars() {
+        if units >= character {
+            return chars;
+        }
+        units += c.len_utf16();
+        chars += 1;
+    }
+    chars + character.saturating_sub(units)
+}
+
+fn position_json(text: &str, p: Position) -> Json {
+    Json::object([("line", p.line.into()), ("character", utf16_col(text, p).into())])
+}
+
+fn range_json(text: &str, start: Position, end: Position) -> Json {
+    Json::object([("start", position_json(text, start)), ("end", position_json(text, end))])
+}
+
+fn location_json(uri: &str, text: &str, start: Position, end: Position) -> Json {
+    Json::object([("uri", uri.into()), ("range", range_json(text, start, end))])
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+        && FANReserved::try_from(name).is_none()
+}
+
+/// Errors of the parser or the checker, pointing at the whole lexem
+pub fn diagnostics(text: &str) -> Vec<Json> {
+    let found: Vec<Diagnostic> = match Parser::parse_str(text) {
+        Err(e) => vec![Diagnostic::from(&e)],
+        Ok(module) => match Checker::check(&module) {
+            Ok(()) => vec![],
+            Err(errors) => errors.iter().map(Diagnostic::from).collect(),
+        },
+    };
+    let tokens = Lexer::lex_str(text).unwrap_or_default();
+    found.into_iter().map(|d| {
+        let start = d.position.unwrap_or_default();
+        let length = tokens.iter().find(|(p, _)| *p == start).map_or(1, |(_, t)| token_length(t));
+        Json::object([
+            ("range", range_json(text, start, Position::new(start.line, start.col + length))),
+            ("severity", 1i64.into()),
+            ("source", "fan".into()),
+            ("message", d.message.into()),
+        ])
+    }).collect()
+}
+
+/// Source line of the declaration up to its block or comment
+fn declaration_line(text: &str, p: Position) -> String {
+    let line = text.lines().nth(p.line).unwrap_or_default();
+    line.split(['{', '#']).next().unwrap_or_default().trim().to_string()
+}
+
+#[derive(Debug)]
+struct ResponseError {
+    code: i64,
+    message: String,
+}
+
+impl ResponseError {
+    const INVALID_PARAMS: i64 = -32602;
+    const METHOD_NOT_FOUND: i64 = -32601;
+
+    fn invalid(message: impl Into<String>) -> Self {
+        Self { code: Self::INVALID_PARAMS, message: message.into() }
+    }
+}
+
+/// Language server over the open documents and the `.fan` files of the workspace root
+pub struct LspServer<R, W> {
+    input: R,
+    output: W,
+    /// Open documents by URI with their current text
+    documents: Vec<(String, String)>,
+    root: Option<PathBuf>,
+}
+
+impl<R: BufRead, W: Write> LspServer<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Self { input, output, documents: vec![], root: None }
+    }
+
+    fn send(&mut self, mut fields: Vec<(String, Json)>) -> Result<(), RpcError> {
+        fields.insert(0, ("jsonrpc".to_string(), "2.0".into()));
+        write_message(&mut self.output, &Json::Object(fields))?;
+        Ok(())
+    }
+
+    fn notify(&mut self, method: &str, params: Json) -> Result<(), RpcError> {
+        self.send(vec![("method".to_string(), method.into()), ("params".to_string(), params)])
+    }
+
+    /// Serves the messages until `exit` or the end of the input
+    pub fn serve(mut self) -> Result<(), RpcError> {
+        while let Some(message) = read_message(&mut self.input)? {
+            let method = message.get("method").and_then(Json::as_str).unwrap_or_default().to_string();
+            let params = message.get("params").cloned().unwrap_or(Json::Null);
+            let Some(id) = message.get("id").cloned() else {
+                if method == "exit" {
+                    return Ok(());
+                }
+                self.notification(&method, &params)?;
+                continue;
+            };
+            let result = match self.request(&method, &params) {
+                Ok(result) => ("result".to_string(), result),
+                Err(e) => ("error".to_string(), Json::object([("code", e.code.into()), ("message", e.message.into())])),
+            };
+            self.send(vec![("id".to_string(), id), result])?;
+        }
+        Ok(())
+    }
+
+    fn notification(&mut self, method: &str, params: &Json) -> Result<(), RpcError> {
+        let document = params.get("textDocument");
+        let uri = document.and_then(|d| d.get("uri")).and_then(Json::as_str).unwrap_or_default().to_string();
+        let text = match method {
+            "textDocument/didOpen" => document.and_then(|d| d.get("text")).and_then(Json::as_str).map(str::to_string),
+            // full synchronization, the last change holds the whole text
+            "textDocument/didChange" => params.get("contentChanges").and_then(Json::as_array)
+                .and_then(|c| c.last()).and_then(|c| c.get("text")).and_then(Json::as_str).map(str::to_string),
+            "textDocument/didClose" => {
+                self.documents.retain(|(u, _)| *u != uri);
+                return self.notify("textDocument/publishDiagnostics", Json::object([
+                    ("uri", uri.into()), ("diagnostics", Json::Array(vec![])),
+                ]));
+            },
+            _ => return Ok(()),
+        };
+        let Some(text) = text else {
+            return Ok(());
+        };
+        let diagnostics = diagnostics(&text);
+        match self.documents.iter_mut().find(|(u, _)| *u == uri) {
+            Some((_, t)) => *t = text,
+            None => self.documents.push((uri.clone(), text)),
+        }
+        self.notify("textDocument/publishDiagnostics", Json::object([
+            ("uri", uri.into()), ("diagnostics", Json::Array(diagnostics)),
+        ]))
+    }
+
+    fn request(&mut self, method: &str, params: &Json) -> Result<Json, ResponseError> {
+        match method {
+            "initialize" => {
+                let root = params.get("rootUri").and_then(Json::as_str).and_then(uri_to_path)
+                    .or_else(|| params.get("rootPath").and_then(Json::as_str).map(PathBuf::from));
+                self.root = root;
+                Ok(Json::object([
+                    ("capabilities", Json::object([
+                        ("textDocumentSync", 1i64.into()),
+                        ("definitionProvider", true.into()),
+                        ("hoverProvider", true.into()),
+                        ("completionProvider", Json::object([("triggerCharacters", vec![">", " "].into())])),
+                        ("documentSymbolProvider", true.into()),
+                        ("renameProvider", true.into()),
+                    ])),
+                    ("serverInfo", Json::object([("name", "fan".into())])),
+                ]))
+            },
+            "shutdown" => Ok(Json::Null),
+            "textDocument/definition" => self.definition(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/completion" => self.completion(params),
+            "textDocument/documentSymbol" => self.document_symbols(params),
+            "textDocument/rename" => self.rename(params),
+            _ => Err(ResponseError { code: ResponseError::METHOD_NOT_FOUND, message: format!("unsupported method `{}`", method) }),
+        }
+    }
+
+    fn text(&self, uri: &str) -> Option<String> {
+        self.documents.iter().find(|(u, _)| u == uri).map(|(_, t)| t.clone())
+            .or_else(|| std::fs::read_to_string(uri_to_path(uri)?).ok())
+    }
+
+    /// Open documents first, then the unopened files of the workspace
+    fn workspace(&self) -> Vec<(String, String)> {
+        let mut documents = self.documents.clone();
+        let files = self.root.as_deref().and_then(|root| fan_files(root).ok()).unwrap_or_default();
+        for file in files {
+            let uri = path_to_uri(&file);
+            if !documents.iter().any(|(u, _)| *u == uri)
+                && let Ok(text) = std::fs::read_to_string(&file)
+            {
+                documents.push((uri, text));
+            }
+        }
+        documents
+    }
+
+    /// Document, its index and the position of the request
+    fn target(&self, params: &Json) -> Result<(String, String, Index, Position), ResponseError> {
+        let uri = params.get("textDocument").and_then(|d| d.get("uri")).and_then(Json::as_str)
+            .ok_or_else(|| ResponseError::invalid("missing textDocument"))?;
+        let text = self.text(uri).ok_or_else(|| ResponseError::invalid(format!("unknown document `{}`", uri)))?;
+        let position = params.get("position")
+            .and_then(|p| Some((p.get("line")?.as_i64()? as usize, p.get("character")?.as_i64()? as usize)))
+            .map_or(Position::default(), |(line, character)| Position::new(line, char_col(&text, line, character)));
+        let index = Index::new(&Lexer::lex_str(&text).unwrap_or_default());
+        Ok((uri.to_string(), text, index, position))
+    }
+
+    fn find_declaration(&self, uri: &str, index: &Index, symbol: &Symbol) -> Option<(String, String, Occurrence)> {
+        if let Some(o) = index.declaration(symbol) {
+            return Some((uri.to_string(), self.text(uri)?, o.clone()));
+        }
+        self.workspace().into_iter().find_map(|(u, text)| {
+            let o = Index::new(&Lexer::lex_str(&text).ok()?).declaration(symbol)?.clone();
+            Some((u, text, o))
+        })
+    }
+
+    fn definition(&self, params: &Json) -> Result<Json, ResponseError> {
+        let (uri, _, index, position) = self.target(params)?;
+        let Some(occurrence) = index.at(position) else {
+            return Ok(Json::Null);
+        };
+        if let Symbol::Uploaded(n) = &occurrence.symbol {
+            // the uploaded type is defined in the file it comes from
+            let from = index.uploads.iter().find(|(u, _)| u == n).map(|(_, from)| from.clone()).unwrap_or_default();
+            let base = uri_to_path(&uri).and_then(|p| p.parent().map(Path::to_path_buf)).unwrap_or_default();
+            let file = base.join(from);
+            let Ok(text) = std::fs::read_to_string(&file) else {
+                return Ok(Json::Null);
+            };
+            let target = Index::new(&Lexer::lex_str(&text).unwrap_or_default()).occurrences.into_iter()
+                .find(|o| o.declaration && matches!(&o.symbol, Symbol::Automata(a) | Symbol::Uploaded(a) if a == n));
+            let (start, end) = target.map_or((Position::default(), Position::default()), |o| {
+                (o.position, Position::new(o.position.line, o.position.col + o.length))
+            });
+            return Ok(location_json(&path_to_uri(&file), &text, start, end));
+        }
+        Ok(match self.find_declaration(&uri, &index, &occurrence.symbol) {
+            Some((u, text, o)) => location_json(&u, &text, o.position, Position::new(o.position.line, o.position.col + o.length)),
+            None => Json::Null,
+        })
+    }
+
+    fn hover(&self, params: &Json) -> Result<Json, ResponseError> {
+        let (uri, document, index, position) = self.target(params)?;
+        let Some(occurrence) = index.at(position) else {
+            return Ok(Json::Null);
+        };
+        let contents = match &occurrence.symbol {
+            Symbol::Uploaded(n) => {
+                let from = index.uploads.iter().find(|(u, _)| u == n).map(|(_, f)| f.as_str()).unwrap_or_default();
+                format!("```fan\nupload {} from {}\n```", n, from)
+            },
+            symbol => {
+                let Some((_, text, declaration)) = self.find_declaration(&uri, &index, symbol) else {
+                    return Ok(Json::Null);
+                };
+                let line = declaration_line(&text, declaration.position);
+                match symbol {
+                    Symbol::State(a, _) => format!("```fan\n{}\n```\nstate of `{}`", line, a),
+                    _ => format!("```fan\n{}\n```", line),
+                }
+            },
+        };
+        Ok(Json::object([
+            ("contents", Json::object([("kind", "markdown".into()), ("value", contents.into())])),
+            ("range", range_json(&document, occurrence.position, Position::new(occurrence.position.line, occurrence.position.col + occurrence.length))),
+        ]))
+    }
+
+    /// States of the enclosing automata after `link self ->`
+    fn completion(&self, params: &Json) -> Result<Json, ResponseError> {
+        let (_, text, index, position) = self.target(params)?;
+        let line = text.lines().nth(position.line).unwrap_or_default();
+        let before: String = line.chars().take(position.col).collect();
+        let before = before.trim_end_matches(|c: char| c.is_alphanumeric() || c == '_');
+        let linked = before.trim_end().strip_suffix("->")
+            .and_then(|b| b.trim_end().strip_suffix("self"))
+            .and_then(|b| b.trim_end().strip_suffix("link"))
+            .is_some_and(|b| !b.ends_with(|c: char| c.is_alphanumeric() || c == '_'));
+        let Some(automata) = index.automata_at(position).filter(|_| linked) else {
+            return Ok(Json::Array(vec![]));
+        };
+        let mut items: Vec<Json> = index.occurrences.iter()
+            .filter_map(|o| match &o.symbol {
+                Symbol::State(a, s) if o.declaration && a == automata => Some(Json::object([
+                    ("label", s.as_str().into()),
+                    ("kind", 20i64.into()),
+                    ("detail", declaration_line(&text, o.position).into()),
+                ])),
+                _ => None,
+            })
+            .collect();
+        items.push(Json::object([("label", "NULL".into()), ("kind", 14i64.into()), ("detail", "finish the automata".into())]));
+        Ok(Json::Array(items))
+    }
+
+    fn document_symbols(&self, params: &Json) -> Result<Json, ResponseError> {
+        let (_, text, index, _) = self.target(params)?;
+        let symbol = |o: &Occurrence, name: &str, kind: i64, children: Vec<Json>| Json::object([
+            ("name", name.into()),
+            ("detail", declaration_line(&text, o.position).into()),
+            ("kind", kind.into()),
+            ("range", range_json(&text, Position::new(o.position.line, 0), o.end)),
+            ("selectionRange", range_json(&text, o.position, Position::new(o.position.line, o.position.col + o.length))),
+            ("children", Json::Array(children)),
+        ]);
+        let symbols = index.occurrences.iter().filter(|o| o.declaration).filter_map(|o| match &o.symbol {
+            Symbol::Automata(a) => {
+                let states = index.occurrences.iter()
+                    .filter(|s| s.declaration && matches!(&s.symbol, Symbol::State(of, _) if of == a))
+                    .map(|s| match &s.symbol {
+                        Symbol::State(_, name) => symbol(s, name, 22, vec![]),
+                        _ => unreachable!("filtered above"),
+                    })
+                    .collect();
+                Some(symbol(o, a, 5, states))
+            },
+            Symbol::Uploaded(n) => Some(symbol(o, n, 26, vec![])),
+            Symbol::State(..) => None,
+        }).collect();
+        Ok(Json::Array(symbols))
+    }
+
+    /// Renames the automata or the state in every document of the workspace
+    fn rename(&self, params: &Json) -> Result<Json, ResponseError> {
+        let (_, _, index, position) = self.target(params)?;
+        let new_name = params.get("newName").and_then(Json::as_str).unwrap_or_default();
+        if !is_identifier(new_name) {
+            return Err(ResponseError::invalid(format!("`{}` is not a name", new_name)));
+        }
+        let symbol = match index.at(position).map(|o| &o.symbol) {
+            Some(s @ (Symbol::Automata(_) | Symbol::State(..))) => s.clone(),
+            _ => return Err(ResponseError::invalid("only automata and states can be renamed")),
+        };
+        let changes = self.workspace().into_iter().filter_map(|(uri, text)| {
+            let edits: Vec<Json> = Index::new(&Lexer::lex_str(&text).ok()?).occurrences.iter()
+                .filter(|o| o.symbol == symbol)
+                .map(|o| Json::object([
+                    ("range", range_json(&text, o.position, Position::new(o.position.line, o.position.col + o.length))),
+                    ("newText", new_name.into()),
+                ]))
+                .collect();
+            Some((uri, Json::Array(edits))).filter(|(_, e)| e.as_array().is_some_and(|e| !e.is_empty()))
+        }).collect();
+        Ok(Json::object([("changes", Json::Object(changes))]))
+    }
+}
+
+#[test]
+fn lsp_test() {
+    let root = std::env::temp_dir().join(format!("lsp_test_{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("types.fan"), "automata Point {\n    state Origin { }\n}\n").unwrap();
+    std::fs::write(root.join("user.fan"), "automata User {\n    state Wait { let s = \"é𝄞\"; if true is Main::Up { } }\n}\n").unwrap();
+    let main = "\
+upload Point from types.fan
+automata Main {
+    state Up<n: int64, p: Point> { # counting
+        if n < 3 { link self -> Up<n + 1, p>; }
+        link self ->
+    }
+    state Down { }
+}
+";
+    let uri = path_to_uri(&root.join("main.fan"));
+    let user = path_to_uri(&root.join("user.fan"));
+
+    let mut input = vec![];
+    let mut id = 0i64;
+    let mut message = |method: &str, params: Json, request: bool| {
+        let mut fields = vec![("jsonrpc", "2.0".into()), ("method", method.into()), ("params", params)];
+        if request {
+            id += 1;
+            fields.insert(1, ("id", id.into()));
+        }
+        write_message(&mut input, &Json::object(fields)).unwrap();
+    };
+    let at = |line: i64, character: i64| Json::object([
+        ("textDocument", Json::object([("uri", uri.as_str().into())])),
+        ("position", Json::object([("line", line.into()), ("character", character.into())])),
+    ]);
+    message("initialize", Json::object([("rootUri", path_to_uri(&root).into())]), true);
+    message("initialized", Json::object([]), false);
+    message("textDocument/didOpen", Json::object([("textDocument", Json::object([
+        ("uri", uri.as_str().into()), ("languageId", "fan".into()), ("version", 1i64.into()), ("text", main.into()),
+    ]))]), false);
+    message("textDocument/definition", at(3, 33), true);
+    message("textDocument/definition", at(2, 26), true);
+    message("textDocument/hover", at(3, 33), true);
+    message("textDocument/completion", at(4, 20), true);
+    message("textDocument/documentSymbol", at(0, 0), true);
+    let mut rename = at(6, 11);
+    if let Json::Object(fields) = &mut rename {
+        fields.push(("newName".to_string(), "link".into()));
+    }
+    message("textDocument/rename", rename, true);
+    let mut rename = at(3, 33);
+    if let Json::Object(fields) = &mut rename {
+        fields.push(("newName".to_string(), "Rise".into()));
+    }
+    message("textDocument/rename", rename, true);
+    message("textDocument/didChange", Json::object([
+        ("textDocument", Json::object([("uri", uri.as_str().into()), ("version", 2i64.into())])),
+        ("contentChanges", Json::Array(vec![Json::object([("text", main.replace("link self ->\n", "").into())])])),
+    ]), false);
+    message("shutdown", Json::Null, true);
+    message("exit", Json::Null, false);
+
+    let mut output = vec![];
+    LspServer::new(input.as_slice(), &mut output).serve().unwrap();
+    std::fs::remove_dir_all(&root).unwrap();
+    let mut reader = output.as_slice();
+    let mut messages = vec![];
+    while let Some(message) = read_message(&mut reader).unwrap() {
+        messages.push(message);
+    }
+    let result = |i: usize| messages[i].get("result").map(Json::to_string).unwrap_or_default();
+    assert_eq!(messages.len(), 11);
+    assert!(result(0).contains(r#""renameProvider":true"#));
+    // the unfinished link does not parse
+    let published = messages[1].get("params").unwrap();
+    assert_eq!(published.get("diagnostics").and_then(Json::as_array).map(|d| d.len()), Some(1));
+    assert_eq!(
+        result(2),
+        format!(r#"{{"uri":"{}","range":{{"start":{{"line":2,"character":10}},"end":{{"line":2,"character":12}}}}}}"#, uri),
+    );
+    assert!(result(3).starts_with(&format!(r#"{{"uri":"{}","#, path_to_uri(&root.join("types.fan")))));
+    assert!(result(3).contains(r#""start":{"line":0,"character":9}"#));
+    assert!(result(4).contains(r#""value":"```fan\nstate Up<n: int64, p: Point>\n```\nstate of `Main`""#));
+    assert_eq!(
+        result(5),
+        r#"[{"label":"Up","kind":20,"detail":"state Up<n: int64, p: Point>"},{"label":"Down","kind":20,"detail":"state Down"},{"label":"NULL","kind":14,"detail":"finish the automata"}]"#,
+    );
+    let symbols = Json::parse(&result(6)).unwrap();
+    let names: Vec<_> = symbols.as_array().unwrap().iter().map(|s| s.get("name").and_then(Json::as_str).unwrap()).collect();
+    assert_eq!(names, ["Point", "Main"]);
+    let states = symbols.as_array().unwrap()[1].get("children").and_then(Json::as_array).unwrap();
+    assert_eq!(states.len(), 2);
+    assert_eq!(states[0].get("range").unwrap().to_string(), r#"{"start":{"line":2,"character":0},"end":{"line":5,"character":5}}"#);
+    assert!(messages[7].get("error").is_some(), "{}", messages[7]);
+    let changes = Json::parse(&result(8)).unwrap();
+    let changes = changes.get("changes").unwrap();
+    assert_eq!(changes.get(&uri).and_then(Json::as_array).map(|e| e.len()), Some(2));
+    assert_eq!(
+        changes.get(&user).unwrap().to_string(),
+        r#"[{"range":{"start":{"line":1,"character":49},"end":{"line":1,"character":51}},"newText":"Rise"}]"#,
+    );
+    // positions count UTF-16 code units, `𝄞` takes two of them
+    assert_eq!(utf16_col("a𝄞b", Position::new(0, 2)), 3);
+    assert_eq!(char_col("a𝄞b", 0, 3), 2);
+    assert_eq!(char_col("ab", 0, 4), 4);
+    assert_eq!(messages[9].get("params").unwrap().get("diagnostics").unwrap().to_string(), "[]");
+}
@@ -1,9 +1,13 @@
-pub mod lexer;
-pub mod parser;
+use std::process::ExitCode;
 
-use lexer::*;
+/// Native stack of the command thread, each of the [`fan_rs::interpreter::MAX_CALL_DEPTH`] nested FAN calls takes several Rust frames
+const STACK_SIZE: usize = 256 << 20;
 
-
-fn main() {
-    println!("Hello, world!");
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = std::thread::Builder::new().stack_size(STACK_SIZE).spawn(move || {
+        fan_rs::cli::main(&args, &mut std::io::stdin().lock(), &mut std::io::stdout(), &mut std::io::stderr())
+    });
+    let code = command.expect("command thread").join().unwrap_or_else(|e| std::panic::resume_unwind(e));
+    ExitCode::from(code)
 }
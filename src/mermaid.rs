@@ -0,0 +1,87 @@
+//! Mermaid `stateDiagram-v2` of the automata for `fan graph --format mermaid`,
+//! to be embedded in Markdown as a `mermaid` code block
+
+use crate::format::INDENT;
+use crate::graph::{AutomataGraph, Graph};
+
+/// Text with the characters Mermaid reads as markup replaced by entity codes
+fn escape(s: &str) -> String {
+    let mut r = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => r.push_str("#lt;"),
+            '>' => r.push_str("#gt;"),
+            '"' => r.push_str("#quot;"),
+            ';' => r.push_str("#59;"),
+            '#' => r.push_str("#35;"),
+            c => r.push(c),
+        }
+    }
+    r
+}
+
+/// Diagram of a single automata: `[*]` starts the initial state and stands for NULL,
+/// the transitions are labelled with their guards and the states running other automata have notes
+pub fn automata_to_mermaid(a: &AutomataGraph) -> String {
+    let mut out = String::from("stateDiagram-v2\n");
+    for s in a.states.iter() {
+        if s.params.is_empty() {
+            out.push_str(&format!("{}{}\n", INDENT, s.name));
+        } else {
+            out.push_str(&format!("{}state \"{}\" as {}\n", INDENT, escape(&s.label()), s.name));
+        }
+    }
+    if let Some(initial) = a.initial() {
+        out.push_str(&format!("{}[*] --> {}\n", INDENT, initial.name));
+    }
+    for (e, label) in a.labelled_edges() {
+        out.push_str(&format!("{}{} --> {}", INDENT, e.from, e.to.as_deref().unwrap_or("[*]")));
+        if !label.is_empty() {
+            out.push_str(&format!(" : {}", escape(&label)));
+        }
+        out.push('\n');
+    }
+    for s in a.states.iter().filter(|s| !s.runs.is_empty()) {
+        out.push_str(&format!("{}note right of {} : run {}\n", INDENT, s.name, s.runs.join(", ")));
+    }
+    out
+}
+
+/// Diagrams of every automata separated by an empty line
+pub fn to_mermaid(graph: &Graph) -> String {
+    graph.automata.iter().map(automata_to_mermaid).collect::<Vec<_>>().join("\n")
+}
+
+#[test]
+fn mermaid_test() {
+    use crate::parser::Parser;
+
+    let module = Parser::parse_str("
+        automata Door {
+            state Closed<locked: bool> {
+                if locked { link self -> Closed<false>; } else if self.previous.is_me() { link self -> Open; }
+            }
+            state Open { let _ = run Timer<3>; link self -> Closed<true>; }
+        }
+        automata Timer {
+            state Tick<n: int64> { if n > 0 { link self -> Tick<n - 1>; } else { link self -> NULL; } }
+        }
+    ").unwrap();
+    assert_eq!(to_mermaid(&Graph::new(&module)), "\
+stateDiagram-v2
+    state \"Closed#lt;locked: bool#gt;\" as Closed
+    Open
+    [*] --> Closed
+    Closed --> Closed : locked
+    Closed --> Open : !locked && self.previous.is_me()
+    Closed --> [*]
+    Open --> Closed
+    note right of Open : run Timer
+
+stateDiagram-v2
+    state \"Tick#lt;n: int64#gt;\" as Tick
+    [*] --> Tick
+    Tick --> Tick : n #gt; 0
+    Tick --> [*] : !(n #gt; 0)
+");
+}
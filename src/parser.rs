@@ -1,33 +1,44 @@
-use std::default;
+use crate::lexer::{BlockSymbol, FANGrammarToken, FANReserved, LexError, Lexer, Operational, Position};
 
-use crate::lexer::{FANGrammarToken, LexError, Operational};
-
-pub trait Expandable {
-    fn expand(&mut self, t: &FANGrammarToken) ->  Result<bool, ParseError>;
-}
-
-#[derive(Debug)]
+/// Template arguments of a name: `Base<context, 1>`
+#[derive(Debug, Clone, Default)]
 pub struct TypeTemplate {
-
+    pub args: Vec<Expression>,
 }
 
-#[derive(Debug)]
-pub struct SingleName(String, Option<TypeTemplate>);
+#[derive(Debug, Clone)]
+pub struct SingleName(pub String, pub Option<TypeTemplate>);
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Name {
     SingleName(SingleName),
     NamespaceName(Vec<SingleName>),
 }
 
-#[derive(Debug, Default)]
-pub struct Tuple{
-    tuple: Vec<Statement>,
-    closed: bool
+impl Name {
+    /// Last segment of the name
+    pub fn last(&self) -> &SingleName {
+        match self {
+            Name::SingleName(n) => n,
+            Name::NamespaceName(v) => v.last().expect("namespace name is never empty"),
+        }
+    }
+
+    /// `a::b::c` like path without templates
+    pub fn path(&self) -> String {
+        match self {
+            Name::SingleName(n) => n.0.clone(),
+            Name::NamespaceName(v) => v.iter().map(|n| n.0.as_str()).collect::<Vec<_>>().join("::"),
+        }
+    }
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct Tuple{
+    pub tuple: Vec<Expression>,
+}
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Literal {
     /// Char
     Char(char),
@@ -38,17 +49,18 @@ pub enum Literal {
     /// any numerical lexem \\
     /// with type or float - need to identify type
     NumericalLexem(String),
+    /// `true` or `false`
+    Bool(bool),
     /// ist NULL :3
     NULL,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct Block {
-    block: Vec::<Expression>,
-    closed: bool
+    pub block: Vec::<Expression>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Statement {
     /// Char String Digital or other Numerical lexem
     Literal(Literal),
@@ -60,276 +72,1223 @@ pub enum Statement {
     Tuple(Tuple),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BinaryOperator {
-    arg1:     Expression,
-    arg2:     Expression,
-    operator: Operational,
+    pub arg1:     Expression,
+    pub arg2:     Expression,
+    pub operator: Operational,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct UnaryOperator {
-    arg:     Expression,
-    operator: Operational,
+    pub arg:     Expression,
+    pub operator: Operational,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+pub struct IfExp {
+    pub condition: Expression,
+    pub then: Block,
+    /// `else if` chains are stored as a block with the single nested `if`
+    pub otherwise: Option<Block>,
+}
+
+/// Left side of the `match` arm or right side of `is`
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// `_`
+    Wildcard,
+    /// any name binds the matched value
+    Binding(String),
+    Literal(Literal),
+    Tuple(Vec<Pattern>),
+    /// `Automata::State`, `Automata::State(a, _)` or `State(record)`.
+    /// With a single binding and the state arity other than one the
+    /// binding holds the whole state, its arguments are members.
+    State {
+        automata: Option<String>,
+        state: String,
+        args: Option<Vec<Pattern>>,
+    },
+}
+
+impl Pattern {
+    /// Pattern matches any value
+    pub fn is_irrefutable(&self) -> bool {
+        match self {
+            Pattern::Wildcard | Pattern::Binding(_) => true,
+            Pattern::Tuple(v) => v.iter().all(|p| p.is_irrefutable()),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Expression,
+    pub position: Position,
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchExp {
+    pub value: Expression,
+    pub arms: Vec<MatchArm>,
+}
+
+#[derive(Debug, Clone)]
 pub enum ReturnableExp {
     Statement(Statement),
     FunctionCall(Statement, Tuple),
-    AutomataCall,
+    /// `run Automata<args>` runs the automata to the end,
+    /// the value is the final state
+    AutomataCall(SingleName),
     BinaryOperator(Box<BinaryOperator>),
     UnaryyOperator(Box<UnaryOperator>),
-    If,
-    Match,
+    /// `value.name` or `tuple.0`
+    Member(Box<Expression>, String),
+    /// `value.name(args)`, builtin methods such as `self.previous.is_me()`
+    MethodCall(Box<Expression>, String, Tuple),
+    /// `value[index]`
+    Index(Box<Expression>, Box<Expression>),
+    If(Box<IfExp>),
+    Match(Box<MatchExp>),
+    /// `value is Pattern`, bindings are visible in the guarded block
+    Is(Box<Expression>, Box<Pattern>),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+pub struct LinkExp {
+    /// Left side of the link, only `self` for now
+    pub from: String,
+    /// `None` stands for NULL
+    pub target: Option<SingleName>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WhileExp {
+    pub condition: Expression,
+    pub body: Block,
+}
+
+#[derive(Debug, Clone)]
+pub struct ForExp {
+    pub variable: String,
+    pub iterable: Expression,
+    pub body: Block,
+}
+
+#[derive(Debug, Clone)]
 pub enum ProceduralExp {
-    For,
-    While,
-    Link,
+    For(Box<ForExp>),
+    While(Box<WhileExp>),
+    Link(Box<LinkExp>),
+    Return(Option<Box<Expression>>),
 }
 
-#[derive(Debug)]
+/// Type annotation: `int64`, `ContextType1`, `(int64, char)` or `()`
+#[derive(Debug, Clone, PartialEq)]
+pub enum FANType {
+    Named(String),
+    Tuple(Vec<FANType>),
+}
+
+impl FANType {
+    pub fn unit() -> Self {
+        Self::Tuple(vec![])
+    }
+}
+
+impl std::fmt::Display for FANType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FANType::Named(n) => write!(f, "{}", n),
+            FANType::Tuple(v) => {
+                write!(f, "(")?;
+                for (i, t) in v.iter().enumerate() {
+                    if i > 0 { write!(f, ", ")?; }
+                    write!(f, "{}", t)?;
+                }
+                if v.len() == 1 { write!(f, ",")?; }
+                write!(f, ")")
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Param {
+    pub name: String,
+    pub ty: Option<FANType>,
+    pub position: Position,
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctionDef {
+    pub name: String,
+    pub params: Vec<Param>,
+    /// `None` means unit
+    pub returns: Option<FANType>,
+    pub body: Block,
+    pub position: Position,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AutomataKind {
+    Moore,
+    /// Mealy automata with the name of the signal variable
+    /// and the optional name of the output variable
+    Mealy(String, Option<String>),
+}
+
+#[derive(Debug, Clone)]
+pub struct StateDef {
+    pub name: String,
+    pub params: Vec<Param>,
+    pub body: Block,
+    /// Annotated with `initial`
+    pub initial: bool,
+    pub position: Position,
+}
+
+#[derive(Debug, Clone)]
+pub struct AutomataDef {
+    pub name: String,
+    pub kind: AutomataKind,
+    pub states: Vec<StateDef>,
+    pub functions: Vec<FunctionDef>,
+    pub position: Position,
+}
+
+impl AutomataDef {
+    pub fn state(&self, name: &str) -> Option<&StateDef> {
+        self.states.iter().find(|s| s.name == name)
+    }
+
+    pub fn function(&self, name: &str) -> Option<&FunctionDef> {
+        self.functions.iter().find(|f| f.name == name)
+    }
+
+    /// State annotated as `initial`, otherwise the first declared one
+    pub fn initial_state(&self) -> Option<&StateDef> {
+        self.states.iter().find(|s| s.initial).or(self.states.first())
+    }
+
+    /// Declared parameter of the Mealy signal in the state
+    pub fn signal_param<'a>(&self, state: &'a StateDef) -> Option<&'a Param> {
+        match &self.kind {
+            AutomataKind::Mealy(signal, _) => state.params.iter().find(|p| p.name == *signal),
+            AutomataKind::Moore => None,
+        }
+    }
+
+    /// Mealy state with the unit signal type, like `state S<signal: ()>`,
+    /// makes the transition without reading the input
+    pub fn consumes_signal(&self, state: &StateDef) -> bool {
+        match &self.kind {
+            AutomataKind::Mealy(..) => self.signal_param(state).is_none_or(|p| p.ty != Some(FANType::unit())),
+            AutomataKind::Moore => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Define {
+    pub name: String,
+    pub ty: Option<FANType>,
+    pub value: Expression,
+}
+
+#[derive(Debug, Clone)]
 pub enum DefinitionExp {
-    Function,
-    Automata,
-    AutomataState,
-    Define
+    Function(Box<FunctionDef>),
+    Automata(Box<AutomataDef>),
+    AutomataState(Box<StateDef>),
+    Define(Box<Define>)
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct Imports {
-
+    pub names: Vec<String>,
+    pub from: String,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone)]
 pub enum ExpressionType {
     Import(Imports),
     Procedural(ProceduralExp),
     Returnable(ReturnableExp),
     Definition(DefinitionExp),
-    #[default]
-    Unpredicted
 }
 
-
-#[derive(Debug, Default)]
+#[derive(Debug, Clone)]
 pub struct Expression {
-    // tokens: Vec<FANGrammarToken>,  
-    predict: ExpressionType,
-    closed: bool
-}
-
-impl Expandable for Expression {
-    fn expand(&mut self, t: &FANGrammarToken) -> Result<bool, ParseError> {
-        match &self.predict {
-            ExpressionType::Import(imports) => todo!(),
-            ExpressionType::Procedural(procedural_exp) => todo!(),
-            ExpressionType::Returnable(returnable_exp) => todo!(),
-            ExpressionType::Definition(definition_exp) => todo!(),
-            ExpressionType::Unpredicted => {
-                // If new Expression starts then we have to make prediction what is it
-                // It can be changed in futute, when more tokens will be accessable
-                match t {
-                    FANGrammarToken::Name(n) => {
-                        self.predict = ExpressionType::Returnable(
-                            ReturnableExp::Statement(
-                                Statement::Name(Name::SingleName(
-                                    SingleName(n.clone(), None)
-                                ))
-                            )
-                        );
-                        Ok(true)
-                    },
-                    FANGrammarToken::Digital(d) => {
-                        self.predict = ExpressionType::Returnable(
-                            ReturnableExp::Statement(
-                                Statement::Literal(
-                                    Literal::Digital(d.clone())
-                                )
-                            )
-                        );
-                        Ok(true)
-                    },
-                    FANGrammarToken::NumericalLexem(nl) => {
-                        self.predict = ExpressionType::Returnable(
-                            ReturnableExp::Statement(
-                                Statement::Literal(
-                                    Literal::NumericalLexem(nl.clone())
-                                )
-                            )
-                        );
-                        Ok(true)
-                    },
-                    FANGrammarToken::CharLiteral(c) => {
-                        self.predict = ExpressionType::Returnable(
-                            ReturnableExp::Statement(
-                                Statement::Literal(
-                                    Literal::Char(c.clone())
-                                )
-                            )
-                        );
-                        Ok(true)
-                    },
-                    FANGrammarToken::StringLiteral(c) => {
-                        self.predict = ExpressionType::Returnable(
-                            ReturnableExp::Statement(
-                                Statement::Literal(
-                                    Literal::String(c.clone())
-                                )
-                            )
-                        );
-                        Ok(true)
-                    },
-                    FANGrammarToken::Reserved(fanreserved) => {
-                        match fanreserved {
-                            crate::lexer::FANReserved::NULL => {
-                                self.predict = ExpressionType::Returnable(
-                                    ReturnableExp::Statement(
-                                        Statement::Literal(
-                                            Literal::NULL
-                                        )
-                                    )
-                                );
-                                Ok(true)
-                            },
-                            crate::lexer::FANReserved::AutomataDeclare => {
-                                self.predict = ExpressionType::Definition(
-                                    DefinitionExp::Automata
-                                );
-                                Ok(true)
-                            },
-                            crate::lexer::FANReserved::StateDeclare => {
-                                self.predict = ExpressionType::Definition(
-                                    DefinitionExp::AutomataState
-                                );
-                                Ok(true)
-                            },
-                            crate::lexer::FANReserved::LocVarDeclare => {
-                                self.predict = ExpressionType::Definition(
-                                    DefinitionExp::Define
-                                );
-                                Ok(true)
-                            },
-                            crate::lexer::FANReserved::LinkDeclare => {
-                                self.predict = ExpressionType::Procedural(
-                                    ProceduralExp::Link
-                                );
-                                Ok(true)
-                            },
-                            crate::lexer::FANReserved::If => {
-                                self.predict = ExpressionType::Returnable(
-                                    ReturnableExp::If
-                                );
-                                Ok(true)
-                            },
-                            crate::lexer::FANReserved::For => {
-                                self.predict = ExpressionType::Procedural(
-                                    ProceduralExp::For
-                                );
-                                Ok(true)
-                            },
-                            crate::lexer::FANReserved::While => {
-                                {
-                                self.predict = ExpressionType::Procedural(
-                                    ProceduralExp::While
-                                );
-                                Ok(true)
-                            }
-                            },
-                            crate::lexer::FANReserved::Upload => {
-                                self.predict = ExpressionType::Import(
-                                    Imports::default()
-                                );
-                                Ok(true)
-                            },
-                            crate::lexer::FANReserved::From | crate::lexer::FANReserved::Else => {
-                                Err(ParseError::Unexpected(t.clone()))
-                            },
-                        }
-                    },
-                    FANGrammarToken::BlockSymbol(block_symbol) => {
-                        match block_symbol {
-                            crate::lexer::BlockSymbol::BlockBracketOpen => {
-                                self.predict = ExpressionType::Returnable(
-                                    ReturnableExp::Statement(
-                                        Statement::Block(
-                                            Box::new(
-                                                Block::default()
-                                            )
-                                        )
-                                    )
-                                );
-                                Ok(true)
-                            },
-                            crate::lexer::BlockSymbol::TupleBracketOpen => {
-                                self.predict = ExpressionType::Returnable(
-                                    ReturnableExp::Statement(
-                                        Statement::Tuple(
-                                            Tuple::default()
-                                        )
-                                    )
-                                );
-                                Ok(true)
-                            },
-                            crate::lexer::BlockSymbol::IndexBracketOpen => {
-                                Err(ParseError::Unexpected(t.clone()))
-                            },
-                            crate::lexer::BlockSymbol::BlockBracketClose | crate::lexer::BlockSymbol::TupleBracketClose | crate::lexer::BlockSymbol::IndexBracketClose => {
-                                Err(ParseError::Unexpected(t.clone()))
-                            },
-                        }
-                    },
-                    FANGrammarToken::Operational(operational) => {
-                        self.predict = ExpressionType::Returnable(
-                            ReturnableExp::UnaryyOperator(
-                                Box::new(UnaryOperator {
-                                    arg: Expression::default(),
-                                    operator: operational.clone()
-                                })
-                            )
-                        );
-                        Ok(true)
-                    },
-                    FANGrammarToken::TokBrk => {
-                        Err(ParseError::Unexpected(t.clone()))
-                    },
-                }
+    pub kind: ExpressionType,
+    pub position: Position,
+}
+
+impl Expression {
+    pub fn new(kind: ExpressionType, position: Position) -> Self {
+        Self { kind, position }
+    }
+
+    fn returnable(r: ReturnableExp, position: Position) -> Self {
+        Self::new(ExpressionType::Returnable(r), position)
+    }
+
+    fn statement(s: Statement, position: Position) -> Self {
+        Self::returnable(ReturnableExp::Statement(s), position)
+    }
+}
+
+impl Expression {
+    /// Visits the expression and all nested expressions, parents first
+    pub fn walk<'a>(&'a self, f: &mut impl FnMut(&'a Expression)) {
+        f(self);
+        match &self.kind {
+            ExpressionType::Import(_) => {},
+            ExpressionType::Definition(d) => match d {
+                DefinitionExp::Function(func) => func.body.walk(f),
+                DefinitionExp::Automata(a) => {
+                    a.functions.iter().for_each(|func| func.body.walk(f));
+                    a.states.iter().for_each(|s| s.body.walk(f));
+                },
+                DefinitionExp::AutomataState(s) => s.body.walk(f),
+                DefinitionExp::Define(d) => d.value.walk(f),
+            },
+            ExpressionType::Procedural(p) => match p {
+                ProceduralExp::For(x) => {
+                    x.iterable.walk(f);
+                    x.body.walk(f);
+                },
+                ProceduralExp::While(w) => {
+                    w.condition.walk(f);
+                    w.body.walk(f);
+                },
+                ProceduralExp::Link(l) => {
+                    l.target.iter().flat_map(|t| t.1.iter()).flat_map(|t| t.args.iter()).for_each(|a| a.walk(f));
+                },
+                ProceduralExp::Return(v) => v.iter().for_each(|v| v.walk(f)),
+            },
+            ExpressionType::Returnable(r) => match r {
+                ReturnableExp::Statement(s) | ReturnableExp::FunctionCall(s, _) => {
+                    match s {
+                        Statement::Block(b) => b.walk(f),
+                        Statement::Tuple(t) => t.tuple.iter().for_each(|e| e.walk(f)),
+                        Statement::Literal(_) | Statement::Name(_) => {},
+                    }
+                    if let ReturnableExp::FunctionCall(_, args) = r {
+                        args.tuple.iter().for_each(|e| e.walk(f));
+                    }
+                },
+                ReturnableExp::AutomataCall(call) => {
+                    call.1.iter().flat_map(|t| t.args.iter()).for_each(|a| a.walk(f));
+                },
+                ReturnableExp::BinaryOperator(b) => {
+                    b.arg1.walk(f);
+                    b.arg2.walk(f);
+                },
+                ReturnableExp::UnaryyOperator(u) => u.arg.walk(f),
+                ReturnableExp::Member(v, _) => v.walk(f),
+                ReturnableExp::MethodCall(v, _, args) => {
+                    v.walk(f);
+                    args.tuple.iter().for_each(|e| e.walk(f));
+                },
+                ReturnableExp::Index(v, i) => {
+                    v.walk(f);
+                    i.walk(f);
+                },
+                ReturnableExp::If(i) => {
+                    i.condition.walk(f);
+                    i.then.walk(f);
+                    i.otherwise.iter().for_each(|o| o.walk(f));
+                },
+                ReturnableExp::Match(m) => {
+                    m.value.walk(f);
+                    m.arms.iter().for_each(|a| a.body.walk(f));
+                },
+                ReturnableExp::Is(v, _) => v.walk(f),
             },
         }
     }
 }
 
+impl Block {
+    pub fn walk<'a>(&'a self, f: &mut impl FnMut(&'a Expression)) {
+        self.block.iter().for_each(|e| e.walk(f));
+    }
+}
+
+/// Parsed `.fan` source
+#[derive(Debug, Clone, Default)]
+pub struct Module {
+    pub expressions: Vec<Expression>,
+}
+
+impl Module {
+    pub fn imports(&self) -> impl Iterator<Item = &Imports> {
+        self.expressions.iter().filter_map(|e| match &e.kind {
+            ExpressionType::Import(i) => Some(i),
+            _ => None,
+        })
+    }
+
+    pub fn automata(&self) -> impl Iterator<Item = &AutomataDef> {
+        self.expressions.iter().filter_map(|e| match &e.kind {
+            ExpressionType::Definition(DefinitionExp::Automata(a)) => Some(a.as_ref()),
+            _ => None,
+        })
+    }
+
+    pub fn functions(&self) -> impl Iterator<Item = &FunctionDef> {
+        self.expressions.iter().filter_map(|e| match &e.kind {
+            ExpressionType::Definition(DefinitionExp::Function(f)) => Some(f.as_ref()),
+            _ => None,
+        })
+    }
+
+    pub fn find_automata(&self, name: &str) -> Option<&AutomataDef> {
+        self.automata().find(|a| a.name == name)
+    }
+
+    pub fn find_function(&self, name: &str) -> Option<&FunctionDef> {
+        self.functions().find(|f| f.name == name)
+    }
+}
+
 /* ============================================================================= */
 
 #[derive(Debug)]
 pub enum ParseError {
-    Unexpected(FANGrammarToken)
+    Lex(LexError),
+    Unexpected(FANGrammarToken, Position),
+    Expected(&'static str, Position),
+    UnexpectedEnd,
 }
 
-pub struct Parser {
+impl ParseError {
+    /// Lexical errors and the end of input have no position
+    pub fn position(&self) -> Option<Position> {
+        match self {
+            ParseError::Unexpected(_, p) | ParseError::Expected(_, p) => Some(*p),
+            ParseError::Lex(_) | ParseError::UnexpectedEnd => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Lex(e) => write!(f, "lexical error: {:?}", e),
+            ParseError::Unexpected(t, p) => write!(f, "{}: unexpected token {:?}", p, t),
+            ParseError::Expected(what, p) => write!(f, "{}: expected {}", p, what),
+            ParseError::UnexpectedEnd => write!(f, "unexpected end of input"),
+        }
+    }
+}
+
+impl From<LexError> for ParseError {
+    fn from(e: LexError) -> Self {
+        ParseError::Lex(e)
+    }
+}
+
+/// Binary operators from the loosest to the tightest binding
+pub(crate) const PRECEDENCE: [&[&str]; 8] = [
+    &["||"],
+    &["&&"],
+    &["==", "!=", "<", ">", "<=", ">="],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+pub(crate) const COMPARISON_PRECEDENCE: usize = 2;
+/// Template arguments can not contain comparison without brackets
+pub(crate) const TEMPLATE_PRECEDENCE: usize = COMPARISON_PRECEDENCE + 1;
+pub(crate) const ASSIGNMENTS: [&str; 5] = ["=", "+=", "-=", "*=", "/="];
 
+pub struct Parser {
+    tokens: Vec<(Position, FANGrammarToken)>,
+    cursor: usize,
 }
 
 impl Parser {
-    pub fn parse(tokens: Vec<FANGrammarToken>) -> Result<(), ParseError> {
+    pub fn parse(tokens: Vec<FANGrammarToken>) -> Result<Module, ParseError> {
+        Self::parse_positioned(tokens.into_iter().map(|t| (Position::default(), t)).collect())
+    }
+
+    pub fn parse_positioned(tokens: Vec<(Position, FANGrammarToken)>) -> Result<Module, ParseError> {
+        let mut parser = Parser { tokens, cursor: 0 };
         let mut expressions: Vec<Expression> = vec![];
-        for tok in tokens.iter() {
-            let expanded = if let Some(lexp) = expressions.last_mut() {
-                lexp.expand(&tok)?
-            } else { false };
-            if !expanded {
-                let mut s = Expression::default();
-                if s.expand(&tok)? {
-                    expressions.push(s);   
+        while parser.peek().is_some() {
+            expressions.push(parser.item()?);
+        }
+        Ok(Module { expressions })
+    }
+
+    /// Lexes and parses the source text
+    pub fn parse_str(src: &str) -> Result<Module, ParseError> {
+        Self::parse_positioned(Lexer::lex_str(src)?)
+    }
+
+    /* ---------------------------- token helpers ---------------------------- */
+
+    fn peek(&self) -> Option<&FANGrammarToken> {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, n: usize) -> Option<&FANGrammarToken> {
+        self.tokens.get(self.cursor + n).map(|(_, t)| t)
+    }
+
+    fn position(&self) -> Position {
+        self.tokens
+            .get(self.cursor)
+            .or(self.tokens.last())
+            .map(|(p, _)| *p)
+            .unwrap_or_default()
+    }
+
+    fn next(&mut self) -> Result<FANGrammarToken, ParseError> {
+        let t = self.tokens.get(self.cursor).map(|(_, t)| t.clone()).ok_or(ParseError::UnexpectedEnd)?;
+        self.cursor += 1;
+        Ok(t)
+    }
+
+    fn unexpected(&self) -> ParseError {
+        match self.peek() {
+            Some(t) => ParseError::Unexpected(t.clone(), self.position()),
+            None => ParseError::UnexpectedEnd,
+        }
+    }
+
+    fn is_op(&self, op: &str) -> bool {
+        matches!(self.peek(), Some(FANGrammarToken::Operational(o)) if o.as_str() == op)
+    }
+
+    fn is_symbol(&self, s: BlockSymbol) -> bool {
+        matches!(self.peek(), Some(FANGrammarToken::BlockSymbol(b)) if *b == s)
+    }
+
+    fn is_reserved(&self, r: FANReserved) -> bool {
+        matches!(self.peek(), Some(FANGrammarToken::Reserved(x)) if *x == r)
+    }
+
+    fn is_name(&self, n: &str) -> bool {
+        matches!(self.peek(), Some(FANGrammarToken::Name(x)) if x == n)
+    }
+
+    fn eat_op(&mut self, op: &str) -> bool {
+        if self.is_op(op) { self.cursor += 1; true } else { false }
+    }
+
+    fn eat_symbol(&mut self, s: BlockSymbol) -> bool {
+        if self.is_symbol(s) { self.cursor += 1; true } else { false }
+    }
+
+    fn eat_reserved(&mut self, r: FANReserved) -> bool {
+        if self.is_reserved(r) { self.cursor += 1; true } else { false }
+    }
+
+    fn expect_op(&mut self, op: &'static str) -> Result<(), ParseError> {
+        if self.eat_op(op) { Ok(()) } else { Err(ParseError::Expected(op, self.position())) }
+    }
+
+    fn expect_symbol(&mut self, s: BlockSymbol, what: &'static str) -> Result<(), ParseError> {
+        if self.eat_symbol(s) { Ok(()) } else { Err(ParseError::Expected(what, self.position())) }
+    }
+
+    fn expect_name(&mut self) -> Result<String, ParseError> {
+        match self.peek() {
+            Some(FANGrammarToken::Name(n)) => {
+                let n = n.clone();
+                self.cursor += 1;
+                Ok(n)
+            },
+            Some(_) => Err(ParseError::Expected("name", self.position())),
+            None => Err(ParseError::UnexpectedEnd),
+        }
+    }
+
+    /// Statement terminator: `;` is optional right before the end of the block
+    fn end_of_statement(&mut self) -> Result<(), ParseError> {
+        if self.eat_op(";") || self.is_symbol(BlockSymbol::BlockBracketClose) {
+            Ok(())
+        } else {
+            Err(ParseError::Expected(";", self.position()))
+        }
+    }
+
+    /* ---------------------------- declarations ----------------------------- */
+
+    fn item(&mut self) -> Result<Expression, ParseError> {
+        let position = self.position();
+        match self.peek() {
+            Some(FANGrammarToken::Reserved(FANReserved::Upload)) => self.upload(),
+            Some(FANGrammarToken::Reserved(FANReserved::AutomataDeclare)) => {
+                let a = self.automata()?;
+                Ok(Expression::new(ExpressionType::Definition(DefinitionExp::Automata(Box::new(a))), position))
+            },
+            Some(FANGrammarToken::Reserved(FANReserved::FunctionDeclare)) => {
+                let f = self.function()?;
+                Ok(Expression::new(ExpressionType::Definition(DefinitionExp::Function(Box::new(f))), position))
+            },
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /// `upload A, B from path/file.fan`
+    fn upload(&mut self) -> Result<Expression, ParseError> {
+        let position = self.position();
+        self.next()?;
+        let mut imports = Imports::default();
+        loop {
+            imports.names.push(self.expect_name()?);
+            if !self.eat_op(",") { break; }
+        }
+        if !self.eat_reserved(FANReserved::From) {
+            return Err(ParseError::Expected("from", self.position()));
+        }
+        if let Some(FANGrammarToken::StringLiteral(s)) = self.peek() {
+            imports.from = s.clone();
+            self.cursor += 1;
+        } else {
+            // raw path: every token up to `;` or next declaration
+            loop {
+                match self.peek() {
+                    None | Some(FANGrammarToken::Reserved(_)) => break,
+                    Some(FANGrammarToken::Operational(o)) if o.as_str() == ";" => break,
+                    Some(FANGrammarToken::Name(s)) | Some(FANGrammarToken::Digital(s)) | Some(FANGrammarToken::NumericalLexem(s)) => {
+                        imports.from.push_str(s);
+                    },
+                    Some(FANGrammarToken::Operational(o)) => imports.from.push_str(o.as_str()),
+                    Some(_) => return Err(self.unexpected()),
+                }
+                self.cursor += 1;
+            }
+        }
+        if imports.from.is_empty() {
+            return Err(ParseError::Expected("path", self.position()));
+        }
+        self.eat_op(";");
+        Ok(Expression::new(ExpressionType::Import(imports), position))
+    }
+
+    /// `automata Name: Mealy<signal, output> { ... }`
+    fn automata(&mut self) -> Result<AutomataDef, ParseError> {
+        let position = self.position();
+        self.next()?;
+        let name = self.expect_name()?;
+        let mut kind = AutomataKind::Moore;
+        if self.eat_op(":") {
+            let kind_position = self.position();
+            match self.expect_name()?.as_str() {
+                "Moore" => {},
+                "Mealy" => {
+                    let mut signal = "signal".to_string();
+                    let mut output = None;
+                    if self.eat_op("<") {
+                        signal = self.expect_name()?;
+                        if self.eat_op(",") {
+                            output = Some(self.expect_name()?);
+                        }
+                        self.expect_op(">")?;
+                    }
+                    kind = AutomataKind::Mealy(signal, output);
+                },
+                _ => return Err(ParseError::Expected("Moore or Mealy", kind_position)),
+            }
+        }
+        self.expect_symbol(BlockSymbol::BlockBracketOpen, "{")?;
+        let mut automata = AutomataDef { name, kind, states: vec![], functions: vec![], position };
+        while !self.eat_symbol(BlockSymbol::BlockBracketClose) {
+            match self.peek() {
+                Some(FANGrammarToken::Reserved(FANReserved::StateDeclare)) => {
+                    let s = self.state(false)?;
+                    automata.states.push(s);
+                },
+                // `initial state Name { ... }` overrides the first state as the start
+                Some(FANGrammarToken::Name(n)) if n == "initial" && self.peek_at(1) == Some(&FANGrammarToken::Reserved(FANReserved::StateDeclare)) => {
+                    self.next()?;
+                    let s = self.state(true)?;
+                    automata.states.push(s);
+                },
+                Some(FANGrammarToken::Reserved(FANReserved::FunctionDeclare)) => {
+                    let f = self.function()?;
+                    automata.functions.push(f);
+                },
+                _ => return Err(self.unexpected()),
+            }
+        }
+        Ok(automata)
+    }
+
+    /// `state Name<a: int64, b> { ... }`
+    fn state(&mut self, initial: bool) -> Result<StateDef, ParseError> {
+        self.next()?;
+        let position = self.position();
+        let name = self.expect_name()?;
+        let mut params = vec![];
+        if self.eat_op("<") {
+            params = self.params(|p| p.is_op(">"))?;
+            self.expect_op(">")?;
+        }
+        let body = self.block()?;
+        Ok(StateDef { name, params, body, initial, position })
+    }
+
+    /// `fn name(a: int64, b: char) -> int64 { ... }`
+    fn function(&mut self) -> Result<FunctionDef, ParseError> {
+        self.next()?;
+        let position = self.position();
+        let name = self.expect_name()?;
+        self.expect_symbol(BlockSymbol::TupleBracketOpen, "(")?;
+        let params = self.params(|p| p.is_symbol(BlockSymbol::TupleBracketClose))?;
+        self.expect_symbol(BlockSymbol::TupleBracketClose, ")")?;
+        let returns = if self.eat_op("->") { Some(self.fan_type()?) } else { None };
+        let body = self.block()?;
+        Ok(FunctionDef { name, params, returns, body, position })
+    }
+
+    fn params(&mut self, end: impl Fn(&Self) -> bool) -> Result<Vec<Param>, ParseError> {
+        let mut params = vec![];
+        while !end(self) {
+            let position = self.position();
+            let name = self.expect_name()?;
+            let ty = if self.eat_op(":") { Some(self.fan_type()?) } else { None };
+            params.push(Param { name, ty, position });
+            if !self.eat_op(",") { break; }
+        }
+        Ok(params)
+    }
+
+    fn fan_type(&mut self) -> Result<FANType, ParseError> {
+        if self.eat_symbol(BlockSymbol::TupleBracketOpen) {
+            let mut v = vec![];
+            while !self.is_symbol(BlockSymbol::TupleBracketClose) {
+                v.push(self.fan_type()?);
+                if !self.eat_op(",") { break; }
+            }
+            self.expect_symbol(BlockSymbol::TupleBracketClose, ")")?;
+            Ok(FANType::Tuple(v))
+        } else {
+            let mut name = self.expect_name()?;
+            while self.eat_op("::") {
+                name.push_str("::");
+                name.push_str(&self.expect_name()?);
+            }
+            Ok(FANType::Named(name))
+        }
+    }
+
+    /* ------------------------------ statements ----------------------------- */
+
+    fn block(&mut self) -> Result<Block, ParseError> {
+        self.expect_symbol(BlockSymbol::BlockBracketOpen, "{")?;
+        let mut block = Block::default();
+        while !self.eat_symbol(BlockSymbol::BlockBracketClose) {
+            if self.peek().is_none() { return Err(ParseError::UnexpectedEnd); }
+            if self.eat_op(";") { continue; }
+            block.block.push(self.body_expression()?);
+        }
+        Ok(block)
+    }
+
+    fn body_expression(&mut self) -> Result<Expression, ParseError> {
+        let position = self.position();
+        match self.peek() {
+            Some(FANGrammarToken::Reserved(FANReserved::LocVarDeclare)) => {
+                self.next()?;
+                let name = self.expect_name()?;
+                let ty = if self.eat_op(":") { Some(self.fan_type()?) } else { None };
+                self.expect_op("=")?;
+                let value = self.expression()?;
+                self.end_of_statement()?;
+                Ok(Expression::new(
+                    ExpressionType::Definition(DefinitionExp::Define(Box::new(Define { name, ty, value }))),
+                    position
+                ))
+            },
+            Some(FANGrammarToken::Reserved(FANReserved::LinkDeclare)) => {
+                self.next()?;
+                let from = self.expect_name()?;
+                self.expect_op("->")?;
+                let target = if self.eat_reserved(FANReserved::NULL) {
+                    None
                 } else {
-                    panic!("Invalid empty expression implementation");
+                    let name = self.expect_name()?;
+                    let template = self.template()?;
+                    Some(SingleName(name, template))
+                };
+                self.end_of_statement()?;
+                Ok(Expression::new(
+                    ExpressionType::Procedural(ProceduralExp::Link(Box::new(LinkExp { from, target }))),
+                    position
+                ))
+            },
+            Some(FANGrammarToken::Reserved(FANReserved::While)) => {
+                self.next()?;
+                let condition = self.condition()?;
+                let body = self.block()?;
+                Ok(Expression::new(
+                    ExpressionType::Procedural(ProceduralExp::While(Box::new(WhileExp { condition, body }))),
+                    position
+                ))
+            },
+            Some(FANGrammarToken::Reserved(FANReserved::For)) => {
+                self.next()?;
+                let variable = self.expect_name()?;
+                if !self.is_name("in") {
+                    return Err(ParseError::Expected("in", self.position()));
                 }
+                self.next()?;
+                let iterable = self.condition()?;
+                let body = self.block()?;
+                Ok(Expression::new(
+                    ExpressionType::Procedural(ProceduralExp::For(Box::new(ForExp { variable, iterable, body }))),
+                    position
+                ))
+            },
+            Some(FANGrammarToken::Reserved(FANReserved::Return)) => {
+                self.next()?;
+                let value = if self.is_op(";") || self.is_symbol(BlockSymbol::BlockBracketClose) {
+                    None
+                } else {
+                    Some(Box::new(self.expression()?))
+                };
+                self.end_of_statement()?;
+                Ok(Expression::new(ExpressionType::Procedural(ProceduralExp::Return(value)), position))
+            },
+            Some(FANGrammarToken::Reserved(FANReserved::If)) => self.if_expression(),
+            Some(FANGrammarToken::Reserved(FANReserved::Match)) => {
+                let m = self.match_expression()?;
+                self.eat_op(";");
+                Ok(m)
+            },
+            Some(FANGrammarToken::BlockSymbol(BlockSymbol::BlockBracketOpen)) => {
+                let b = self.block()?;
+                Ok(Expression::statement(Statement::Block(Box::new(b)), position))
+            },
+            _ => {
+                let e = self.expression()?;
+                self.end_of_statement()?;
+                Ok(e)
+            },
+        }
+    }
+
+    /// Optional `<args>` after the name
+    fn template(&mut self) -> Result<Option<TypeTemplate>, ParseError> {
+        if !self.eat_op("<") {
+            return Ok(None);
+        }
+        let mut template = TypeTemplate::default();
+        while !self.is_op(">") {
+            template.args.push(self.binary(TEMPLATE_PRECEDENCE)?);
+            if !self.eat_op(",") { break; }
+        }
+        self.expect_op(">")?;
+        Ok(Some(template))
+    }
+
+    fn if_expression(&mut self) -> Result<Expression, ParseError> {
+        let position = self.position();
+        self.next()?;
+        let condition = self.condition()?;
+        let then = self.block()?;
+        let otherwise = if self.eat_reserved(FANReserved::Else) {
+            if self.is_reserved(FANReserved::If) {
+                Some(Block { block: vec![self.if_expression()?] })
+            } else {
+                Some(self.block()?)
             }
+        } else {
+            None
         };
+        Ok(Expression::returnable(ReturnableExp::If(Box::new(IfExp { condition, then, otherwise })), position))
+    }
 
-        Ok(())
+    /// `match value { Pattern => expression, ... }`
+    fn match_expression(&mut self) -> Result<Expression, ParseError> {
+        let position = self.position();
+        self.next()?;
+        let value = self.condition()?;
+        self.expect_symbol(BlockSymbol::BlockBracketOpen, "{")?;
+        let mut arms = vec![];
+        while !self.eat_symbol(BlockSymbol::BlockBracketClose) {
+            let position = self.position();
+            let pattern = self.pattern()?;
+            self.expect_op("=>")?;
+            let body_is_block = self.is_symbol(BlockSymbol::BlockBracketOpen);
+            let body = self.expression()?;
+            arms.push(MatchArm { pattern, body, position });
+            if !self.eat_op(",") && !body_is_block && !self.is_symbol(BlockSymbol::BlockBracketClose) {
+                return Err(ParseError::Expected(",", self.position()));
+            }
+        }
+        Ok(Expression::returnable(ReturnableExp::Match(Box::new(MatchExp { value, arms })), position))
+    }
+
+    fn pattern(&mut self) -> Result<Pattern, ParseError> {
+        let token = self.peek().cloned().ok_or(ParseError::UnexpectedEnd)?;
+        match token {
+            FANGrammarToken::Name(n) if n == "_" => {
+                self.next()?;
+                Ok(Pattern::Wildcard)
+            },
+            FANGrammarToken::Name(n) if n == "true" || n == "false" => {
+                self.next()?;
+                Ok(Pattern::Literal(Literal::Bool(n == "true")))
+            },
+            FANGrammarToken::Name(n) => {
+                self.next()?;
+                let mut path = vec![n];
+                while self.eat_op("::") {
+                    path.push(self.expect_name()?);
+                }
+                let args = if self.eat_symbol(BlockSymbol::TupleBracketOpen) {
+                    let mut args = vec![];
+                    while !self.is_symbol(BlockSymbol::TupleBracketClose) {
+                        args.push(self.pattern()?);
+                        if !self.eat_op(",") { break; }
+                    }
+                    self.expect_symbol(BlockSymbol::TupleBracketClose, ")")?;
+                    Some(args)
+                } else {
+                    None
+                };
+                if path.len() == 1 && args.is_none() {
+                    return Ok(Pattern::Binding(path.pop().unwrap()));
+                }
+                let state = path.pop().unwrap();
+                let automata = if path.is_empty() { None } else { Some(path.join("::")) };
+                Ok(Pattern::State { automata, state, args })
+            },
+            FANGrammarToken::BlockSymbol(BlockSymbol::TupleBracketOpen) => {
+                self.next()?;
+                let mut v = vec![];
+                let mut trailing_comma = false;
+                while !self.is_symbol(BlockSymbol::TupleBracketClose) {
+                    v.push(self.pattern()?);
+                    trailing_comma = self.eat_op(",");
+                    if !trailing_comma { break; }
+                }
+                self.expect_symbol(BlockSymbol::TupleBracketClose, ")")?;
+                if v.len() == 1 && !trailing_comma {
+                    return Ok(v.pop().unwrap());
+                }
+                Ok(Pattern::Tuple(v))
+            },
+            FANGrammarToken::Operational(o) if o.as_str() == "-" => {
+                self.next()?;
+                match self.pattern()? {
+                    Pattern::Literal(Literal::Digital(d)) => Ok(Pattern::Literal(Literal::Digital(format!("-{}", d)))),
+                    Pattern::Literal(Literal::NumericalLexem(d)) => Ok(Pattern::Literal(Literal::NumericalLexem(format!("-{}", d)))),
+                    _ => Err(ParseError::Expected("number", self.position())),
+                }
+            },
+            FANGrammarToken::Digital(_) | FANGrammarToken::NumericalLexem(_) | FANGrammarToken::CharLiteral(_)
+            | FANGrammarToken::StringLiteral(_) | FANGrammarToken::Reserved(FANReserved::NULL) => {
+                match self.primary()?.kind {
+                    ExpressionType::Returnable(ReturnableExp::Statement(Statement::Literal(l))) => Ok(Pattern::Literal(l)),
+                    _ => Err(ParseError::Expected("literal", self.position())),
+                }
+            },
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /* ----------------------------- expressions ----------------------------- */
+
+    /// Conditions of `if`/`while` are plain expressions, the block starts after them
+    fn condition(&mut self) -> Result<Expression, ParseError> {
+        self.binary(0)
+    }
+
+    pub fn expression(&mut self) -> Result<Expression, ParseError> {
+        let position = self.position();
+        let target = self.binary(0)?;
+        if let Some(FANGrammarToken::Operational(op)) = self.peek()
+            && ASSIGNMENTS.contains(&op.as_str()) {
+            let operator = op.clone();
+            self.next()?;
+            let value = self.expression()?;
+            return Ok(Expression::returnable(
+                ReturnableExp::BinaryOperator(Box::new(BinaryOperator { arg1: target, arg2: value, operator })),
+                position
+            ));
+        }
+        Ok(target)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expression, ParseError> {
+        if level >= PRECEDENCE.len() {
+            return self.unary();
+        }
+        let position = self.position();
+        let mut left = self.binary(level + 1)?;
+        loop {
+            if level == COMPARISON_PRECEDENCE && self.eat_reserved(FANReserved::Is) {
+                let pattern = self.pattern()?;
+                left = Expression::returnable(ReturnableExp::Is(Box::new(left), Box::new(pattern)), position);
+                continue;
+            }
+            let operator = match self.peek() {
+                Some(FANGrammarToken::Operational(op)) if PRECEDENCE[level].contains(&op.as_str()) => op.clone(),
+                _ => break,
+            };
+            self.next()?;
+            let right = self.binary(level + 1)?;
+            left = Expression::returnable(
+                ReturnableExp::BinaryOperator(Box::new(BinaryOperator { arg1: left, arg2: right, operator })),
+                position
+            );
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expression, ParseError> {
+        let position = self.position();
+        if let Some(FANGrammarToken::Operational(op)) = self.peek()
+            && ["-", "!", "~"].contains(&op.as_str()) {
+            let operator = op.clone();
+            self.next()?;
+            let arg = self.unary()?;
+            return Ok(Expression::returnable(
+                ReturnableExp::UnaryyOperator(Box::new(UnaryOperator { arg, operator })),
+                position
+            ));
+        }
+        self.postfix()
+    }
+
+    fn postfix(&mut self) -> Result<Expression, ParseError> {
+        let position = self.position();
+        let mut e = self.primary()?;
+        loop {
+            if self.is_op(".") {
+                self.next()?;
+                let member = match self.next()? {
+                    FANGrammarToken::Name(n) | FANGrammarToken::Digital(n) => n,
+                    // `self.automata`, keywords are plain names after the dot
+                    FANGrammarToken::Reserved(r) => r.as_str().to_string(),
+                    t => return Err(ParseError::Unexpected(t, self.position())),
+                };
+                e = if self.is_symbol(BlockSymbol::TupleBracketOpen) {
+                    let args = self.arguments()?;
+                    Expression::returnable(ReturnableExp::MethodCall(Box::new(e), member, args), position)
+                } else {
+                    Expression::returnable(ReturnableExp::Member(Box::new(e), member), position)
+                };
+            } else if self.eat_symbol(BlockSymbol::IndexBracketOpen) {
+                let index = self.expression()?;
+                self.expect_symbol(BlockSymbol::IndexBracketClose, "]")?;
+                e = Expression::returnable(ReturnableExp::Index(Box::new(e), Box::new(index)), position);
+            } else {
+                break;
+            }
+        }
+        Ok(e)
+    }
+
+    fn arguments(&mut self) -> Result<Tuple, ParseError> {
+        self.expect_symbol(BlockSymbol::TupleBracketOpen, "(")?;
+        let mut tuple = Tuple::default();
+        while !self.is_symbol(BlockSymbol::TupleBracketClose) {
+            tuple.tuple.push(self.expression()?);
+            if !self.eat_op(",") { break; }
+        }
+        self.expect_symbol(BlockSymbol::TupleBracketClose, ")")?;
+        Ok(tuple)
+    }
+
+    fn primary(&mut self) -> Result<Expression, ParseError> {
+        let position = self.position();
+        let token = self.peek().cloned().ok_or(ParseError::UnexpectedEnd)?;
+        match token {
+            FANGrammarToken::Name(n) => {
+                self.next()?;
+                if n == "true" || n == "false" {
+                    return Ok(Expression::statement(Statement::Literal(Literal::Bool(n == "true")), position));
+                }
+                let mut path = vec![SingleName(n, None)];
+                while self.is_op("::") {
+                    self.next()?;
+                    path.push(SingleName(self.expect_name()?, None));
+                }
+                let name = if path.len() == 1 {
+                    Name::SingleName(path.pop().unwrap())
+                } else {
+                    Name::NamespaceName(path)
+                };
+                if self.is_symbol(BlockSymbol::TupleBracketOpen) {
+                    let args = self.arguments()?;
+                    return Ok(Expression::returnable(ReturnableExp::FunctionCall(Statement::Name(name), args), position));
+                }
+                Ok(Expression::statement(Statement::Name(name), position))
+            },
+            FANGrammarToken::Digital(d) => {
+                self.next()?;
+                // `1.5`, `1.5f32` and `1.f32` are split by the lexer
+                if self.is_op(".") {
+                    let fraction = match self.peek_at(1) {
+                        Some(FANGrammarToken::Digital(f)) | Some(FANGrammarToken::NumericalLexem(f)) => Some(f.clone()),
+                        Some(FANGrammarToken::Name(f)) if f == "f32" || f == "f64" => Some(f.clone()),
+                        _ => None,
+                    };
+                    if let Some(f) = fraction {
+                        self.cursor += 2;
+                        return Ok(Expression::statement(
+                            Statement::Literal(Literal::NumericalLexem(format!("{}.{}", d, f))),
+                            position
+                        ));
+                    }
+                }
+                Ok(Expression::statement(Statement::Literal(Literal::Digital(d)), position))
+            },
+            FANGrammarToken::NumericalLexem(n) => {
+                self.next()?;
+                Ok(Expression::statement(Statement::Literal(Literal::NumericalLexem(n)), position))
+            },
+            FANGrammarToken::CharLiteral(c) => {
+                self.next()?;
+                Ok(Expression::statement(Statement::Literal(Literal::Char(c)), position))
+            },
+            FANGrammarToken::StringLiteral(s) => {
+                self.next()?;
+                Ok(Expression::statement(Statement::Literal(Literal::String(s)), position))
+            },
+            FANGrammarToken::Reserved(FANReserved::NULL) => {
+                self.next()?;
+                Ok(Expression::statement(Statement::Literal(Literal::NULL), position))
+            },
+            FANGrammarToken::Reserved(FANReserved::If) => self.if_expression(),
+            FANGrammarToken::Reserved(FANReserved::Match) => self.match_expression(),
+            FANGrammarToken::Reserved(FANReserved::Run) => {
+                self.next()?;
+                let name = self.expect_name()?;
+                let template = self.template()?;
+                Ok(Expression::returnable(ReturnableExp::AutomataCall(SingleName(name, template)), position))
+            },
+            FANGrammarToken::BlockSymbol(BlockSymbol::BlockBracketOpen) => {
+                let b = self.block()?;
+                Ok(Expression::statement(Statement::Block(Box::new(b)), position))
+            },
+            FANGrammarToken::BlockSymbol(BlockSymbol::TupleBracketOpen) => {
+                self.next()?;
+                let mut tuple = Tuple::default();
+                let mut trailing_comma = false;
+                while !self.is_symbol(BlockSymbol::TupleBracketClose) {
+                    tuple.tuple.push(self.expression()?);
+                    trailing_comma = self.eat_op(",");
+                    if !trailing_comma { break; }
+                }
+                self.expect_symbol(BlockSymbol::TupleBracketClose, ")")?;
+                if tuple.tuple.len() == 1 && !trailing_comma {
+                    // just brackets
+                    return Ok(tuple.tuple.pop().unwrap());
+                }
+                Ok(Expression::statement(Statement::Tuple(tuple), position))
+            },
+            _ => Err(self.unexpected()),
+        }
+    }
+}
+
+#[test]
+fn parse_function_test() {
+    let module = Parser::parse_str("
+        fn fib(n: int64) -> int64 {
+            if n < 2 { return n; }
+            return fib(n - 1) + fib(n - 2);
+        }
+
+        automata A {
+            state Base<x: int64> {
+                link self -> Base<fib(x), (1, 2)>;
+            }
+        }
+    ").unwrap();
+    let fib = module.find_function("fib").unwrap();
+    assert_eq!(fib.params.len(), 1);
+    assert_eq!(fib.returns, Some(FANType::Named("int64".to_string())));
+    assert_eq!(fib.body.block.len(), 2);
+    let a = module.find_automata("A").unwrap();
+    match &a.states[0].body.block[0].kind {
+        ExpressionType::Procedural(ProceduralExp::Link(l)) => {
+            let target = l.target.as_ref().unwrap();
+            assert_eq!(target.0, "Base");
+            assert_eq!(target.1.as_ref().unwrap().args.len(), 2);
+            assert!(matches!(
+                target.1.as_ref().unwrap().args[0].kind,
+                ExpressionType::Returnable(ReturnableExp::FunctionCall(..))
+            ));
+        },
+        e => panic!("link expected, got {:?}", e),
+    }
+}
+
+#[test]
+fn parse_errors_test() {
+    assert!(matches!(Parser::parse_str("fn f(a: int64 { }"), Err(ParseError::Expected(")", _))));
+    assert!(matches!(Parser::parse_str("automata A { state B { let x = 1 link self -> NULL; } }"), Err(ParseError::Expected(";", _))));
+}
+
+#[test]
+fn parse_match_test() {
+    let module = Parser::parse_str("
+        fn f(v: Other, t: (int64, char)) -> int64 {
+            if v is Other::Verdict1(verd1) { return verd1.arg1; }
+            match t {
+                (0, _) => 1,
+                (-1, 'a') => { 2 }
+                (n, c) => n,
+            }
+        }
+    ").unwrap();
+    let f = module.find_function("f").unwrap();
+    match &f.body.block[0].kind {
+        ExpressionType::Returnable(ReturnableExp::If(i)) => match &i.condition.kind {
+            ExpressionType::Returnable(ReturnableExp::Is(_, p)) => assert!(matches!(
+                p.as_ref(),
+                Pattern::State { automata: Some(a), state, args: Some(args) }
+                    if a == "Other" && state == "Verdict1" && matches!(&args[..], [Pattern::Binding(b)] if b == "verd1")
+            )),
+            e => panic!("is expected, got {:?}", e),
+        },
+        e => panic!("if expected, got {:?}", e),
+    }
+    match &f.body.block[1].kind {
+        ExpressionType::Returnable(ReturnableExp::Match(m)) => {
+            assert_eq!(m.arms.len(), 3);
+            assert!(matches!(&m.arms[1].pattern, Pattern::Tuple(v) if matches!(&v[0], Pattern::Literal(Literal::Digital(d)) if d == "-1")));
+            assert!(m.arms[2].pattern.is_irrefutable());
+        },
+        e => panic!("match expected, got {:?}", e),
+    }
+}
+
+#[test]
+fn parse_run_test() {
+    let module = Parser::parse_str("
+        automata A {
+            state S<c1: int64, c2: int64> {
+                let result = run ContextAutomata<c1, c2 + 1>;
+                if result is ContextAutomata::Verdict1(v) { link self -> NULL; }
+            }
+        }
+    ").unwrap();
+    let a = module.find_automata("A").unwrap();
+    match &a.states[0].body.block[0].kind {
+        ExpressionType::Definition(DefinitionExp::Define(d)) => match &d.value.kind {
+            ExpressionType::Returnable(ReturnableExp::AutomataCall(SingleName(name, Some(t)))) => {
+                assert_eq!(name, "ContextAutomata");
+                assert_eq!(t.args.len(), 2);
+            },
+            e => panic!("run expected, got {:?}", e),
+        },
+        e => panic!("let expected, got {:?}", e),
     }
 }
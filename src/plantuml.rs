@@ -0,0 +1,81 @@
+//! PlantUML state diagrams of the automata for `fan graph --format plantuml`, one `@startuml` block per automata
+
+use crate::format::INDENT;
+use crate::graph::{AutomataGraph, Graph};
+
+/// PlantUML string literal, double quotes can't be escaped so they are replaced by single ones
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "'"))
+}
+
+/// `@startuml` block of a single automata: `[*]` starts the initial state and stands for NULL,
+/// the transitions are labelled with their guards and the states running other automata have notes
+pub fn automata_to_plantuml(a: &AutomataGraph) -> String {
+    let mut out = format!("@startuml {}\n", a.name);
+    for s in a.states.iter() {
+        if s.params.is_empty() {
+            out.push_str(&format!("state {}\n", s.name));
+        } else {
+            out.push_str(&format!("state {} as {}\n", quote(&s.label()), s.name));
+        }
+    }
+    if let Some(initial) = a.initial() {
+        out.push_str(&format!("[*] --> {}\n", initial.name));
+    }
+    for (e, label) in a.labelled_edges() {
+        out.push_str(&format!("{} --> {}", e.from, e.to.as_deref().unwrap_or("[*]")));
+        if !label.is_empty() {
+            out.push_str(&format!(" : {}", label));
+        }
+        out.push('\n');
+    }
+    for s in a.states.iter().filter(|s| !s.runs.is_empty()) {
+        out.push_str(&format!("note right of {}\n{}run {}\nend note\n", s.name, INDENT, s.runs.join(", ")));
+    }
+    out.push_str("@enduml\n");
+    out
+}
+
+/// Blocks of every automata separated by an empty line
+pub fn to_plantuml(graph: &Graph) -> String {
+    graph.automata.iter().map(automata_to_plantuml).collect::<Vec<_>>().join("\n")
+}
+
+#[test]
+fn plantuml_test() {
+    use crate::parser::Parser;
+
+    let module = Parser::parse_str("
+        automata Door {
+            state Closed<locked: bool> {
+                if locked { link self -> Closed<false>; } else if self.previous.is_me() { link self -> Open; }
+            }
+            state Open { let _ = run Timer<3>; link self -> Closed<true>; }
+        }
+        automata Timer {
+            state Tick<n: int64> { if n > 0 { link self -> Tick<n - 1>; } else { link self -> NULL; } }
+        }
+    ").unwrap();
+    assert_eq!(to_plantuml(&Graph::new(&module)), "\
+@startuml Door
+state \"Closed<locked: bool>\" as Closed
+state Open
+[*] --> Closed
+Closed --> Closed : locked
+Closed --> Open : !locked && self.previous.is_me()
+Closed --> [*]
+Open --> Closed
+note right of Open
+    run Timer
+end note
+@enduml
+
+@startuml Timer
+state \"Tick<n: int64>\" as Tick
+[*] --> Tick
+Tick --> Tick : n > 0
+Tick --> [*] : !(n > 0)
+@enduml
+");
+    assert_eq!(quote("say \"hi\""), "\"say 'hi'\"");
+}
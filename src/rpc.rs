@@ -0,0 +1,83 @@
+//! Base protocol shared by the DAP and LSP servers: JSON messages framed by the `Content-Length` header
+
+use std::io::{BufRead, Write};
+
+use crate::json::Json;
+
+/// Largest accepted message body, the `Content-Length` comes from the client
+pub const MAX_MESSAGE_LENGTH: usize = 8 << 20;
+
+#[derive(Debug)]
+pub enum RpcError {
+    Io(std::io::Error),
+    /// Message is not framed or not JSON
+    Protocol(String),
+}
+
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RpcError::Io(e) => write!(f, "{}", e),
+            RpcError::Protocol(e) => write!(f, "protocol error: {}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for RpcError {
+    fn from(e: std::io::Error) -> Self {
+        RpcError::Io(e)
+    }
+}
+
+/// Reads the message framed by the `Content-Length` header, `None` at the end of the input
+pub fn read_message(input: &mut impl BufRead) -> Result<Option<Json>, RpcError> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return match length {
+                None => Ok(None),
+                Some(_) => Err(RpcError::Protocol("unexpected end of the headers".to_string())),
+            };
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("Content-Length")
+        {
+            length = Some(value.trim().parse::<usize>().map_err(|_| RpcError::Protocol(format!("invalid header `{}`", line)))?);
+        }
+    }
+    let length = length.ok_or_else(|| RpcError::Protocol("missing Content-Length".to_string()))?;
+    if length > MAX_MESSAGE_LENGTH {
+        return Err(RpcError::Protocol(format!("message of {} bytes exceeds the limit of {} bytes", length, MAX_MESSAGE_LENGTH)));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    let text = String::from_utf8(body).map_err(|_| RpcError::Protocol("message is not UTF-8".to_string()))?;
+    Json::parse(&text).map(Some).map_err(|e| RpcError::Protocol(e.to_string()))
+}
+
+pub fn write_message(output: &mut impl Write, message: &Json) -> std::io::Result<()> {
+    let text = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", text.len(), text)?;
+    output.flush()
+}
+
+#[test]
+fn rpc_test() {
+    let mut output = vec![];
+    write_message(&mut output, &Json::object([("id", 1i64.into())])).unwrap();
+    assert_eq!(output, b"Content-Length: 8\r\n\r\n{\"id\":1}");
+    let mut input = output.as_slice();
+    assert_eq!(read_message(&mut input).unwrap(), Some(Json::object([("id", 1i64.into())])));
+    assert!(read_message(&mut input).unwrap().is_none());
+
+    // the body is not allocated for the oversized length
+    let header = format!("Content-Length: {}\r\n\r\n", MAX_MESSAGE_LENGTH + 1);
+    let error = read_message(&mut header.as_bytes()).unwrap_err();
+    assert!(matches!(&error, RpcError::Protocol(m) if m.contains("exceeds the limit")), "{}", error);
+    assert!(matches!(read_message(&mut "Content-Length: x\r\n\r\n".as_bytes()), Err(RpcError::Protocol(_))));
+}
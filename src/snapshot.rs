@@ -0,0 +1,475 @@
+//! Persisted execution of the automata: the stack of nested `run`s with the history seen by `self`.
+//!
+//! Both encodings start with [`SNAPSHOT_VERSION`] and the [`fingerprint`] of the program,
+//! restoring checks them and every stored state against the parsed module.
+
+use crate::interpreter::{Interpreter, RunFrame, StateValue, Value};
+use crate::json::Json;
+use crate::parser::{AutomataKind, Module};
+
+/// Version of the encodings, bumped on every incompatible change
+pub const SNAPSHOT_VERSION: u32 = 1;
+const MAGIC: &[u8; 4] = b"FANS";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// [`fingerprint`] of the program the snapshot was taken in
+    pub program: u64,
+    /// Running automata, the outermost first
+    pub frames: Vec<RunFrame>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnapshotError {
+    /// Malformed encoding
+    Format(String),
+    Version { expected: u32, found: u32 },
+    /// Automata, states or their parameters changed since the snapshot was taken
+    Program { expected: u64, found: u64 },
+    /// Stored frames do not fit the program
+    Mismatch(String),
+    /// Values of the uploaded types can not be persisted
+    Unserializable(String),
+}
+
+impl std::fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::Format(m) => write!(f, "malformed snapshot: {}", m),
+            SnapshotError::Version { expected, found } => write!(f, "snapshot version {} is not supported, expected {}", found, expected),
+            SnapshotError::Program { expected, found } =>
+                write!(f, "snapshot was taken in another program ({:016x}), the current one is {:016x}", found, expected),
+            SnapshotError::Mismatch(m) => write!(f, "{}", m),
+            SnapshotError::Unserializable(t) => write!(f, "value of the uploaded type `{}` can not be persisted", t),
+        }
+    }
+}
+
+/// FNV-1a hash of the automata signatures: names, kinds, states and their typed parameters.
+/// Bodies do not count, so fixed bodies still resume older snapshots.
+pub fn fingerprint(module: &Module) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut feed = |s: &str| {
+        for b in s.bytes().chain([0]) {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    };
+    for a in module.automata() {
+        feed(&a.name);
+        match &a.kind {
+            AutomataKind::Moore => feed("Moore"),
+            AutomataKind::Mealy(signal, output) => {
+                feed("Mealy");
+                feed(signal);
+                feed(output.as_deref().unwrap_or(""));
+            },
+        }
+        for s in a.states.iter() {
+            feed(&s.name);
+            for p in s.params.iter() {
+                feed(&p.name);
+                feed(&p.ty.as_ref().map(|t| t.to_string()).unwrap_or_default());
+            }
+        }
+    }
+    hash
+}
+
+impl Snapshot {
+    pub fn new(module: &Module, frames: Vec<RunFrame>) -> Self {
+        Self { program: fingerprint(module), frames }
+    }
+
+    /// Outermost automata
+    pub fn automata(&self) -> &str {
+        self.frames.first().map_or("", |f| f.current.automata.as_str())
+    }
+
+    /// Checks the snapshot can be resumed in the module
+    pub fn check(&self, module: &Module) -> Result<(), SnapshotError> {
+        let expected = fingerprint(module);
+        if self.program != expected {
+            return Err(SnapshotError::Program { expected, found: self.program });
+        }
+        if self.frames.is_empty() {
+            return Err(SnapshotError::Mismatch("snapshot has no running automata".to_string()));
+        }
+        let interpreter = Interpreter::new(module);
+        for (i, f) in self.frames.iter().enumerate() {
+            let states = std::iter::once(&f.current).chain(f.previous.iter());
+            for state in states {
+                let mismatch = |e| SnapshotError::Mismatch(format!("`{}`: {}", state, e));
+                let a = interpreter.find_automata(&state.automata).map_err(mismatch)?;
+                let args = state.args.iter().map(|(_, v)| v.clone()).collect();
+                let restored = interpreter.enter(a, &state.state, args).map_err(mismatch)?;
+                if restored != *state {
+                    return Err(SnapshotError::Mismatch(format!("`{}` does not match `{}`", state, restored)));
+                }
+            }
+            if f.previous.as_ref().is_some_and(|p| p.automata != f.current.automata) {
+                return Err(SnapshotError::Mismatch(format!("previous state of `{}` is of another automata", f.current)));
+            }
+            // the parents are inside the bodies running the nested automata
+            if i + 1 < self.frames.len() && f.runs == 0 {
+                return Err(SnapshotError::Mismatch(format!("`{}` does not run the nested automata", f.current)));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> Result<Json, SnapshotError> {
+        let frames = self.frames.iter().map(|f| Ok(Json::object([
+            ("current", state_to_json(&f.current)?),
+            ("previous", f.previous.as_ref().map(state_to_json).transpose()?.unwrap_or(Json::Null)),
+            ("steps", f.steps.into()),
+            ("runs", f.runs.into()),
+        ]))).collect::<Result<Vec<_>, SnapshotError>>()?;
+        Ok(Json::object([
+            ("version", (SNAPSHOT_VERSION as i64).into()),
+            ("program", format!("{:016x}", self.program).into()),
+            ("frames", Json::Array(frames)),
+        ]))
+    }
+
+    pub fn from_json(json: &Json) -> Result<Snapshot, SnapshotError> {
+        let found = json.get("version").and_then(Json::as_i64).ok_or_else(|| format_error("`version` is missing"))?;
+        if found != SNAPSHOT_VERSION as i64 {
+            return Err(SnapshotError::Version { expected: SNAPSHOT_VERSION, found: found as u32 });
+        }
+        let program = json.get("program").and_then(Json::as_str).and_then(|p| u64::from_str_radix(p, 16).ok())
+            .ok_or_else(|| format_error("`program` is missing"))?;
+        let frames = json.get("frames").and_then(Json::as_array).ok_or_else(|| format_error("`frames` is missing"))?;
+        let frames = frames.iter().map(|f| {
+            let count = |key| f.get(key).and_then(Json::as_i64).and_then(|n| usize::try_from(n).ok())
+                .ok_or_else(|| format_error(&format!("`{}` is missing", key)));
+            Ok(RunFrame {
+                current: state_from_json(f.get("current").ok_or_else(|| format_error("`current` is missing"))?)?,
+                previous: match f.get("previous") {
+                    None | Some(Json::Null) => None,
+                    Some(p) => Some(state_from_json(p)?),
+                },
+                steps: count("steps")?,
+                runs: count("runs")?,
+            })
+        }).collect::<Result<_, SnapshotError>>()?;
+        Ok(Snapshot { program, frames })
+    }
+
+    /// Compact binary encoding: the magic `FANS`, the version and the little endian fields
+    pub fn to_bytes(&self) -> Result<Vec<u8>, SnapshotError> {
+        let mut w = Writer(MAGIC.to_vec());
+        w.u32(SNAPSHOT_VERSION);
+        w.u64(self.program);
+        w.u32(self.frames.len() as u32);
+        for f in self.frames.iter() {
+            w.state(&f.current)?;
+            match &f.previous {
+                Some(p) => {
+                    w.0.push(1);
+                    w.state(p)?;
+                },
+                None => w.0.push(0),
+            }
+            w.u64(f.steps as u64);
+            w.u64(f.runs as u64);
+        }
+        Ok(w.0)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot, SnapshotError> {
+        let mut r = Reader { bytes, offset: 0 };
+        if r.take(4)? != MAGIC {
+            return Err(format_error("not a FAN snapshot"));
+        }
+        let found = r.u32()?;
+        if found != SNAPSHOT_VERSION {
+            return Err(SnapshotError::Version { expected: SNAPSHOT_VERSION, found });
+        }
+        let program = r.u64()?;
+        let mut frames = vec![];
+        for _ in 0..r.u32()? {
+            let current = r.state()?;
+            let previous = match r.u8()? {
+                0 => None,
+                _ => Some(r.state()?),
+            };
+            frames.push(RunFrame { current, previous, steps: r.u64()? as usize, runs: r.u64()? as usize });
+        }
+        if r.offset != bytes.len() {
+            return Err(format_error("trailing bytes"));
+        }
+        Ok(Snapshot { program, frames })
+    }
+}
+
+fn format_error(message: &str) -> SnapshotError {
+    SnapshotError::Format(message.to_string())
+}
+
+pub fn state_to_json(s: &StateValue) -> Result<Json, SnapshotError> {
+    let args = s.args.iter()
+        .map(|(name, v)| Ok(Json::Array(vec![name.as_str().into(), value_to_json(v)?])))
+        .collect::<Result<Vec<_>, SnapshotError>>()?;
+    Ok(Json::object([
+        ("automata", s.automata.as_str().into()),
+        ("state", s.state.as_str().into()),
+        ("args", Json::Array(args)),
+    ]))
+}
+
+fn state_from_json(json: &Json) -> Result<StateValue, SnapshotError> {
+    let field = |key| json.get(key).and_then(Json::as_str).map(str::to_string)
+        .ok_or_else(|| format_error(&format!("`{}` of the state is missing", key)));
+    let args = json.get("args").and_then(Json::as_array).ok_or_else(|| format_error("`args` of the state are missing"))?;
+    let args = args.iter().map(|a| match a.as_array() {
+        Some([Json::String(name), v]) => Ok((name.clone(), value_from_json(v)?)),
+        _ => Err(format_error("argument is not a pair of the name and the value")),
+    }).collect::<Result<_, SnapshotError>>()?;
+    Ok(StateValue { automata: field("automata")?, state: field("state")?, args })
+}
+
+/// Values are objects tagged with their type, `null` is NULL
+pub fn value_to_json(v: &Value) -> Result<Json, SnapshotError> {
+    Ok(match v {
+        Value::Int(i) => Json::object([("int", (*i).into())]),
+        // non finite floats are not JSON numbers
+        Value::Float(f) if f.is_finite() => Json::object([("float", Json::Number(format!("{:?}", f)))]),
+        Value::Float(f) => Json::object([("float", f.to_string().into())]),
+        Value::Bool(b) => Json::object([("bool", (*b).into())]),
+        Value::Char(c) => Json::object([("char", c.to_string().into())]),
+        Value::String(s) => Json::object([("string", s.as_str().into())]),
+        Value::Tuple(t) => Json::object([("tuple", Json::Array(t.iter().map(value_to_json).collect::<Result<_, _>>()?))]),
+        Value::State(s) => Json::object([("state", state_to_json(s)?)]),
+        Value::Host(h) => return Err(SnapshotError::Unserializable(h.type_name().to_string())),
+        Value::Null => Json::Null,
+    })
+}
+
+pub fn value_from_json(json: &Json) -> Result<Value, SnapshotError> {
+    let invalid = || format_error(&format!("invalid value `{}`", json));
+    let Json::Object(fields) = json else {
+        return if json.is_null() { Ok(Value::Null) } else { Err(invalid()) };
+    };
+    let [(tag, v)] = fields.as_slice() else {
+        return Err(invalid());
+    };
+    Ok(match (tag.as_str(), v) {
+        ("int", v) => Value::Int(v.as_i64().ok_or_else(invalid)?),
+        ("float", Json::String(s)) => Value::Float(s.parse().map_err(|_| invalid())?),
+        ("float", v) => Value::Float(v.as_f64().ok_or_else(invalid)?),
+        ("bool", v) => Value::Bool(v.as_bool().ok_or_else(invalid)?),
+        ("char", v) => {
+            let mut chars = v.as_str().ok_or_else(invalid)?.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Value::Char(c),
+                _ => return Err(invalid()),
+            }
+        },
+        ("string", v) => Value::String(v.as_str().ok_or_else(invalid)?.to_string()),
+        ("tuple", v) => Value::Tuple(v.as_array().ok_or_else(invalid)?.iter().map(value_from_json).collect::<Result<_, _>>()?),
+        ("state", v) => Value::State(Box::new(state_from_json(v)?)),
+        _ => return Err(invalid()),
+    })
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u32(&mut self, v: u32) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn str(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.0.extend_from_slice(s.as_bytes());
+    }
+
+    fn state(&mut self, s: &StateValue) -> Result<(), SnapshotError> {
+        self.str(&s.automata);
+        self.str(&s.state);
+        self.u32(s.args.len() as u32);
+        for (name, v) in s.args.iter() {
+            self.str(name);
+            self.value(v)?;
+        }
+        Ok(())
+    }
+
+    fn value(&mut self, v: &Value) -> Result<(), SnapshotError> {
+        match v {
+            Value::Int(i) => {
+                self.0.push(0);
+                self.u64(*i as u64);
+            },
+            Value::Float(f) => {
+                self.0.push(1);
+                self.u64(f.to_bits());
+            },
+            Value::Bool(b) => self.0.extend([2, *b as u8]),
+            Value::Char(c) => {
+                self.0.push(3);
+                self.u32(*c as u32);
+            },
+            Value::String(s) => {
+                self.0.push(4);
+                self.str(s);
+            },
+            Value::Tuple(t) => {
+                self.0.push(5);
+                self.u32(t.len() as u32);
+                for v in t {
+                    self.value(v)?;
+                }
+            },
+            Value::State(s) => {
+                self.0.push(6);
+                self.state(s)?;
+            },
+            Value::Null => self.0.push(7),
+            Value::Host(h) => return Err(SnapshotError::Unserializable(h.type_name().to_string())),
+        }
+        Ok(())
+    }
+}
+
+struct Reader<'b> {
+    bytes: &'b [u8],
+    offset: usize,
+}
+
+impl<'b> Reader<'b> {
+    fn take(&mut self, n: usize) -> Result<&'b [u8], SnapshotError> {
+        let bytes = self.bytes.get(self.offset..self.offset + n).ok_or_else(|| format_error("unexpected end"))?;
+        self.offset += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().expect("4 bytes are taken")))
+    }
+
+    fn u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().expect("8 bytes are taken")))
+    }
+
+    fn str(&mut self) -> Result<String, SnapshotError> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| format_error("invalid UTF-8"))
+    }
+
+    fn state(&mut self) -> Result<StateValue, SnapshotError> {
+        let automata = self.str()?;
+        let state = self.str()?;
+        let mut args = vec![];
+        for _ in 0..self.u32()? {
+            args.push((self.str()?, self.value()?));
+        }
+        Ok(StateValue { automata, state, args })
+    }
+
+    fn value(&mut self) -> Result<Value, SnapshotError> {
+        Ok(match self.u8()? {
+            0 => Value::Int(self.u64()? as i64),
+            1 => Value::Float(f64::from_bits(self.u64()?)),
+            2 => Value::Bool(self.u8()? != 0),
+            3 => Value::Char(char::from_u32(self.u32()?).ok_or_else(|| format_error("invalid char"))?),
+            4 => Value::String(self.str()?),
+            5 => {
+                let mut t = vec![];
+                for _ in 0..self.u32()? {
+                    t.push(self.value()?);
+                }
+                Value::Tuple(t)
+            },
+            6 => Value::State(Box::new(self.state()?)),
+            7 => Value::Null,
+            tag => return Err(format_error(&format!("unknown value tag {}", tag))),
+        })
+    }
+}
+
+#[test]
+fn snapshot_test() {
+    use crate::interpreter::{ExecError, MealyHalt, Progress};
+    use crate::parser::Parser;
+    let source = "
+        automata Inner {
+            state Count<k: int64, limit: int64> {
+                if k < limit { link self -> Count<k + 1, limit>; }
+            }
+        }
+        automata Outer {
+            state Loop<n: int64, total: int64> {
+                let warm = run Inner<0, 1>;
+                let r = run Inner<0, n>;
+                if n < 4 { link self -> Loop<n + 1, total + r.k + warm.k + self.steps>; }
+            }
+        }
+        automata Digits: Mealy<signal, out> {
+            state Read<signal: char, acc: int64> {
+                out = acc * 10 + (signal - '0');
+                link self -> Read<out>;
+            }
+        }
+    ";
+    let module = Parser::parse_str(source).unwrap();
+    let args = || vec![Value::Int(0), Value::Int(0)];
+    let expected = Interpreter::new(&module).run_automata("Outer", args()).unwrap();
+
+    let mut interpreter = Interpreter::new(&module);
+    let mut progress = interpreter.run_for("Outer", args(), 1).unwrap();
+    let (mut pauses, mut nested) = (0, 0);
+    let done = loop {
+        let snapshot = match progress {
+            Progress::Done(state) => break state,
+            Progress::Paused(snapshot) => snapshot,
+        };
+        pauses += 1;
+        if snapshot.frames.len() == 2 && snapshot.frames[0].runs == 2 {
+            nested += 1;
+        }
+        // a restart in between
+        let restored = if pauses % 2 == 0 {
+            Snapshot::from_bytes(&snapshot.to_bytes().unwrap()).unwrap()
+        } else {
+            Snapshot::from_json(&Json::parse(&snapshot.to_json().unwrap().to_string()).unwrap()).unwrap()
+        };
+        assert_eq!(restored, *snapshot);
+        progress = Interpreter::new(&module).resume(&restored, Some(1)).unwrap();
+    };
+    assert_eq!(done, expected);
+    // 4 outer transitions, 5 warming runs and 1 + 2 + 3 + 4 in the second nested run
+    assert_eq!((pauses, nested), (19, 10));
+
+    let mut interpreter = Interpreter::new(&module);
+    let snapshot = interpreter.start_snapshot("Digits", vec![Value::Int(0)]).unwrap();
+    let (run, snapshot) = interpreter.resume_mealy(&snapshot, "12".chars().map(Value::Char)).unwrap();
+    assert_eq!((run.halt, run.outputs), (MealyHalt::Consumed, vec![Value::Int(1), Value::Int(12)]));
+    let snapshot = Snapshot::from_bytes(&snapshot.to_bytes().unwrap()).unwrap();
+    assert_eq!((snapshot.frames[0].steps, snapshot.frames[0].previous.is_some()), (2, true));
+    let (run, _) = Interpreter::new(&module).resume_mealy(&snapshot, "3".chars().map(Value::Char)).unwrap();
+    assert_eq!(run.outputs, vec![Value::Int(123)]);
+
+    let changed = Parser::parse_str(&source.replace("acc: int64", "acc: float64")).unwrap();
+    assert!(matches!(
+        Interpreter::new(&changed).resume_mealy(&snapshot, vec![]),
+        Err(ExecError::InvalidSnapshot(SnapshotError::Program { .. })),
+    ));
+    let mut bytes = snapshot.to_bytes().unwrap();
+    bytes[4] = 9;
+    assert_eq!(Snapshot::from_bytes(&bytes), Err(SnapshotError::Version { expected: SNAPSHOT_VERSION, found: 9 }));
+    let bytes = snapshot.to_bytes().unwrap();
+    assert!(matches!(Snapshot::from_bytes(&bytes[..bytes.len() - 1]), Err(SnapshotError::Format(_))));
+    let mut forged = snapshot.clone();
+    forged.frames[0].current.state = "Write".to_string();
+    assert!(matches!(forged.check(&module), Err(SnapshotError::Mismatch(_))));
+}
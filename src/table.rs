@@ -0,0 +1,178 @@
+//! Transition tables of the automata for `fan table`: a row per `link` statement with its state,
+//! guard, target and arguments, exported as text, CSV, Markdown or JSON for reviews.
+
+use crate::graph::{AutomataGraph, Graph};
+use crate::json::Json;
+use crate::lexer::Position;
+
+/// `link` of a state body, the guard is empty for the unconditional ones
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    pub from: String,
+    pub guard: String,
+    /// `None` stands for NULL
+    pub to: Option<String>,
+    pub args: Vec<String>,
+    pub position: Position,
+}
+
+impl Row {
+    /// Target with the arguments, `Closed<false>`
+    pub fn target(&self) -> String {
+        match &self.to {
+            Some(to) if !self.args.is_empty() => format!("{}<{}>", to, self.args.join(", ")),
+            Some(to) => to.clone(),
+            None => "NULL".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransitionTable {
+    pub automata: String,
+    /// In the order of the `link`s in the source
+    pub rows: Vec<Row>,
+}
+
+impl TransitionTable {
+    /// Rows of the explicit `link`s, the default link to NULL is not a statement
+    pub fn new(a: &AutomataGraph) -> Self {
+        let rows = a.edges.iter().filter(|e| !e.implicit).map(|e| Row {
+            from: e.from.clone(),
+            guard: e.condition(),
+            to: e.to.clone(),
+            args: e.args.clone(),
+            position: e.position,
+        }).collect();
+        Self { automata: a.name.clone(), rows }
+    }
+
+    pub fn to_json(&self) -> Json {
+        let rows = self.rows.iter().map(|r| Json::object([
+            ("from", r.from.as_str().into()),
+            ("guard", r.guard.as_str().into()),
+            ("to", r.to.as_deref().into()),
+            ("args", r.args.clone().into()),
+            ("line", (r.position.line + 1).into()),
+        ])).collect();
+        Json::object([("automata", self.automata.as_str().into()), ("rows", Json::Array(rows))])
+    }
+
+    /// Table under a `## Name` heading, the code is in code spans
+    pub fn to_markdown(&self) -> String {
+        let mut out = format!("## {}\n\n| State | Guard | Target | Arguments |\n| --- | --- | --- | --- |\n", self.automata);
+        for r in self.rows.iter() {
+            out.push_str(&format!(
+                "| {} | {} | {} | {} |\n",
+                code(&r.from), code(&r.guard), code(r.to.as_deref().unwrap_or("NULL")), code(&r.args.join(", ")),
+            ));
+        }
+        out
+    }
+}
+
+/// `link`s of the automata, `from [guard] -> Target<args>` for the guarded ones
+impl std::fmt::Display for TransitionTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "automata {}", self.automata)?;
+        for r in self.rows.iter() {
+            match r.guard.as_str() {
+                "" => writeln!(f, "    {} -> {}", r.from, r.target())?,
+                guard => writeln!(f, "    {} [{}] -> {}", r.from, guard, r.target())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Markdown code span of a table cell, empty for the empty text
+fn code(s: &str) -> String {
+    if s.is_empty() {
+        return String::new();
+    }
+    let s = s.replace('|', "\\|");
+    if s.contains('`') { format!("`` {} ``", s) } else { format!("`{}`", s) }
+}
+
+/// CSV field quoted as in RFC 4180 when it has a separator, a quote or a line break
+fn field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) { format!("\"{}\"", s.replace('"', "\"\"")) } else { s.to_string() }
+}
+
+pub fn tables(graph: &Graph) -> Vec<TransitionTable> {
+    graph.automata.iter().map(TransitionTable::new).collect()
+}
+
+/// Single CSV of the tables with the automata in the first column and the arguments joined by `, `
+pub fn to_csv(tables: &[TransitionTable]) -> String {
+    let mut out = String::from("automata,state,guard,target,arguments\r\n");
+    for t in tables.iter() {
+        for r in t.rows.iter() {
+            let fields = [t.automata.as_str(), &r.from, &r.guard, r.to.as_deref().unwrap_or("NULL"), &r.args.join(", ")];
+            out.push_str(&fields.map(field).join(","));
+            out.push_str("\r\n");
+        }
+    }
+    out
+}
+
+/// Markdown tables separated by an empty line
+pub fn to_markdown(tables: &[TransitionTable]) -> String {
+    tables.iter().map(TransitionTable::to_markdown).collect::<Vec<_>>().join("\n")
+}
+
+#[test]
+fn table_test() {
+    use crate::parser::Parser;
+
+    let module = Parser::parse_str(r#"
+        automata Door {
+            state Closed<locked: bool, code: string> {
+                if locked || code == "a|b" { link self -> Closed<false, "x, \"y\"">; } else if self.previous.is_me() { link self -> Open; }
+            }
+            state Open { link self -> Closed<true, "z">; }
+        }
+        automata Timer {
+            state Tick<n: int64> { if n > 0 { link self -> Tick<n - 1>; } else { link self -> NULL; } }
+        }
+    "#).unwrap();
+    let tables = tables(&Graph::new(&module));
+    assert_eq!(tables[0].rows[1], Row {
+        from: "Closed".to_string(),
+        guard: "!(locked || code == \"a|b\") && self.previous.is_me()".to_string(),
+        to: Some("Open".to_string()),
+        args: vec![],
+        position: Position::new(3, 119),
+    });
+    assert_eq!(tables[0].to_string(), "\
+automata Door
+    Closed [(locked || code == \"a|b\")] -> Closed<false, \"x, \\\"y\\\"\">
+    Closed [!(locked || code == \"a|b\") && self.previous.is_me()] -> Open
+    Open -> Closed<true, \"z\">
+");
+    assert_eq!(to_csv(&tables), "\
+automata,state,guard,target,arguments\r
+Door,Closed,\"(locked || code == \"\"a|b\"\")\",Closed,\"false, \"\"x, \\\"\"y\\\"\"\"\"\"\r
+Door,Closed,\"!(locked || code == \"\"a|b\"\") && self.previous.is_me()\",Open,\r
+Door,Open,,Closed,\"true, \"\"z\"\"\"\r
+Timer,Tick,n > 0,Tick,n - 1\r
+Timer,Tick,!(n > 0),NULL,\r
+");
+    assert_eq!(to_markdown(&tables[1..]), "\
+## Timer
+
+| State | Guard | Target | Arguments |
+| --- | --- | --- | --- |
+| `Tick` | `n > 0` | `Tick` | `n - 1` |
+| `Tick` | `!(n > 0)` | `NULL` |  |
+");
+    assert!(tables[0].to_markdown().contains("| `Closed` | `(locked \\|\\| code == \"a\\|b\")` |"));
+    assert_eq!(
+        tables[1].to_json().to_string(),
+        concat!(
+            r#"{"automata":"Timer","rows":[{"from":"Tick","guard":"n > 0","to":"Tick","args":["n - 1"],"line":9},"#,
+            r#"{"from":"Tick","guard":"!(n > 0)","to":null,"args":[],"line":9}]}"#,
+        ),
+    );
+    assert_eq!(code("a`b"), "`` a`b ``");
+}
@@ -0,0 +1,252 @@
+//! Execution traces in JSON Lines: one event per line, recorded by [`Interpreter::with_trace`]
+//! and re-executed by [`Interpreter::replay`], which answers the host calls from the trace.
+//!
+//! [`Interpreter::with_trace`]: crate::interpreter::Interpreter::with_trace
+//! [`Interpreter::replay`]: crate::interpreter::Interpreter::replay
+
+use std::io::{BufRead, Write};
+
+use crate::interpreter::{ExecError, StateValue, Value};
+use crate::json::{Json, JsonError};
+use crate::snapshot::{state_to_json, value_from_json, value_to_json};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceEvent {
+    /// Automata started with the context arguments, nested `run`s included
+    Run { automata: String, args: Vec<Value> },
+    /// Initial state of the started automata
+    Enter { state: StateValue },
+    /// Signal read by the Mealy automata
+    Signal { value: Value },
+    /// Link chosen by the state body, `None` stands for NULL
+    Link { to: Option<StateValue> },
+    /// Native function, constructor or method of the uploaded type, `Type.method` for the latter
+    HostCall { function: String, args: Vec<Value>, result: Result<Value, String> },
+}
+
+/// Values of the uploaded types are recorded by their display only
+pub fn trace_value(v: &Value) -> Json {
+    value_to_json(v).unwrap_or_else(|_| Json::object([("opaque", v.to_string().into())]))
+}
+
+/// State object without the value tag, opaque when an argument is an uploaded value
+pub fn trace_state(s: &StateValue) -> Json {
+    state_to_json(s).unwrap_or_else(|_| Json::object([("opaque", s.to_string().into())]))
+}
+
+fn state_json(s: &StateValue) -> Json {
+    trace_value(&Value::State(Box::new(s.clone())))
+}
+
+impl TraceEvent {
+    pub fn to_json(&self) -> Json {
+        let values = |v: &[Value]| Json::Array(v.iter().map(trace_value).collect());
+        match self {
+            TraceEvent::Run { automata, args } => Json::object([
+                ("event", "run".into()), ("automata", automata.as_str().into()), ("args", values(args)),
+            ]),
+            TraceEvent::Enter { state } => Json::object([("event", "enter".into()), ("state", state_json(state))]),
+            TraceEvent::Signal { value } => Json::object([("event", "signal".into()), ("value", trace_value(value))]),
+            TraceEvent::Link { to } => Json::object([
+                ("event", "link".into()), ("to", to.as_ref().map_or(Json::Null, state_json)),
+            ]),
+            TraceEvent::HostCall { function, args, result } => {
+                let result = match result {
+                    Ok(v) => ("result", trace_value(v)),
+                    Err(e) => ("error", e.as_str().into()),
+                };
+                Json::object([("event", "host".into()), ("function", function.as_str().into()), ("args", values(args)), result])
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TraceError {
+    Io(std::io::Error),
+    /// Line is not JSON, one based
+    Json { line: usize, error: JsonError },
+}
+
+impl std::fmt::Display for TraceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TraceError::Io(e) => write!(f, "{}", e),
+            TraceError::Json { line, error } => write!(f, "line {}: {}", line, error),
+        }
+    }
+}
+
+/// Reads the JSON Lines trace, blank lines are skipped
+pub fn read_trace(reader: impl BufRead) -> Result<Vec<Json>, TraceError> {
+    let mut events = vec![];
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.map_err(TraceError::Io)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        events.push(Json::parse(&line).map_err(|error| TraceError::Json { line: i + 1, error })?);
+    }
+    Ok(events)
+}
+
+/// First event the replay did not reproduce
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    /// Index of the event in the trace
+    pub index: usize,
+    /// Recorded event, `None` when the trace ended earlier
+    pub expected: Option<Json>,
+    /// Replayed event, `None` when the replay ended earlier
+    pub found: Option<Json>,
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "event {} diverged: expected ", self.index)?;
+        match &self.expected {
+            Some(e) => write!(f, "{}", e)?,
+            None => write!(f, "end of the trace")?,
+        }
+        write!(f, ", found ")?;
+        match &self.found {
+            Some(e) => write!(f, "{}", e),
+            None => write!(f, "end of the execution"),
+        }
+    }
+}
+
+pub(crate) enum Tracer {
+    Record(Box<dyn Write>),
+    Replay { events: Vec<Json>, next: usize },
+}
+
+impl Tracer {
+    /// Writes or checks the event
+    pub(crate) fn event(&mut self, event: TraceEvent) -> Result<(), ExecError> {
+        let json = event.to_json();
+        match self {
+            Tracer::Record(w) => writeln!(w, "{}", json).map_err(|e| ExecError::Trace(e.to_string())),
+            Tracer::Replay { events, next } => {
+                let expected = events.get(*next);
+                if expected != Some(&json) {
+                    return Err(diverged(*next, expected.cloned(), Some(json)));
+                }
+                *next += 1;
+                Ok(())
+            },
+        }
+    }
+
+    /// Recorded result of the host call when replaying, the call itself has to match the trace
+    pub(crate) fn replayed_call(&mut self, function: &str, args: &[Value]) -> Result<Option<Result<Value, ExecError>>, ExecError> {
+        let Tracer::Replay { events, next } = self else {
+            return Ok(None);
+        };
+        let call = TraceEvent::HostCall { function: function.to_string(), args: args.to_vec(), result: Ok(Value::unit()) }.to_json();
+        let expected = events.get(*next);
+        let recorded = expected.filter(|e| e.get("event") == call.get("event")
+            && e.get("function") == call.get("function") && e.get("args") == call.get("args"));
+        let Some(recorded) = recorded else {
+            return Err(diverged(*next, expected.cloned(), Some(call)));
+        };
+        let result = match (recorded.get("result"), recorded.get("error")) {
+            (_, Some(e)) => Some(Err(ExecError::Host(e.as_str().unwrap_or_default().to_string()))),
+            // the live host produces the values of the uploaded types again
+            (Some(r), _) if r.get("opaque").is_some() => None,
+            (Some(r), _) => Some(Ok(value_from_json(r).map_err(|e| ExecError::Trace(e.to_string()))?)),
+            (None, None) => return Err(ExecError::Trace(format!("event {} has no result", *next))),
+        };
+        if result.is_some() {
+            *next += 1;
+        }
+        Ok(result)
+    }
+
+    /// Events left unreplayed
+    pub(crate) fn finish(&self) -> Result<(), ExecError> {
+        match self {
+            Tracer::Replay { events, next } if *next < events.len() => Err(diverged(*next, Some(events[*next].clone()), None)),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn diverged(index: usize, expected: Option<Json>, found: Option<Json>) -> ExecError {
+    ExecError::Diverged(Box::new(Divergence { index, expected, found }))
+}
+
+#[test]
+fn trace_test() {
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+    use crate::host::Host;
+    use crate::interpreter::{Interpreter, Replayed};
+    use crate::parser::Parser;
+
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let source = "
+        automata Half {
+            state Halve<n: int64> { if n > 1 { link self -> Halve<n / 2>; } }
+        }
+        automata Sensor: Mealy<signal, out> {
+            state Read<signal: int64, total: int64> {
+                let h = run Half<signal>;
+                out = total + h.n + jitter();
+                link self -> Read<out>;
+            }
+        }
+    ";
+    let module = Parser::parse_str(source).unwrap();
+    let seed = Rc::new(Cell::new(0));
+    let jitter = seed.clone();
+    let host = Host::new().with_function("jitter", move |_| {
+        jitter.set(jitter.get() + 7);
+        Ok(Value::Int(jitter.get()))
+    });
+    let buffer = Shared::default();
+    let input = vec![Value::Int(4), Value::Int(9)];
+    let recorded = Interpreter::new(&module).with_host(host).with_trace(buffer.clone())
+        .run_mealy("Sensor", vec![Value::Int(0)], input).unwrap();
+    assert_eq!(recorded.outputs, vec![Value::Int(8), Value::Int(23)]);
+
+    let text = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+    let events = read_trace(text.as_bytes()).unwrap();
+    let kinds: Vec<_> = events.iter().map(|e| e.get("event").and_then(Json::as_str).unwrap()).collect();
+    assert_eq!(kinds, [
+        "run", "enter",
+        "signal", "run", "enter", "link", "link", "link", "host", "link",
+        "signal", "run", "enter", "link", "link", "link", "link", "host", "link",
+    ]);
+    assert_eq!(events[8].to_string(), r#"{"event":"host","function":"jitter","args":[],"result":{"int":7}}"#);
+
+    // no host: the results come from the trace
+    let replayed = Interpreter::new(&module).replay(events.clone()).unwrap();
+    assert_eq!(replayed, Replayed::Mealy(recorded));
+    assert_eq!(seed.get(), 14);
+
+    let changed = Parser::parse_str(&source.replace("n / 2", "n / 3")).unwrap();
+    match Interpreter::new(&changed).replay(events.clone()) {
+        Err(ExecError::Diverged(d)) => {
+            assert_eq!(d.index, 5);
+            assert_eq!(d.found.unwrap().to_string(), r#"{"event":"link","to":{"state":{"automata":"Half","state":"Halve","args":[["n",{"int":1}]]}}}"#);
+        },
+        other => panic!("{:?}", other),
+    }
+    let truncated = events[..events.len() - 1].to_vec();
+    assert!(matches!(
+        Interpreter::new(&module).replay(truncated),
+        Err(ExecError::Diverged(d)) if d.index == 18 && d.expected.is_none(),
+    ));
+    assert!(matches!(read_trace("{}\nnot json".as_bytes()), Err(TraceError::Json { line: 2, .. })));
+}
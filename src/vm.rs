@@ -0,0 +1,743 @@
+use std::time::Duration;
+
+use crate::budget::{Budget, Clock, Limit, SystemClock, TracePosition};
+use crate::bytecode::{BinaryOp, Chunk, CompiledState, Instr, Program};
+use crate::checker::Type;
+use crate::interpreter::{
+    arithmetic, compare, index_of, match_pattern, member_of, ExecError, MealyHalt, MealyRun, StateValue, Value,
+};
+use crate::host::Host;
+use crate::lexer::Position;
+
+/// Linked state index with its arguments, `None` stands for NULL
+type Linked = Option<(usize, Vec<Value>)>;
+
+/// How the chunk finished
+enum Exit {
+    Return(Value),
+    Link(Linked),
+}
+
+/// Running automata, states are kept as indices until they leave the VM
+struct Frame {
+    automata: usize,
+    state: usize,
+    args: Vec<Value>,
+    previous: Option<(usize, Vec<Value>)>,
+    steps: usize,
+}
+
+/// Stack machine executing the compiled [`Program`], observably the same as the interpreter
+pub struct Vm<'p> {
+    program: &'p Program,
+    /// Locals of the running chunks followed by their operands
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    depth: usize,
+    budget: Budget,
+    clock: Box<dyn Clock>,
+    transitions: usize,
+    iterations: usize,
+    started: Duration,
+    position: Position,
+    host: Host,
+}
+
+impl<'p> Vm<'p> {
+    pub fn new(program: &'p Program) -> Self {
+        Self {
+            program,
+            stack: Vec::with_capacity(256),
+            frames: vec![],
+            depth: 0,
+            budget: Budget::default(),
+            clock: Box::new(SystemClock::new()),
+            transitions: 0,
+            iterations: 0,
+            started: Duration::ZERO,
+            position: Position::default(),
+            host: Host::default(),
+        }
+    }
+
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Uploaded Rust types and native functions callable from FAN
+    pub fn with_host(mut self, host: Host) -> Self {
+        self.host = host;
+        self
+    }
+
+    /// Replaces the clock measuring the wall clock limit
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Calls the module level function
+    pub fn call_function(&mut self, name: &str, args: Vec<Value>) -> Result<Value, ExecError> {
+        let f = *self.program.exports.get(name).ok_or_else(|| ExecError::UndefinedFunction(name.to_string()))?;
+        self.begin();
+        let argc = args.len();
+        self.stack.extend(args);
+        self.call(f, argc)
+    }
+
+    /// Runs the Moore automata from its initial state until it links to NULL.
+    /// Returns the final state with its arguments.
+    pub fn run_automata(&mut self, name: &str, args: Vec<Value>) -> Result<StateValue, ExecError> {
+        let a = self.find_automata(name)?;
+        self.run_moore(a, args)
+    }
+
+    /// Feeds the signals to the Mealy automata started with the context arguments.
+    /// States with the unit signal type do not read the input.
+    pub fn run_mealy(&mut self, name: &str, args: Vec<Value>, input: impl IntoIterator<Item = Value>) -> Result<MealyRun, ExecError> {
+        let a = self.find_automata(name)?;
+        if !self.program.automata[a].mealy {
+            return Err(ExecError::UnexpectedAutomataKind(name.to_string()));
+        }
+        self.push_frame(a, args)?;
+        let result = self.run_mealy_states(input);
+        let frame = self.frames.pop().expect("frame is pushed above");
+        result.map(|(halt, outputs, consumed)| MealyRun {
+            halt,
+            state: self.state_value(frame.automata, frame.state, frame.args),
+            outputs,
+            consumed,
+        })
+    }
+
+    fn find_automata(&self, name: &str) -> Result<usize, ExecError> {
+        self.program.find_automata(name).ok_or_else(|| ExecError::UndefinedAutomata(name.to_string()))
+    }
+
+    fn state_value(&self, automata: usize, state: usize, args: Vec<Value>) -> StateValue {
+        let a = &self.program.automata[automata];
+        let s = &a.states[state];
+        StateValue {
+            automata: a.name.clone(),
+            state: s.name.clone(),
+            args: s.params.iter().map(|(n, _)| n.clone()).zip(args).collect(),
+        }
+    }
+
+    /// Checks and coerces the template arguments of the state
+    fn enter(&self, automata: usize, state: usize, args: Vec<Value>) -> Result<Vec<Value>, ExecError> {
+        let s = &self.program.automata[automata].states[state];
+        if s.params.len() != args.len() {
+            return Err(ExecError::ArityMismatch { name: s.name.clone(), expected: s.params.len(), found: args.len() });
+        }
+        s.params.iter().zip(args).map(|((_, t), v)| v.coerce(t)).collect()
+    }
+
+    fn begin(&mut self) {
+        if self.frames.is_empty() && self.depth == 0 {
+            self.transitions = 0;
+            self.iterations = 0;
+            self.started = self.clock.now();
+        }
+    }
+
+    fn run_stack(&self) -> Vec<String> {
+        self.frames.iter().map(|f| self.program.automata[f.automata].name.clone()).collect()
+    }
+
+    fn exhausted(&self, limit: Limit) -> ExecError {
+        let frame = self.frames.last();
+        ExecError::BudgetExhausted {
+            limit,
+            position: Box::new(TracePosition {
+                run_stack: self.run_stack(),
+                state: frame.map(|f| self.state_value(f.automata, f.state, f.args.clone())),
+                steps: frame.map_or(0, |f| f.steps),
+                source: self.position,
+            }),
+        }
+    }
+
+    fn check_clock(&self) -> Result<(), ExecError> {
+        match self.budget.max_duration {
+            Some(max) if self.clock.now().saturating_sub(self.started) > max => Err(self.exhausted(Limit::WallClock(max))),
+            _ => Ok(()),
+        }
+    }
+
+    fn push_frame(&mut self, automata: usize, args: Vec<Value>) -> Result<(), ExecError> {
+        if self.frames.len() >= self.budget.max_run_depth {
+            let mut error = self.exhausted(Limit::RunDepth(self.budget.max_run_depth));
+            if let ExecError::BudgetExhausted { position, .. } = &mut error {
+                position.run_stack.push(self.program.automata[automata].name.clone());
+            }
+            return Err(error);
+        }
+        self.begin();
+        let a = &self.program.automata[automata];
+        let state = a.initial.ok_or_else(|| ExecError::NoStates(a.name.clone()))?;
+        let args = self.enter(automata, state, args)?;
+        self.frames.push(Frame { automata, state, args, previous: None, steps: 0 });
+        Ok(())
+    }
+
+    /// Moves the innermost frame to the linked state
+    fn advance(&mut self, state: usize, args: Vec<Value>) -> Result<(), ExecError> {
+        self.transitions += 1;
+        if let Some(max) = self.budget.max_transitions
+            && self.transitions > max
+        {
+            return Err(self.exhausted(Limit::Transitions(max)));
+        }
+        self.check_clock()?;
+        let frame = self.frames.last_mut().expect("frame is pushed by the caller");
+        let current = (std::mem::replace(&mut frame.state, state), std::mem::replace(&mut frame.args, args));
+        frame.previous = Some(current);
+        frame.steps += 1;
+        Ok(())
+    }
+
+    fn current_state(&self) -> &'p CompiledState {
+        let frame = self.frames.last().expect("frame is pushed by the caller");
+        &self.program.automata[frame.automata].states[frame.state]
+    }
+
+    fn run_moore(&mut self, automata: usize, args: Vec<Value>) -> Result<StateValue, ExecError> {
+        if self.program.automata[automata].mealy {
+            return Err(ExecError::UnexpectedAutomataKind(self.program.automata[automata].name.clone()));
+        }
+        self.push_frame(automata, args)?;
+        let result = self.run_moore_states();
+        let frame = self.frames.pop().expect("frame is pushed above");
+        result.map(|_| self.state_value(frame.automata, frame.state, frame.args))
+    }
+
+    fn run_moore_states(&mut self) -> Result<(), ExecError> {
+        while let (Some((state, args)), _) = self.frame_step(None)? {
+            self.advance(state, args)?;
+        }
+        Ok(())
+    }
+
+    fn run_mealy_states(&mut self, input: impl IntoIterator<Item = Value>) -> Result<(MealyHalt, Vec<Value>, usize), ExecError> {
+        let mut input = input.into_iter().peekable();
+        let mut outputs = vec![];
+        let mut consumed = 0;
+        loop {
+            let state = self.current_state();
+            let signal = if state.consumes_signal {
+                match input.peek() {
+                    Some(signal) => signal.clone(),
+                    None => return Ok((MealyHalt::Consumed, outputs, consumed)),
+                }
+            } else {
+                Value::unit()
+            };
+            let accepted = state.signal.as_ref().is_none_or(|t| signal.clone().coerce(t).is_ok());
+            if !accepted {
+                return Ok((MealyHalt::Rejected(signal), outputs, consumed));
+            }
+            let (next, output) = self.frame_step(Some(signal))?;
+            if state.consumes_signal {
+                input.next();
+                consumed += 1;
+            }
+            outputs.extend(output);
+            match next {
+                Some((state, args)) => self.advance(state, args)?,
+                None => return Ok((MealyHalt::Null { remaining: input.count() }, outputs, consumed)),
+            }
+        }
+    }
+
+    /// Executes the current state of the innermost frame.
+    /// Returns the linked state and the output of the transition.
+    fn frame_step(&mut self, signal: Option<Value>) -> Result<(Linked, Option<Value>), ExecError> {
+        let state = self.current_state();
+        let base = self.stack.len();
+        let frame = self.frames.last().expect("frame is pushed by the caller");
+        self.stack.extend(frame.args.iter().cloned());
+        self.stack.resize(base + state.chunk.slots, Value::unit());
+        if let Some(slot) = state.signal_slot {
+            let signal = signal.unwrap_or(Value::unit()).coerce(state.signal.as_ref().unwrap_or(&Type::Unknown));
+            match signal {
+                Ok(signal) => self.stack[base + slot] = signal,
+                Err(e) => {
+                    self.stack.truncate(base);
+                    return Err(e);
+                },
+            }
+        }
+        let result = self.execute(&state.chunk, base);
+        let output = state.output_slot.map(|o| std::mem::replace(&mut self.stack[base + o], Value::unit()));
+        self.stack.truncate(base);
+        match result? {
+            Exit::Link(next) => Ok((next, output)),
+            Exit::Return(_) => Err(ExecError::ReturnOutsideFunction),
+        }
+    }
+
+    /// Calls the function with the arguments on the top of the stack
+    fn call(&mut self, function: usize, argc: usize) -> Result<Value, ExecError> {
+        let f = &self.program.functions[function];
+        let base = self.stack.len() - argc;
+        if f.params != argc {
+            self.stack.truncate(base);
+            return Err(ExecError::ArityMismatch { name: f.name.clone(), expected: f.params, found: argc });
+        }
+        if self.depth >= self.budget.max_call_depth {
+            self.stack.truncate(base);
+            return Err(self.exhausted(Limit::CallDepth(self.budget.max_call_depth)));
+        }
+        if let Err(e) = self.check_clock() {
+            self.stack.truncate(base);
+            return Err(e);
+        }
+        self.stack.resize(base + f.chunk.slots, Value::unit());
+        self.depth += 1;
+        let result = self.execute(&f.chunk, base);
+        self.depth -= 1;
+        self.stack.truncate(base);
+        match result? {
+            Exit::Return(v) => Ok(v),
+            Exit::Link(_) => Err(ExecError::LinkInFunction),
+        }
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("compiler keeps the stack balanced")
+    }
+
+    fn pop_args(&mut self, argc: usize) -> Vec<Value> {
+        self.stack.split_off(self.stack.len() - argc)
+    }
+
+    fn execute(&mut self, chunk: &'p Chunk, base: usize) -> Result<Exit, ExecError> {
+        let program = self.program;
+        let mut ip = 0;
+        loop {
+            let instr = &chunk.code[ip];
+            ip += 1;
+            match instr {
+                Instr::Const(i) => self.stack.push(program.constants[*i].clone()),
+                Instr::Unit => self.stack.push(Value::unit()),
+                Instr::Load(slot) => self.stack.push(self.stack[base + slot].clone()),
+                Instr::Store(slot) => self.stack[base + slot] = self.pop(),
+                Instr::Pop => {
+                    self.pop();
+                },
+                Instr::Swap => {
+                    let n = self.stack.len();
+                    self.stack.swap(n - 1, n - 2);
+                },
+                Instr::Coerce(t) => {
+                    let v = self.pop().coerce(&program.types[*t])?;
+                    self.stack.push(v);
+                },
+                Instr::Tuple(n) => {
+                    let v = self.pop_args(*n);
+                    self.stack.push(Value::Tuple(v));
+                },
+                Instr::Binary(op) => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    let v = binary(*op, a, b)?;
+                    self.stack.push(v);
+                },
+                Instr::Neg => {
+                    let v = match self.pop() {
+                        Value::Int(i) => Value::Int(i.checked_neg().ok_or(ExecError::Overflow)?),
+                        Value::Float(x) => Value::Float(-x),
+                        v => return Err(ExecError::TypeMismatch { expected: "operand of `-`".to_string(), found: v.type_name() }),
+                    };
+                    self.stack.push(v);
+                },
+                Instr::Not => {
+                    let v = match self.pop() {
+                        Value::Bool(b) => Value::Bool(!b),
+                        v => return Err(ExecError::TypeMismatch { expected: "operand of `!`".to_string(), found: v.type_name() }),
+                    };
+                    self.stack.push(v);
+                },
+                Instr::BitNot => {
+                    let v = match self.pop() {
+                        Value::Int(i) => Value::Int(!i),
+                        v => return Err(ExecError::TypeMismatch { expected: "operand of `~`".to_string(), found: v.type_name() }),
+                    };
+                    self.stack.push(v);
+                },
+                Instr::Member(name) => {
+                    let v = member_of(self.pop(), &program.names[*name])?;
+                    self.stack.push(v);
+                },
+                Instr::Index => {
+                    let i = self.pop();
+                    let v = index_of(self.pop(), &i)?;
+                    self.stack.push(v);
+                },
+                Instr::LoadIndex(slot) => {
+                    let i = self.pop();
+                    let v = match (&self.stack[base + slot], &i) {
+                        (Value::Tuple(t), Value::Int(at)) if (0..t.len() as i64).contains(at) => t[*at as usize].clone(),
+                        (v, _) => index_of(v.clone(), &i)?,
+                    };
+                    self.stack.push(v);
+                },
+                Instr::StorePath { slot, depth } => {
+                    let path = self.pop_args(*depth);
+                    let value = self.pop();
+                    let mut target = &mut self.stack[base + slot];
+                    for i in path {
+                        let i = i.as_index()?;
+                        target = match target {
+                            Value::Tuple(v) => v.get_mut(i).ok_or(ExecError::IndexOutOfBounds(i as i64))?,
+                            v => return Err(ExecError::TypeMismatch { expected: "tuple".to_string(), found: v.type_name() }),
+                        };
+                    }
+                    *target = value;
+                },
+                Instr::Jump(t) => ip = *t,
+                Instr::JumpIfFalse(t) => {
+                    if !self.pop().as_bool()? {
+                        ip = *t;
+                    }
+                },
+                Instr::JumpIfFalseOrPop(t) | Instr::JumpIfTrueOrPop(t) => {
+                    let jump_on = matches!(instr, Instr::JumpIfTrueOrPop(_));
+                    let top = self.stack.last().expect("compiler keeps the stack balanced").as_bool()?;
+                    if top == jump_on {
+                        ip = *t;
+                    } else {
+                        self.pop();
+                    }
+                },
+                Instr::Items(slot) => {
+                    let items = match self.pop() {
+                        v @ (Value::Tuple(_) | Value::Int(_)) => v,
+                        Value::String(s) => Value::Tuple(s.chars().map(Value::Char).collect()),
+                        v => return Err(ExecError::TypeMismatch { expected: "tuple".to_string(), found: v.type_name() }),
+                    };
+                    self.stack[base + slot] = items;
+                },
+                Instr::ForNext { items, index, variable, exit } => {
+                    let Value::Int(i) = self.stack[base + index] else { unreachable!("index slot holds int") };
+                    let item = match &self.stack[base + items] {
+                        Value::Int(n) if i < *n => Some(Value::Int(i)),
+                        Value::Tuple(v) => v.get(i as usize).cloned(),
+                        _ => None,
+                    };
+                    match item {
+                        Some(item) => {
+                            self.stack[base + variable] = item;
+                            self.stack[base + index] = Value::Int(i + 1);
+                        },
+                        None => ip = *exit,
+                    }
+                },
+                Instr::Iteration => {
+                    self.position = chunk.positions[ip - 1];
+                    self.iterations += 1;
+                    if let Some(max) = self.budget.max_iterations
+                        && self.iterations > max
+                    {
+                        return Err(self.exhausted(Limit::Iterations(max)));
+                    }
+                    self.check_clock()?;
+                },
+                Instr::Call { function, argc } => {
+                    let v = self.call(*function, *argc)?;
+                    self.stack.push(v);
+                },
+                Instr::Native { name, argc } => {
+                    let args = self.pop_args(*argc);
+                    let v = self.host.call(&program.names[*name], args)?;
+                    self.stack.push(v);
+                },
+                Instr::Method { name, argc } => {
+                    let args = self.pop_args(*argc);
+                    let v = match self.pop() {
+                        Value::Host(h) => h.method(&program.names[*name], args)?,
+                        _ => return Err(ExecError::UndefinedMethod(program.names[*name].clone())),
+                    };
+                    self.stack.push(v);
+                },
+                Instr::Return => return Ok(Exit::Return(self.pop())),
+                Instr::Link { state, argc } => {
+                    self.position = chunk.positions[ip - 1];
+                    let args = self.pop_args(*argc);
+                    let automata = self.frames.last().expect("states run in a frame").automata;
+                    return Ok(Exit::Link(Some((*state, self.enter(automata, *state, args)?))));
+                },
+                Instr::LinkNull => return Ok(Exit::Link(None)),
+                Instr::Run { automata, argc } => {
+                    self.position = chunk.positions[ip - 1];
+                    let args = self.pop_args(*argc);
+                    let v = self.run_moore(*automata, args)?;
+                    self.stack.push(Value::State(Box::new(v)));
+                },
+                Instr::Test { pattern, first } => {
+                    let value = self.pop();
+                    let mut bindings = vec![];
+                    let matched = match_pattern(&program.patterns[*pattern], &value, &mut bindings)?;
+                    if matched {
+                        for (i, (_, v)) in bindings.into_iter().enumerate() {
+                            self.stack[base + first + i] = v;
+                        }
+                    }
+                    self.stack.push(Value::Bool(matched));
+                },
+                Instr::NoMatch => return Err(ExecError::NoMatch(self.pop().to_string())),
+                Instr::SelfState => {
+                    let f = self.frames.last().expect("states run in a frame");
+                    let v = self.state_value(f.automata, f.state, f.args.clone());
+                    self.stack.push(Value::State(Box::new(v)));
+                },
+                Instr::SelfPrevious => {
+                    let f = self.frames.last().expect("states run in a frame");
+                    let v = match &f.previous {
+                        Some((state, args)) => Value::State(Box::new(self.state_value(f.automata, *state, args.clone()))),
+                        None => Value::Null,
+                    };
+                    self.stack.push(v);
+                },
+                Instr::SelfSteps => {
+                    let steps = self.frames.last().expect("states run in a frame").steps;
+                    self.stack.push(Value::Int(steps as i64));
+                },
+                Instr::SelfAutomata => {
+                    let a = self.frames.last().expect("states run in a frame").automata;
+                    self.stack.push(Value::String(program.automata[a].name.clone()));
+                },
+                Instr::IsMe => {
+                    let current = self.frames.last().map(|f| {
+                        let a = &program.automata[f.automata];
+                        (&a.name, &a.states[f.state].name)
+                    });
+                    let v = match self.pop() {
+                        Value::State(s) => current.is_some_and(|(a, st)| s.automata == *a && s.state == *st),
+                        Value::Null => false,
+                        v => return Err(ExecError::TypeMismatch { expected: "state".to_string(), found: v.type_name() }),
+                    };
+                    self.stack.push(Value::Bool(v));
+                },
+                Instr::PreviousIsMe => {
+                    let f = self.frames.last().expect("states run in a frame");
+                    let v = f.previous.as_ref().is_some_and(|(state, _)| *state == f.state);
+                    self.stack.push(Value::Bool(v));
+                },
+                Instr::Fail(e) => return Err(program.errors[*e].clone()),
+            }
+        }
+    }
+}
+
+/// Binary operator with the fast path for integers
+fn binary(op: BinaryOp, a: Value, b: Value) -> Result<Value, ExecError> {
+    if let (Value::Int(x), Value::Int(y)) = (&a, &b) {
+        let (x, y) = (*x, *y);
+        return match op {
+            BinaryOp::Add => x.checked_add(y).map(Value::Int).ok_or(ExecError::Overflow),
+            BinaryOp::Sub => x.checked_sub(y).map(Value::Int).ok_or(ExecError::Overflow),
+            BinaryOp::Mul => x.checked_mul(y).map(Value::Int).ok_or(ExecError::Overflow),
+            BinaryOp::Eq => Ok(Value::Bool(x == y)),
+            BinaryOp::Ne => Ok(Value::Bool(x != y)),
+            BinaryOp::Lt => Ok(Value::Bool(x < y)),
+            BinaryOp::Gt => Ok(Value::Bool(x > y)),
+            BinaryOp::Le => Ok(Value::Bool(x <= y)),
+            BinaryOp::Ge => Ok(Value::Bool(x >= y)),
+            _ => arithmetic(op.as_str(), a, b),
+        };
+    }
+    if op.is_comparison() {
+        compare(op.as_str(), &a, &b)
+    } else {
+        arithmetic(op.as_str(), a, b)
+    }
+}
+
+#[cfg(test)]
+const PARITY_SOURCE: &str = "
+    fn fib(n: int64) -> int64 {
+        if n < 2 { return n; }
+        fib(n - 1) + fib(n - 2)
+    }
+    fn classify(v: (int64, char)) -> string {
+        match v {
+            (0, _) => \"zero\",
+            (n, 'a') => \"a\",
+            _ => \"other\",
+        }
+    }
+    fn sum(s: string) -> int64 {
+        let total = 0;
+        for c in s { total += c - '0'; }
+        let i = 0;
+        while i < 3 && total > 0 { i += 1; }
+        total * 10 + i
+    }
+    automata ContextAutomata {
+        state Base<context1: int64, context2: int64> {
+            if context1 > context2 {
+                link self -> Verdict1<context1, context2>;
+            } else {
+                link self -> Verdict2<context2>;
+            }
+        }
+        state Verdict1<arg1: int64, arg2: int64> { link self -> NULL; }
+        state Verdict2<arg1: int64> { }
+    }
+    automata MyAutomata {
+        state Base<context: (int64, int64, int64)> {
+            if self.previous.is_me() {
+                context[0] += 1;
+            }
+            if context[0] == 10 {
+                link self -> Verdict<context[1], context[2], self.steps>;
+            } else {
+                link self -> Base<context>;
+            }
+        }
+        state Verdict<a: int64, b: int64, steps: int64> {
+            let r = run ContextAutomata<a, b>;
+            if r is ContextAutomata::Verdict1(verd1) {
+                link self -> Done<verd1.arg1 - verd1.arg2, self.automata>;
+            } else {
+                link self -> Done<0, self.automata>;
+            }
+        }
+        state Done<diff: int64, name: string> { }
+    }
+    automata Mathematica: Mealy<signal, out> {
+        state Number<signal: char, acc: int64> {
+            if signal == '=' {
+                link self -> Result<acc>;
+            } else {
+                out = acc * 10 + (signal - '0');
+                link self -> Number<out>;
+            }
+        }
+        state Result<signal: (), acc: int64> {
+            out = acc;
+        }
+    }
+";
+
+#[test]
+fn vm_parity_test() {
+    use crate::host::Host;
+    use crate::interpreter::Interpreter;
+    use crate::parser::Parser;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    let module = Parser::parse_str(PARITY_SOURCE).unwrap();
+    let program = Program::compile(&module);
+    let mut interpreter = Interpreter::new(&module);
+    let mut vm = Vm::new(&program);
+
+    let tuple = |v: Vec<Value>| vec![Value::Tuple(v)];
+    let calls = [
+        ("fib", vec![Value::Int(15)]),
+        ("classify", tuple(vec![Value::Int(0), Value::Char('a')])),
+        ("classify", tuple(vec![Value::Int(3), Value::Char('a')])),
+        ("classify", tuple(vec![Value::Int(3), Value::Char('b')])),
+        ("sum", vec![Value::String("123".to_string())]),
+        ("sum", vec![Value::String("".to_string())]),
+        ("sum", vec![Value::String("1x".to_string())]),
+        ("fib", vec![]),
+        ("missing", vec![]),
+    ];
+    for (name, args) in calls {
+        assert_eq!(vm.call_function(name, args.clone()), interpreter.call_function(name, args), "{}", name);
+    }
+    assert_eq!(vm.call_function("fib", vec![Value::Int(15)]), Ok(Value::Int(610)));
+
+    for (a, b) in [(7, 2), (1, 2)] {
+        let args = tuple(vec![Value::Int(0), Value::Int(a), Value::Int(b)]);
+        let expected = interpreter.run_automata("MyAutomata", args.clone());
+        assert_eq!(vm.run_automata("MyAutomata", args), expected);
+        assert_eq!(expected.unwrap().state, "Done");
+    }
+    assert_eq!(vm.run_automata("MyAutomata", vec![]), interpreter.run_automata("MyAutomata", vec![]));
+    assert_eq!(vm.run_automata("Mathematica", vec![]), interpreter.run_automata("Mathematica", vec![]));
+
+    for input in ["12=", "12", "1=2", ""] {
+        let signals = || input.chars().map(Value::Char);
+        let expected = interpreter.run_mealy("Mathematica", vec![Value::Int(0)], signals());
+        assert_eq!(vm.run_mealy("Mathematica", vec![Value::Int(0)], signals()), expected, "{}", input);
+    }
+
+    // the host sees the calls of a compound assignment in the same order
+    let module = Parser::parse_str("
+        fn order() -> (int64, int64) {
+            let t = (1, 2);
+            t[log(0)] += log(1);
+            t
+        }
+    ").unwrap();
+    let program = Program::compile(&module);
+    let calls = |log: &Rc<RefCell<Vec<Value>>>| {
+        let log = log.clone();
+        Host::new().with_function("log", move |args| {
+            log.borrow_mut().push(args[0].clone());
+            Ok(args[0].clone())
+        })
+    };
+    let (interpreted, compiled) = (Rc::new(RefCell::new(vec![])), Rc::new(RefCell::new(vec![])));
+    let expected = Interpreter::new(&module).with_host(calls(&interpreted)).call_function("order", vec![]);
+    assert_eq!(Vm::new(&program).with_host(calls(&compiled)).call_function("order", vec![]), expected);
+    assert_eq!(expected, Ok(Value::Tuple(vec![Value::Int(2), Value::Int(2)])));
+    assert_eq!(*compiled.borrow(), *interpreted.borrow());
+    assert_eq!(*interpreted.borrow(), vec![Value::Int(1), Value::Int(0), Value::Int(0)]);
+}
+
+#[test]
+fn vm_budget_test() {
+    use crate::parser::Parser;
+    let module = Parser::parse_str("
+        automata Loop {
+            state Base<n: int64> { link self -> Base<n + 1>; }
+        }
+        automata Runaway {
+            state Again { let r = run Runaway; }
+        }
+    ").unwrap();
+    let program = Program::compile(&module);
+    let budget = Budget { max_transitions: Some(1000), max_run_depth: 3, ..Budget::default() };
+    let mut vm = Vm::new(&program).with_budget(budget);
+    match vm.run_automata("Loop", vec![Value::Int(0)]) {
+        Err(ExecError::BudgetExhausted { limit: Limit::Transitions(1000), position }) => {
+            assert_eq!(position.steps, 1000);
+            assert_eq!(position.state.unwrap().args[0].1, Value::Int(1000));
+            assert_eq!(position.source, Position::new(2, 35));
+        },
+        r => panic!("{:?}", r),
+    }
+    match vm.run_automata("Runaway", vec![]) {
+        Err(ExecError::BudgetExhausted { limit: Limit::RunDepth(3), position }) =>
+            assert_eq!(position.run_stack, vec!["Runaway".to_string(); 4]),
+        r => panic!("{:?}", r),
+    }
+    // the stack is unwound after the error
+    assert!(vm.stack.is_empty() && vm.frames.is_empty());
+}
+
+#[test]
+fn vm_recursion_test() {
+    use crate::interpreter::MAX_CALL_DEPTH;
+    use crate::parser::Parser;
+    std::thread::Builder::new().stack_size(64 << 20).spawn(|| {
+        let module = Parser::parse_str("
+            fn sum(n: int64) -> int64 { if n == 0 { return 0; } return n + sum(n - 1); }
+        ").unwrap();
+        let program = Program::compile(&module);
+        let mut vm = Vm::new(&program);
+        assert_eq!(vm.call_function("sum", vec![Value::Int(100)]), Ok(Value::Int(5050)));
+        assert_eq!(vm.call_function("sum", vec![Value::Int(999)]), Ok(Value::Int(499500)));
+        match vm.call_function("sum", vec![Value::Int(MAX_CALL_DEPTH as i64)]) {
+            Err(ExecError::BudgetExhausted { limit: Limit::CallDepth(MAX_CALL_DEPTH), .. }) => {},
+            r => panic!("{:?}", r),
+        }
+        assert!(vm.stack.is_empty());
+    }).unwrap().join().unwrap();
+}